@@ -9,13 +9,17 @@ use std::{str::FromStr, time::Duration};
 use jmap_proto::request::capability::BaseCapabilities;
 use mail_parser::HeaderName;
 use nlp::language::Language;
-use store::rand::{distributions::Alphanumeric, thread_rng, Rng};
+use store::{
+    query::QueryLimits,
+    rand::{distributions::Alphanumeric, thread_rng, Rng},
+};
 use utils::config::{cron::SimpleCron, utils::ParseValue, Config, Rate};
 
 #[derive(Default, Clone)]
 pub struct JmapConfig {
     pub default_language: Language,
     pub query_max_results: usize,
+    pub query_limits: QueryLimits,
     pub snippet_max_results: usize,
 
     pub changes_max_results: usize,
@@ -58,6 +62,7 @@ pub struct JmapConfig {
     pub push_timeout: Duration,
     pub push_verify_timeout: Duration,
     pub push_throttle: Duration,
+    pub state_change_debounce: Duration,
 
     pub web_socket_throttle: Duration,
     pub web_socket_timeout: Duration,
@@ -252,6 +257,12 @@ impl JmapConfig {
             query_max_results: config
                 .property("jmap.protocol.query.max-results")
                 .unwrap_or(5000),
+            query_limits: QueryLimits {
+                timeout: config
+                    .property::<Option<Duration>>("jmap.protocol.query.timeout")
+                    .unwrap_or_default(),
+                max_memory: config.property("jmap.protocol.query.max-memory"),
+            },
             changes_max_results: config
                 .property("jmap.protocol.changes.max-results")
                 .unwrap_or(5000),
@@ -413,6 +424,10 @@ impl JmapConfig {
             push_throttle: config
                 .property_or_default("jmap.push.throttle", "1s")
                 .unwrap_or_else(|| Duration::from_secs(1)),
+            state_change_debounce: config
+                .property::<Option<Duration>>("jmap.state-change.debounce")
+                .unwrap_or_default()
+                .unwrap_or_default(),
             session_purge_frequency: config
                 .property_or_default::<SimpleCron>("jmap.session.purge.frequency", "15 * *")
                 .unwrap_or_else(|| SimpleCron::parse_value("15 * *").unwrap()),
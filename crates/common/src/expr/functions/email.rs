@@ -6,6 +6,8 @@
 
 use std::borrow::Cow;
 
+use nlp::tokenizers::address::split_address;
+
 use crate::expr::Variable;
 
 pub(crate) fn fn_is_email(v: Vec<Variable>) -> Variable {
@@ -84,19 +86,17 @@ pub(crate) fn fn_email_part(v: Vec<Variable>) -> Variable {
     let part = v.next().unwrap().into_string();
 
     value.transform(|s| match s {
-        Cow::Borrowed(s) => s
-            .rsplit_once('@')
+        Cow::Borrowed(s) => split_address(s)
             .map(|(u, d)| match part.as_ref() {
-                "local" => Variable::from(u.trim()),
-                "domain" => Variable::from(d.trim()),
+                "local" => Variable::from(u),
+                "domain" => Variable::from(d),
                 _ => Variable::default(),
             })
             .unwrap_or_default(),
-        Cow::Owned(s) => s
-            .rsplit_once('@')
+        Cow::Owned(s) => split_address(&s)
             .map(|(u, d)| match part.as_ref() {
-                "local" => Variable::from(u.trim().to_string()),
-                "domain" => Variable::from(d.trim().to_string()),
+                "local" => Variable::from(u.to_string()),
+                "domain" => Variable::from(d.to_string()),
                 _ => Variable::default(),
             })
             .unwrap_or_default(),
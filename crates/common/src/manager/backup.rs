@@ -56,6 +56,11 @@ pub(super) enum Family {
     Index = 9,
     Bitmap = 10,
     Log = 11,
+    Intent = 12,
+    Quarantine = 13,
+    Vector = 14,
+    Collection = 15,
+    Label = 16,
     None = 255,
 }
 
@@ -84,6 +89,11 @@ impl Core {
             self.backup_index(&dest),
             self.backup_bitmaps(&dest),
             self.backup_logs(&dest),
+            self.backup_intents(&dest),
+            self.backup_quarantine(&dest),
+            self.backup_vectors(&dest),
+            self.backup_collections(&dest),
+            self.backup_labels(&dest),
         ] {
             async_handle.await.failed("Task failed");
             sync_handles.push(sync_handle);
@@ -1053,6 +1063,285 @@ impl Core {
             handle,
         )
     }
+    fn backup_intents(&self, dest: &Path) -> TaskHandle {
+        let store = self.storage.data.clone();
+        let (handle, writer) = spawn_writer(dest.join("intent"));
+        (
+            tokio::spawn(async move {
+                writer
+                    .send(Op::Family(Family::Intent))
+                    .failed("Failed to send family");
+
+                store
+                    .iterate(
+                        IterateParams::new(
+                            ValueKey::from(ValueClass::Intent(0)),
+                            ValueKey::from(ValueClass::Intent(u64::MAX)),
+                        ),
+                        |key, value| {
+                            writer
+                                .send(Op::KeyValue((key.to_vec(), value.to_vec())))
+                                .failed("Failed to send key value");
+
+                            Ok(true)
+                        },
+                    )
+                    .await
+                    .failed("Failed to iterate over data store");
+            }),
+            handle,
+        )
+    }
+    fn backup_quarantine(&self, dest: &Path) -> TaskHandle {
+        let store = self.storage.data.clone();
+        let (handle, writer) = spawn_writer(dest.join("quarantine"));
+        (
+            tokio::spawn(async move {
+                writer
+                    .send(Op::Family(Family::Quarantine))
+                    .failed("Failed to send family");
+
+                let mut last_account_id = u32::MAX;
+                let mut last_collection = u8::MAX;
+
+                store
+                    .iterate(
+                        IterateParams::new(
+                            ValueKey {
+                                account_id: 0,
+                                collection: 0,
+                                document_id: 0,
+                                class: ValueClass::Quarantine,
+                            },
+                            ValueKey {
+                                account_id: u32::MAX,
+                                collection: u8::MAX,
+                                document_id: u32::MAX,
+                                class: ValueClass::Quarantine,
+                            },
+                        ),
+                        |key, value| {
+                            let account_id = key.deserialize_be_u32(0)?;
+                            let collection = key.deserialize_u8(U32_LEN)?;
+                            let document_id = key.deserialize_be_u32(U32_LEN + 1)?;
+
+                            if account_id != last_account_id {
+                                writer
+                                    .send(Op::AccountId(account_id))
+                                    .failed("Failed to send account id");
+                                last_account_id = account_id;
+                            }
+
+                            if collection != last_collection {
+                                writer
+                                    .send(Op::Collection(collection))
+                                    .failed("Failed to send collection");
+                                last_collection = collection;
+                            }
+
+                            writer
+                                .send(Op::DocumentId(document_id))
+                                .failed("Failed to send document id");
+
+                            writer
+                                .send(Op::KeyValue((vec![], value.to_vec())))
+                                .failed("Failed to send key value");
+
+                            Ok(true)
+                        },
+                    )
+                    .await
+                    .failed("Failed to iterate over data store");
+            }),
+            handle,
+        )
+    }
+    fn backup_vectors(&self, dest: &Path) -> TaskHandle {
+        let store = self.storage.data.clone();
+        let (handle, writer) = spawn_writer(dest.join("vector"));
+        (
+            tokio::spawn(async move {
+                writer
+                    .send(Op::Family(Family::Vector))
+                    .failed("Failed to send family");
+
+                let mut last_account_id = u32::MAX;
+                let mut last_collection = u8::MAX;
+
+                store
+                    .iterate(
+                        IterateParams::new(
+                            ValueKey {
+                                account_id: 0,
+                                collection: 0,
+                                document_id: 0,
+                                class: ValueClass::Vector(0),
+                            },
+                            ValueKey {
+                                account_id: u32::MAX,
+                                collection: u8::MAX,
+                                document_id: u32::MAX,
+                                class: ValueClass::Vector(u8::MAX),
+                            },
+                        ),
+                        |key, value| {
+                            let account_id = key.deserialize_be_u32(0)?;
+                            let collection = key.deserialize_u8(U32_LEN)?;
+                            let field = key.deserialize_u8(U32_LEN + 1)?;
+                            let document_id = key.deserialize_be_u32(U32_LEN + 2)?;
+
+                            if account_id != last_account_id {
+                                writer
+                                    .send(Op::AccountId(account_id))
+                                    .failed("Failed to send account id");
+                                last_account_id = account_id;
+                            }
+
+                            if collection != last_collection {
+                                writer
+                                    .send(Op::Collection(collection))
+                                    .failed("Failed to send collection");
+                                last_collection = collection;
+                            }
+
+                            writer
+                                .send(Op::DocumentId(document_id))
+                                .failed("Failed to send document id");
+
+                            writer
+                                .send(Op::KeyValue((vec![field], value.to_vec())))
+                                .failed("Failed to send key value");
+
+                            Ok(true)
+                        },
+                    )
+                    .await
+                    .failed("Failed to iterate over data store");
+            }),
+            handle,
+        )
+    }
+    fn backup_collections(&self, dest: &Path) -> TaskHandle {
+        let store = self.storage.data.clone();
+        let (handle, writer) = spawn_writer(dest.join("collection"));
+        (
+            tokio::spawn(async move {
+                writer
+                    .send(Op::Family(Family::Collection))
+                    .failed("Failed to send family");
+
+                let mut last_account_id = u32::MAX;
+
+                store
+                    .iterate(
+                        IterateParams::new(
+                            ValueKey {
+                                account_id: 0,
+                                collection: 0,
+                                document_id: 0,
+                                class: ValueClass::Collection,
+                            },
+                            ValueKey {
+                                account_id: u32::MAX,
+                                collection: u8::MAX,
+                                document_id: u32::MAX,
+                                class: ValueClass::CollectionName,
+                            },
+                        ),
+                        |key, value| {
+                            let account_id = key.deserialize_be_u32(0)?;
+                            let collection = key.deserialize_u8(U32_LEN)?;
+
+                            if account_id != last_account_id {
+                                writer
+                                    .send(Op::AccountId(account_id))
+                                    .failed("Failed to send account id");
+                                last_account_id = account_id;
+                            }
+
+                            writer
+                                .send(Op::Collection(collection))
+                                .failed("Failed to send collection");
+
+                            // Registry entries have an empty key, names are keyed by a marker
+                            writer
+                                .send(Op::KeyValue((
+                                    key.get(U32_LEN + 1..).unwrap_or_default().to_vec(),
+                                    value.to_vec(),
+                                )))
+                                .failed("Failed to send key value");
+
+                            Ok(true)
+                        },
+                    )
+                    .await
+                    .failed("Failed to iterate over data store");
+            }),
+            handle,
+        )
+    }
+    fn backup_labels(&self, dest: &Path) -> TaskHandle {
+        let store = self.storage.data.clone();
+        let (handle, writer) = spawn_writer(dest.join("label"));
+        (
+            tokio::spawn(async move {
+                writer
+                    .send(Op::Family(Family::Label))
+                    .failed("Failed to send family");
+
+                let mut last_account_id = u32::MAX;
+                let mut last_collection = u8::MAX;
+
+                store
+                    .iterate(
+                        IterateParams::new(
+                            ValueKey {
+                                account_id: 0,
+                                collection: 0,
+                                document_id: 0,
+                                class: ValueClass::Label(vec![0]),
+                            },
+                            ValueKey {
+                                account_id: u32::MAX,
+                                collection: u8::MAX,
+                                document_id: u32::MAX,
+                                class: ValueClass::Label(vec![u8::MAX; 10]),
+                            },
+                        ),
+                        |key, value| {
+                            let account_id = key.deserialize_be_u32(0)?;
+                            let collection = key.deserialize_u8(U32_LEN)?;
+
+                            if account_id != last_account_id {
+                                writer
+                                    .send(Op::AccountId(account_id))
+                                    .failed("Failed to send account id");
+                                last_account_id = account_id;
+                            }
+
+                            if collection != last_collection {
+                                writer
+                                    .send(Op::Collection(collection))
+                                    .failed("Failed to send collection");
+                                last_collection = collection;
+                            }
+
+                            writer
+                                .send(Op::KeyValue((
+                                    key.range(U32_LEN + 1..usize::MAX)?.to_vec(),
+                                    value.to_vec(),
+                                )))
+                                .failed("Failed to send key value");
+
+                            Ok(true)
+                        },
+                    )
+                    .await
+                    .failed("Failed to iterate over data store");
+            }),
+            handle,
+        )
+    }
 }
 
 fn spawn_writer(path: PathBuf) -> (std::thread::JoinHandle<()>, SyncSender<Op>) {
@@ -324,6 +324,11 @@ async fn restore_file(store: Store, blob_store: BlobStore, path: &Path) {
                         let document_ids = RoaringBitmap::deserialize_from(&value[..])
                             .expect("Failed to deserialize bitmap");
 
+                        // Dumps taken before the collection registry existed
+                        if matches!(class, BitmapClass::DocumentIds) && !document_ids.is_empty() {
+                            batch.set(ValueClass::Collection, vec![]);
+                        }
+
                         for document_id in document_ids {
                             batch.ops.push(Operation::DocumentId { document_id });
                             batch.ops.push(Operation::Bitmap {
@@ -354,6 +359,39 @@ async fn restore_file(store: Store, blob_store: BlobStore, path: &Path) {
                             set: MaybeDynamicValue::Static(value),
                         });
                     }
+                    Family::Intent => {
+                        batch.set(
+                            ValueClass::Intent(
+                                key.as_slice()
+                                    .deserialize_be_u64(0)
+                                    .expect("Failed to deserialize intent id"),
+                            ),
+                            value,
+                        );
+                    }
+                    Family::Quarantine => {
+                        batch.set(ValueClass::Quarantine, value);
+                    }
+                    Family::Vector => {
+                        batch.set(
+                            ValueClass::Vector(
+                                key.as_slice()
+                                    .deserialize_u8(0)
+                                    .expect("Failed to deserialize field"),
+                            ),
+                            value,
+                        );
+                    }
+                    Family::Collection => {
+                        if key.is_empty() {
+                            batch.set(ValueClass::Collection, vec![]);
+                        } else {
+                            batch.set(ValueClass::CollectionName, value);
+                        }
+                    }
+                    Family::Label => {
+                        batch.set(ValueClass::Label(key), value);
+                    }
                     Family::None => failed("No family specified in file"),
                 }
             }
@@ -471,6 +509,11 @@ impl TryFrom<u8> for Family {
             9 => Ok(Self::Index),
             10 => Ok(Self::Bitmap),
             11 => Ok(Self::Log),
+            12 => Ok(Self::Intent),
+            13 => Ok(Self::Quarantine),
+            14 => Ok(Self::Vector),
+            15 => Ok(Self::Collection),
+            16 => Ok(Self::Label),
             other => Err(format!("Unknown family type {other}")),
         }
     }
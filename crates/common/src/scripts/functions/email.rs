@@ -4,6 +4,7 @@
  * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
  */
 
+use nlp::tokenizers::address::split_address;
 use sieve::{runtime::Variable, Context};
 
 use super::ApplyString;
@@ -80,10 +81,10 @@ pub fn fn_is_email<'x>(_: &'x Context<'x>, v: Vec<Variable>) -> Variable {
 
 pub fn fn_email_part<'x>(_: &'x Context<'x>, v: Vec<Variable>) -> Variable {
     v[0].transform(|s| {
-        s.rsplit_once('@')
+        split_address(s)
             .map(|(u, d)| match v[1].to_string().as_ref() {
-                "local" => Variable::from(u.trim()),
-                "domain" => Variable::from(d.trim()),
+                "local" => Variable::from(u),
+                "domain" => Variable::from(d),
                 _ => Variable::default(),
             })
             .unwrap_or_default()
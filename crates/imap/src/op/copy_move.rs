@@ -13,7 +13,14 @@ use imap_proto::{
 
 use crate::core::{MailboxId, SelectedMailbox, Session, SessionData};
 use common::listener::SessionStream;
-use jmap::{email::set::TagManager, mailbox::UidMailbox};
+use jmap::{
+    email::{
+        intent::{MessageMoveIntent, MessageMoveStep},
+        metadata::MessageMetadata,
+        set::TagManager,
+    },
+    mailbox::UidMailbox,
+};
 use jmap_proto::{
     error::{method::MethodError, set::SetErrorType},
     types::{
@@ -23,7 +30,7 @@ use jmap_proto::{
 };
 use store::{
     roaring::RoaringBitmap,
-    write::{assert::HashedValue, log::ChangeLogBuilder, BatchBuilder, F_VALUE},
+    write::{assert::HashedValue, log::ChangeLogBuilder, BatchBuilder, Bincode, F_VALUE},
 };
 
 impl<T: SessionStream> Session<T> {
@@ -273,6 +280,45 @@ impl<T: SessionStream> SessionData<T> {
                 })?
                 .quota as i64;
             let mut destroy_ids = RoaringBitmap::new();
+
+            // Record the move so it can be completed if the server stops halfway
+            let mut intent = if is_move {
+                let mut messages = Vec::with_capacity(ids.len());
+                for (id, _) in &ids {
+                    if let Some(metadata) = self
+                        .jmap
+                        .get_property::<Bincode<MessageMetadata>>(
+                            src_account_id,
+                            Collection::Email,
+                            *id,
+                            Property::BodyStructure,
+                        )
+                        .await
+                        .map_err(|_| StatusResponse::database_failure().with_tag(&arguments.tag))?
+                    {
+                        messages.push(MessageMoveStep {
+                            document_id: *id,
+                            blob_hash: metadata.inner.blob_hash,
+                            dest_document_id: None,
+                        });
+                    }
+                }
+                let intent = MessageMoveIntent {
+                    src_account_id,
+                    src_mailbox_id: src_mailbox.id.mailbox_id,
+                    dest_account_id,
+                    messages,
+                };
+                let intent_id = self
+                    .jmap
+                    .email_move_intent_begin(&intent)
+                    .await
+                    .map_err(|_| StatusResponse::database_failure().with_tag(&arguments.tag))?;
+                Some((intent_id, intent))
+            } else {
+                None
+            };
+
             for (id, imap_id) in ids {
                 match self
                     .jmap
@@ -293,6 +339,23 @@ impl<T: SessionStream> SessionData<T> {
                             debug_assert!(*assigned_uid > 0);
                             copied_ids.push((imap_id.uid, *assigned_uid));
                         }
+
+                        // Record the copy before the original is removed
+                        if let Some((intent_id, intent)) = &mut intent {
+                            if let Some(step) = intent
+                                .messages
+                                .iter_mut()
+                                .find(|step| step.document_id == id)
+                            {
+                                step.dest_document_id = email.id.document_id().into();
+                                self.jmap
+                                    .email_move_intent_update(*intent_id, intent)
+                                    .await
+                                    .map_err(|_| {
+                                        StatusResponse::database_failure().with_tag(&arguments.tag)
+                                    })?;
+                            }
+                        }
                     }
                     Ok(Err(err)) => {
                         if err.type_ != SetErrorType::NotFound {
@@ -326,6 +389,12 @@ impl<T: SessionStream> SessionData<T> {
                 .map_err(|err| err.with_tag(&arguments.tag))?;
                 did_move = true;
             }
+            if let Some((intent_id, _)) = intent {
+                self.jmap
+                    .email_move_intent_end(intent_id)
+                    .await
+                    .map_err(|_| StatusResponse::database_failure().with_tag(&arguments.tag))?;
+            }
 
             // Broadcast changes on destination account
             if let Some(change_id) = dest_change_id {
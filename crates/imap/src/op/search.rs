@@ -259,10 +259,9 @@ impl<T: SessionStream> SessionData<T> {
                     for cond in conds {
                         match cond {
                             search::Filter::Bcc(text) => {
-                                fts_filters.push(FtsFilter::has_text(
+                                fts_filters.push(FtsFilter::has_address(
                                     Field::Header(HeaderName::Bcc),
                                     text,
-                                    Language::None,
                                 ));
                             }
                             search::Filter::Body(text) => {
@@ -273,17 +272,15 @@ impl<T: SessionStream> SessionData<T> {
                                 ));
                             }
                             search::Filter::Cc(text) => {
-                                fts_filters.push(FtsFilter::has_text(
+                                fts_filters.push(FtsFilter::has_address(
                                     Field::Header(HeaderName::Cc),
                                     text,
-                                    Language::None,
                                 ));
                             }
                             search::Filter::From(text) => {
-                                fts_filters.push(FtsFilter::has_text(
+                                fts_filters.push(FtsFilter::has_address(
                                     Field::Header(HeaderName::From),
                                     text,
-                                    Language::None,
                                 ));
                             }
                             search::Filter::Header(header, value) => {
@@ -332,25 +329,21 @@ impl<T: SessionStream> SessionData<T> {
                             }
                             search::Filter::Text(text) => {
                                 fts_filters.push(FtsFilter::Or);
-                                fts_filters.push(FtsFilter::has_text(
+                                fts_filters.push(FtsFilter::has_address(
                                     Field::Header(HeaderName::From),
                                     &text,
-                                    Language::None,
                                 ));
-                                fts_filters.push(FtsFilter::has_text(
+                                fts_filters.push(FtsFilter::has_address(
                                     Field::Header(HeaderName::To),
                                     &text,
-                                    Language::None,
                                 ));
-                                fts_filters.push(FtsFilter::has_text(
+                                fts_filters.push(FtsFilter::has_address(
                                     Field::Header(HeaderName::Cc),
                                     &text,
-                                    Language::None,
                                 ));
-                                fts_filters.push(FtsFilter::has_text(
+                                fts_filters.push(FtsFilter::has_address(
                                     Field::Header(HeaderName::Bcc),
                                     &text,
-                                    Language::None,
                                 ));
                                 fts_filters.push(FtsFilter::has_text_detect(
                                     Field::Header(HeaderName::Subject),
@@ -370,10 +363,9 @@ impl<T: SessionStream> SessionData<T> {
                                 fts_filters.push(FtsFilter::End);
                             }
                             search::Filter::To(text) => {
-                                fts_filters.push(FtsFilter::has_text(
+                                fts_filters.push(FtsFilter::has_address(
                                     Field::Header(HeaderName::To),
                                     text,
-                                    Language::None,
                                 ));
                             }
                             search::Filter::And => {
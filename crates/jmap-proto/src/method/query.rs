@@ -82,6 +82,7 @@ pub enum Filter {
     HasKeyword(Keyword),
     NotKeyword(Keyword),
     HasAttachment(bool),
+    AttachmentType(String),
     From(String),
     To(String),
     Cc(String),
@@ -368,6 +369,11 @@ pub fn parse_filter(parser: &mut Parser) -> crate::parser::Result<Vec<Filter>> {
                                 .next_token::<String>()?
                                 .unwrap_bool("hasAttachment")?,
                         ),
+                        (0x6570_7954_746e_656d_6863_6174_7461, _) => Filter::AttachmentType(
+                            parser
+                                .next_token::<String>()?
+                                .unwrap_string("attachmentType")?,
+                        ),
                         (0x6d6f_7266, _) => {
                             Filter::From(parser.next_token::<String>()?.unwrap_string("from")?)
                         }
@@ -612,6 +618,7 @@ impl Display for Filter {
             Filter::HasKeyword(_) => "hasKeyword",
             Filter::NotKeyword(_) => "notKeyword",
             Filter::HasAttachment(_) => "hasAttachment",
+            Filter::AttachmentType(_) => "attachmentType",
             Filter::From(_) => "from",
             Filter::To(_) => "to",
             Filter::Cc(_) => "cc",
@@ -690,6 +697,7 @@ impl Filter {
                 | Filter::MaxSize(_)
                 | Filter::Text(_)
                 | Filter::HasAttachment(_)
+                | Filter::AttachmentType(_)
                 | Filter::From(_)
                 | Filter::To(_)
                 | Filter::Cc(_)
@@ -8,14 +8,17 @@ use std::fmt::Display;
 
 use store::{
     write::{
-        BitmapClass, DeserializeFrom, MaybeDynamicId, Operation, SerializeInto, TagValue, ToBitmaps,
+        labels::LabelRegistry, BitmapClass, DeserializeFrom, MaybeDynamicId, Operation,
+        SerializeInto, TagValue, ToBitmaps,
     },
-    Serialize,
+    Deserialize, Serialize,
 };
 use utils::codec::leb128::{Leb128Iterator, Leb128Vec};
 
 use crate::parser::{json::Parser, JsonObjectParser};
 
+use super::{collection::Collection, property::Property};
+
 pub const SEEN: usize = 0;
 pub const DRAFT: usize = 1;
 pub const FLAGGED: usize = 2;
@@ -306,6 +309,35 @@ impl Keyword {
             Keyword::Other(string) => Err(string),
         }
     }
+
+    /// Bit of this keyword in the system flags bitset, custom keywords have none.
+    pub fn flag(&self) -> Option<u32> {
+        self.id().ok().map(|id| 1 << id)
+    }
+
+    /// Registry of the custom keywords of an account, renaming or deleting a
+    /// keyword also updates the keywords of each email and logs the change.
+    pub fn label_registry(account_id: u32) -> LabelRegistry {
+        LabelRegistry::new(account_id, Collection::Email, Property::Keywords)
+            .with_thread_field(Property::ThreadId)
+            .with_relabel_value(Keyword::relabel)
+    }
+
+    fn relabel(value: &[u8], name: &str, new_name: Option<&str>) -> Option<Vec<u8>> {
+        let mut keywords = Vec::<Keyword>::deserialize(value).ok()?;
+        let pos = keywords
+            .iter()
+            .position(|keyword| matches!(keyword, Keyword::Other(other) if other == name))?;
+        match new_name.map(|new_name| Keyword::from(new_name.to_string())) {
+            Some(keyword) if !keywords.contains(&keyword) => {
+                keywords[pos] = keyword;
+            }
+            _ => {
+                keywords.remove(pos);
+            }
+        }
+        Some(keywords.serialize())
+    }
 }
 
 impl From<Keyword> for TagValue<u32> {
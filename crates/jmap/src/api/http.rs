@@ -336,7 +336,7 @@ impl JMAP {
                 }
                 "ready" => {
                     return {
-                        if !self.core.storage.data.is_none() {
+                        if self.core.storage.data.health_check().await.is_ok() {
                             StatusCode::OK
                         } else {
                             StatusCode::SERVICE_UNAVAILABLE
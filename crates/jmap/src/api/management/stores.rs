@@ -7,7 +7,7 @@
 use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
 use common::manager::webadmin::Resource;
 use hyper::Method;
-use jmap_proto::error::request::RequestError;
+use jmap_proto::{error::request::RequestError, types::collection::Collection};
 use serde_json::json;
 use utils::url_params::UrlParams;
 
@@ -113,6 +113,120 @@ impl JMAP {
                 self.housekeeper_request(Event::Purge(PurgeType::Account(account_id)))
                     .await
             }
+            (Some("reindex"), Some(id), collection, &Method::POST) => {
+                let account_id = if let Ok(account_id) = id.parse::<u32>() {
+                    account_id
+                } else {
+                    return RequestError::invalid_parameters().into_http_response();
+                };
+                let collection = match collection {
+                    Some(name) => {
+                        if let Some(collection) = (0..=u8::MAX)
+                            .map(Collection::from)
+                            .take_while(|collection| *collection != Collection::None)
+                            .find(|collection| collection.to_string() == name)
+                        {
+                            collection
+                        } else {
+                            return RequestError::invalid_parameters().into_http_response();
+                        }
+                    }
+                    None => Collection::Email,
+                };
+                if collection != Collection::Email {
+                    return RequestError::invalid_parameters().into_http_response();
+                }
+                let from_document_id = UrlParams::new(req.uri().query()).parse("from").unwrap_or(0);
+
+                // Reindexing can take a while, run it in the background
+                let jmap = self.clone();
+                tokio::spawn(async move {
+                    match jmap
+                        .reindex_fts(account_id, collection, from_document_id)
+                        .await
+                    {
+                        Ok(total) => {
+                            tracing::info!(
+                                context = "reindex_fts",
+                                event = "finish",
+                                account_id = account_id,
+                                collection = ?collection,
+                                total = total,
+                                "Finished rebuilding FTS index"
+                            );
+                        }
+                        Err(err) => {
+                            tracing::error!(
+                                context = "reindex_fts",
+                                event = "error",
+                                account_id = account_id,
+                                collection = ?collection,
+                                reason = ?err,
+                                "Failed to rebuild FTS index"
+                            );
+                        }
+                    }
+                });
+
+                JsonResponse::new(json!({
+                    "data": (),
+                }))
+                .into_http_response()
+            }
+            (Some("quarantine"), None, _, &Method::GET) => {
+                match self.core.storage.data.quarantined_documents().await {
+                    Ok(documents) => JsonResponse::new(json!({
+                        "data": documents
+                            .into_iter()
+                            .map(|document| {
+                                json!({
+                                    "accountId": document.account_id,
+                                    "collection": document.collection,
+                                    "documentId": document.document_id,
+                                    "failures": document.entry.failures,
+                                    "error": document.entry.last_error,
+                                    "lastFailure": document.entry.last_failure,
+                                })
+                            })
+                            .collect::<Vec<_>>(),
+                    }))
+                    .into_http_response(),
+                    Err(err) => err.into_http_response(),
+                }
+            }
+            (Some("quarantine"), Some(account_id), Some(document_id), method)
+                if [Method::POST, Method::DELETE].contains(method) =>
+            {
+                let (account_id, document_id) =
+                    match (account_id.parse::<u32>(), document_id.parse::<u32>()) {
+                        (Ok(account_id), Ok(document_id)) => (account_id, document_id),
+                        _ => return RequestError::invalid_parameters().into_http_response(),
+                    };
+
+                if method == Method::POST {
+                    // Release the email and queue it for indexing again
+                    match self.reprocess_quarantined(account_id, document_id).await {
+                        Ok(true) => (),
+                        Ok(false) => return RequestError::not_found().into_http_response(),
+                        Err(_) => {
+                            return RequestError::internal_server_error().into_http_response()
+                        }
+                    }
+                } else if let Err(err) = self
+                    .core
+                    .storage
+                    .data
+                    .clear_failures(account_id, Collection::Email, document_id)
+                    .await
+                {
+                    return err.into_http_response();
+                }
+
+                JsonResponse::new(json!({
+                    "data": (),
+                }))
+                .into_http_response()
+            }
             _ => RequestError::not_found().into_http_response(),
         }
     }
@@ -0,0 +1,127 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs Ltd <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use jmap_proto::types::{collection::Collection, property::Property};
+use mail_parser::{parsers::MessageStream, Header, HeaderName, HeaderValue};
+use store::{
+    query::Filter,
+    write::{BatchBuilder, Bincode, ValueClass, F_INDEX},
+    BitmapKey, ValueKey,
+};
+
+use crate::JMAP;
+
+use super::metadata::MessageMetadata;
+
+impl JMAP {
+    /// Indexes `receivedAt` for the messages of an account that were stored without
+    /// it. The timestamp is taken from the message metadata or, when unknown, from the
+    /// most recent Received header or the Date header. Returns the number of messages
+    /// that were indexed.
+    pub async fn backfill_received_at(&self, account_id: u32) -> store::Result<u64> {
+        let store = &self.core.storage.data;
+        let mut missing_ids = store
+            .get_bitmap(BitmapKey::document_ids(account_id, Collection::Email))
+            .await?
+            .unwrap_or_default();
+        if missing_ids.is_empty() {
+            return Ok(0);
+        }
+        missing_ids -= store
+            .filter(
+                account_id,
+                Collection::Email,
+                vec![Filter::ge(Property::ReceivedAt, 0u64)],
+            )
+            .await?
+            .results;
+
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(account_id)
+            .with_collection(Collection::Email);
+        let mut count = 0;
+        for document_id in missing_ids {
+            let metadata = if let Some(metadata) = store
+                .get_value::<Bincode<MessageMetadata>>(ValueKey {
+                    account_id,
+                    collection: Collection::Email.into(),
+                    document_id,
+                    class: ValueClass::Property(Property::BodyStructure.into()),
+                })
+                .await?
+            {
+                metadata.inner
+            } else {
+                continue;
+            };
+
+            let received_at = if metadata.received_at != 0 {
+                metadata.received_at
+            } else if let Some(received_at) = metadata
+                .contents
+                .parts
+                .first()
+                .and_then(|part| received_at_from_headers(&part.headers, &metadata.raw_headers))
+            {
+                received_at
+            } else {
+                tracing::debug!(
+                    context = "backfill_received_at",
+                    event = "skip",
+                    account_id = account_id,
+                    document_id = document_id,
+                    "Message has no usable Received or Date header."
+                );
+                continue;
+            };
+
+            batch
+                .update_document(document_id)
+                .value(Property::ReceivedAt, received_at, F_INDEX);
+            count += 1;
+
+            if batch.ops.len() >= 1000 {
+                store.write(batch.build_batch()).await?;
+                batch
+                    .with_account_id(account_id)
+                    .with_collection(Collection::Email);
+            }
+        }
+        if !batch.is_empty() {
+            store.write(batch.build()).await?;
+        }
+
+        Ok(count)
+    }
+}
+
+/// Returns the date of the topmost Received header, which was added last, falling
+/// back to the Date header.
+pub fn received_at_from_headers(headers: &[Header<'_>], raw_headers: &[u8]) -> Option<u64> {
+    headers
+        .iter()
+        .filter(|header| header.name == HeaderName::Received)
+        .find_map(|header| {
+            // Received headers are not kept parsed in the message metadata
+            match MessageStream::new(raw_headers.get(header.offset_start..header.offset_end)?)
+                .parse_received()
+            {
+                HeaderValue::Received(received) => received.date.map(|date| date.to_timestamp()),
+                _ => None,
+            }
+        })
+        .or_else(|| {
+            headers.iter().find_map(|header| match &header.value {
+                HeaderValue::DateTime(date) if header.name == HeaderName::Date => {
+                    Some(date.to_timestamp())
+                }
+                _ => None,
+            })
+        })
+        .filter(|timestamp| *timestamp > 0)
+        .map(|timestamp| timestamp as u64)
+}
@@ -21,6 +21,17 @@ pub struct Threads {
     pub modseq: Option<u64>,
 }
 
+/// Message and thread counts of a mailbox, valid while the account's last
+/// email change id matches `modseq`.
+#[derive(Debug, Default)]
+pub struct MailboxCounts {
+    pub total_emails: u64,
+    pub unread_emails: u64,
+    pub total_threads: u64,
+    pub unread_threads: u64,
+    pub modseq: Option<u64>,
+}
+
 impl JMAP {
     pub async fn get_cached_thread_ids(
         &self,
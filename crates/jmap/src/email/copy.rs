@@ -29,7 +29,6 @@ use jmap_proto::{
         value::{MaybePatchValue, Value},
     },
 };
-use mail_parser::{parsers::fields::thread::thread_name, HeaderName, HeaderValue};
 use store::{
     write::{
         log::{Changes, LogInsert},
@@ -43,8 +42,8 @@ use utils::map::vec_map::VecMap;
 use crate::{auth::AccessToken, mailbox::UidMailbox, services::housekeeper::Event, JMAP};
 
 use super::{
-    index::{EmailIndexBuilder, TrimTextValue, VisitValues, MAX_ID_LENGTH, MAX_SORT_FIELD_LENGTH},
-    ingest::{IngestedEmail, LogEmailInsert},
+    index::EmailIndexBuilder,
+    ingest::{IngestedEmail, LogEmailInsert, ThreadReferences},
     metadata::MessageMetadata,
 };
 
@@ -310,34 +309,11 @@ impl JMAP {
         }
 
         // Obtain threadId
-        let mut references = Vec::with_capacity(5);
-        let mut subject = "";
-        for header in &metadata.contents.parts[0].headers {
-            match &header.name {
-                HeaderName::MessageId
-                | HeaderName::InReplyTo
-                | HeaderName::References
-                | HeaderName::ResentMessageId => {
-                    header.value.visit_text(|id| {
-                        if !id.is_empty() && id.len() < MAX_ID_LENGTH {
-                            references.push(id);
-                        }
-                    });
-                }
-                HeaderName::Subject if subject.is_empty() => {
-                    subject = thread_name(match &header.value {
-                        HeaderValue::Text(text) => text.as_ref(),
-                        HeaderValue::TextList(list) if !list.is_empty() => {
-                            list.first().unwrap().as_ref()
-                        }
-                        _ => "",
-                    })
-                    .trim_text(MAX_SORT_FIELD_LENGTH);
-                }
-                _ => (),
-            }
-        }
-
+        let ThreadReferences {
+            subject,
+            references,
+            ..
+        } = ThreadReferences::parse(&metadata.contents.parts[0].headers);
         let thread_id = if !references.is_empty() {
             self.find_or_merge_thread(account_id, subject, &references)
                 .await
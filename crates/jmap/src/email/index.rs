@@ -75,6 +75,8 @@ impl IndexMessage for BatchBuilder {
         // Index size
         let account_id = self.last_account_id().unwrap();
         self.value(Property::Size, message.raw_message.len() as u32, F_INDEX)
+            .set_content_length(message.raw_message.len() as u32)
+            .set_size_bucket(message.raw_message.len() as u32)
             .add(
                 DirectoryClass::UsedQuota(account_id),
                 message.raw_message.len() as i64,
@@ -83,6 +85,17 @@ impl IndexMessage for BatchBuilder {
         // Index receivedAt
         self.value(Property::ReceivedAt, received_at, F_INDEX);
 
+        // Index attachment content types
+        for content_type in attachment_types(
+            message
+                .attachments
+                .iter()
+                .filter_map(|part_id| message.parts.get(*part_id))
+                .map(|part| &part.headers),
+        ) {
+            self.tag(Property::HasAttachment, content_type, 0);
+        }
+
         let mut has_attachments = false;
         let mut preview = None;
         let preview_part_id = message
@@ -145,24 +158,22 @@ impl IndexMessage for BatchBuilder {
 
         // Store message metadata
         let root_part = message.root_part();
-        self.value(
-            Property::BodyStructure,
-            Bincode::new(MessageMetadata {
-                preview: preview.unwrap_or_default().into_owned(),
-                size: message.raw_message.len(),
-                raw_headers: message
-                    .raw_message
-                    .as_ref()
-                    .get(root_part.offset_header..root_part.offset_body)
-                    .unwrap_or_default()
-                    .to_vec(),
-                contents: message.into(),
-                received_at,
-                has_attachments,
-                blob_hash,
-            }),
-            F_VALUE,
-        );
+        let metadata = MessageMetadata {
+            preview: preview.unwrap_or_default().into_owned(),
+            size: message.raw_message.len(),
+            raw_headers: message
+                .raw_message
+                .as_ref()
+                .get(root_part.offset_header..root_part.offset_body)
+                .unwrap_or_default()
+                .to_vec(),
+            contents: message.into(),
+            received_at,
+            has_attachments,
+            blob_hash,
+        };
+        self.set_fingerprint(&metadata.blob_hash, &metadata.fingerprint());
+        self.value(Property::BodyStructure, Bincode::new(metadata), F_VALUE);
 
         self
     }
@@ -302,7 +313,7 @@ impl<'x> IndexMessageText<'x> for FtsDocument<'x, HeaderName<'x>> {
                         HeaderName::From | HeaderName::To | HeaderName::Cc | HeaderName::Bcc => {
                             header.value.visit_addresses(|_, value| {
                                 // Index an address name or email without stemming
-                                self.index_tokenized(
+                                self.index_address(
                                     Field::Header(header.name.clone()),
                                     value.to_string(),
                                 );
@@ -424,6 +435,15 @@ impl<'x> IntoOperations for EmailIndexBuilder<'x> {
                     -(metadata.size as i64)
                 },
             );
+        if self.set {
+            batch
+                .set_content_length(metadata.size as u32)
+                .set_size_bucket(metadata.size as u32);
+        } else {
+            batch
+                .clear_content_length()
+                .clear_size_bucket(metadata.size as u32);
+        }
         batch.value(
             Property::ReceivedAt,
             metadata.received_at,
@@ -432,26 +452,61 @@ impl<'x> IntoOperations for EmailIndexBuilder<'x> {
         if metadata.has_attachments {
             batch.tag(Property::HasAttachment, (), options);
         }
+        for content_type in attachment_types(
+            metadata
+                .contents
+                .attachments
+                .iter()
+                .filter_map(|part_id| metadata.contents.parts.get(*part_id))
+                .map(|part| &part.headers),
+        ) {
+            batch.tag(Property::HasAttachment, content_type, options);
+        }
 
         // Index headers
         batch.index_headers(&metadata.contents.parts[0].headers, options);
 
         // Link blob
         if self.set {
-            batch.set(
-                BlobOp::Link {
-                    hash: metadata.blob_hash.clone(),
-                },
-                Vec::new(),
-            );
+            batch
+                .set(
+                    BlobOp::Link {
+                        hash: metadata.blob_hash.clone(),
+                    },
+                    Vec::new(),
+                )
+                .set_fingerprint(&metadata.blob_hash, &metadata.fingerprint());
         } else {
-            batch.clear(BlobOp::Link {
-                hash: metadata.blob_hash.clone(),
-            });
+            batch
+                .clear(BlobOp::Link {
+                    hash: metadata.blob_hash.clone(),
+                })
+                .clear_fingerprint(&metadata.blob_hash, &metadata.fingerprint());
         }
     }
 }
 
+// Lowercase content types of the attachments, used by attachment type filters
+fn attachment_types<'x: 'y, 'y>(headers: impl Iterator<Item = &'y Vec<Header<'x>>>) -> Vec<String> {
+    let mut content_types = headers
+        .filter_map(|headers| {
+            headers
+                .header_value(&HeaderName::ContentType)
+                .and_then(|value| value.as_content_type())
+        })
+        .map(|ct| {
+            ct.subtype()
+                .map(|st| format!("{}/{}", ct.ctype(), st))
+                .unwrap_or_else(|| ct.ctype().to_string())
+                .to_lowercase()
+        })
+        .filter(|content_type| !content_type.is_empty() && content_type.len() <= MAX_ID_LENGTH)
+        .collect::<Vec<_>>();
+    content_types.sort_unstable();
+    content_types.dedup();
+    content_types
+}
+
 impl SortedAddressBuilder {
     pub fn new() -> Self {
         Self {
@@ -15,7 +15,8 @@ use jmap_proto::{
     },
 };
 use mail_parser::{
-    parsers::fields::thread::thread_name, HeaderName, HeaderValue, Message, MessageParser, PartType,
+    parsers::fields::thread::thread_name, Header, HeaderName, HeaderValue, Message, MessageParser,
+    PartType,
 };
 
 use rand::Rng;
@@ -73,6 +74,57 @@ pub enum IngestSource {
 
 const MAX_RETRIES: u32 = 10;
 
+/// Headers linking a message to its thread: the Message-ID, In-Reply-To,
+/// References and Resent-Message-ID identifiers, and the base subject.
+#[derive(Debug, Default)]
+pub struct ThreadReferences<'x> {
+    pub message_id: &'x str,
+    pub subject: &'x str,
+    pub references: Vec<&'x str>,
+}
+
+impl<'x> ThreadReferences<'x> {
+    pub fn parse(headers: &'x [Header<'x>]) -> Self {
+        let mut thread = ThreadReferences {
+            references: Vec::with_capacity(5),
+            ..Default::default()
+        };
+
+        for header in headers.iter().rev() {
+            match &header.name {
+                HeaderName::MessageId => header.value.visit_text(|id| {
+                    if !id.is_empty() && id.len() < MAX_ID_LENGTH {
+                        if thread.message_id.is_empty() {
+                            thread.message_id = id;
+                        }
+                        thread.references.push(id);
+                    }
+                }),
+                HeaderName::InReplyTo | HeaderName::References | HeaderName::ResentMessageId => {
+                    header.value.visit_text(|id| {
+                        if !id.is_empty() && id.len() < MAX_ID_LENGTH {
+                            thread.references.push(id);
+                        }
+                    });
+                }
+                HeaderName::Subject if thread.subject.is_empty() => {
+                    thread.subject = thread_name(match &header.value {
+                        HeaderValue::Text(text) => text.as_ref(),
+                        HeaderValue::TextList(list) if !list.is_empty() => {
+                            list.first().unwrap().as_ref()
+                        }
+                        _ => "",
+                    })
+                    .trim_text(MAX_SORT_FIELD_LENGTH);
+                }
+                _ => (),
+            }
+        }
+
+        thread
+    }
+}
+
 impl JMAP {
     #[allow(clippy::blocks_in_conditions)]
     pub async fn email_ingest(
@@ -113,41 +165,11 @@ impl JMAP {
 
         // Obtain message references and thread name
         let thread_id = {
-            let mut references = Vec::with_capacity(5);
-            let mut subject = "";
-            let mut message_id = "";
-            for header in message.root_part().headers().iter().rev() {
-                match &header.name {
-                    HeaderName::MessageId => header.value.visit_text(|id| {
-                        if !id.is_empty() && id.len() < MAX_ID_LENGTH {
-                            if message_id.is_empty() {
-                                message_id = id;
-                            }
-                            references.push(id);
-                        }
-                    }),
-                    HeaderName::InReplyTo
-                    | HeaderName::References
-                    | HeaderName::ResentMessageId => {
-                        header.value.visit_text(|id| {
-                            if !id.is_empty() && id.len() < MAX_ID_LENGTH {
-                                references.push(id);
-                            }
-                        });
-                    }
-                    HeaderName::Subject if subject.is_empty() => {
-                        subject = thread_name(match &header.value {
-                            HeaderValue::Text(text) => text.as_ref(),
-                            HeaderValue::TextList(list) if !list.is_empty() => {
-                                list.first().unwrap().as_ref()
-                            }
-                            _ => "",
-                        })
-                        .trim_text(MAX_SORT_FIELD_LENGTH);
-                    }
-                    _ => (),
-                }
-            }
+            let ThreadReferences {
+                message_id,
+                subject,
+                references,
+            } = ThreadReferences::parse(message.root_part().headers());
 
             // Check for duplicates
             if params.source == IngestSource::Smtp
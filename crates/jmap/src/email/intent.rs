@@ -0,0 +1,265 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs Ltd <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use jmap_proto::{
+    error::method::MethodError,
+    types::{
+        collection::Collection, id::Id, property::Property, state::StateChange,
+        type_state::DataType,
+    },
+};
+use serde::{Deserialize, Serialize};
+use store::{
+    roaring::RoaringBitmap,
+    write::{assert::HashedValue, log::ChangeLogBuilder, BatchBuilder, Bincode, F_VALUE},
+    BlobClass, Deserialize as _, Serialize as _,
+};
+use utils::BlobHash;
+
+use crate::{mailbox::UidMailbox, JMAP};
+
+use super::set::TagManager;
+
+/// Plan of a message move between accounts. Messages are first copied to the
+/// destination account and then removed from the source mailbox. The id of each
+/// copy is recorded as soon as it is written, if the server stops in between,
+/// recovery completes the move for every message whose recorded copy exists in
+/// the destination account and leaves the rest in place.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MessageMoveIntent {
+    pub src_account_id: u32,
+    pub src_mailbox_id: u32,
+    pub dest_account_id: u32,
+    pub messages: Vec<MessageMoveStep>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MessageMoveStep {
+    pub document_id: u32,
+    pub blob_hash: BlobHash,
+    pub dest_document_id: Option<u32>,
+}
+
+impl JMAP {
+    pub async fn email_move_intent_begin(
+        &self,
+        intent: &MessageMoveIntent,
+    ) -> Result<u64, MethodError> {
+        let intent_id = self.generate_snowflake_id()?;
+        self.core
+            .storage
+            .data
+            .intent_begin(intent_id, Bincode::new(intent.clone()).serialize())
+            .await
+            .map_err(|err| {
+                tracing::error!(
+                    event = "error",
+                    context = "intent",
+                    error = ?err,
+                    "Failed to record message move intent."
+                );
+                MethodError::ServerPartialFail
+            })?;
+
+        Ok(intent_id)
+    }
+
+    pub async fn email_move_intent_update(
+        &self,
+        intent_id: u64,
+        intent: &MessageMoveIntent,
+    ) -> Result<(), MethodError> {
+        self.core
+            .storage
+            .data
+            .intent_update(intent_id, Bincode::new(intent.clone()).serialize())
+            .await
+            .map_err(|err| {
+                tracing::error!(
+                    event = "error",
+                    context = "intent",
+                    error = ?err,
+                    "Failed to update message move intent."
+                );
+                MethodError::ServerPartialFail
+            })
+    }
+
+    pub async fn email_move_intent_end(&self, intent_id: u64) -> Result<(), MethodError> {
+        self.core
+            .storage
+            .data
+            .intent_end(intent_id)
+            .await
+            .map_err(|err| {
+                tracing::error!(
+                    event = "error",
+                    context = "intent",
+                    error = ?err,
+                    "Failed to remove message move intent."
+                );
+                MethodError::ServerPartialFail
+            })
+    }
+
+    pub async fn recover_intents(&self) {
+        let intents = match self.core.storage.data.pending_intents().await {
+            Ok(intents) => intents,
+            Err(err) => {
+                tracing::error!(
+                    event = "error",
+                    context = "intent",
+                    error = ?err,
+                    "Failed to obtain pending intents."
+                );
+                return;
+            }
+        };
+
+        for (intent_id, plan) in intents {
+            let result = match Bincode::<MessageMoveIntent>::deserialize(&plan) {
+                Ok(intent) => self.recover_move_intent(intent.inner).await,
+                Err(err) => {
+                    tracing::warn!(
+                        event = "error",
+                        context = "intent",
+                        intent_id = intent_id,
+                        error = ?err,
+                        "Discarding invalid intent."
+                    );
+                    Ok(())
+                }
+            };
+
+            match result {
+                Ok(_) => {
+                    tracing::info!(
+                        event = "recovered",
+                        context = "intent",
+                        intent_id = intent_id,
+                        "Recovered interrupted message move."
+                    );
+                    let _ = self.email_move_intent_end(intent_id).await;
+                }
+                Err(err) => {
+                    tracing::error!(
+                        event = "error",
+                        context = "intent",
+                        intent_id = intent_id,
+                        error = ?err,
+                        "Failed to recover interrupted message move."
+                    );
+                }
+            }
+        }
+    }
+
+    async fn recover_move_intent(&self, intent: MessageMoveIntent) -> Result<(), MethodError> {
+        let account_id = intent.src_account_id;
+        let mailbox_id = UidMailbox::new_unassigned(intent.src_mailbox_id);
+
+        // Messages whose copy exists in the destination account are removed from the
+        // source mailbox, the rest are left in place which rolls back the move.
+        let mut copied_ids = RoaringBitmap::new();
+        for step in &intent.messages {
+            if let Some(dest_document_id) = step.dest_document_id {
+                if self
+                    .core
+                    .storage
+                    .data
+                    .blob_has_access(
+                        &step.blob_hash,
+                        BlobClass::Linked {
+                            account_id: intent.dest_account_id,
+                            collection: Collection::Email.into(),
+                            document_id: dest_document_id,
+                        },
+                    )
+                    .await
+                    .map_err(|err| {
+                        tracing::error!(
+                            event = "error",
+                            context = "intent",
+                            error = ?err,
+                            "Failed to verify copied message."
+                        );
+                        MethodError::ServerPartialFail
+                    })?
+                {
+                    copied_ids.insert(step.document_id);
+                }
+            }
+        }
+        if copied_ids.is_empty() {
+            return Ok(());
+        }
+
+        let mut changelog = ChangeLogBuilder::new();
+        let mut destroy_ids = RoaringBitmap::new();
+        for (document_id, mailbox_ids) in self
+            .get_properties::<HashedValue<Vec<UidMailbox>>, _, _>(
+                account_id,
+                Collection::Email,
+                &copied_ids,
+                Property::MailboxIds,
+            )
+            .await?
+        {
+            let mut mailboxes = TagManager::new(mailbox_ids);
+            if !mailboxes.current().contains(&mailbox_id) {
+                continue;
+            } else if mailboxes.current().len() == 1 {
+                destroy_ids.insert(document_id);
+                continue;
+            }
+            let thread_id = if let Some(thread_id) = self
+                .get_property::<u32>(
+                    account_id,
+                    Collection::Email,
+                    document_id,
+                    Property::ThreadId,
+                )
+                .await?
+            {
+                thread_id
+            } else {
+                continue;
+            };
+
+            mailboxes.update(mailbox_id, false);
+            if changelog.change_id == u64::MAX {
+                changelog.change_id = self.assign_change_id(account_id).await?;
+            }
+            let mut batch = BatchBuilder::new();
+            batch
+                .with_account_id(account_id)
+                .with_collection(Collection::Email)
+                .update_document(document_id);
+            mailboxes.update_batch(&mut batch, Property::MailboxIds);
+            batch.value(Property::Cid, changelog.change_id, F_VALUE);
+            self.write_batch(batch).await?;
+            changelog.log_update(Collection::Email, Id::from_parts(thread_id, document_id));
+            changelog.log_child_update(Collection::Mailbox, mailbox_id.mailbox_id);
+        }
+
+        if !destroy_ids.is_empty() {
+            let (changes, _) = self.emails_tombstone(account_id, destroy_ids).await?;
+            changelog.merge(changes);
+        }
+
+        if !changelog.is_empty() {
+            let change_id = self.commit_changes(account_id, changelog).await?;
+            self.broadcast_state_change(
+                StateChange::new(account_id)
+                    .with_change(DataType::Email, change_id)
+                    .with_change(DataType::Mailbox, change_id),
+            )
+            .await;
+        }
+
+        Ok(())
+    }
+}
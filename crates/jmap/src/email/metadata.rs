@@ -58,6 +58,39 @@ pub enum MetadataPartType<'x> {
     Multipart(Vec<MessagePartId>),
 }
 
+impl<'x> MessageMetadata<'x> {
+    // Content fingerprint that ignores the headers added in transit, such as
+    // Received or DKIM-Signature, used to detect copies of a message
+    pub fn fingerprint(&self) -> Vec<u8> {
+        let mut fingerprint = Vec::with_capacity(self.preview.len() + 128);
+        let root_offset = self
+            .contents
+            .parts
+            .first()
+            .map_or(0, |part| part.offset_body);
+        if let Some(message_id) = self
+            .contents
+            .parts
+            .first()
+            .and_then(|part| part.headers.header_value(&HeaderName::MessageId))
+            .and_then(|value| value.as_text())
+        {
+            fingerprint.extend_from_slice(message_id.as_bytes());
+        }
+        fingerprint.push(0);
+        fingerprint.extend_from_slice(self.preview.as_bytes());
+        for part in self.contents.parts.iter().skip(1) {
+            for offset in [part.offset_header, part.offset_body, part.offset_end] {
+                fingerprint
+                    .extend_from_slice(&(offset.saturating_sub(root_offset) as u64).to_be_bytes());
+            }
+        }
+        fingerprint
+            .extend_from_slice(&(self.size.saturating_sub(root_offset) as u64).to_be_bytes());
+        fingerprint
+    }
+}
+
 impl<'x> MessageMetadataContents<'x> {
     pub fn into_message(self, raw_message: &'x [u8]) -> Message<'x> {
         Message {
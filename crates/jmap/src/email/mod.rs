@@ -4,6 +4,7 @@
  * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
  */
 
+pub mod backfill;
 pub mod body;
 pub mod cache;
 pub mod copy;
@@ -14,6 +15,7 @@ pub mod headers;
 pub mod import;
 pub mod index;
 pub mod ingest;
+pub mod intent;
 pub mod metadata;
 pub mod parse;
 pub mod query;
@@ -39,25 +39,21 @@ impl JMAP {
                         match cond {
                             Filter::Text(text) => {
                                 fts_filters.push(FtsFilter::Or);
-                                fts_filters.push(FtsFilter::has_text(
+                                fts_filters.push(FtsFilter::has_address(
                                     Field::Header(HeaderName::From),
                                     &text,
-                                    Language::None,
                                 ));
-                                fts_filters.push(FtsFilter::has_text(
+                                fts_filters.push(FtsFilter::has_address(
                                     Field::Header(HeaderName::To),
                                     &text,
-                                    Language::None,
                                 ));
-                                fts_filters.push(FtsFilter::has_text(
+                                fts_filters.push(FtsFilter::has_address(
                                     Field::Header(HeaderName::Cc),
                                     &text,
-                                    Language::None,
                                 ));
-                                fts_filters.push(FtsFilter::has_text(
+                                fts_filters.push(FtsFilter::has_address(
                                     Field::Header(HeaderName::Bcc),
                                     &text,
-                                    Language::None,
                                 ));
                                 fts_filters.push(FtsFilter::has_text_detect(
                                     Field::Header(HeaderName::Subject),
@@ -76,26 +72,16 @@ impl JMAP {
                                 ));
                                 fts_filters.push(FtsFilter::End);
                             }
-                            Filter::From(text) => fts_filters.push(FtsFilter::has_text(
+                            Filter::From(text) => fts_filters.push(FtsFilter::has_address(
                                 Field::Header(HeaderName::From),
                                 text,
-                                Language::None,
-                            )),
-                            Filter::To(text) => fts_filters.push(FtsFilter::has_text(
-                                Field::Header(HeaderName::To),
-                                text,
-                                Language::None,
-                            )),
-                            Filter::Cc(text) => fts_filters.push(FtsFilter::has_text(
-                                Field::Header(HeaderName::Cc),
-                                text,
-                                Language::None,
-                            )),
-                            Filter::Bcc(text) => fts_filters.push(FtsFilter::has_text(
-                                Field::Header(HeaderName::Bcc),
-                                text,
-                                Language::None,
                             )),
+                            Filter::To(text) => fts_filters
+                                .push(FtsFilter::has_address(Field::Header(HeaderName::To), text)),
+                            Filter::Cc(text) => fts_filters
+                                .push(FtsFilter::has_address(Field::Header(HeaderName::Cc), text)),
+                            Filter::Bcc(text) => fts_filters
+                                .push(FtsFilter::has_address(Field::Header(HeaderName::Bcc), text)),
                             Filter::Subject(text) => fts_filters.push(FtsFilter::has_text_detect(
                                 Field::Header(HeaderName::Subject),
                                 text,
@@ -234,6 +220,12 @@ impl JMAP {
                             }
                             filters.push(query::Filter::is_in_set(set));
                         }
+                        Filter::AttachmentType(content_type) => {
+                            filters.push(query::Filter::has_attachment(
+                                Property::HasAttachment,
+                                content_type.into(),
+                            ))
+                        }
                         Filter::SentBefore(date) => {
                             filters.push(query::Filter::lt(Property::SentAt, date))
                         }
@@ -290,15 +282,9 @@ impl JMAP {
                     SortProperty::SentAt => {
                         query::Comparator::field(Property::SentAt, comparator.is_ascending)
                     }
-                    SortProperty::HasKeyword => query::Comparator::set(
-                        self.get_tag(
-                            account_id,
-                            Collection::Email,
-                            Property::Keywords,
-                            comparator.keyword.unwrap_or(Keyword::Seen),
-                        )
-                        .await?
-                        .unwrap_or_default(),
+                    SortProperty::HasKeyword => query::Comparator::tag(
+                        Property::Keywords,
+                        comparator.keyword.unwrap_or(Keyword::Seen),
                         comparator.is_ascending,
                     ),
                     SortProperty::AllInThreadHaveKeyword => query::Comparator::set(
@@ -16,7 +16,7 @@ use jmap_proto::{
         value::{MaybePatchValue, Value},
     },
 };
-use store::write::{log::ChangeLogBuilder, BatchBuilder, F_CLEAR, F_VALUE};
+use store::write::{log::ChangeLogBuilder, BatchBuilder, RetryPolicy, F_CLEAR, F_VALUE};
 
 use crate::JMAP;
 
@@ -90,6 +90,7 @@ impl JMAP {
             // Insert record
             let mut batch = BatchBuilder::new();
             batch
+                .with_retry_policy(RetryPolicy::INTERACTIVE)
                 .with_account_id(account_id)
                 .with_collection(Collection::Identity)
                 .create_document()
@@ -146,6 +147,7 @@ impl JMAP {
             // Update record
             let mut batch = BatchBuilder::new();
             batch
+                .with_retry_policy(RetryPolicy::INTERACTIVE)
                 .with_account_id(account_id)
                 .with_collection(Collection::Identity)
                 .update_document(document_id)
@@ -162,6 +164,7 @@ impl JMAP {
                 // Update record
                 let mut batch = BatchBuilder::new();
                 batch
+                    .with_retry_policy(RetryPolicy::INTERACTIVE)
                     .with_account_id(account_id)
                     .with_collection(Collection::Identity)
                     .delete_document(document_id)
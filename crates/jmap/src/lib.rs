@@ -19,7 +19,7 @@ use common::{
 };
 use dashmap::DashMap;
 use directory::QueryBy;
-use email::cache::Threads;
+use email::cache::{MailboxCounts, Threads};
 use jmap_proto::{
     error::method::MethodError,
     method::{
@@ -38,7 +38,7 @@ use smtp::core::SMTP;
 use store::{
     dispatch::DocumentSet,
     fts::FtsFilter,
-    query::{sort::Pagination, Comparator, Filter, ResultSet, SortedResultSet},
+    query::{sort::Pagination, Comparator, Filter, FilterOptions, ResultSet, SortedResultSet},
     roaring::RoaringBitmap,
     write::{
         key::DeserializeBigEndian, AssignedIds, BatchBuilder, BitmapClass, DirectoryClass,
@@ -101,6 +101,7 @@ pub struct Inner {
     pub housekeeper_tx: mpsc::Sender<housekeeper::Event>,
 
     pub cache_threads: LruCache<u32, Arc<Threads>>,
+    pub cache_mailbox_counts: LruCache<(u32, u32, u32), Arc<MailboxCounts>>,
 }
 
 #[derive(Debug)]
@@ -144,6 +145,9 @@ impl JMAP {
             cache_threads: LruCache::with_capacity(
                 config.property("cache.thread.size").unwrap_or(2048),
             ),
+            cache_mailbox_counts: LruCache::with_capacity(
+                config.property("cache.mailbox.size").unwrap_or(2048),
+            ),
             config_version: 0.into(),
         };
 
@@ -167,6 +171,36 @@ impl JMAP {
         // Spawn housekeeper
         spawn_housekeeper(jmap_instance.clone(), housekeeper_rx);
 
+        // Complete or roll back operations interrupted by a crash
+        let jmap = JMAP::from(jmap_instance.clone());
+        tokio::spawn(async move {
+            jmap.recover_intents().await;
+        });
+
+        // Register the collections of accounts created before the collection registry
+        let store = jmap_instance.core.load().storage.data.clone();
+        tokio::spawn(async move {
+            match store.backfill_collections().await {
+                Ok(0) => {}
+                Ok(registered) => {
+                    tracing::info!(
+                        context = "migrate",
+                        event = "backfill",
+                        registered = registered,
+                        "Registered existing collections."
+                    );
+                }
+                Err(err) => {
+                    tracing::error!(
+                        context = "migrate",
+                        event = "error",
+                        error = ?err,
+                        "Failed to register existing collections."
+                    );
+                }
+            }
+        });
+
         jmap_instance
     }
 
@@ -437,17 +471,48 @@ impl JMAP {
         self.core
             .storage
             .data
-            .filter(account_id, collection, filters)
+            .filter_with_options(
+                account_id,
+                collection,
+                filters,
+                &FilterOptions {
+                    limits: self.core.jmap.query_limits,
+                    ..Default::default()
+                },
+            )
             .await
-            .map_err(|err| {
-                tracing::error!(event = "error",
+            .map_err(|err| match err {
+                store::Error::Timeout(timeout) => {
+                    tracing::warn!(event = "error",
+                               context = "filter",
+                               account_id = account_id,
+                               collection = ?collection,
+                               timeout = ?timeout,
+                               "Filter exceeded its timeout.");
+
+                    MethodError::ServerFail("Query timed out".to_string())
+                }
+                store::Error::MemoryLimitExceeded { size, max_size } => {
+                    tracing::warn!(event = "error",
+                               context = "filter",
+                               account_id = account_id,
+                               collection = ?collection,
+                               size = size,
+                               max_size = max_size,
+                               "Filter exceeded its memory limit.");
+
+                    MethodError::ServerFail("Query exceeded its memory limit".to_string())
+                }
+                err => {
+                    tracing::error!(event = "error",
                                 context = "filter",
                                 account_id = account_id,
                                 collection = ?collection,
                                 error = ?err,
                                 "Failed to execute filter.");
 
-                MethodError::ServerPartialFail
+                    MethodError::ServerPartialFail
+                }
             })
     }
 
@@ -565,7 +630,10 @@ impl JMAP {
             .await
             .map_err(|err| {
                 match err {
-                    store::Error::InternalError(err) => {
+                    store::Error::InternalError(err)
+                    | store::Error::NotFound(err)
+                    | store::Error::Unsupported(err)
+                    | store::Error::Corrupted(err) => {
                         tracing::error!(
                         event = "error",
                         context = "write_batch",
@@ -573,6 +641,52 @@ impl JMAP {
                         "Failed to write batch.");
                         MethodError::ServerPartialFail
                     }
+                    store::Error::ValueTooLarge { size, max_size } => {
+                        tracing::error!(
+                            event = "error",
+                            context = "write_batch",
+                            size = size,
+                            max_size = max_size,
+                            "Failed to write batch, value too large."
+                        );
+                        MethodError::ServerPartialFail
+                    }
+                    store::Error::QuotaExceeded { used, limit } => {
+                        tracing::warn!(
+                            event = "error",
+                            context = "write_batch",
+                            used = used,
+                            limit = limit,
+                            "Failed to write batch, quota exceeded."
+                        );
+                        MethodError::ServerPartialFail
+                    }
+                    store::Error::Timeout(_) | store::Error::MemoryLimitExceeded { .. } => {
+                        // Only returned by queries
+                        tracing::error!(
+                            event = "error",
+                            context = "write_batch",
+                            error = ?err,
+                            "Failed to write batch."
+                        );
+                        MethodError::ServerPartialFail
+                    }
+                    store::Error::Unavailable(err) => {
+                        tracing::warn!(
+                        event = "error",
+                        context = "write_batch",
+                        error = ?err,
+                        "Store is unavailable.");
+                        MethodError::ServerUnavailable
+                    }
+                    store::Error::ReadOnly => {
+                        tracing::warn!(
+                            event = "error",
+                            context = "write_batch",
+                            "Store is in read-only mode."
+                        );
+                        MethodError::ServerUnavailable
+                    }
                     store::Error::AssertValueFailed => {
                         // This should not occur, as we are not using assertions.
                         tracing::debug!(
@@ -4,6 +4,8 @@
  * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
  */
 
+use std::sync::Arc;
+
 use jmap_proto::{
     error::method::MethodError,
     method::get::{GetRequest, GetResponse, RequestArguments},
@@ -11,9 +13,11 @@ use jmap_proto::{
     types::{acl::Acl, collection::Collection, keyword::Keyword, property::Property, value::Value},
 };
 use store::{ahash::AHashSet, query::Filter, roaring::RoaringBitmap};
+use utils::lru_cache::LruCached;
 
 use crate::{
     auth::{acl::EffectiveAcl, AccessToken},
+    email::cache::MailboxCounts,
     JMAP,
 };
 
@@ -54,6 +58,32 @@ impl JMAP {
                 .map(Into::into)
                 .collect::<Vec<_>>()
         };
+        let fetch_counts = properties.iter().any(|p| {
+            matches!(
+                p,
+                Property::TotalEmails
+                    | Property::UnreadEmails
+                    | Property::TotalThreads
+                    | Property::UnreadThreads
+            )
+        });
+        let modseq = if fetch_counts {
+            self.core
+                .storage
+                .data
+                .get_last_change_id(account_id, Collection::Email)
+                .await
+                .map_err(|err| {
+                    tracing::error!(event = "error",
+                                    context = "store",
+                                    account_id = account_id,
+                                    error = ?err,
+                                    "Failed to retrieve emails last change id");
+                    MethodError::ServerPartialFail
+                })?
+        } else {
+            None
+        };
         let fetch_properties = properties.iter().any(|p| {
             matches!(
                 p,
@@ -103,6 +133,20 @@ impl JMAP {
                 Object::with_capacity(0)
             };
 
+            let counts = if fetch_counts {
+                Some(
+                    self.get_cached_mailbox_counts(
+                        account_id,
+                        document_id,
+                        access_token,
+                        modseq,
+                        &message_ids,
+                    )
+                    .await?,
+                )
+            } else {
+                None
+            };
             let mut mailbox = Object::with_capacity(properties.len());
 
             for property in &properties {
@@ -123,44 +167,18 @@ impl JMAP {
                             _ => Value::Null,
                         })
                         .unwrap_or_default(),
-                    Property::TotalEmails => Value::UnsignedInt(
-                        self.get_tag(
-                            account_id,
-                            Collection::Email,
-                            Property::MailboxIds,
-                            document_id,
-                        )
-                        .await?
-                        .map(|v| v.len())
-                        .unwrap_or(0),
-                    ),
-                    Property::UnreadEmails => Value::UnsignedInt(
-                        self.mailbox_unread_tags(account_id, document_id, &message_ids)
-                            .await?
-                            .map(|v| v.len())
-                            .unwrap_or(0),
-                    ),
-                    Property::TotalThreads => Value::UnsignedInt(
-                        self.mailbox_count_threads(
-                            account_id,
-                            self.get_tag(
-                                account_id,
-                                Collection::Email,
-                                Property::MailboxIds,
-                                document_id,
-                            )
-                            .await?,
-                        )
-                        .await? as u64,
-                    ),
-                    Property::UnreadThreads => Value::UnsignedInt(
-                        self.mailbox_count_threads(
-                            account_id,
-                            self.mailbox_unread_tags(account_id, document_id, &message_ids)
-                                .await?,
-                        )
-                        .await? as u64,
-                    ),
+                    Property::TotalEmails => {
+                        Value::UnsignedInt(counts.as_ref().map_or(0, |c| c.total_emails))
+                    }
+                    Property::UnreadEmails => {
+                        Value::UnsignedInt(counts.as_ref().map_or(0, |c| c.unread_emails))
+                    }
+                    Property::TotalThreads => {
+                        Value::UnsignedInt(counts.as_ref().map_or(0, |c| c.total_threads))
+                    }
+                    Property::UnreadThreads => {
+                        Value::UnsignedInt(counts.as_ref().map_or(0, |c| c.unread_threads))
+                    }
                     Property::MyRights => {
                         if access_token.is_shared(account_id) {
                             let acl = values.effective_acl(access_token);
@@ -237,6 +255,58 @@ impl JMAP {
         Ok(response)
     }
 
+    /// Returns the message and thread counts of a mailbox, recomputing them only
+    /// when emails of the account changed since they were cached. `modseq` must be
+    /// obtained before the counts are read, so that a concurrent write leaves the
+    /// cached entry outdated rather than wrong. Entries are kept per principal,
+    /// counts obtained for one principal are never returned to another.
+    pub async fn get_cached_mailbox_counts(
+        &self,
+        account_id: u32,
+        mailbox_id: u32,
+        access_token: &AccessToken,
+        modseq: Option<u64>,
+        message_ids: &Option<RoaringBitmap>,
+    ) -> Result<Arc<MailboxCounts>, MethodError> {
+        if let Some(counts) = self
+            .inner
+            .cache_mailbox_counts
+            .get(&(account_id, mailbox_id, access_token.primary_id()))
+            .filter(|counts| counts.modseq.unwrap_or(0) >= modseq.unwrap_or(0))
+        {
+            return Ok(counts);
+        }
+
+        let mailbox_message_ids = self
+            .get_tag(
+                account_id,
+                Collection::Email,
+                Property::MailboxIds,
+                mailbox_id,
+            )
+            .await?;
+        let unread_message_ids = self
+            .mailbox_unread_tags(account_id, mailbox_id, message_ids)
+            .await?;
+        let counts = Arc::new(MailboxCounts {
+            total_emails: mailbox_message_ids.as_ref().map_or(0, |ids| ids.len()),
+            unread_emails: unread_message_ids.as_ref().map_or(0, |ids| ids.len()),
+            total_threads: self
+                .mailbox_count_threads(account_id, mailbox_message_ids)
+                .await? as u64,
+            unread_threads: self
+                .mailbox_count_threads(account_id, unread_message_ids)
+                .await? as u64,
+            modseq,
+        });
+        self.inner.cache_mailbox_counts.insert(
+            (account_id, mailbox_id, access_token.primary_id()),
+            counts.clone(),
+        );
+
+        Ok(counts)
+    }
+
     async fn mailbox_count_threads(
         &self,
         account_id: u32,
@@ -20,7 +20,7 @@ use jmap_proto::{
 };
 use store::{
     rand::{distributions::Alphanumeric, thread_rng, Rng},
-    write::{now, BatchBuilder, F_CLEAR, F_VALUE},
+    write::{now, BatchBuilder, RetryPolicy, F_CLEAR, F_VALUE},
 };
 
 use crate::{auth::AccessToken, JMAP};
@@ -105,6 +105,7 @@ impl JMAP {
             // Insert record
             let mut batch = BatchBuilder::new();
             batch
+                .with_retry_policy(RetryPolicy::INTERACTIVE)
                 .with_account_id(account_id)
                 .with_collection(Collection::PushSubscription)
                 .create_document()
@@ -166,6 +167,7 @@ impl JMAP {
             // Update record
             let mut batch = BatchBuilder::new();
             batch
+                .with_retry_policy(RetryPolicy::INTERACTIVE)
                 .with_account_id(account_id)
                 .with_collection(Collection::PushSubscription)
                 .update_document(document_id)
@@ -181,6 +183,7 @@ impl JMAP {
                 // Update record
                 let mut batch = BatchBuilder::new();
                 batch
+                    .with_retry_policy(RetryPolicy::INTERACTIVE)
                     .with_account_id(account_id)
                     .with_collection(Collection::PushSubscription)
                     .delete_document(document_id)
@@ -4,9 +4,13 @@
  * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
  */
 
-use jmap_proto::types::{collection::Collection, property::Property};
+use jmap_proto::{
+    error::method::MethodError,
+    types::{collection::Collection, property::Property},
+};
 use store::{
     fts::index::FtsDocument,
+    roaring::RoaringBitmap,
     write::{
         key::DeserializeBigEndian, now, BatchBuilder, Bincode, FtsQueueClass, MaybeDynamicId,
         ValueClass,
@@ -33,6 +37,8 @@ struct IndexEmail {
 }
 
 const INDEX_LOCK_EXPIRY: u64 = 60 * 5;
+const MAX_INDEX_FAILURES: u32 = 3;
+const REINDEX_BATCH_SIZE: usize = 100;
 
 impl JMAP {
     pub async fn fts_index_queued(&self) {
@@ -99,6 +105,7 @@ impl JMAP {
             if !self.try_lock_index(&event).await {
                 continue;
             }
+            let mut is_indexed = false;
 
             match self
                 .get_property::<Bincode<MessageMetadata>>(
@@ -112,51 +119,60 @@ impl JMAP {
                 Ok(Some(metadata))
                     if metadata.inner.blob_hash.as_slice() == event.insert_hash.as_slice() =>
                 {
-                    // Obtain raw message
-                    let raw_message = if let Ok(Some(raw_message)) = self
+                    let result = match self
                         .get_blob(&metadata.inner.blob_hash, 0..usize::MAX)
                         .await
                     {
-                        raw_message
-                    } else {
-                        tracing::warn!(
-                            context = "fts_index_queued",
-                            event = "error",
-                            account_id = event.account_id,
-                            document_id = event.document_id,
-                            blob_hash = ?metadata.inner.blob_hash,
-                            "Message blob not found"
-                        );
-                        continue;
+                        Ok(Some(raw_message)) => {
+                            let message = metadata.inner.contents.into_message(&raw_message);
+
+                            // Index message
+                            let document =
+                                FtsDocument::with_default_language(self.core.jmap.default_language)
+                                    .with_account_id(event.account_id)
+                                    .with_collection(Collection::Email)
+                                    .with_document_id(event.document_id)
+                                    .index_message(&message);
+                            self.core
+                                .storage
+                                .fts
+                                .index(document)
+                                .await
+                                .map_err(|err| err.to_string())
+                        }
+                        _ => Err(format!(
+                            "Message blob {:?} not found",
+                            metadata.inner.blob_hash
+                        )),
                     };
-                    let message = metadata.inner.contents.into_message(&raw_message);
-
-                    // Index message
-                    let document =
-                        FtsDocument::with_default_language(self.core.jmap.default_language)
-                            .with_account_id(event.account_id)
-                            .with_collection(Collection::Email)
-                            .with_document_id(event.document_id)
-                            .index_message(&message);
-                    if let Err(err) = self.core.storage.fts.index(document).await {
-                        tracing::error!(
-                            context = "fts_index_queued",
-                            event = "error",
-                            account_id = event.account_id,
-                            document_id = event.document_id,
-                            reason = ?err,
-                            "Failed to index email in FTS index"
-                        );
-                        continue;
-                    }
 
-                    tracing::debug!(
-                        context = "fts_index_queued",
-                        event = "index",
-                        account_id = event.account_id,
-                        document_id = event.document_id,
-                        "Indexed document in FTS index"
-                    );
+                    match result {
+                        Ok(_) => {
+                            is_indexed = true;
+                            tracing::debug!(
+                                context = "fts_index_queued",
+                                event = "index",
+                                account_id = event.account_id,
+                                document_id = event.document_id,
+                                "Indexed document in FTS index"
+                            );
+                        }
+                        Err(reason) => {
+                            tracing::error!(
+                                context = "fts_index_queued",
+                                event = "error",
+                                account_id = event.account_id,
+                                document_id = event.document_id,
+                                reason = reason,
+                                "Failed to index email in FTS index"
+                            );
+
+                            // Keep retrying until the message is quarantined
+                            if !self.index_failed(&event, reason).await {
+                                continue;
+                            }
+                        }
+                    }
                 }
 
                 Err(err) => {
@@ -183,20 +199,16 @@ impl JMAP {
             }
 
             // Remove entry from queue
-            if let Err(err) = self
-                .core
-                .storage
-                .data
-                .write(
-                    BatchBuilder::new()
-                        .with_account_id(event.account_id)
-                        .with_collection(Collection::Email)
-                        .update_document(event.document_id)
-                        .clear(event.value_class())
-                        .build_batch(),
-                )
-                .await
-            {
+            let mut batch = BatchBuilder::new();
+            batch
+                .with_account_id(event.account_id)
+                .with_collection(Collection::Email)
+                .update_document(event.document_id)
+                .clear(event.value_class());
+            if is_indexed {
+                batch.clear(ValueClass::Quarantine);
+            }
+            if let Err(err) = self.core.storage.data.write(batch.build_batch()).await {
                 tracing::error!(
                     context = "fts_index_queued",
                     event = "error",
@@ -212,6 +224,223 @@ impl JMAP {
         }
     }
 
+    /// Rebuilds the full-text index of the documents of a collection, starting at
+    /// `from_document_id`. Documents are processed in ascending id order in small
+    /// batches, so an interrupted reindex can be resumed from the last logged id.
+    /// The documents of a batch are read before their terms are removed, and if
+    /// any of them fails to be indexed the rest of the batch is handed over to the
+    /// indexing queue, so no document is left without terms. Returns the number of
+    /// reindexed documents.
+    pub async fn reindex_fts(
+        &self,
+        account_id: u32,
+        collection: Collection,
+        from_document_id: u32,
+    ) -> Result<u64, MethodError> {
+        // Emails are the only collection with full-text indexed documents
+        if collection != Collection::Email {
+            return Err(MethodError::InvalidArguments(format!(
+                "Collection {collection} has no full-text index."
+            )));
+        }
+
+        let mut document_ids = self
+            .get_document_ids(account_id, collection)
+            .await?
+            .unwrap_or_default();
+        document_ids.remove_range(..from_document_id);
+        let mut total = 0;
+
+        let document_ids = document_ids.into_iter().collect::<Vec<_>>();
+        for chunk in document_ids.chunks(REINDEX_BATCH_SIZE) {
+            let mut documents = Vec::with_capacity(chunk.len());
+            for &document_id in chunk {
+                let metadata = if let Some(metadata) = self
+                    .get_property::<Bincode<MessageMetadata>>(
+                        account_id,
+                        collection,
+                        document_id,
+                        Property::BodyStructure,
+                    )
+                    .await?
+                {
+                    metadata.inner
+                } else {
+                    continue;
+                };
+                if let Some(raw_message) = self.get_blob(&metadata.blob_hash, 0..usize::MAX).await?
+                {
+                    documents.push((document_id, metadata, raw_message));
+                } else {
+                    tracing::warn!(
+                        context = "reindex_fts",
+                        event = "error",
+                        account_id = account_id,
+                        document_id = document_id,
+                        blob_hash = ?metadata.blob_hash,
+                        "Message blob not found"
+                    );
+                }
+            }
+
+            // Remove existing terms so entries no longer produced by the tokenizer are dropped
+            let chunk_ids = RoaringBitmap::from_sorted_iter(chunk.iter().copied()).unwrap();
+            self.core
+                .storage
+                .fts
+                .remove(account_id, collection.into(), &chunk_ids)
+                .await
+                .map_err(|err| {
+                    tracing::error!(
+                        context = "reindex_fts",
+                        event = "error",
+                        account_id = account_id,
+                        reason = ?err,
+                        "Failed to remove documents from FTS index"
+                    );
+                    MethodError::ServerPartialFail
+                })?;
+
+            let mut documents = documents.into_iter();
+            while let Some((document_id, metadata, raw_message)) = documents.next() {
+                let blob_hash = metadata.blob_hash.clone();
+                let message = metadata.contents.into_message(&raw_message);
+                let document = FtsDocument::with_default_language(self.core.jmap.default_language)
+                    .with_account_id(account_id)
+                    .with_collection(collection)
+                    .with_document_id(document_id)
+                    .index_message(&message);
+
+                if let Err(err) = self.core.storage.fts.index(document).await {
+                    tracing::error!(
+                        context = "reindex_fts",
+                        event = "error",
+                        account_id = account_id,
+                        document_id = document_id,
+                        reason = ?err,
+                        "Failed to index document in FTS index"
+                    );
+
+                    // Leave the documents whose terms were removed to the indexer
+                    let mut batch = BatchBuilder::new();
+                    batch
+                        .with_account_id(account_id)
+                        .with_collection(collection);
+                    for (document_id, blob_hash) in [(document_id, blob_hash)].into_iter().chain(
+                        documents
+                            .map(|(document_id, metadata, _)| (document_id, metadata.blob_hash)),
+                    ) {
+                        batch.update_document(document_id).set(
+                            ValueClass::FtsQueue(FtsQueueClass {
+                                seq: self.generate_snowflake_id()?,
+                                hash: blob_hash,
+                            }),
+                            0u64.serialize(),
+                        );
+                    }
+                    self.write_batch(batch).await?;
+                    let _ = self.inner.housekeeper_tx.send(Event::IndexStart).await;
+
+                    return Err(MethodError::ServerPartialFail);
+                }
+                total += 1;
+            }
+
+            tracing::debug!(
+                context = "reindex_fts",
+                event = "progress",
+                account_id = account_id,
+                collection = ?collection,
+                last_document_id = chunk.last().copied().unwrap_or_default(),
+                total = total,
+                "Reindexed documents in FTS index"
+            );
+        }
+
+        Ok(total)
+    }
+
+    /// Releases a quarantined email and queues it for indexing again,
+    /// returns `false` if the email no longer exists.
+    pub async fn reprocess_quarantined(
+        &self,
+        account_id: u32,
+        document_id: u32,
+    ) -> Result<bool, MethodError> {
+        let metadata = if let Some(metadata) = self
+            .get_property::<Bincode<MessageMetadata>>(
+                account_id,
+                Collection::Email,
+                document_id,
+                Property::BodyStructure,
+            )
+            .await?
+        {
+            metadata
+        } else {
+            return Ok(false);
+        };
+
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(account_id)
+            .with_collection(Collection::Email)
+            .update_document(document_id)
+            .clear(ValueClass::Quarantine)
+            .set(
+                ValueClass::FtsQueue(FtsQueueClass {
+                    seq: self.generate_snowflake_id()?,
+                    hash: metadata.inner.blob_hash,
+                }),
+                0u64.serialize(),
+            );
+        self.write_batch(batch).await?;
+
+        let _ = self.inner.housekeeper_tx.send(Event::IndexStart).await;
+
+        Ok(true)
+    }
+
+    // Returns `true` when the message failed too many times and has been quarantined
+    async fn index_failed(&self, event: &IndexEmail, reason: String) -> bool {
+        match self
+            .core
+            .storage
+            .data
+            .record_failure(
+                event.account_id,
+                Collection::Email,
+                event.document_id,
+                reason,
+                MAX_INDEX_FAILURES,
+            )
+            .await
+        {
+            Ok(true) => {
+                tracing::warn!(
+                    context = "fts_index_queued",
+                    event = "quarantine",
+                    account_id = event.account_id,
+                    document_id = event.document_id,
+                    "Email failed to index {MAX_INDEX_FAILURES} times and has been quarantined"
+                );
+                true
+            }
+            Ok(false) => false,
+            Err(err) => {
+                tracing::error!(
+                    context = "fts_index_queued",
+                    event = "error",
+                    account_id = event.account_id,
+                    document_id = event.document_id,
+                    reason = ?err,
+                    "Failed to record indexing failure"
+                );
+                false
+            }
+        }
+    }
+
     async fn try_lock_index(&self, event: &IndexEmail) -> bool {
         let mut batch = BatchBuilder::new();
         batch
@@ -83,12 +83,45 @@ pub fn spawn_state_manager(core: JmapInstance, mut change_rx: mpsc::Receiver<Eve
             AHashMap::default();
 
         let mut last_purge = Instant::now();
-
-        while let Some(event) = change_rx.recv().await {
+        let mut pending = PendingChanges::default();
+
+        loop {
+            pending.window = core.core.load().jmap.state_change_debounce;
+            let event = match pending.poll(&mut change_rx).await {
+                PendingEvent::Due(state_changes) => {
+                    // Publish the latest state of the accounts whose window closed
+                    let mut purge_needed = last_purge.elapsed() >= PURGE_EVERY;
+                    for state_change in state_changes {
+                        purge_needed |= publish_state_change(
+                            state_change,
+                            &subscribers,
+                            &shared_accounts_map,
+                            &push_tx,
+                        )
+                        .await;
+                    }
+                    if purge_needed {
+                        purge_subscribers(&mut subscribers);
+                        last_purge = Instant::now();
+                    }
+                    continue;
+                }
+                PendingEvent::Event(Some(event)) => event,
+                PendingEvent::Event(None) => break,
+            };
             let mut purge_needed = last_purge.elapsed() >= PURGE_EVERY;
 
             match event {
                 Event::Stop => {
+                    for state_change in pending.take_all() {
+                        publish_state_change(
+                            state_change,
+                            &subscribers,
+                            &shared_accounts_map,
+                            &push_tx,
+                        )
+                        .await;
+                    }
                     if let Err(err) = push_tx.send(crate::push::Event::Reset).await {
                         tracing::debug!("Error sending push reset: {}", err);
                     }
@@ -173,78 +206,16 @@ pub fn spawn_state_manager(core: JmapInstance, mut change_rx: mpsc::Receiver<Eve
                         );
                 }
                 Event::Publish { state_change } => {
-                    if let Some(shared_accounts) = shared_accounts_map.get(&state_change.account_id)
-                    {
-                        let current_time = SystemTime::now()
-                            .duration_since(SystemTime::UNIX_EPOCH)
-                            .map(|d| d.as_secs())
-                            .unwrap_or(0);
-                        let mut push_ids = Vec::new();
-
-                        for (owner_account_id, allowed_types) in shared_accounts {
-                            if let Some(subscribers) = subscribers.get(owner_account_id) {
-                                for (subscriber_id, subscriber) in subscribers {
-                                    let mut types = Vec::with_capacity(state_change.types.len());
-                                    for (state_type, change_id) in &state_change.types {
-                                        if subscriber.types.contains(*state_type)
-                                            && allowed_types.contains(*state_type)
-                                        {
-                                            types.push((*state_type, *change_id));
-                                        }
-                                    }
-                                    if !types.is_empty() {
-                                        match &subscriber.subscription {
-                                            SubscriberType::Ipc { tx } if !tx.is_closed() => {
-                                                let subscriber_tx = tx.clone();
-                                                let state_change = state_change.clone();
-
-                                                tokio::spawn(async move {
-                                                    // Timeout after 500ms in case there is a blocked client
-                                                    if let Err(err) = subscriber_tx
-                                                        .send_timeout(
-                                                            StateChange {
-                                                                account_id: state_change.account_id,
-                                                                types,
-                                                            },
-                                                            SEND_TIMEOUT,
-                                                        )
-                                                        .await
-                                                    {
-                                                        tracing::debug!(
-                                                        "Error sending state change to subscriber: {}",
-                                                        err
-                                                    );
-                                                    }
-                                                });
-                                            }
-                                            SubscriberType::Push { expires }
-                                                if expires > &current_time =>
-                                            {
-                                                push_ids.push(Id::from_parts(
-                                                    *owner_account_id,
-                                                    (*subscriber_id).into(),
-                                                ));
-                                            }
-                                            _ => {
-                                                purge_needed = true;
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                        }
-
-                        if !push_ids.is_empty() {
-                            if let Err(err) = push_tx
-                                .send(crate::push::Event::Push {
-                                    ids: push_ids,
-                                    state_change,
-                                })
-                                .await
-                            {
-                                tracing::debug!("Error sending push updates: {}", err);
-                            }
-                        }
+                    if pending.window.is_zero() {
+                        purge_needed |= publish_state_change(
+                            state_change,
+                            &subscribers,
+                            &shared_accounts_map,
+                            &push_tx,
+                        )
+                        .await;
+                    } else {
+                        pending.add(state_change);
                     }
                 }
                 Event::UpdateSubscriptions {
@@ -331,38 +302,207 @@ pub fn spawn_state_manager(core: JmapInstance, mut change_rx: mpsc::Receiver<Eve
             }
 
             if purge_needed {
-                let mut remove_account_ids = Vec::new();
-                let current_time = SystemTime::now()
-                    .duration_since(SystemTime::UNIX_EPOCH)
-                    .map(|d| d.as_secs())
-                    .unwrap_or(0);
-
-                for (account_id, subscriber_map) in &mut subscribers {
-                    let mut remove_subscription_ids = Vec::new();
-                    for (id, subscriber) in subscriber_map.iter() {
-                        if !subscriber.is_valid(current_time) {
-                            remove_subscription_ids.push(*id);
+                purge_subscribers(&mut subscribers);
+                last_purge = Instant::now();
+            }
+        }
+    });
+}
+
+fn purge_subscribers(subscribers: &mut AHashMap<u32, AHashMap<SubscriberId, Subscriber>>) {
+    let mut remove_account_ids = Vec::new();
+    let current_time = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    for (account_id, subscriber_map) in subscribers.iter_mut() {
+        let mut remove_subscription_ids = Vec::new();
+        for (id, subscriber) in subscriber_map.iter() {
+            if !subscriber.is_valid(current_time) {
+                remove_subscription_ids.push(*id);
+            }
+        }
+        if !remove_subscription_ids.is_empty() {
+            if remove_subscription_ids.len() < subscriber_map.len() {
+                for remove_subscription_id in remove_subscription_ids {
+                    subscriber_map.remove(&remove_subscription_id);
+                }
+            } else {
+                remove_account_ids.push(*account_id);
+            }
+        }
+    }
+
+    for remove_account_id in remove_account_ids {
+        subscribers.remove(&remove_account_id);
+    }
+}
+
+async fn publish_state_change(
+    state_change: StateChange,
+    subscribers: &AHashMap<u32, AHashMap<SubscriberId, Subscriber>>,
+    shared_accounts_map: &AHashMap<u32, AHashMap<u32, Bitmap<DataType>>>,
+    push_tx: &mpsc::Sender<crate::push::Event>,
+) -> bool {
+    let mut purge_needed = false;
+
+    if let Some(shared_accounts) = shared_accounts_map.get(&state_change.account_id) {
+        let current_time = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let mut push_ids = Vec::new();
+
+        for (owner_account_id, allowed_types) in shared_accounts {
+            if let Some(subscribers) = subscribers.get(owner_account_id) {
+                for (subscriber_id, subscriber) in subscribers {
+                    let mut types = Vec::with_capacity(state_change.types.len());
+                    for (state_type, change_id) in &state_change.types {
+                        if subscriber.types.contains(*state_type)
+                            && allowed_types.contains(*state_type)
+                        {
+                            types.push((*state_type, *change_id));
                         }
                     }
-                    if !remove_subscription_ids.is_empty() {
-                        if remove_subscription_ids.len() < subscriber_map.len() {
-                            for remove_subscription_id in remove_subscription_ids {
-                                subscriber_map.remove(&remove_subscription_id);
+                    if !types.is_empty() {
+                        match &subscriber.subscription {
+                            SubscriberType::Ipc { tx } if !tx.is_closed() => {
+                                let subscriber_tx = tx.clone();
+                                let state_change = state_change.clone();
+
+                                tokio::spawn(async move {
+                                    // Timeout after 500ms in case there is a blocked client
+                                    if let Err(err) = subscriber_tx
+                                        .send_timeout(
+                                            StateChange {
+                                                account_id: state_change.account_id,
+                                                types,
+                                            },
+                                            SEND_TIMEOUT,
+                                        )
+                                        .await
+                                    {
+                                        tracing::debug!(
+                                            "Error sending state change to subscriber: {}",
+                                            err
+                                        );
+                                    }
+                                });
+                            }
+                            SubscriberType::Push { expires } if expires > &current_time => {
+                                push_ids.push(Id::from_parts(
+                                    *owner_account_id,
+                                    (*subscriber_id).into(),
+                                ));
+                            }
+                            _ => {
+                                purge_needed = true;
                             }
-                        } else {
-                            remove_account_ids.push(*account_id);
                         }
                     }
                 }
+            }
+        }
 
-                for remove_account_id in remove_account_ids {
-                    subscribers.remove(&remove_account_id);
-                }
+        if !push_ids.is_empty() {
+            if let Err(err) = push_tx
+                .send(crate::push::Event::Push {
+                    ids: push_ids,
+                    state_change,
+                })
+                .await
+            {
+                tracing::debug!("Error sending push updates: {}", err);
+            }
+        }
+    }
 
-                last_purge = Instant::now();
+    purge_needed
+}
+
+/// State changes waiting for their account's debounce window to close. The window
+/// starts with the first change received for an account, later changes only replace
+/// the change ids so that the latest state is published when it closes.
+#[derive(Debug, Default)]
+struct PendingChanges {
+    window: Duration,
+    changes: AHashMap<u32, (StateChange, Instant)>,
+}
+
+#[derive(Debug)]
+enum PendingEvent<T> {
+    Due(Vec<StateChange>),
+    Event(Option<T>),
+}
+
+impl PendingChanges {
+    fn add(&mut self, state_change: StateChange) {
+        let window = self.window;
+        let (pending, _) = self
+            .changes
+            .entry(state_change.account_id)
+            .or_insert_with(|| {
+                (
+                    StateChange::new(state_change.account_id),
+                    Instant::now() + window,
+                )
+            });
+        for (state_type, change_id) in state_change.types {
+            if let Some((_, last_change_id)) = pending
+                .types
+                .iter_mut()
+                .find(|(pending_type, _)| *pending_type == state_type)
+            {
+                *last_change_id = std::cmp::max(*last_change_id, change_id);
+            } else {
+                pending.types.push((state_type, change_id));
             }
         }
-    });
+    }
+
+    /// Returns the changes whose window closed, otherwise waits for the next event
+    /// until the earliest window closes. Due changes are checked before receiving
+    /// so that a steady flow of events does not hold them back.
+    async fn poll<T>(&mut self, rx: &mut mpsc::Receiver<T>) -> PendingEvent<T> {
+        let due = self.take_due(Instant::now());
+        if !due.is_empty() {
+            return PendingEvent::Due(due);
+        }
+
+        if let Some(deadline) = self.next_deadline() {
+            match tokio::time::timeout_at(deadline.into(), rx.recv()).await {
+                Ok(event) => PendingEvent::Event(event),
+                Err(_) => PendingEvent::Due(self.take_due(Instant::now())),
+            }
+        } else {
+            PendingEvent::Event(rx.recv().await)
+        }
+    }
+
+    fn next_deadline(&self) -> Option<Instant> {
+        self.changes.values().map(|(_, deadline)| *deadline).min()
+    }
+
+    fn take_due(&mut self, now: Instant) -> Vec<StateChange> {
+        let due = self
+            .changes
+            .iter()
+            .filter(|(_, (_, deadline))| *deadline <= now)
+            .map(|(account_id, _)| *account_id)
+            .collect::<Vec<_>>();
+        due.into_iter()
+            .filter_map(|account_id| self.changes.remove(&account_id))
+            .map(|(state_change, _)| state_change)
+            .collect()
+    }
+
+    fn take_all(&mut self) -> Vec<StateChange> {
+        self.changes
+            .drain()
+            .map(|(_, (state_change, _))| state_change)
+            .collect()
+    }
 }
 
 impl JMAP {
@@ -442,3 +582,76 @@ impl From<SubscriberId> for u32 {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use jmap_proto::types::{state::StateChange, type_state::DataType};
+    use tokio::sync::mpsc;
+
+    use super::{PendingChanges, PendingEvent};
+
+    fn state_change(account_id: u32, change_id: u64) -> StateChange {
+        StateChange::new(account_id).with_change(DataType::Email, change_id)
+    }
+
+    fn ids(state_changes: Vec<StateChange>) -> Vec<(u32, Vec<(DataType, u64)>)> {
+        let mut ids = state_changes
+            .into_iter()
+            .map(|state_change| (state_change.account_id, state_change.types))
+            .collect::<Vec<_>>();
+        ids.sort_unstable_by_key(|(account_id, _)| *account_id);
+        ids
+    }
+
+    #[tokio::test]
+    async fn debounce_state_changes() {
+        let mut pending = PendingChanges {
+            window: Duration::from_millis(50),
+            ..Default::default()
+        };
+
+        // Changes are merged per account until their window closes
+        pending.add(state_change(1, 1));
+        pending.add(state_change(1, 3));
+        pending.add(state_change(1, 2));
+        pending.add(state_change(2, 1));
+        assert!(pending.take_due(Instant::now()).is_empty());
+        assert_eq!(
+            ids(pending.take_due(Instant::now() + pending.window)),
+            vec![
+                (1, vec![(DataType::Email, 3)]),
+                (2, vec![(DataType::Email, 1)])
+            ]
+        );
+        assert!(pending.next_deadline().is_none());
+
+        // Due changes are published under a steady flow of events
+        let (tx, mut rx) = mpsc::channel::<u32>(8);
+        tokio::spawn(async move {
+            while tx.send(0).await.is_ok() {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        });
+        pending.add(state_change(3, 1));
+        let start = Instant::now();
+        let due = loop {
+            match pending.poll(&mut rx).await {
+                PendingEvent::Due(due) => break due,
+                PendingEvent::Event(event) => {
+                    assert_eq!(event, Some(0));
+                    assert!(start.elapsed() < Duration::from_secs(1));
+                }
+            }
+        };
+        assert_eq!(ids(due), vec![(3, vec![(DataType::Email, 1)])]);
+        assert!(start.elapsed() >= pending.window);
+
+        // Without pending changes, the next event is awaited
+        assert!(matches!(
+            pending.poll(&mut rx).await,
+            PendingEvent::Event(Some(0))
+        ));
+    }
+}
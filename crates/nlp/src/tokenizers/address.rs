@@ -0,0 +1,153 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs Ltd <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use super::word::WordTokenizer;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AddressToken {
+    /// A full address, its local part or its domain, matched as a whole.
+    Part(String),
+    /// A word from a display name or from within an address.
+    Word(String),
+}
+
+/// Tokenizes addresses and display names for indexing. Each address produces the
+/// full address, its local part, its domain with and without a leading `@` and the
+/// words within them, so that `john`, `john.doe`, `example.com` and `@example.com`
+/// all match `John Doe <john.doe@example.com>`.
+pub fn tokenize_address(text: &str, max_token_length: usize) -> Vec<AddressToken> {
+    let mut tokens = Vec::new();
+
+    for chunk in address_chunks(text) {
+        if let Some((local, domain)) = split_address(chunk) {
+            let address = chunk.to_lowercase();
+            let (local, domain) = (local.to_lowercase(), domain.to_lowercase());
+            for part in [
+                (!local.is_empty() && !domain.is_empty()).then_some(address),
+                (!local.is_empty()).then_some(local),
+                (!domain.is_empty()).then(|| format!("@{domain}")),
+                (!domain.is_empty()).then_some(domain),
+            ]
+            .into_iter()
+            .flatten()
+            {
+                if part.len() <= max_token_length {
+                    tokens.push(AddressToken::Part(part));
+                }
+            }
+        }
+
+        tokens.extend(
+            WordTokenizer::new(chunk, max_token_length)
+                .map(|token| AddressToken::Word(token.word.into_owned())),
+        );
+    }
+
+    tokens
+}
+
+/// Tokenizes an address search query. Terms containing an `@` are looked up as a
+/// whole, either as a full address, a local part ending in `@` or a domain starting
+/// with `@`, while any other terms are split into words.
+///
+/// Addresses indexed before full address, local part and domain tokens were added
+/// only have their words indexed, so callers should fall back to matching the
+/// words of each `AddressToken::Part` as a phrase.
+pub fn tokenize_address_query(text: &str, max_token_length: usize) -> Vec<AddressToken> {
+    let mut tokens = Vec::new();
+
+    for chunk in address_chunks(text) {
+        if chunk.contains('@') {
+            let part = chunk
+                .strip_suffix('@')
+                .filter(|local| !local.contains('@'))
+                .unwrap_or(chunk)
+                .to_lowercase();
+            if part.len() <= max_token_length && part != "@" {
+                tokens.push(AddressToken::Part(part));
+            }
+        } else {
+            tokens.extend(
+                WordTokenizer::new(chunk, max_token_length)
+                    .map(|token| AddressToken::Word(token.word.into_owned())),
+            );
+        }
+    }
+
+    tokens
+}
+
+/// Splits an address into its local part and domain at the last `@`, the same
+/// split used by the `email_part` function available to the spam filter.
+pub fn split_address(address: &str) -> Option<(&str, &str)> {
+    address
+        .rsplit_once('@')
+        .map(|(local, domain)| (local.trim(), domain.trim()))
+}
+
+fn address_chunks(text: &str) -> impl Iterator<Item = &str> {
+    text.split(|ch: char| ch.is_whitespace() || matches!(ch, '<' | '>' | ',' | ';'))
+        .map(|chunk| chunk.trim_matches(|ch| matches!(ch, '"' | '\'' | '(' | ')')))
+        .filter(|chunk| !chunk.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn address_tokenizer() {
+        assert_eq!(
+            tokenize_address("John Doe <John.Doe@Example.com>", 40),
+            vec![
+                AddressToken::Word("john".into()),
+                AddressToken::Word("doe".into()),
+                AddressToken::Part("john.doe@example.com".into()),
+                AddressToken::Part("john.doe".into()),
+                AddressToken::Part("@example.com".into()),
+                AddressToken::Part("example.com".into()),
+                AddressToken::Word("john".into()),
+                AddressToken::Word("doe".into()),
+                AddressToken::Word("example".into()),
+                AddressToken::Word("com".into()),
+            ]
+        );
+
+        for (query, expected) in [
+            (
+                "@example.com",
+                vec![AddressToken::Part("@example.com".into())],
+            ),
+            ("John.Doe@", vec![AddressToken::Part("john.doe".into())]),
+            (
+                "john.doe@example.com",
+                vec![AddressToken::Part("john.doe@example.com".into())],
+            ),
+            (
+                "John Doe",
+                vec![
+                    AddressToken::Word("john".into()),
+                    AddressToken::Word("doe".into()),
+                ],
+            ),
+            (
+                "\"Jane\" <jane@example.org>",
+                vec![
+                    AddressToken::Word("jane".into()),
+                    AddressToken::Part("jane@example.org".into()),
+                ],
+            ),
+        ] {
+            assert_eq!(tokenize_address_query(query, 40), expected, "{query}");
+        }
+
+        assert_eq!(
+            split_address(" john.doe@example.com "),
+            Some(("john.doe", "example.com"))
+        );
+        assert_eq!(split_address("john.doe"), None);
+    }
+}
@@ -4,6 +4,7 @@
  * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
  */
 
+pub mod address;
 pub mod chinese;
 pub mod japanese;
 pub mod osb;
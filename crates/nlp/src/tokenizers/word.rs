@@ -6,6 +6,10 @@
 
 use std::{borrow::Cow, str::CharIndices};
 
+use unicode_normalization::{
+    char::is_combining_mark, is_nfkc_quick, IsNormalized, UnicodeNormalization,
+};
+
 use super::Token;
 
 pub struct WordTokenizer<'x> {
@@ -24,7 +28,9 @@ impl<'x> WordTokenizer<'x> {
     }
 }
 
-/// Parses indo-european text into lowercase tokens.
+/// Parses indo-european text into lowercase tokens. Tokens are NFKC normalized
+/// before lowercasing, so that decomposed characters and compatibility forms such
+/// as full-width letters produce the same tokens as their canonical forms.
 impl<'x> Iterator for WordTokenizer<'x> {
     type Item = Token<Cow<'x, str>>;
 
@@ -34,7 +40,7 @@ impl<'x> Iterator for WordTokenizer<'x> {
                 let mut is_uppercase = ch.is_uppercase();
                 let token_end = (&mut self.iterator)
                     .filter_map(|(pos, ch)| {
-                        if ch.is_alphanumeric() {
+                        if ch.is_alphanumeric() || is_combining_mark(ch) {
                             if !is_uppercase && ch.is_uppercase() {
                                 is_uppercase = true;
                             }
@@ -48,13 +54,16 @@ impl<'x> Iterator for WordTokenizer<'x> {
 
                 let token_len = token_end - token_start;
                 if token_end > token_start && token_len <= self.max_token_length {
+                    let word = &self.text[token_start..token_end];
                     return Token::new(
                         token_start,
                         token_len,
-                        if is_uppercase {
-                            self.text[token_start..token_end].to_lowercase().into()
+                        if is_nfkc_quick(word.chars()) != IsNormalized::Yes {
+                            word.nfkc().collect::<String>().to_lowercase().into()
+                        } else if is_uppercase {
+                            word.to_lowercase().into()
                         } else {
-                            self.text[token_start..token_end].into()
+                            word.into()
                         },
                     )
                     .into();
@@ -147,4 +156,17 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn unicode_normalization() {
+        assert_eq!(
+            WordTokenizer::new("cafe\u{301} Café ＡＢＣ１２３ ﾃﾞｰﾀ", 40).collect::<Vec<_>>(),
+            vec![
+                Token::new(0, 6, "café".into()),
+                Token::new(7, 5, "café".into()),
+                Token::new(13, 18, "abc123".into()),
+                Token::new(32, 12, "データ".into()),
+            ]
+        );
+    }
 }
@@ -8,7 +8,9 @@ use crate::queue::DomainPart;
 use std::borrow::Cow;
 use std::time::{Duration, SystemTime};
 use store::write::key::DeserializeBigEndian;
-use store::write::{now, BatchBuilder, Bincode, BlobOp, QueueClass, QueueEvent, ValueClass};
+use store::write::{
+    now, BatchBuilder, Bincode, BlobOp, QueueClass, QueueEvent, RetryPolicy, ValueClass,
+};
 use store::{Deserialize, IterateParams, Serialize, ValueKey, U64_LEN};
 use utils::BlobHash;
 
@@ -245,8 +247,9 @@ impl Message {
             "Message queued for delivery."
         );
 
-        // Write message to queue
+        // Write message to queue, the message is lost if this write fails
         let mut batch = BatchBuilder::new();
+        batch.with_retry_policy(RetryPolicy::CRITICAL);
 
         // Reserve quotas
         for quota_key in &self.quota_keys {
@@ -384,6 +387,7 @@ impl Message {
 
         // Update message queue
         let mut batch = BatchBuilder::new();
+        batch.with_retry_policy(RetryPolicy::CRITICAL);
         if let (Some(prev_event), Some(next_event)) = (prev_event, next_event) {
             batch
                 .clear(ValueClass::Queue(QueueClass::MessageEvent(QueueEvent {
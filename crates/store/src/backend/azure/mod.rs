@@ -0,0 +1,240 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs Ltd <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::{
+    collections::HashMap,
+    ops::Range,
+    sync::{atomic::AtomicBool, Arc},
+    time::Duration,
+};
+
+use azure_core::{
+    request_options::{IfMatchCondition, Metadata},
+    ExponentialRetryOptions, RetryOptions, StatusCode, TransportOptions,
+};
+use azure_storage::{CloudLocation, StorageCredentials};
+use azure_storage_blobs::prelude::{ClientBuilder, ContainerClient};
+use futures::StreamExt;
+use utils::config::{utils::AsKey, Config};
+
+use crate::backend::{BlobKeyEncoding, BlobKeyMapping};
+
+pub struct AzureStore {
+    client: ContainerClient,
+    prefix: Option<String>,
+    key_encoding: BlobKeyEncoding,
+    pub(crate) read_only: AtomicBool,
+}
+
+impl AzureStore {
+    pub async fn open(config: &mut Config, prefix: impl AsKey) -> Option<Self> {
+        let prefix = prefix.as_key();
+        let account = config
+            .value_require((&prefix, "storage-account"))?
+            .to_string();
+        let container = config.value_require((&prefix, "container"))?.to_string();
+
+        // Use the account key if provided, otherwise a SAS token or managed identity
+        let credentials = if let Some(access_key) = config.value((&prefix, "access-key")) {
+            StorageCredentials::access_key(account.clone(), access_key.to_string())
+        } else if let Some(sas_token) = config.value((&prefix, "sas-token")) {
+            StorageCredentials::sas_token(sas_token)
+                .map_err(|err| {
+                    config.new_build_error(
+                        prefix.as_str(),
+                        format!("Failed to parse SAS token: {err}"),
+                    )
+                })
+                .ok()?
+        } else {
+            StorageCredentials::token_credential(
+                azure_identity::create_default_credential()
+                    .map_err(|err| {
+                        config.new_build_error(
+                            prefix.as_str(),
+                            format!("Failed to obtain managed identity credentials: {err}"),
+                        )
+                    })
+                    .ok()?,
+            )
+        };
+
+        let timeout = config
+            .property_or_default::<Duration>((&prefix, "timeout"), "30s")
+            .unwrap_or_else(|| Duration::from_secs(30));
+        let max_retries = config
+            .property_or_default::<u32>((&prefix, "max-retries"), "3")
+            .unwrap_or(3);
+
+        // The timeout is applied by the HTTP client to each request attempt
+        let http_client = reqwest::Client::builder()
+            .pool_max_idle_per_host(0)
+            .timeout(timeout)
+            .build()
+            .map_err(|err| {
+                config.new_build_error(
+                    prefix.as_str(),
+                    format!("Failed to build HTTP client: {err}"),
+                )
+            })
+            .ok()?;
+
+        // Custom endpoints are used by sovereign clouds and the Azurite emulator
+        let location = if let Some(uri) = config.value((&prefix, "endpoint")) {
+            CloudLocation::Custom {
+                account,
+                uri: uri.to_string(),
+            }
+        } else {
+            CloudLocation::Public { account }
+        };
+
+        Some(AzureStore {
+            client: ClientBuilder::with_location(location, credentials)
+                .retry(RetryOptions::exponential(
+                    ExponentialRetryOptions::default().max_retries(max_retries),
+                ))
+                .transport(TransportOptions::new(Arc::new(http_client)))
+                .container_client(container),
+            prefix: config.value((&prefix, "key-prefix")).map(|s| s.to_string()),
+            key_encoding: config
+                .property_or_default((&prefix, "key-encoding"), "base32")
+                .unwrap_or_default(),
+            read_only: AtomicBool::new(
+                config
+                    .property_or_default((&prefix, "read-only"), "false")
+                    .unwrap_or(false),
+            ),
+        })
+    }
+
+    pub(crate) async fn get_blob(
+        &self,
+        key: &[u8],
+        range: Range<usize>,
+    ) -> crate::Result<Option<Vec<u8>>> {
+        let request = self.client.blob_client(self.map_key(key)).get();
+        let mut stream = if range.start != 0 || range.end != usize::MAX {
+            request.range(range.start as u64..range.end as u64)
+        } else {
+            request
+        }
+        .into_stream();
+
+        let mut data = Vec::new();
+        while let Some(response) = stream.next().await {
+            match response {
+                Ok(response) => {
+                    data.extend_from_slice(&response.data.collect().await?);
+                }
+                Err(err) if is_not_found(&err) => return Ok(None),
+                Err(err) if is_range_not_satisfiable(&err) => return Ok(Some(Vec::new())),
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        Ok(Some(data))
+    }
+
+    pub(crate) async fn put_blob(&self, key: &[u8], data: &[u8]) -> crate::Result<()> {
+        self.client
+            .blob_client(self.map_key(key))
+            .put_block_blob(data.to_vec())
+            .await
+            .map(|_| ())
+            .map_err(Into::into)
+    }
+
+    pub(crate) async fn put_blob_if_absent(&self, key: &[u8], data: &[u8]) -> crate::Result<bool> {
+        match self
+            .client
+            .blob_client(self.map_key(key))
+            .put_block_blob(data.to_vec())
+            .if_match(IfMatchCondition::NotMatch("*".to_string()))
+            .await
+        {
+            Ok(_) => Ok(true),
+            Err(err) if is_conflict(&err) => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub(crate) async fn put_blob_with_meta(
+        &self,
+        key: &[u8],
+        data: &[u8],
+        meta: &HashMap<String, String>,
+    ) -> crate::Result<()> {
+        let mut metadata = Metadata::new();
+        for (name, value) in meta {
+            metadata.insert(name.clone(), value.clone());
+        }
+        self.client
+            .blob_client(self.map_key(key))
+            .put_block_blob(data.to_vec())
+            .metadata(metadata)
+            .await
+            .map(|_| ())
+            .map_err(Into::into)
+    }
+
+    pub(crate) async fn get_blob_meta(
+        &self,
+        key: &[u8],
+    ) -> crate::Result<Option<HashMap<String, String>>> {
+        match self
+            .client
+            .blob_client(self.map_key(key))
+            .get_properties()
+            .await
+        {
+            Ok(response) => Ok(Some(response.blob.metadata.unwrap_or_default())),
+            Err(err) if is_not_found(&err) => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub(crate) async fn delete_blob(&self, key: &[u8]) -> crate::Result<bool> {
+        match self.client.blob_client(self.map_key(key)).delete().await {
+            Ok(_) => Ok(true),
+            Err(err) if is_not_found(&err) => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+impl BlobKeyMapping for AzureStore {
+    fn map_key(&self, key: &[u8]) -> String {
+        self.key_encoding.encode(self.prefix.as_deref(), key)
+    }
+}
+
+fn is_not_found(err: &azure_core::Error) -> bool {
+    err.as_http_error()
+        .map_or(false, |err| err.status() == StatusCode::NotFound)
+}
+
+// Blobs written with `If-None-Match: *` fail with 409 when they exist
+fn is_conflict(err: &azure_core::Error) -> bool {
+    err.as_http_error().map_or(false, |err| {
+        matches!(
+            err.status(),
+            StatusCode::Conflict | StatusCode::PreconditionFailed
+        )
+    })
+}
+
+fn is_range_not_satisfiable(err: &azure_core::Error) -> bool {
+    err.as_http_error().map_or(false, |err| {
+        err.status() == StatusCode::RequestedRangeNotSatisfiable
+    })
+}
+
+impl From<azure_core::Error> for crate::Error {
+    fn from(err: azure_core::Error) -> Self {
+        Self::InternalError(format!("Azure error: {}", err))
+    }
+}
@@ -30,6 +30,7 @@ impl ElasticSearchStore {
             match filter {
                 FtsFilter::Exact { field, text, .. }
                 | FtsFilter::Contains { field, text, .. }
+                | FtsFilter::Address { field, text }
                 | FtsFilter::Keyword { field, text, .. } => {
                     let match_type = if is_exact { "term" } else { "match" };
 
@@ -10,114 +10,108 @@ use foundationdb::{options::StreamingMode, KeySelector, RangeOption};
 use futures::TryStreamExt;
 use utils::BLOB_HASH_LEN;
 
-use crate::{write::key::KeySerializer, SUBSPACE_BLOBS};
+use crate::{
+    write::{key::KeySerializer, now},
+    Deserialize, SUBSPACE_BLOBS,
+};
 
 use super::{FdbStore, MAX_VALUE_SIZE};
 
+// Chunks written per transaction are bounded by size, well below the 10MB limit
+const MAX_TRX_BLOB_SIZE: usize = ((1 << 5) - 1) * MAX_VALUE_SIZE;
+
+// Seconds after which a conditional write spanning several transactions that has
+// not completed is considered interrupted
+const MAX_PENDING_WRITE_TIME: u64 = 300;
+
 impl FdbStore {
     pub(crate) async fn get_blob(
         &self,
         key: &[u8],
         range: Range<usize>,
     ) -> crate::Result<Option<Vec<u8>>> {
-        let block_start = range.start / MAX_VALUE_SIZE;
-        let bytes_start = range.start % MAX_VALUE_SIZE;
-        let block_end = (range.end / MAX_VALUE_SIZE) + 1;
-
-        let begin = KeySerializer::new(key.len() + 3)
-            .write(SUBSPACE_BLOBS)
-            .write(key)
-            .write(block_start as u16)
-            .finalize();
-        let end = KeySerializer::new(key.len() + 3)
-            .write(SUBSPACE_BLOBS)
-            .write(key)
-            .write(block_end as u16)
-            .finalize();
-        let key_len = begin.len();
+        // Blobs keep the chunk size they were written with, which is the size
+        // of their first chunk
         let trx = self.read_trx().await?;
+        let first_chunk = if let Some(chunk) = trx.get(&chunk_key(key, 0), true).await? {
+            chunk
+        } else {
+            return Ok(None);
+        };
+        let chunk_size = first_chunk.len();
+        if range.end <= chunk_size || chunk_size == 0 {
+            return Ok(Some(
+                first_chunk
+                    .get(
+                        std::cmp::min(range.start, chunk_size)
+                            ..std::cmp::min(range.end, chunk_size),
+                    )
+                    .unwrap_or_default()
+                    .to_vec(),
+            ));
+        }
+
+        let blob_range = range.end.saturating_sub(range.start);
+        let mut blob_data = Vec::with_capacity(if blob_range <= (5 * (1 << 20)) {
+            blob_range
+        } else {
+            chunk_size * 2
+        });
+        if range.start < chunk_size {
+            blob_data.extend_from_slice(&first_chunk[range.start..]);
+        }
+
+        let block_start = std::cmp::max(range.start / chunk_size, 1);
+        let block_end = std::cmp::min(
+            (range.end / chunk_size).saturating_add(1),
+            u16::MAX as usize,
+        );
+        let mut bytes_start = range.start.saturating_sub(block_start * chunk_size);
+        let begin = chunk_key(key, block_start as u16);
+        let key_len = begin.len();
         let mut values = trx.get_ranges_keyvalues(
             RangeOption {
                 begin: KeySelector::first_greater_or_equal(begin),
-                end: KeySelector::first_greater_or_equal(end),
+                end: KeySelector::first_greater_or_equal(chunk_key(key, block_end as u16)),
                 mode: StreamingMode::WantAll,
                 reverse: false,
                 ..RangeOption::default()
             },
             true,
         );
-        let mut blob_data: Option<Vec<u8>> = None;
-        let blob_range = range.end - range.start;
-
-        'outer: while let Some(value) = values.try_next().await? {
-            let key = value.key();
-            if key.len() == key_len {
-                let value = value.value();
-                if let Some(blob_data) = &mut blob_data {
-                    blob_data.extend_from_slice(
-                        value
-                            .get(
-                                ..std::cmp::min(
-                                    blob_range.saturating_sub(blob_data.len()),
-                                    value.len(),
-                                ),
-                            )
-                            .unwrap_or(&[]),
-                    );
-                    if blob_data.len() == blob_range {
-                        break 'outer;
-                    }
-                } else {
-                    let blob_size = if blob_range <= (5 * (1 << 20)) {
-                        blob_range
-                    } else if value.len() == MAX_VALUE_SIZE {
-                        MAX_VALUE_SIZE * 2
-                    } else {
-                        value.len()
-                    };
-                    let mut blob_data_ = Vec::with_capacity(blob_size);
-                    blob_data_.extend_from_slice(
-                        value
-                            .get(bytes_start..std::cmp::min(bytes_start + blob_range, value.len()))
-                            .unwrap_or(&[]),
-                    );
-                    if blob_data_.len() == blob_range {
-                        return Ok(Some(blob_data_));
-                    }
-                    blob_data = blob_data_.into();
+
+        while let Some(value) = values.try_next().await? {
+            if value.key().len() == key_len {
+                let value = value.value().get(bytes_start..).unwrap_or_default();
+                bytes_start = 0;
+                blob_data.extend_from_slice(
+                    &value[..std::cmp::min(blob_range - blob_data.len(), value.len())],
+                );
+                if blob_data.len() == blob_range {
+                    break;
                 }
             }
         }
 
-        Ok(blob_data)
+        Ok(Some(blob_data))
     }
 
     pub(crate) async fn put_blob(&self, key: &[u8], data: &[u8]) -> crate::Result<()> {
-        const N_CHUNKS: usize = (1 << 5) - 1;
-        let last_chunk = std::cmp::max(
-            (data.len() / MAX_VALUE_SIZE)
-                + if data.len() % MAX_VALUE_SIZE > 0 {
-                    1
-                } else {
-                    0
-                },
-            1,
-        ) - 1;
-        let mut trx = self.db.create_trx()?;
-
-        for (chunk_pos, chunk_bytes) in data.chunks(MAX_VALUE_SIZE).enumerate() {
-            trx.set(
-                &KeySerializer::new(key.len() + 3)
-                    .write(SUBSPACE_BLOBS)
-                    .write(key)
-                    .write(chunk_pos as u16)
-                    .finalize(),
-                chunk_bytes,
-            );
-            if chunk_pos == last_chunk || (chunk_pos > 0 && chunk_pos % N_CHUNKS == 0) {
+        self.check_blob_size(data)?;
+        let chunks_per_trx = std::cmp::max(MAX_TRX_BLOB_SIZE / self.blob_chunk_size, 1);
+        let last_chunk = std::cmp::max(data.len().div_ceil(self.blob_chunk_size), 1) - 1;
+        let mut trx = self.create_trx()?;
+
+        // Chunks of a previous copy written with a smaller chunk size would
+        // otherwise be read as part of this one
+        trx.clear_range(&chunk_key(key, 0), &chunk_key(key, u16::MAX));
+
+        for (chunk_pos, chunk_bytes) in data.chunks(self.blob_chunk_size).enumerate() {
+            trx.set(&chunk_key(key, chunk_pos as u16), chunk_bytes);
+            if chunk_pos == last_chunk || (chunk_pos > 0 && chunk_pos % chunks_per_trx == 0) {
                 self.commit(trx, false).await?;
                 if chunk_pos < last_chunk {
-                    trx = self.db.create_trx()?;
+                    trx = self.create_trx()?;
                 } else {
                     break;
                 }
@@ -127,25 +121,107 @@ impl FdbStore {
         Ok(())
     }
 
+    /// Writes a blob unless its first chunk exists, returning whether it was
+    /// written. The check commits in the same transaction as the first chunks,
+    /// so only one of several concurrent writers succeeds. Blobs larger than a
+    /// transaction are completed in further transactions, as with `put_blob`,
+    /// under a pending marker removed by the last one. A blob whose marker
+    /// outlived `MAX_PENDING_WRITE_TIME` was left incomplete and is written again.
+    pub(crate) async fn put_blob_if_absent(&self, key: &[u8], data: &[u8]) -> crate::Result<bool> {
+        self.check_blob_size(data)?;
+        let chunks_per_trx = std::cmp::max(MAX_TRX_BLOB_SIZE / self.blob_chunk_size, 1);
+        let mut chunks = data
+            .chunks(self.blob_chunk_size)
+            .enumerate()
+            .collect::<Vec<_>>();
+        if chunks.is_empty() {
+            chunks.push((0, data));
+        }
+        let mut batches = chunks.chunks(chunks_per_trx).peekable();
+        let first_batch = batches.next().unwrap_or_default();
+        let pending_key = pending_key(key);
+
+        loop {
+            let trx = self.create_trx()?;
+            if trx.get(&chunk_key(key, 0), false).await?.is_some() {
+                match trx.get(&pending_key, false).await? {
+                    Some(until) if u64::deserialize(&until)? <= now() => {}
+                    _ => return Ok(false),
+                }
+            }
+
+            // Remove chunks left behind by an interrupted write
+            trx.clear_range(&chunk_key(key, 0), &chunk_key(key, u16::MAX));
+            for (chunk_pos, chunk_bytes) in first_batch {
+                trx.set(&chunk_key(key, *chunk_pos as u16), chunk_bytes);
+            }
+            if batches.peek().is_some() {
+                trx.set(
+                    &pending_key,
+                    &(now() + MAX_PENDING_WRITE_TIME).to_be_bytes(),
+                );
+            }
+
+            if self.commit(trx, true).await? {
+                break;
+            }
+        }
+
+        while let Some(batch) = batches.next() {
+            let trx = self.create_trx()?;
+            for (chunk_pos, chunk_bytes) in batch {
+                trx.set(&chunk_key(key, *chunk_pos as u16), chunk_bytes);
+            }
+            if batches.peek().is_none() {
+                trx.clear(&pending_key);
+            }
+            self.commit(trx, false).await?;
+        }
+
+        Ok(true)
+    }
+
     pub(crate) async fn delete_blob(&self, key: &[u8]) -> crate::Result<bool> {
         if key.len() < BLOB_HASH_LEN {
             return Ok(false);
         }
 
-        let trx = self.db.create_trx()?;
-        trx.clear_range(
-            &KeySerializer::new(key.len() + 3)
-                .write(SUBSPACE_BLOBS)
-                .write(key)
-                .write(0u16)
-                .finalize(),
-            &KeySerializer::new(key.len() + 3)
-                .write(SUBSPACE_BLOBS)
-                .write(key)
-                .write(u16::MAX)
-                .finalize(),
-        );
+        let trx = self.create_trx()?;
+        trx.clear_range(&chunk_key(key, 0), &chunk_key(key, u16::MAX));
 
         self.commit(trx, false).await
     }
+
+    // Chunk positions are stored as u16, the last one is the end of the range
+    // cleared when a blob is replaced or deleted
+    fn check_blob_size(&self, data: &[u8]) -> crate::Result<()> {
+        let max_size = (u16::MAX as usize).saturating_mul(self.blob_chunk_size);
+        if data.len() <= max_size {
+            Ok(())
+        } else {
+            Err(crate::Error::ValueTooLarge {
+                size: data.len(),
+                max_size,
+            })
+        }
+    }
+}
+
+// Sorts right after the first chunk, within the range cleared with the blob, and
+// is skipped when reading chunks as its length differs
+fn pending_key(key: &[u8]) -> Vec<u8> {
+    KeySerializer::new(key.len() + 4)
+        .write(SUBSPACE_BLOBS)
+        .write(key)
+        .write(0u16)
+        .write(u8::MAX)
+        .finalize()
+}
+
+fn chunk_key(key: &[u8], chunk_pos: u16) -> Vec<u8> {
+    KeySerializer::new(key.len() + 3)
+        .write(SUBSPACE_BLOBS)
+        .write(key)
+        .write(chunk_pos)
+        .finalize()
 }
@@ -0,0 +1,138 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs Ltd <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::{
+    sync::atomic::{AtomicU32, AtomicU64, Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use foundationdb::FdbError;
+
+use super::FdbStore;
+
+// FoundationDB error codes returned when the cluster can't be reached
+const FDB_TIMED_OUT: i32 = 1004;
+const FDB_TRANSACTION_TIMED_OUT: i32 = 1031;
+const FDB_DATABASE_LOCKED: i32 = 1038;
+
+/// Tracks consecutive "cluster unavailable" errors. Once `threshold` is reached
+/// the circuit opens and operations fail immediately for `cooldown`, after which
+/// a single operation is let through to probe whether the cluster has recovered.
+pub(crate) struct CircuitBreaker {
+    failures: AtomicU32,
+    open_until: AtomicU64,
+    threshold: u32,
+    cooldown: Duration,
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold: u32, cooldown: Duration) -> Self {
+        CircuitBreaker {
+            failures: AtomicU32::new(0),
+            open_until: AtomicU64::new(0),
+            threshold: std::cmp::max(threshold, 1),
+            cooldown,
+        }
+    }
+
+    pub fn check(&self) -> crate::Result<()> {
+        let open_until = self.open_until.load(Ordering::Acquire);
+        if open_until == 0 {
+            return Ok(());
+        }
+
+        let now = now_ms();
+        if now < open_until {
+            Err(crate::Error::Unavailable(
+                "FoundationDB cluster is unavailable".to_string(),
+            ))
+        } else if self
+            .open_until
+            .compare_exchange(
+                open_until,
+                now + self.cooldown.as_millis() as u64,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            )
+            .is_ok()
+        {
+            // Let this request through to probe the cluster
+            Ok(())
+        } else {
+            Err(crate::Error::Unavailable(
+                "FoundationDB cluster is unavailable".to_string(),
+            ))
+        }
+    }
+
+    pub fn success(&self) {
+        if self.failures.swap(0, Ordering::AcqRel) > 0
+            && self.open_until.swap(0, Ordering::AcqRel) != 0
+        {
+            tracing::info!(
+                context = "foundationdb",
+                event = "recovered",
+                "FoundationDB cluster is available again."
+            );
+        }
+    }
+
+    pub fn failure(&self, err: FdbError) -> crate::Error {
+        if is_unavailable(&err) {
+            let failures = self.failures.fetch_add(1, Ordering::AcqRel) + 1;
+            if failures >= self.threshold
+                && self
+                    .open_until
+                    .compare_exchange(
+                        0,
+                        now_ms() + self.cooldown.as_millis() as u64,
+                        Ordering::AcqRel,
+                        Ordering::Acquire,
+                    )
+                    .is_ok()
+            {
+                tracing::warn!(
+                    context = "foundationdb",
+                    event = "unavailable",
+                    failures = failures,
+                    "FoundationDB cluster is unavailable, failing fast for {:?}.",
+                    self.cooldown
+                );
+            }
+
+            crate::Error::Unavailable(format!("FoundationDB error: {}", err.message()))
+        } else {
+            err.into()
+        }
+    }
+}
+
+impl FdbStore {
+    pub(crate) async fn health_check(&self) -> crate::Result<()> {
+        // Bypass the circuit breaker, a successful read closes it
+        let trx = self.db.create_trx()?;
+        match trx.get_read_version().await {
+            Ok(_) => {
+                self.health.success();
+                Ok(())
+            }
+            Err(err) => Err(self.health.failure(err)),
+        }
+    }
+}
+
+fn is_unavailable(err: &FdbError) -> bool {
+    matches!(
+        err.code(),
+        FDB_TIMED_OUT | FDB_TRANSACTION_TIMED_OUT | FDB_DATABASE_LOCKED
+    )
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
@@ -4,12 +4,21 @@
  * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
  */
 
-use std::time::Duration;
+use std::{sync::atomic::AtomicBool, time::Duration};
 
 use foundationdb::{api, options::DatabaseOption, Database};
 use utils::config::{utils::AsKey, Config};
 
-use super::FdbStore;
+use crate::{
+    fts::TokenLimits,
+    write::{
+        cardinality::CardinalityCache,
+        queue::{WriteQueue, DEFAULT_WRITE_QUEUE_DEPTH},
+    },
+    DEFAULT_MAX_VALUE_SIZE,
+};
+
+use super::{health::CircuitBreaker, FdbStore, MAX_VALUE_SIZE, MIN_BLOB_CHUNK_SIZE};
 
 impl FdbStore {
     pub async fn open(config: &mut Config, prefix: impl AsKey) -> Option<Self> {
@@ -102,10 +111,51 @@ impl FdbStore {
                 .ok()?;
         }
 
+        let health = CircuitBreaker::new(
+            config
+                .property_or_default((&prefix, "circuit-breaker.threshold"), "3")
+                .unwrap_or(3),
+            config
+                .property_or_default::<Duration>((&prefix, "circuit-breaker.cooldown"), "30s")
+                .unwrap_or_else(|| Duration::from_secs(30)),
+        );
+
+        let blob_chunk_size = config
+            .property_or_default((&prefix, "blob.chunk-size"), "100000")
+            .unwrap_or(MAX_VALUE_SIZE);
+        let blob_chunk_size = if (MIN_BLOB_CHUNK_SIZE..=MAX_VALUE_SIZE).contains(&blob_chunk_size) {
+            blob_chunk_size
+        } else {
+            config.new_parse_error(
+                (&prefix, "blob.chunk-size"),
+                format!(
+                    "Blob chunk size must be between {MIN_BLOB_CHUNK_SIZE} and {MAX_VALUE_SIZE} bytes"
+                ),
+            );
+            MAX_VALUE_SIZE
+        };
+
         Some(Self {
             guard,
             db,
             version: Default::default(),
+            health,
+            max_value_size: config
+                .property_or_default((&prefix, "max-value-size"), "1048576")
+                .unwrap_or(DEFAULT_MAX_VALUE_SIZE),
+            blob_chunk_size,
+            read_only: AtomicBool::new(
+                config
+                    .property_or_default((&prefix, "read-only"), "false")
+                    .unwrap_or(false),
+            ),
+            write_queue: WriteQueue::new(
+                config
+                    .property_or_default((&prefix, "write-queue.depth"), "1024")
+                    .unwrap_or(DEFAULT_WRITE_QUEUE_DEPTH),
+            ),
+            cardinality_cache: CardinalityCache::parse(config, &prefix, 0),
+            token_limits: TokenLimits::parse(config, &prefix),
         })
     }
 }
@@ -4,18 +4,31 @@
  * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
  */
 
-use std::time::{Duration, Instant};
+use std::{
+    sync::atomic::AtomicBool,
+    time::{Duration, Instant},
+};
 
 use foundationdb::{api::NetworkAutoStop, Database, FdbError, Transaction};
 
-use crate::Error;
+use crate::{
+    fts::TokenLimits,
+    write::{cardinality::CardinalityCache, queue::WriteQueue},
+    Error,
+};
+
+use self::health::CircuitBreaker;
 
 pub mod blob;
+pub mod health;
 pub mod main;
 pub mod read;
 pub mod write;
 
 const MAX_VALUE_SIZE: usize = 100000;
+// Smallest blob chunk size accepted in `blob.chunk-size`, blobs are limited to
+// `u16::MAX` chunks
+const MIN_BLOB_CHUNK_SIZE: usize = 1024;
 pub const TRANSACTION_EXPIRY: Duration = Duration::from_secs(1);
 pub const TRANSACTION_TIMEOUT: Duration = Duration::from_secs(4);
 
@@ -24,6 +37,13 @@ pub struct FdbStore {
     db: Database,
     guard: NetworkAutoStop,
     version: parking_lot::Mutex<ReadVersion>,
+    health: CircuitBreaker,
+    pub(crate) max_value_size: usize,
+    pub(crate) blob_chunk_size: usize,
+    pub(crate) read_only: AtomicBool,
+    pub(crate) write_queue: WriteQueue,
+    pub(crate) cardinality_cache: CardinalityCache,
+    pub(crate) token_limits: TokenLimits,
 }
 
 pub(crate) struct TimedTransaction {
@@ -77,8 +97,20 @@ impl TimedTransaction {
     }
 }
 
+impl FdbStore {
+    pub(crate) fn create_trx(&self) -> crate::Result<Transaction> {
+        self.health.check()?;
+        self.db.create_trx().map_err(|err| self.health.failure(err))
+    }
+}
+
 impl From<FdbError> for Error {
     fn from(error: FdbError) -> Self {
-        Self::InternalError(format!("FoundationDB error: {}", error.message()))
+        let msg = format!("FoundationDB error: {}", error.message());
+        if error.is_retryable() {
+            Self::Unavailable(msg)
+        } else {
+            Self::InternalError(msg)
+        }
     }
 }
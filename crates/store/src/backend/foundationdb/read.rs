@@ -165,10 +165,14 @@ impl FdbStore {
             let version = self.version.lock();
             (version.is_expired(), version.version)
         };
-        let trx = self.db.create_trx()?;
+        let trx = self.create_trx()?;
 
         if is_expired {
-            read_version = trx.get_read_version().await?;
+            read_version = trx
+                .get_read_version()
+                .await
+                .map_err(|err| self.health.failure(err))?;
+            self.health.success();
             *self.version.lock() = ReadVersion::new(read_version);
         } else {
             trx.set_read_version(read_version);
@@ -178,10 +182,7 @@ impl FdbStore {
     }
 
     pub(crate) async fn timed_read_trx(&self) -> crate::Result<TimedTransaction> {
-        self.db
-            .create_trx()
-            .map_err(Into::into)
-            .map(TimedTransaction::new)
+        self.create_trx().map(TimedTransaction::new)
     }
 }
 
@@ -4,25 +4,21 @@
  * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
  */
 
-use std::{
-    cmp::Ordering,
-    time::{Duration, Instant},
-};
+use std::{cmp::Ordering, time::Instant};
 
 use foundationdb::{
     options::{self, MutationType, StreamingMode},
     FdbError, KeySelector, RangeOption, Transaction,
 };
 use futures::TryStreamExt;
-use rand::Rng;
 use roaring::RoaringBitmap;
 
 use crate::{
     backend::deserialize_i64_le,
     write::{
         key::{DeserializeBigEndian, KeySerializer},
-        AssignedIds, Batch, BitmapClass, Operation, RandomAvailableId, ValueOp,
-        MAX_COMMIT_ATTEMPTS, MAX_COMMIT_TIME,
+        AssignedIds, AvailableId, Batch, BitmapClass, IdAllocator, Isolation, Operation,
+        ValueClass, ValueOp, MAX_COMMIT_ATTEMPTS,
     },
     BitmapKey, IndexKey, Key, LogKey, SUBSPACE_COUNTER, SUBSPACE_QUOTA, U32_LEN, WITH_SUBSPACE,
 };
@@ -36,6 +32,7 @@ impl FdbStore {
     pub(crate) async fn write(&self, batch: Batch) -> crate::Result<AssignedIds> {
         let start = Instant::now();
         let mut retry_count = 0;
+        let snapshot = batch.isolation == Isolation::Snapshot;
 
         loop {
             let mut account_id = u32::MAX;
@@ -44,7 +41,7 @@ impl FdbStore {
             let mut change_id = u64::MAX;
             let mut result = AssignedIds::default();
 
-            let trx = self.db.create_trx()?;
+            let trx = self.create_trx()?;
 
             for op in &batch.ops {
                 match op {
@@ -109,6 +106,8 @@ impl FdbStore {
                                 trx.atomic_op(&key, &by.to_le_bytes()[..], MutationType::Add);
                             }
                             ValueOp::AddAndGet(by) => {
+                                // Read without snapshot isolation so that concurrent
+                                // increments conflict rather than being lost
                                 let num = if let Some(bytes) = trx.get(&key, false).await? {
                                     deserialize_i64_le(&bytes)? + *by
                                 } else {
@@ -117,6 +116,23 @@ impl FdbStore {
                                 trx.set(&key, &num.to_le_bytes()[..]);
                                 result.push_counter_id(num);
                             }
+                            ValueOp::AddWithLimit { by, limit } => {
+                                // Read without snapshot isolation so that concurrent
+                                // writes can't both stay under the limit
+                                let used = if let Some(bytes) = trx.get(&key, false).await? {
+                                    deserialize_i64_le(&bytes)? + *by
+                                } else {
+                                    *by
+                                };
+                                if *by > 0 && used > *limit {
+                                    trx.cancel();
+                                    return Err(crate::Error::QuotaExceeded {
+                                        used: used as u64,
+                                        limit: *limit as u64,
+                                    });
+                                }
+                                trx.set(&key, &used.to_le_bytes()[..]);
+                            }
                             ValueOp::Clear => {
                                 if do_chunk {
                                     trx.clear_range(
@@ -188,7 +204,31 @@ impl FdbStore {
                                     break;
                                 }
                             }
-                            document_id = found_ids.random_available_id();
+                            document_id = match batch.id_allocator {
+                                IdAllocator::MonotonicNeverReuse => {
+                                    // Always read the counter with conflicts so that
+                                    // concurrent batches never share an id
+                                    let key = ValueClass::<u32>::DocumentIdCounter.serialize(
+                                        account_id,
+                                        collection,
+                                        0,
+                                        WITH_SUBSPACE,
+                                        None,
+                                    );
+                                    let counter = if let Some(bytes) = trx.get(&key, false).await? {
+                                        deserialize_i64_le(&bytes)?
+                                    } else {
+                                        0
+                                    };
+                                    let id = std::cmp::max(
+                                        counter,
+                                        found_ids.next_available_id() as i64,
+                                    );
+                                    trx.set(&key, &(id + 1).to_le_bytes()[..]);
+                                    id as u32
+                                }
+                                IdAllocator::ReuseFreed => found_ids.lowest_available_id(),
+                            };
                             result.push_document_id(document_id);
                         }
 
@@ -241,7 +281,7 @@ impl FdbStore {
                             (&result).into(),
                         );
 
-                        let matches = match read_chunked_value(&key, &trx, false).await {
+                        let matches = match read_chunked_value(&key, &trx, snapshot).await {
                             Ok(ChunkedValue::Single(bytes)) => assert_value.matches(bytes.as_ref()),
                             Ok(ChunkedValue::Chunked { bytes, .. }) => {
                                 assert_value.matches(bytes.as_ref())
@@ -259,16 +299,12 @@ impl FdbStore {
             }
 
             if self
-                .commit(
-                    trx,
-                    retry_count < MAX_COMMIT_ATTEMPTS && start.elapsed() < MAX_COMMIT_TIME,
-                )
+                .commit(trx, batch.retry.can_retry(retry_count, start.elapsed()))
                 .await?
             {
                 return Ok(result);
             } else {
-                let backoff = rand::thread_rng().gen_range(50..=300);
-                tokio::time::sleep(Duration::from_millis(backoff)).await;
+                tokio::time::sleep(batch.retry.delay(retry_count)).await;
                 retry_count += 1;
             }
         }
@@ -282,14 +318,17 @@ impl FdbStore {
                 if commit_version > version.version {
                     *version = ReadVersion::new(commit_version);
                 }
+                self.health.success();
                 Ok(true)
             }
             Err(err) => {
                 if will_retry {
-                    err.on_error().await?;
+                    err.on_error()
+                        .await
+                        .map_err(|err| self.health.failure(err))?;
                     Ok(false)
                 } else {
-                    Err(FdbError::from(err).into())
+                    Err(self.health.failure(FdbError::from(err)))
                 }
             }
         }
@@ -299,7 +338,7 @@ impl FdbStore {
         // Obtain all zero counters
         let mut delete_keys = Vec::new();
         for subspace in [SUBSPACE_COUNTER, SUBSPACE_QUOTA] {
-            let trx = self.db.create_trx()?;
+            let trx = self.create_trx()?;
             let from_key = [subspace, 0u8];
             let to_key = [subspace, u8::MAX, u8::MAX, u8::MAX, u8::MAX, u8::MAX];
 
@@ -330,7 +369,7 @@ impl FdbStore {
         for chunk in delete_keys.chunks(1024) {
             let mut retry_count = 0;
             loop {
-                let trx = self.db.create_trx()?;
+                let trx = self.create_trx()?;
                 for key in chunk {
                     trx.atomic_op(key, &integer, MutationType::CompareAndClear);
                 }
@@ -350,7 +389,7 @@ impl FdbStore {
         let from = from.serialize(WITH_SUBSPACE);
         let to = to.serialize(WITH_SUBSPACE);
 
-        let trx = self.db.create_trx()?;
+        let trx = self.create_trx()?;
         trx.clear_range(&from, &to);
         self.commit(trx, false).await.map(|_| ())
     }
@@ -4,20 +4,34 @@
  * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
  */
 
-use std::{io::SeekFrom, ops::Range, path::PathBuf};
+use std::{
+    io::SeekFrom,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::atomic::AtomicBool,
+};
 
 use tokio::{
     fs::{self, File},
     io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
 };
-use utils::{
-    codec::base32_custom::Base32Writer,
-    config::{utils::AsKey, Config},
-};
+use utils::config::{utils::AsKey, Config};
+
+use crate::backend::{BlobKeyEncoding, BlobKeyMapping};
 
 pub struct FsStore {
     path: PathBuf,
     hash_levels: usize,
+    sync: bool,
+    key_encoding: BlobKeyEncoding,
+    pub(crate) read_only: AtomicBool,
+}
+
+pub(crate) struct FsBlobWriter {
+    file: File,
+    temp_path: Option<PathBuf>,
+    blob_path: PathBuf,
+    sync: bool,
 }
 
 impl FsStore {
@@ -44,6 +58,17 @@ impl FsStore {
                     .unwrap_or(2),
                 5,
             ),
+            sync: config
+                .property_or_default((&prefix, "sync"), "false")
+                .unwrap_or(false),
+            key_encoding: config
+                .property_or_default((&prefix, "key-encoding"), "base32")
+                .unwrap_or_default(),
+            read_only: AtomicBool::new(
+                config
+                    .property_or_default((&prefix, "read-only"), "false")
+                    .unwrap_or(false),
+            ),
         })
     }
 
@@ -59,12 +84,10 @@ impl FsStore {
         };
         let mut blob = File::open(&blob_path).await?;
 
-        Ok(Some(if range.start != 0 || range.end != usize::MAX {
-            let from_offset = if range.start < blob_size {
-                range.start
-            } else {
-                0
-            };
+        Ok(Some(if range.start >= blob_size && range.start != 0 {
+            Vec::new()
+        } else if range.start != 0 || range.end != usize::MAX {
+            let from_offset = range.start;
             let mut buf = vec![0; (std::cmp::min(range.end, blob_size) - from_offset) as usize];
 
             if from_offset > 0 {
@@ -86,32 +109,239 @@ impl FsStore {
             .await
             .map_or(true, |m| m.len() as usize != data.len())
         {
-            fs::create_dir_all(blob_path.parent().unwrap()).await?;
-            let mut blob_file = File::create(&blob_path).await?;
+            let temp_path = self.write_temp(&blob_path, data).await?;
+            if let Err(err) = fs::rename(&temp_path, &blob_path).await {
+                let _ = fs::remove_file(&temp_path).await;
+                return Err(err.into());
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(crate) async fn put_blob_if_absent(&self, key: &[u8], data: &[u8]) -> crate::Result<bool> {
+        let blob_path = self.build_path(key);
+        if fs::metadata(&blob_path).await.is_ok() {
+            return Ok(false);
+        }
+
+        // Unlike a rename, linking fails if the destination exists
+        let temp_path = self.write_temp(&blob_path, data).await?;
+        let result = fs::hard_link(&temp_path, &blob_path).await;
+        let _ = fs::remove_file(&temp_path).await;
+        match result {
+            Ok(_) => Ok(true),
+            Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub(crate) async fn has_blob(&self, key: &[u8]) -> crate::Result<bool> {
+        match fs::metadata(self.build_path(key)).await {
+            Ok(_) => Ok(true),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub(crate) async fn put_blob_meta(&self, key: &[u8], meta: &[u8]) -> crate::Result<()> {
+        let meta_path = self.build_path(key).with_extension("meta");
+        let temp_path = self.write_temp(&meta_path, meta).await?;
+        if let Err(err) = fs::rename(&temp_path, &meta_path).await {
+            let _ = fs::remove_file(&temp_path).await;
+            return Err(err.into());
+        }
+
+        Ok(())
+    }
+
+    pub(crate) async fn get_blob_meta(&self, key: &[u8]) -> crate::Result<Option<Vec<u8>>> {
+        match fs::read(self.build_path(key).with_extension("meta")).await {
+            Ok(meta) => Ok(Some(meta)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Creates a temporary file the blob is streamed into, which replaces any blob
+    /// stored under `key` once the writer is finished.
+    pub(crate) async fn create_writer(&self, key: &[u8]) -> crate::Result<FsBlobWriter> {
+        let blob_path = self.build_path(key);
+        let (file, temp_path) = self.create_temp(&blob_path).await?;
+        Ok(FsBlobWriter {
+            file,
+            temp_path: Some(temp_path),
+            blob_path,
+            sync: self.sync,
+        })
+    }
+
+    // Write to a temporary file first so readers never see a partial blob
+    async fn write_temp(&self, blob_path: &Path, data: &[u8]) -> crate::Result<PathBuf> {
+        let (mut blob_file, temp_path) = self.create_temp(blob_path).await?;
+        let result = async {
             blob_file.write_all(data).await?;
             blob_file.flush().await?;
+            if self.sync {
+                blob_file.sync_all().await?;
+            }
+            Ok::<_, std::io::Error>(())
         }
+        .await;
 
-        Ok(())
+        match result {
+            Ok(_) => Ok(temp_path),
+            Err(err) => {
+                let _ = fs::remove_file(&temp_path).await;
+                Err(err.into())
+            }
+        }
+    }
+
+    async fn create_temp(&self, blob_path: &Path) -> crate::Result<(File, PathBuf)> {
+        fs::create_dir_all(blob_path.parent().unwrap()).await?;
+
+        let temp_path = blob_path.with_extension(format!("{:x}.tmp", rand::random::<u64>()));
+        match File::create(&temp_path).await {
+            Ok(file) => Ok((file, temp_path)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                // The directory was pruned by a concurrent delete
+                fs::create_dir_all(blob_path.parent().unwrap()).await?;
+                Ok((File::create(&temp_path).await?, temp_path))
+            }
+            Err(err) => Err(err.into()),
+        }
     }
 
     pub(crate) async fn delete_blob(&self, key: &[u8]) -> crate::Result<bool> {
         let blob_path = self.build_path(key);
         if fs::metadata(&blob_path).await.is_ok() {
             fs::remove_file(&blob_path).await?;
+            let _ = fs::remove_file(blob_path.with_extension("meta")).await;
+
+            // Remove empty hash directories, removal fails on non-empty ones
+            let mut dir = blob_path.parent();
+            for _ in 0..self.hash_levels {
+                match dir {
+                    Some(path) if fs::remove_dir(path).await.is_ok() => {
+                        dir = path.parent();
+                    }
+                    _ => break,
+                }
+            }
+
             Ok(true)
         } else {
             Ok(false)
         }
     }
 
+    /// Returns a memory-mapped view of the blob, mapping only the requested range.
+    /// Repeated reads are served from the page cache without copying into a buffer.
+    #[cfg(feature = "fs-mmap")]
+    pub(crate) async fn get_blob_mapped(
+        &self,
+        key: &[u8],
+        range: Range<usize>,
+    ) -> crate::Result<Option<MappedBlob>> {
+        let blob_path = self.build_path(key);
+
+        tokio::task::spawn_blocking(move || {
+            let blob = match std::fs::File::open(&blob_path) {
+                Ok(blob) => blob,
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+                Err(err) => return Err(err.into()),
+            };
+            let blob_size = blob.metadata()?.len() as usize;
+            let end = std::cmp::min(range.end, blob_size);
+            if range.start >= end {
+                return Ok(Some(MappedBlob::Owned(Vec::new())));
+            }
+
+            // Blobs are written to a temporary file and renamed into place and are never
+            // modified afterwards. Deleting a blob unlinks it while existing mappings keep
+            // the file alive, so a mapped region can not be truncated by this store.
+            unsafe {
+                memmap2::MmapOptions::new()
+                    .offset(range.start as u64)
+                    .len(end - range.start)
+                    .map(&blob)
+            }
+            .map(|mmap| Some(MappedBlob::Mapped(mmap)))
+            .map_err(Into::into)
+        })
+        .await
+        .map_err(|err| crate::Error::InternalError(format!("Failed to map blob: {err}")))?
+    }
+
     fn build_path(&self, key: &[u8]) -> PathBuf {
         let mut path = self.path.clone();
 
         for byte in key.iter().take(self.hash_levels) {
             path.push(format!("{:x}", byte));
         }
-        path.push(Base32Writer::from_bytes(key).finalize());
+        path.push(self.map_key(key));
         path
     }
 }
+
+#[cfg(feature = "fs-mmap")]
+pub enum MappedBlob {
+    Mapped(memmap2::Mmap),
+    Owned(Vec<u8>),
+}
+
+#[cfg(feature = "fs-mmap")]
+impl std::ops::Deref for MappedBlob {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            MappedBlob::Mapped(mmap) => mmap,
+            MappedBlob::Owned(data) => data,
+        }
+    }
+}
+
+#[cfg(feature = "fs-mmap")]
+impl AsRef<[u8]> for MappedBlob {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl BlobKeyMapping for FsStore {
+    fn map_key(&self, key: &[u8]) -> String {
+        self.key_encoding.encode(None, key)
+    }
+}
+
+impl FsBlobWriter {
+    pub(crate) async fn append(&mut self, chunk: &[u8]) -> crate::Result<()> {
+        self.file.write_all(chunk).await.map_err(Into::into)
+    }
+
+    pub(crate) async fn finish(mut self) -> crate::Result<()> {
+        self.file.flush().await?;
+        if self.sync {
+            self.file.sync_all().await?;
+        }
+        if let Some(temp_path) = self.temp_path.take() {
+            if let Err(err) = fs::rename(&temp_path, &self.blob_path).await {
+                let _ = fs::remove_file(&temp_path).await;
+                return Err(err.into());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for FsBlobWriter {
+    fn drop(&mut self) {
+        // Discard blobs that were never finished
+        if let Some(temp_path) = self.temp_path.take() {
+            let _ = std::fs::remove_file(temp_path);
+        }
+    }
+}
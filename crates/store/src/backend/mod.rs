@@ -4,6 +4,8 @@
  * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
  */
 
+#[cfg(feature = "azure")]
+pub mod azure;
 #[cfg(feature = "elastic")]
 pub mod elastic;
 #[cfg(feature = "foundation")]
@@ -16,19 +18,176 @@ pub mod mysql;
 pub mod postgres;
 #[cfg(feature = "redis")]
 pub mod redis;
+pub mod replicated;
 #[cfg(feature = "rocks")]
 pub mod rocksdb;
 #[cfg(feature = "s3")]
 pub mod s3;
 #[cfg(feature = "sqlite")]
 pub mod sqlite;
+pub mod tiered;
+
+use std::{fmt::Write, time::Duration};
+
+#[cfg(any(feature = "sqlite", feature = "rocks"))]
+use std::sync::mpsc::{self, RecvTimeoutError};
+
+use utils::{
+    codec::base32_custom::Base32Writer,
+    config::{
+        utils::{AsKey, ParseValue},
+        Config,
+    },
+};
 
 pub const MAX_TOKEN_LENGTH: usize = (u8::MAX >> 1) as usize;
 pub const MAX_TOKEN_MASK: usize = MAX_TOKEN_LENGTH - 1;
 
 impl From<std::io::Error> for crate::Error {
     fn from(err: std::io::Error) -> Self {
-        Self::InternalError(format!("IO error: {}", err))
+        use std::io::ErrorKind;
+
+        let msg = format!("IO error: {}", err);
+        match err.kind() {
+            ErrorKind::NotFound => Self::NotFound(msg),
+            ErrorKind::TimedOut
+            | ErrorKind::Interrupted
+            | ErrorKind::WouldBlock
+            | ErrorKind::ConnectionRefused
+            | ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::NotConnected
+            | ErrorKind::BrokenPipe => Self::Unavailable(msg),
+            ErrorKind::Unsupported => Self::Unsupported(msg),
+            ErrorKind::InvalidData | ErrorKind::UnexpectedEof => Self::Corrupted(msg),
+            _ => Self::InternalError(msg),
+        }
+    }
+}
+
+/// Maps internal blob keys to object names accepted by a blob backend. Backends
+/// that can store arbitrary bytes as keys (i.e. database stores) use the internal
+/// key as is.
+pub trait BlobKeyMapping {
+    fn map_key(&self, key: &[u8]) -> String;
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BlobKeyEncoding {
+    #[default]
+    Base32,
+    Hex,
+}
+
+impl BlobKeyEncoding {
+    pub fn encode(&self, prefix: Option<&str>, key: &[u8]) -> String {
+        match self {
+            BlobKeyEncoding::Base32 => {
+                if let Some(prefix) = prefix {
+                    let mut writer =
+                        Base32Writer::with_raw_capacity(prefix.len() + ((key.len() + 3) / 4 * 5));
+                    writer.push_string(prefix);
+                    std::io::Write::write_all(&mut writer, key).unwrap();
+                    writer.finalize()
+                } else {
+                    Base32Writer::from_bytes(key).finalize()
+                }
+            }
+            BlobKeyEncoding::Hex => {
+                let prefix = prefix.unwrap_or_default();
+                let mut name = String::with_capacity(prefix.len() + key.len() * 2);
+                name.push_str(prefix);
+                for byte in key {
+                    let _ = write!(&mut name, "{byte:02x}");
+                }
+                name
+            }
+        }
+    }
+}
+
+impl ParseValue for BlobKeyEncoding {
+    fn parse_value(value: &str) -> utils::config::Result<Self> {
+        match value {
+            "base32" => Ok(BlobKeyEncoding::Base32),
+            "hex" => Ok(BlobKeyEncoding::Hex),
+            encoding => Err(format!("Invalid blob key encoding: {encoding}")),
+        }
+    }
+}
+
+/// When committed writes reach stable storage. Process crashes never lose
+/// committed writes under any policy, the data-loss windows below apply to
+/// power failures and kernel crashes only.
+///
+/// - SQLite: `synchronous = FULL`, periodic WAL checkpoints or `synchronous = NORMAL`.
+/// - RocksDB: synced WAL writes, periodic WAL syncs or unsynced WAL writes.
+/// - PostgreSQL: `synchronous_commit` on, off (flushed by the WAL writer) or off.
+/// - FoundationDB: ignored, commits are always durable once acknowledged.
+/// - MySQL: ignored, durability is set server-wide by `innodb_flush_log_at_trx_commit`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DurabilityPolicy {
+    /// Every commit is synced to disk before the write returns, nothing
+    /// committed is ever lost.
+    Synchronous,
+    /// Commits are synced together every `interval`, losing at most the
+    /// writes committed during the last `interval`.
+    GroupCommit { interval: Duration },
+    /// Syncing is left to the operating system, which can lose several
+    /// seconds of committed writes.
+    Relaxed,
+}
+
+impl DurabilityPolicy {
+    /// Returns `None` when no policy is configured, in which case the
+    /// backend defaults are used.
+    pub fn parse(config: &mut Config, prefix: impl AsKey) -> Option<Self> {
+        let prefix = prefix.as_key();
+        match config.value((&prefix, "durability"))?.to_string().as_str() {
+            "sync" => Some(DurabilityPolicy::Synchronous),
+            "group-commit" => Some(DurabilityPolicy::GroupCommit {
+                interval: config
+                    .property_or_default((&prefix, "durability-interval"), "100ms")
+                    .unwrap_or(Duration::from_millis(100)),
+            }),
+            "relaxed" => Some(DurabilityPolicy::Relaxed),
+            policy => {
+                let err = format!("Invalid durability policy: {policy}");
+                config.new_parse_error((&prefix, "durability"), err);
+                None
+            }
+        }
+    }
+}
+
+/// Background thread syncing a store every interval, stops when dropped.
+#[cfg(any(feature = "sqlite", feature = "rocks"))]
+pub(crate) struct GroupCommit {
+    _tx: mpsc::Sender<()>,
+}
+
+#[cfg(any(feature = "sqlite", feature = "rocks"))]
+impl GroupCommit {
+    pub fn spawn(
+        interval: Duration,
+        sync: impl Fn() -> crate::Result<()> + Send + 'static,
+    ) -> Self {
+        let (tx, rx) = mpsc::channel::<()>();
+
+        std::thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = rx.recv_timeout(interval) {
+                if let Err(err) = sync() {
+                    tracing::warn!(
+                        context = "group_commit",
+                        event = "error",
+                        reason = ?err,
+                        "Failed to sync store"
+                    );
+                }
+            }
+        });
+
+        GroupCommit { _tx: tx }
     }
 }
 
@@ -38,3 +197,79 @@ fn deserialize_i64_le(bytes: &[u8]) -> crate::Result<i64> {
         crate::Error::InternalError("Failed to deserialize i64 value.".to_string())
     })?))
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use utils::config::Config;
+
+    use super::DurabilityPolicy;
+
+    #[test]
+    fn parse_durability_policy() {
+        let mut config = Config::new(
+            r#"[store.a]
+durability = "sync"
+
+[store.b]
+durability = "group-commit"
+durability-interval = "5ms"
+
+[store.c]
+durability = "relaxed"
+
+[store.d]
+durability = "eventually"
+"#,
+        )
+        .unwrap();
+        for (prefix, expected) in [
+            ("store.a", Some(DurabilityPolicy::Synchronous)),
+            (
+                "store.b",
+                Some(DurabilityPolicy::GroupCommit {
+                    interval: Duration::from_millis(5),
+                }),
+            ),
+            ("store.c", Some(DurabilityPolicy::Relaxed)),
+            ("store.d", None),
+            ("store.e", None),
+        ] {
+            assert_eq!(
+                DurabilityPolicy::parse(&mut config, prefix),
+                expected,
+                "{prefix}"
+            );
+        }
+        assert!(config.errors.contains_key("store.d.durability"));
+    }
+
+    #[cfg(any(feature = "sqlite", feature = "rocks"))]
+    #[test]
+    fn group_commit_syncs_until_dropped() {
+        use std::sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        };
+
+        let syncs = Arc::new(AtomicUsize::new(0));
+        let group_commit = super::GroupCommit::spawn(Duration::from_millis(5), {
+            let syncs = syncs.clone();
+            move || {
+                syncs.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            }
+        });
+
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(syncs.load(Ordering::Relaxed) >= 2);
+
+        // No further syncs once the store is dropped
+        drop(group_commit);
+        std::thread::sleep(Duration::from_millis(20));
+        let after_drop = syncs.load(Ordering::Relaxed);
+        std::thread::sleep(Duration::from_millis(50));
+        assert_eq!(syncs.load(Ordering::Relaxed), after_drop);
+    }
+}
@@ -46,6 +46,17 @@ impl MysqlStore {
             .map(|_| ())
     }
 
+    pub(crate) async fn put_blob_if_absent(&self, key: &[u8], data: &[u8]) -> crate::Result<bool> {
+        let mut conn = self.conn_pool.get_conn().await?;
+        let s = conn
+            .prep("INSERT IGNORE INTO t (k, v) VALUES (?, ?)")
+            .await?;
+        conn.exec_iter(&s, (key, data))
+            .await
+            .map_err(|e| crate::Error::InternalError(format!("Failed to insert blob: {}", e)))
+            .map(|hits| hits.affected_rows() > 0)
+    }
+
     pub(crate) async fn delete_blob(&self, key: &[u8]) -> crate::Result<bool> {
         let mut conn = self.conn_pool.get_conn().await?;
         let s = conn.prep("DELETE FROM t WHERE k = ?").await?;
@@ -4,12 +4,19 @@
  * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
  */
 
-use std::time::Duration;
+use std::{sync::atomic::AtomicBool, time::Duration};
 
 use mysql_async::{prelude::Queryable, OptsBuilder, Pool, PoolConstraints, PoolOpts, SslOpts};
 use utils::config::{utils::AsKey, Config};
 
-use crate::*;
+use crate::{
+    fts::TokenLimits,
+    write::{
+        cardinality::CardinalityCache,
+        queue::{WriteQueue, DEFAULT_WRITE_QUEUE_DEPTH},
+    },
+    *,
+};
 
 use super::MysqlStore;
 
@@ -72,6 +79,21 @@ impl MysqlStore {
 
         let db = Self {
             conn_pool: Pool::new(opts),
+            max_value_size: config
+                .property_or_default((&prefix, "max-value-size"), "1048576")
+                .unwrap_or(DEFAULT_MAX_VALUE_SIZE),
+            read_only: AtomicBool::new(
+                config
+                    .property_or_default((&prefix, "read-only"), "false")
+                    .unwrap_or(false),
+            ),
+            write_queue: WriteQueue::new(
+                config
+                    .property_or_default((&prefix, "write-queue.depth"), "1024")
+                    .unwrap_or(DEFAULT_WRITE_QUEUE_DEPTH),
+            ),
+            cardinality_cache: CardinalityCache::parse(config, &prefix, 0),
+            token_limits: TokenLimits::parse(config, &prefix),
         };
 
         if let Err(err) = db.create_tables().await {
@@ -99,6 +121,12 @@ impl MysqlStore {
             SUBSPACE_REPORT_IN,
             SUBSPACE_FTS_INDEX,
             SUBSPACE_LOGS,
+            SUBSPACE_INTENTS,
+            SUBSPACE_QUARANTINE,
+            SUBSPACE_VECTORS,
+            SUBSPACE_COLLECTIONS,
+            SUBSPACE_LABELS,
+            SUBSPACE_CLUSTER,
         ] {
             let table = char::from(table);
             conn.query_drop(&format!(
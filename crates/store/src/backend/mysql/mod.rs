@@ -4,8 +4,15 @@
  * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
  */
 
+use std::sync::atomic::AtomicBool;
+
 use mysql_async::Pool;
 
+use crate::{
+    fts::TokenLimits,
+    write::{cardinality::CardinalityCache, queue::WriteQueue},
+};
+
 pub mod blob;
 pub mod lookup;
 pub mod main;
@@ -14,11 +21,24 @@ pub mod write;
 
 pub struct MysqlStore {
     pub(crate) conn_pool: Pool,
+    pub(crate) max_value_size: usize,
+    pub(crate) read_only: AtomicBool,
+    pub(crate) write_queue: WriteQueue,
+    pub(crate) cardinality_cache: CardinalityCache,
+    pub(crate) token_limits: TokenLimits,
 }
 
 impl From<mysql_async::Error> for crate::Error {
     fn from(err: mysql_async::Error) -> Self {
-        Self::InternalError(format!("mySQL error: {}", err))
+        let msg = format!("mySQL error: {}", err);
+        match &err {
+            mysql_async::Error::Io(_) => Self::Unavailable(msg),
+            // Deadlock found and lock wait timeout exceeded
+            mysql_async::Error::Server(err) if matches!(err.code, 1213 | 1205) => {
+                Self::Unavailable(msg)
+            }
+            _ => Self::InternalError(msg),
+        }
     }
 }
 
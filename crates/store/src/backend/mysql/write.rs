@@ -4,18 +4,17 @@
  * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
  */
 
-use std::time::{Duration, Instant};
+use std::time::Instant;
 
 use ahash::AHashMap;
 use futures::TryStreamExt;
-use mysql_async::{params, prelude::Queryable, Conn, Error, IsolationLevel, TxOpts};
-use rand::Rng;
+use mysql_async::{params, prelude::Queryable, Conn, Error, IsolationLevel, Transaction, TxOpts};
 use roaring::RoaringBitmap;
 
 use crate::{
     write::{
-        key::DeserializeBigEndian, AssignedIds, Batch, BitmapClass, Operation, RandomAvailableId,
-        ValueOp, MAX_COMMIT_ATTEMPTS, MAX_COMMIT_TIME,
+        key::DeserializeBigEndian, AssignedIds, AvailableId, Batch, BitmapClass, IdAllocator,
+        Operation, ValueClass, ValueOp,
     },
     BitmapKey, IndexKey, Key, LogKey, SUBSPACE_COUNTER, SUBSPACE_QUOTA, U32_LEN,
 };
@@ -42,10 +41,9 @@ impl MysqlStore {
                 }
                 Err(CommitError::Mysql(Error::Server(err)))
                     if [1062, 1213].contains(&err.code)
-                        && retry_count < MAX_COMMIT_ATTEMPTS
-                        && start.elapsed() < MAX_COMMIT_TIME => {}
+                        && batch.retry.can_retry(retry_count, start.elapsed()) => {}
                 Err(CommitError::Retry) => {
-                    if retry_count > MAX_COMMIT_ATTEMPTS || start.elapsed() > MAX_COMMIT_TIME {
+                    if !batch.retry.can_retry(retry_count, start.elapsed()) {
                         return Err(crate::Error::AssertValueFailed);
                     }
                 }
@@ -57,8 +55,7 @@ impl MysqlStore {
                 }
             }
 
-            let backoff = rand::thread_rng().gen_range(50..=300);
-            tokio::time::sleep(Duration::from_millis(backoff)).await;
+            tokio::time::sleep(batch.retry.delay(retry_count)).await;
             retry_count += 1;
         }
     }
@@ -164,27 +161,18 @@ impl MysqlStore {
                             }
                         }
                         ValueOp::AddAndGet(by) => {
-                            let s = trx
-                                .prep(&format!(
-                                    concat!(
-                                        "INSERT INTO {} (k, v) VALUES (:k, LAST_INSERT_ID(:v)) ",
-                                        "ON DUPLICATE KEY UPDATE v = LAST_INSERT_ID(v + :v)"
-                                    ),
-                                    table
-                                ))
-                                .await?;
-                            trx.exec_drop(&s, params! {"k" => key, "v" => by}).await?;
-                            let s = trx.prep("SELECT LAST_INSERT_ID()").await?;
-                            result.push_counter_id(
-                                trx.exec_first::<i64, _, _>(&s, ()).await?.ok_or_else(|| {
-                                    mysql_async::Error::Io(mysql_async::IoError::Io(
-                                        std::io::Error::new(
-                                            std::io::ErrorKind::Other,
-                                            "LAST_INSERT_ID() did not return a value",
-                                        ),
-                                    ))
-                                })?,
-                            );
+                            result.push_counter_id(add_and_get(&mut trx, table, &key, *by).await?);
+                        }
+                        ValueOp::AddWithLimit { by, limit } => {
+                            let used = add_and_get(&mut trx, table, &key, *by).await?;
+                            if *by > 0 && used > *limit {
+                                trx.rollback().await?;
+                                return Err(crate::Error::QuotaExceeded {
+                                    used: used as u64,
+                                    limit: *limit as u64,
+                                }
+                                .into());
+                            }
                         }
                         ValueOp::Clear => {
                             let s = trx
@@ -232,18 +220,34 @@ impl MysqlStore {
                         let key_len = begin.len();
 
                         let s = trx.prep("SELECT k FROM b WHERE k >= ? AND k <= ?").await?;
-                        let mut rows = trx.exec_stream::<Vec<u8>, _, _>(&s, (begin, end)).await?;
                         let mut found_ids = RoaringBitmap::new();
-
-                        while let Some(key) = rows.try_next().await? {
-                            if key.len() == key_len {
-                                found_ids.insert(
-                                    key.as_slice().deserialize_be_u32(key.len() - U32_LEN)?,
-                                );
+                        {
+                            let mut rows =
+                                trx.exec_stream::<Vec<u8>, _, _>(&s, (begin, end)).await?;
+                            while let Some(key) = rows.try_next().await? {
+                                if key.len() == key_len {
+                                    found_ids.insert(
+                                        key.as_slice().deserialize_be_u32(key.len() - U32_LEN)?,
+                                    );
+                                }
                             }
                         }
 
-                        document_id = found_ids.random_available_id();
+                        document_id = match batch.id_allocator {
+                            IdAllocator::MonotonicNeverReuse => {
+                                let key = ValueClass::<u32>::DocumentIdCounter
+                                    .serialize(account_id, collection, 0, 0, None);
+                                let table = char::from(SUBSPACE_QUOTA);
+                                let next_id = found_ids.next_available_id() as i64;
+                                let mut id = add_and_get(&mut trx, table, &key, 1).await? - 1;
+                                if id < next_id {
+                                    add_and_get(&mut trx, table, &key, next_id - id).await?;
+                                    id = next_id;
+                                }
+                                id as u32
+                            }
+                            IdAllocator::ReuseFreed => found_ids.lowest_available_id(),
+                        };
                         result.push_document_id(document_id);
                     }
                     let key =
@@ -345,6 +349,31 @@ impl MysqlStore {
     }
 }
 
+async fn add_and_get(
+    trx: &mut Transaction<'_>,
+    table: char,
+    key: &[u8],
+    by: i64,
+) -> Result<i64, mysql_async::Error> {
+    let s = trx
+        .prep(&format!(
+            concat!(
+                "INSERT INTO {} (k, v) VALUES (:k, LAST_INSERT_ID(:v)) ",
+                "ON DUPLICATE KEY UPDATE v = LAST_INSERT_ID(v + :v)"
+            ),
+            table
+        ))
+        .await?;
+    trx.exec_drop(&s, params! {"k" => key, "v" => by}).await?;
+    let s = trx.prep("SELECT LAST_INSERT_ID()").await?;
+    trx.exec_first::<i64, _, _>(&s, ()).await?.ok_or_else(|| {
+        mysql_async::Error::Io(mysql_async::IoError::Io(std::io::Error::new(
+            std::io::ErrorKind::Other,
+            "LAST_INSERT_ID() did not return a value",
+        )))
+    })
+}
+
 impl From<crate::Error> for CommitError {
     fn from(err: crate::Error) -> Self {
         CommitError::Internal(err)
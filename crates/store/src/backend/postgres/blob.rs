@@ -49,6 +49,17 @@ impl PostgresStore {
             .map(|_| ())
     }
 
+    pub(crate) async fn put_blob_if_absent(&self, key: &[u8], data: &[u8]) -> crate::Result<bool> {
+        let conn = self.conn_pool.get().await?;
+        let s = conn
+            .prepare_cached("INSERT INTO t (k, v) VALUES ($1, $2) ON CONFLICT (k) DO NOTHING")
+            .await?;
+        conn.execute(&s, &[&key, &data])
+            .await
+            .map_err(|e| crate::Error::InternalError(format!("Failed to insert blob: {}", e)))
+            .map(|hits| hits > 0)
+    }
+
     pub(crate) async fn delete_blob(&self, key: &[u8]) -> crate::Result<bool> {
         let conn = self.conn_pool.get().await?;
         let s = conn.prepare_cached("DELETE FROM t WHERE k = $1").await?;
@@ -4,9 +4,17 @@
  * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
  */
 
-use std::time::Duration;
+use std::{sync::atomic::AtomicBool, time::Duration};
 
-use crate::{backend::postgres::tls::MakeRustlsConnect, *};
+use crate::{
+    backend::{postgres::tls::MakeRustlsConnect, DurabilityPolicy},
+    fts::TokenLimits,
+    write::{
+        cardinality::CardinalityCache,
+        queue::{WriteQueue, DEFAULT_WRITE_QUEUE_DEPTH},
+    },
+    *,
+};
 
 use super::PostgresStore;
 
@@ -34,6 +42,16 @@ impl PostgresStore {
         cfg.manager = Some(ManagerConfig {
             recycling_method: RecyclingMethod::Fast,
         });
+        // Asynchronous commits are flushed by the WAL writer in groups
+        match DurabilityPolicy::parse(config, &prefix) {
+            Some(DurabilityPolicy::Synchronous) => {
+                cfg.options = Some("-c synchronous_commit=on".to_string());
+            }
+            Some(DurabilityPolicy::GroupCommit { .. } | DurabilityPolicy::Relaxed) => {
+                cfg.options = Some("-c synchronous_commit=off".to_string());
+            }
+            None => (),
+        }
         if let Some(max_conn) = config.property::<usize>((&prefix, "pool.max-connections")) {
             cfg.pool = PoolConfig::new(max_conn).into();
         }
@@ -60,6 +78,21 @@ impl PostgresStore {
                 )
             })
             .ok()?,
+            max_value_size: config
+                .property_or_default((&prefix, "max-value-size"), "1048576")
+                .unwrap_or(DEFAULT_MAX_VALUE_SIZE),
+            read_only: AtomicBool::new(
+                config
+                    .property_or_default((&prefix, "read-only"), "false")
+                    .unwrap_or(false),
+            ),
+            write_queue: WriteQueue::new(
+                config
+                    .property_or_default((&prefix, "write-queue.depth"), "1024")
+                    .unwrap_or(DEFAULT_WRITE_QUEUE_DEPTH),
+            ),
+            cardinality_cache: CardinalityCache::parse(config, &prefix, 0),
+            token_limits: TokenLimits::parse(config, &prefix),
         };
 
         if let Err(err) = db.create_tables().await {
@@ -88,6 +121,12 @@ impl PostgresStore {
             SUBSPACE_FTS_INDEX,
             SUBSPACE_LOGS,
             SUBSPACE_BLOBS,
+            SUBSPACE_INTENTS,
+            SUBSPACE_QUARANTINE,
+            SUBSPACE_VECTORS,
+            SUBSPACE_COLLECTIONS,
+            SUBSPACE_LABELS,
+            SUBSPACE_CLUSTER,
         ] {
             let table = char::from(table);
             conn.execute(
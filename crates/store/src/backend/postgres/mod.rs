@@ -4,8 +4,15 @@
  * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
  */
 
+use std::sync::atomic::AtomicBool;
+
 use deadpool_postgres::{Pool, PoolError};
 
+use crate::{
+    fts::TokenLimits,
+    write::{cardinality::CardinalityCache, queue::WriteQueue},
+};
+
 pub mod blob;
 pub mod lookup;
 pub mod main;
@@ -15,17 +22,41 @@ pub mod write;
 
 pub struct PostgresStore {
     pub(crate) conn_pool: Pool,
+    pub(crate) max_value_size: usize,
+    pub(crate) read_only: AtomicBool,
+    pub(crate) write_queue: WriteQueue,
+    pub(crate) cardinality_cache: CardinalityCache,
+    pub(crate) token_limits: TokenLimits,
 }
 
 impl From<PoolError> for crate::Error {
     fn from(err: PoolError) -> Self {
-        Self::InternalError(format!("Connection pool error: {}", err))
+        Self::Unavailable(format!("Connection pool error: {}", err))
     }
 }
 
 impl From<tokio_postgres::Error> for crate::Error {
     fn from(err: tokio_postgres::Error) -> Self {
-        Self::InternalError(format!("PostgreSQL error: {}", err))
+        use tokio_postgres::error::SqlState;
+
+        let msg = format!("PostgreSQL error: {}", err);
+        if err.is_closed() {
+            return Self::Unavailable(msg);
+        }
+        match err.code() {
+            Some(
+                &SqlState::T_R_SERIALIZATION_FAILURE
+                | &SqlState::T_R_DEADLOCK_DETECTED
+                | &SqlState::LOCK_NOT_AVAILABLE
+                | &SqlState::TOO_MANY_CONNECTIONS
+                | &SqlState::ADMIN_SHUTDOWN
+                | &SqlState::CANNOT_CONNECT_NOW,
+            ) => Self::Unavailable(msg),
+            Some(&SqlState::DATA_CORRUPTED | &SqlState::INDEX_CORRUPTED) => Self::Corrupted(msg),
+            Some(&SqlState::FEATURE_NOT_SUPPORTED) => Self::Unsupported(msg),
+            Some(&SqlState::QUERY_CANCELED) => Self::Unavailable(msg),
+            _ => Self::InternalError(msg),
+        }
     }
 }
 
@@ -4,19 +4,18 @@
  * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
  */
 
-use std::time::{Duration, Instant};
+use std::time::Instant;
 
 use ahash::AHashMap;
-use deadpool_postgres::Object;
+use deadpool_postgres::{Object, Transaction};
 use futures::{pin_mut, TryStreamExt};
-use rand::Rng;
 use roaring::RoaringBitmap;
 use tokio_postgres::{error::SqlState, IsolationLevel};
 
 use crate::{
     write::{
-        key::DeserializeBigEndian, AssignedIds, Batch, BitmapClass, Operation, RandomAvailableId,
-        ValueOp, MAX_COMMIT_ATTEMPTS, MAX_COMMIT_TIME,
+        key::DeserializeBigEndian, AssignedIds, AvailableId, Batch, BitmapClass, IdAllocator,
+        Operation, ValueClass, ValueOp,
     },
     BitmapKey, IndexKey, Key, LogKey, SUBSPACE_COUNTER, SUBSPACE_QUOTA, U32_LEN,
 };
@@ -47,8 +46,7 @@ impl PostgresStore {
                             Some(
                                 &SqlState::T_R_SERIALIZATION_FAILURE
                                 | &SqlState::T_R_DEADLOCK_DETECTED,
-                            ) if retry_count < MAX_COMMIT_ATTEMPTS
-                                && start.elapsed() < MAX_COMMIT_TIME => {}
+                            ) if batch.retry.can_retry(retry_count, start.elapsed()) => {}
                             Some(&SqlState::UNIQUE_VIOLATION) => {
                                 return Err(crate::Error::AssertValueFailed);
                             }
@@ -56,16 +54,13 @@ impl PostgresStore {
                         },
                         CommitError::Internal(err) => return Err(err),
                         CommitError::Retry => {
-                            if retry_count > MAX_COMMIT_ATTEMPTS
-                                || start.elapsed() > MAX_COMMIT_TIME
-                            {
+                            if !batch.retry.can_retry(retry_count, start.elapsed()) {
                                 return Err(crate::Error::AssertValueFailed);
                             }
                         }
                     }
 
-                    let backoff = rand::thread_rng().gen_range(50..=300);
-                    tokio::time::sleep(Duration::from_millis(backoff)).await;
+                    tokio::time::sleep(batch.retry.delay(retry_count)).await;
                     retry_count += 1;
                 }
             }
@@ -173,20 +168,17 @@ impl PostgresStore {
                             }
                         }
                         ValueOp::AddAndGet(by) => {
-                            let s = trx
-                                .prepare_cached(&format!(
-                                    concat!(
-                                    "INSERT INTO {} (k, v) VALUES ($1, $2) ",
-                                    "ON CONFLICT(k) DO UPDATE SET v = {}.v + EXCLUDED.v RETURNING v"
-                                ),
-                                    table, table
-                                ))
-                                .await?;
-                            result.push_counter_id(
-                                trx.query_one(&s, &[&key, &by])
-                                    .await
-                                    .and_then(|row| row.try_get::<_, i64>(0))?,
-                            );
+                            result.push_counter_id(add_and_get(&trx, table, &key, *by).await?);
+                        }
+                        ValueOp::AddWithLimit { by, limit } => {
+                            let used = add_and_get(&trx, table, &key, *by).await?;
+                            if *by > 0 && used > *limit {
+                                return Err(crate::Error::QuotaExceeded {
+                                    used: used as u64,
+                                    limit: *limit as u64,
+                                }
+                                .into());
+                            }
                         }
                         ValueOp::Clear => {
                             let s = trx
@@ -252,7 +244,21 @@ impl PostgresStore {
                             }
                         }
 
-                        document_id = found_ids.random_available_id();
+                        document_id = match batch.id_allocator {
+                            IdAllocator::MonotonicNeverReuse => {
+                                let key = ValueClass::<u32>::DocumentIdCounter
+                                    .serialize(account_id, collection, 0, 0, None);
+                                let table = char::from(SUBSPACE_QUOTA);
+                                let next_id = found_ids.next_available_id() as i64;
+                                let mut id = add_and_get(&trx, table, &key, 1).await? - 1;
+                                if id < next_id {
+                                    add_and_get(&trx, table, &key, next_id - id).await?;
+                                    id = next_id;
+                                }
+                                id as u32
+                            }
+                            IdAllocator::ReuseFreed => found_ids.lowest_available_id(),
+                        };
                         result.push_document_id(document_id);
                     }
 
@@ -367,6 +373,26 @@ impl From<crate::Error> for CommitError {
     }
 }
 
+async fn add_and_get(
+    trx: &Transaction<'_>,
+    table: char,
+    key: &[u8],
+    by: i64,
+) -> Result<i64, tokio_postgres::Error> {
+    let s = trx
+        .prepare_cached(&format!(
+            concat!(
+                "INSERT INTO {} (k, v) VALUES ($1, $2) ",
+                "ON CONFLICT(k) DO UPDATE SET v = {}.v + EXCLUDED.v RETURNING v"
+            ),
+            table, table
+        ))
+        .await?;
+    trx.query_one(&s, &[&key, &by])
+        .await
+        .and_then(|row| row.try_get::<_, i64>(0))
+}
+
 impl From<tokio_postgres::Error> for CommitError {
     fn from(err: tokio_postgres::Error) -> Self {
         CommitError::Postgres(err)
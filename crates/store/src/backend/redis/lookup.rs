@@ -71,6 +71,13 @@ impl RedisStore {
         }
     }
 
+    pub async fn key_range(&self, from: Vec<u8>, to: Vec<u8>) -> crate::Result<Vec<Vec<u8>>> {
+        match &self.pool {
+            RedisPool::Single(pool) => self.key_range_(pool.get().await?.as_mut(), from, to).await,
+            RedisPool::Cluster(pool) => self.key_range_(pool.get().await?.as_mut(), from, to).await,
+        }
+    }
+
     pub async fn key_exists(&self, key: Vec<u8>) -> crate::Result<bool> {
         match &self.pool {
             RedisPool::Single(pool) => self.key_exists_(pool.get().await?.as_mut(), key).await,
@@ -101,6 +108,48 @@ impl RedisStore {
             .map_err(Into::into)
     }
 
+    async fn key_range_(
+        &self,
+        conn: &mut impl AsyncCommands,
+        from: Vec<u8>,
+        to: Vec<u8>,
+    ) -> crate::Result<Vec<Vec<u8>>> {
+        let key_index = self.key_index.as_ref().ok_or_else(|| {
+            crate::Error::InternalError("Redis store has no key-index configured".into())
+        })?;
+        let keys = conn
+            .zrangebylex::<_, _, _, Vec<Vec<u8>>>(
+                key_index,
+                [b"[".as_slice(), &from].concat(),
+                [b"[".as_slice(), &to].concat(),
+            )
+            .await?;
+        if keys.is_empty() {
+            return Ok(keys);
+        }
+
+        // A single MGET is split by hash slot on clusters, unlike a pipeline
+        let values = redis::cmd("MGET")
+            .arg(&keys)
+            .query_async::<_, Vec<Option<redis::Value>>>(conn)
+            .await?;
+        let (results, expired): (Vec<_>, Vec<_>) = keys
+            .into_iter()
+            .zip(values)
+            .partition(|(_, value)| value.is_some());
+
+        // Drop index entries of keys that expired in the meantime
+        if !expired.is_empty() {
+            conn.zrem::<_, _, ()>(
+                key_index,
+                expired.into_iter().map(|(key, _)| key).collect::<Vec<_>>(),
+            )
+            .await?;
+        }
+
+        Ok(results.into_iter().map(|(key, _)| key).collect())
+    }
+
     async fn key_exists_(
         &self,
         conn: &mut impl AsyncCommands,
@@ -116,11 +165,15 @@ impl RedisStore {
         value: Vec<u8>,
         expires: Option<u64>,
     ) -> crate::Result<()> {
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        self.index_key(conn, &mut pipe, &key).await?;
         if let Some(expires) = expires {
-            conn.set_ex(key, value, expires).await.map_err(Into::into)
+            pipe.set_ex(&key, value, expires).ignore();
         } else {
-            conn.set(key, value).await.map_err(Into::into)
+            pipe.set(&key, value).ignore();
         }
+        pipe.query_async::<_, ()>(conn).await.map_err(Into::into)
     }
 
     async fn key_incr_(
@@ -130,22 +183,56 @@ impl RedisStore {
         value: i64,
         expires: Option<u64>,
     ) -> crate::Result<i64> {
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        self.index_key(conn, &mut pipe, &key).await?;
+        pipe.incr(&key, value);
         if let Some(expires) = expires {
-            redis::pipe()
+            pipe.expire(&key, expires as i64).ignore();
+        }
+        pipe.query_async::<_, Vec<i64>>(conn)
+            .await
+            .map_err(Into::into)
+            .map(|v| v.first().copied().unwrap_or(0))
+    }
+
+    async fn key_delete_(&self, conn: &mut impl AsyncCommands, key: Vec<u8>) -> crate::Result<()> {
+        match &self.key_index {
+            Some(key_index) if matches!(self.pool, RedisPool::Single(_)) => redis::pipe()
                 .atomic()
-                .incr(&key, value)
-                .expire(&key, expires as i64)
+                .del(&key)
                 .ignore()
-                .query_async::<_, Vec<i64>>(conn)
+                .zrem(key_index, &key)
+                .ignore()
+                .query_async::<_, ()>(conn)
                 .await
-                .map_err(Into::into)
-                .map(|v| v.first().copied().unwrap_or(0))
-        } else {
-            conn.incr(&key, value).await.map_err(Into::into)
+                .map_err(Into::into),
+            Some(key_index) => {
+                conn.del::<_, ()>(&key).await?;
+                conn.zrem(key_index, &key).await.map_err(Into::into)
+            }
+            None => conn.del(key).await.map_err(Into::into),
         }
     }
 
-    async fn key_delete_(&self, conn: &mut impl AsyncCommands, key: Vec<u8>) -> crate::Result<()> {
-        conn.del(key).await.map_err(Into::into)
+    // Adds the key to the index within the transaction. On clusters the index and
+    // the key live in different hash slots and can't share a transaction, so the
+    // key is indexed beforehand and `key_range` prunes entries of keys never written.
+    async fn index_key(
+        &self,
+        conn: &mut impl AsyncCommands,
+        pipe: &mut redis::Pipeline,
+        key: &[u8],
+    ) -> crate::Result<()> {
+        match (&self.key_index, &self.pool) {
+            (Some(key_index), RedisPool::Single(_)) => {
+                pipe.zadd(key_index, key, 0).ignore();
+            }
+            (Some(key_index), RedisPool::Cluster(_)) => {
+                conn.zadd::<_, _, _, ()>(key_index, key, 0).await?;
+            }
+            (None, _) => {}
+        }
+        Ok(())
     }
 }
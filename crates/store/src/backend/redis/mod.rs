@@ -4,7 +4,7 @@
  * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
  */
 
-use std::time::Duration;
+use std::{sync::atomic::AtomicBool, time::Duration};
 
 use deadpool::{
     managed::{Manager, Pool, PoolError},
@@ -21,6 +21,8 @@ pub mod pool;
 
 pub struct RedisStore {
     pool: RedisPool,
+    key_index: Option<String>,
+    pub(crate) read_only: AtomicBool,
 }
 
 struct RedisConnectionManager {
@@ -50,6 +52,12 @@ impl RedisStore {
             return None;
         }
 
+        // Redis has no ordered key scans, keep a sorted set of keys if range queries are needed
+        let key_index = config.value((&prefix, "key-index")).map(|v| v.to_string());
+        let read_only = config
+            .property_or_default((&prefix, "read-only"), "false")
+            .unwrap_or(false);
+
         Some(
             match config.value((&prefix, "redis-type")).unwrap_or("single") {
                 "single" => {
@@ -76,6 +84,8 @@ impl RedisStore {
                                 })
                                 .ok()?,
                         ),
+                        key_index,
+                        read_only: AtomicBool::new(read_only),
                     }
                 }
                 "cluster" => {
@@ -133,6 +143,8 @@ impl RedisStore {
                             })
                             .ok()?,
                         ),
+                        key_index,
+                        read_only: AtomicBool::new(read_only),
                     }
                 }
                 invalid => {
@@ -0,0 +1,228 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs Ltd <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::{
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime},
+};
+
+use utils::config::{utils::AsKey, Config};
+
+use crate::{
+    write::{AnyClass, AnyKey, Batch, BatchBuilder, ValueClass},
+    Serialize, Store, Stores, SUBSPACE_CLUSTER,
+};
+
+/// Written to the primary and read back from the replicas to measure their lag.
+pub const HEARTBEAT_KEY: &[u8] = b"heartbeat";
+
+/// A primary store and its read-only replicas, which are kept up to date by the
+/// replication mechanism of the backend. Writes always go to the primary while
+/// reads may be routed to a replica when they tolerate some staleness.
+pub struct ReplicatedStore {
+    pub primary: Store,
+    pub replicas: Vec<Replica>,
+    pub heartbeat_interval: Duration,
+    next_replica: AtomicUsize,
+}
+
+pub struct Replica {
+    pub store: Store,
+    // Last primary heartbeat seen on the replica, in milliseconds
+    heartbeat: AtomicU64,
+}
+
+impl ReplicatedStore {
+    pub fn new(primary: Store, replicas: Vec<Store>) -> Self {
+        ReplicatedStore {
+            primary,
+            replicas: replicas
+                .into_iter()
+                .map(|store| Replica {
+                    store,
+                    heartbeat: AtomicU64::new(0),
+                })
+                .collect(),
+            heartbeat_interval: Duration::from_secs(1),
+            next_replica: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn open(config: &mut Config, prefix: impl AsKey, stores: &Stores) -> Option<Self> {
+        let prefix = prefix.as_key();
+        let primary_id = config.value_require((&prefix, "primary"))?.to_string();
+        let replica_ids = config
+            .values((&prefix, "replicas"))
+            .map(|(_, id)| id.to_string())
+            .collect::<Vec<_>>();
+
+        let primary = if let Some(store) = stores.stores.get(&primary_id) {
+            if matches!(store, Store::Replicated(_)) {
+                config.new_build_error(
+                    (&prefix, "primary"),
+                    "The primary cannot be a replicated store",
+                );
+                return None;
+            }
+            store.clone()
+        } else {
+            config.new_build_error(
+                (&prefix, "primary"),
+                format!("Data store {primary_id:?} not found"),
+            );
+            return None;
+        };
+        let mut replicas = Vec::with_capacity(replica_ids.len());
+        for id in replica_ids {
+            if id == primary_id {
+                config.new_build_error(
+                    (&prefix, "replicas"),
+                    "The primary cannot be listed as a replica",
+                );
+                return None;
+            } else if let Some(store) = stores.stores.get(&id) {
+                if matches!(store, Store::Replicated(_)) {
+                    config.new_build_error(
+                        (&prefix, "replicas"),
+                        "A replica cannot be a replicated store",
+                    );
+                    return None;
+                }
+                replicas.push(store.clone());
+            } else {
+                config.new_build_error(
+                    (&prefix, "replicas"),
+                    format!("Data store {id:?} not found"),
+                );
+                return None;
+            }
+        }
+
+        let mut store = ReplicatedStore::new(primary, replicas);
+        store.heartbeat_interval = config
+            .property_or_default((&prefix, "heartbeat-interval"), "1s")
+            .unwrap_or(Duration::from_secs(1));
+        Some(store)
+    }
+
+    pub async fn write(&self, batch: Batch) -> crate::Result<crate::write::AssignedIds> {
+        self.primary.write(batch).await
+    }
+
+    /// Returns the store to read from. Reads that need fresh data should pass
+    /// `None` to read from the primary, otherwise a replica lagging at most
+    /// `max_staleness` behind the primary is picked, falling back to the primary
+    /// if there is none. The lag is only as precise as the heartbeat interval.
+    pub fn read_store(&self, max_staleness: Option<Duration>) -> &Store {
+        if let Some(max_staleness) = max_staleness {
+            let now = now_millis();
+            let start = self.next_replica.fetch_add(1, Ordering::Relaxed);
+            for offset in 0..self.replicas.len() {
+                let replica = &self.replicas[(start + offset) % self.replicas.len()];
+                if replica
+                    .lag_at(now)
+                    .map_or(false, |lag| lag <= max_staleness)
+                {
+                    return &replica.store;
+                }
+            }
+        }
+
+        &self.primary
+    }
+
+    /// Returns the lag of each replica, or `None` if it has not been measured yet.
+    pub fn replica_lag(&self) -> Vec<Option<Duration>> {
+        let now = now_millis();
+        self.replicas
+            .iter()
+            .map(|replica| replica.lag_at(now))
+            .collect()
+    }
+
+    /// Writes the current time to the primary.
+    pub async fn heartbeat(&self) -> crate::Result<()> {
+        let mut batch = BatchBuilder::new();
+        batch.set(heartbeat_class().into_dynamic(), now_millis().serialize());
+        self.primary.write(batch.build()).await.map(|_| ())
+    }
+
+    /// Reads the last heartbeat replicated to each replica. Replicas that cannot be
+    /// read are treated as too stale until they recover.
+    pub async fn refresh_lag(&self) {
+        for replica in &self.replicas {
+            let heartbeat = match replica
+                .store
+                .get_value::<u64>(AnyKey {
+                    subspace: SUBSPACE_CLUSTER,
+                    key: HEARTBEAT_KEY,
+                })
+                .await
+            {
+                Ok(heartbeat) => heartbeat.unwrap_or_default(),
+                Err(err) => {
+                    tracing::warn!("Failed to read heartbeat from replica: {err}");
+                    0
+                }
+            };
+            replica.heartbeat.store(heartbeat, Ordering::Relaxed);
+        }
+    }
+
+    /// Writes a heartbeat and measures the lag of the replicas every
+    /// `heartbeat_interval`, until the store is dropped.
+    pub fn spawn_heartbeat(self: &Arc<Self>) {
+        let store = Arc::downgrade(self);
+        let heartbeat_interval = self.heartbeat_interval;
+        tokio::spawn(async move {
+            while let Some(store) = store.upgrade() {
+                if let Err(err) = store.heartbeat().await {
+                    tracing::warn!("Failed to write heartbeat to primary: {err}");
+                }
+                store.refresh_lag().await;
+                drop(store);
+
+                tokio::time::sleep(heartbeat_interval).await;
+            }
+        });
+    }
+}
+
+impl Store {
+    /// Returns the store to read from when `max_staleness` is tolerated, which is
+    /// one of the replicas of a replicated store or the store itself otherwise.
+    pub fn read_store(&self, max_staleness: Option<Duration>) -> &Store {
+        match self {
+            Store::Replicated(store) => store.read_store(max_staleness),
+            store => store,
+        }
+    }
+}
+
+impl Replica {
+    fn lag_at(&self, now: u64) -> Option<Duration> {
+        match self.heartbeat.load(Ordering::Relaxed) {
+            0 => None,
+            heartbeat => Some(Duration::from_millis(now.saturating_sub(heartbeat))),
+        }
+    }
+}
+
+fn heartbeat_class() -> ValueClass<u32> {
+    ValueClass::Any(AnyClass {
+        subspace: SUBSPACE_CLUSTER,
+        key: HEARTBEAT_KEY.to_vec(),
+    })
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
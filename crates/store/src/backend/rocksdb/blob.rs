@@ -43,6 +43,25 @@ impl RocksDbStore {
         .await
     }
 
+    pub(crate) async fn put_blob_if_absent(&self, key: &[u8], data: &[u8]) -> crate::Result<bool> {
+        let db = self.db.clone();
+        self.spawn_worker(move || {
+            let cf = db.cf_handle(CF_BLOBS).unwrap();
+            let txn = db.transaction();
+            if txn.get_pinned_for_update_cf(&cf, key, true)?.is_some() {
+                return Ok(false);
+            }
+            txn.put_cf(&cf, key, data)?;
+            match txn.commit() {
+                Ok(_) => Ok(true),
+                // A concurrent writer created the blob first
+                Err(err) if err.kind() == rocksdb::ErrorKind::Busy => Ok(false),
+                Err(err) => Err(err.into()),
+            }
+        })
+        .await
+    }
+
     pub(crate) async fn delete_blob(&self, key: &[u8]) -> crate::Result<bool> {
         let db = self.db.clone();
         self.spawn_worker(move || {
@@ -4,14 +4,31 @@
  * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
  */
 
-use std::path::PathBuf;
+use std::{
+    path::PathBuf,
+    sync::{atomic::AtomicBool, Arc},
+};
 
-use rocksdb::{ColumnFamilyDescriptor, MergeOperands, OptimisticTransactionDB, Options};
+use rocksdb::{
+    BlockBasedOptions, ColumnFamilyDescriptor, MergeOperands, MultiThreaded,
+    OptimisticTransactionDB, Options,
+};
 
 use tokio::sync::oneshot;
 use utils::config::{utils::AsKey, Config};
 
-use crate::*;
+use crate::{
+    backend::{DurabilityPolicy, GroupCommit},
+    *,
+};
+
+use crate::{
+    fts::TokenLimits,
+    write::{
+        cardinality::{CardinalityCache, DEFAULT_CARDINALITY_CACHE_SIZE},
+        queue::{WriteQueue, DEFAULT_WRITE_QUEUE_DEPTH},
+    },
+};
 
 use super::{RocksDbStore, CF_BLOBS};
 
@@ -35,6 +52,15 @@ impl RocksDbStore {
 
         let mut cfs = Vec::new();
 
+        // Index and bitmap keys share long prefixes. Keys are stored as a delta from
+        // the previous key within a block, so restart the encoding less often.
+        let mut prefix_table_opts = BlockBasedOptions::default();
+        prefix_table_opts.set_block_restart_interval(
+            config
+                .property_or_default((&prefix, "index.restart-interval"), "64")
+                .unwrap_or(64),
+        );
+
         // Bitmaps
         for subspace in [
             SUBSPACE_BITMAP_ID,
@@ -43,12 +69,21 @@ impl RocksDbStore {
         ] {
             let mut cf_opts = Options::default();
             cf_opts.set_max_write_buffer_number(16);
+            cf_opts.set_block_based_table_factory(&prefix_table_opts);
             cfs.push(ColumnFamilyDescriptor::new(
                 std::str::from_utf8(&[subspace]).unwrap(),
                 cf_opts,
             ));
         }
 
+        // Indexes
+        let mut cf_opts = Options::default();
+        cf_opts.set_block_based_table_factory(&prefix_table_opts);
+        cfs.push(ColumnFamilyDescriptor::new(
+            std::str::from_utf8(&[SUBSPACE_INDEXES]).unwrap(),
+            cf_opts,
+        ));
+
         // Counters
         for subspace in [SUBSPACE_COUNTER, SUBSPACE_QUOTA] {
             let mut cf_opts = Options::default();
@@ -71,7 +106,6 @@ impl RocksDbStore {
 
         // Other cfs
         for subspace in [
-            SUBSPACE_INDEXES,
             SUBSPACE_ACL,
             SUBSPACE_DIRECTORY,
             SUBSPACE_FTS_QUEUE,
@@ -87,6 +121,12 @@ impl RocksDbStore {
             SUBSPACE_FTS_INDEX,
             SUBSPACE_LOGS,
             SUBSPACE_BLOBS,
+            SUBSPACE_INTENTS,
+            SUBSPACE_QUARANTINE,
+            SUBSPACE_VECTORS,
+            SUBSPACE_COLLECTIONS,
+            SUBSPACE_LABELS,
+            SUBSPACE_CLUSTER,
         ] {
             let cf_opts = Options::default();
             cfs.push(ColumnFamilyDescriptor::new(
@@ -110,8 +150,8 @@ impl RocksDbStore {
                 .unwrap_or(134217728),
         );
 
-        Some(RocksDbStore {
-            db: OptimisticTransactionDB::open_cf_descriptors(&db_opts, idx_path, cfs)
+        let db: Arc<OptimisticTransactionDB<MultiThreaded>> =
+            OptimisticTransactionDB::open_cf_descriptors(&db_opts, idx_path, cfs)
                 .map_err(|err| {
                     config.new_build_error(
                         prefix.as_str(),
@@ -119,7 +159,22 @@ impl RocksDbStore {
                     )
                 })
                 .ok()?
-                .into(),
+                .into();
+
+        let durability = DurabilityPolicy::parse(config, &prefix);
+        let group_commit = if let Some(DurabilityPolicy::GroupCommit { interval }) = durability {
+            let db = db.clone();
+            Some(GroupCommit::spawn(interval, move || {
+                db.flush_wal(true).map_err(Into::into)
+            }))
+        } else {
+            None
+        };
+
+        Some(RocksDbStore {
+            db,
+            sync_writes: durability == Some(DurabilityPolicy::Synchronous),
+            _group_commit: group_commit,
             worker_pool: rayon::ThreadPoolBuilder::new()
                 .num_threads(std::cmp::max(
                     config
@@ -136,6 +191,25 @@ impl RocksDbStore {
                     )
                 })
                 .ok()?,
+            max_value_size: config
+                .property_or_default((&prefix, "max-value-size"), "1048576")
+                .unwrap_or(DEFAULT_MAX_VALUE_SIZE),
+            read_only: AtomicBool::new(
+                config
+                    .property_or_default((&prefix, "read-only"), "false")
+                    .unwrap_or(false),
+            ),
+            write_queue: WriteQueue::new(
+                config
+                    .property_or_default((&prefix, "write-queue.depth"), "1024")
+                    .unwrap_or(DEFAULT_WRITE_QUEUE_DEPTH),
+            ),
+            cardinality_cache: CardinalityCache::parse(
+                config,
+                &prefix,
+                DEFAULT_CARDINALITY_CACHE_SIZE,
+            ),
+            token_limits: TokenLimits::parse(config, &prefix),
         })
     }
 
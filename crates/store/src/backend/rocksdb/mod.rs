@@ -4,11 +4,17 @@
  * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
  */
 
-use std::sync::Arc;
+use std::sync::{atomic::AtomicBool, Arc};
 
 use rocksdb::{BoundColumnFamily, MultiThreaded, OptimisticTransactionDB};
 
-use crate::{SUBSPACE_BLOBS, SUBSPACE_INDEXES, SUBSPACE_LOGS};
+use crate::{
+    fts::TokenLimits,
+    write::{cardinality::CardinalityCache, queue::WriteQueue},
+    SUBSPACE_BLOBS, SUBSPACE_INDEXES, SUBSPACE_LOGS,
+};
+
+use super::GroupCommit;
 
 pub mod blob;
 pub mod main;
@@ -21,7 +27,18 @@ static CF_BLOBS: &str = unsafe { std::str::from_utf8_unchecked(&[SUBSPACE_BLOBS]
 
 impl From<rocksdb::Error> for crate::Error {
     fn from(value: rocksdb::Error) -> Self {
-        Self::InternalError(format!("RocksDB error: {}", value))
+        let msg = format!("RocksDB error: {}", value);
+        match value.kind() {
+            rocksdb::ErrorKind::Busy
+            | rocksdb::ErrorKind::TryAgain
+            | rocksdb::ErrorKind::TimedOut
+            | rocksdb::ErrorKind::MergeInProgress
+            | rocksdb::ErrorKind::ShutdownInProgress => Self::Unavailable(msg),
+            rocksdb::ErrorKind::Corruption => Self::Corrupted(msg),
+            rocksdb::ErrorKind::NotSupported => Self::Unsupported(msg),
+            rocksdb::ErrorKind::NotFound => Self::NotFound(msg),
+            _ => Self::InternalError(msg),
+        }
     }
 }
 
@@ -40,4 +57,11 @@ impl CfHandle for OptimisticTransactionDB<MultiThreaded> {
 pub struct RocksDbStore {
     db: Arc<OptimisticTransactionDB<MultiThreaded>>,
     worker_pool: rayon::ThreadPool,
+    sync_writes: bool,
+    _group_commit: Option<GroupCommit>,
+    pub(crate) max_value_size: usize,
+    pub(crate) read_only: AtomicBool,
+    pub(crate) write_queue: WriteQueue,
+    pub(crate) cardinality_cache: CardinalityCache,
+    pub(crate) token_limits: TokenLimits,
 }
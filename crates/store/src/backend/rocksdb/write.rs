@@ -4,25 +4,20 @@
  * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
  */
 
-use std::{
-    sync::Arc,
-    thread::sleep,
-    time::{Duration, Instant},
-};
+use std::{sync::Arc, thread::sleep, time::Instant};
 
-use rand::Rng;
 use roaring::RoaringBitmap;
 use rocksdb::{
     BoundColumnFamily, Direction, ErrorKind, IteratorMode, OptimisticTransactionDB,
-    OptimisticTransactionOptions, WriteOptions,
+    OptimisticTransactionOptions, Transaction, WriteOptions,
 };
 
 use super::{CfHandle, RocksDbStore, CF_INDEXES, CF_LOGS};
 use crate::{
     backend::deserialize_i64_le,
     write::{
-        key::DeserializeBigEndian, AssignedIds, Batch, BitmapClass, Operation, RandomAvailableId,
-        ValueOp, MAX_COMMIT_ATTEMPTS, MAX_COMMIT_TIME,
+        key::DeserializeBigEndian, AssignedIds, AvailableId, Batch, BitmapClass, IdAllocator,
+        Operation, ValueClass, ValueOp,
     },
     BitmapKey, Deserialize, IndexKey, Key, LogKey, SUBSPACE_COUNTER, SUBSPACE_QUOTA, U32_LEN,
 };
@@ -30,10 +25,12 @@ use crate::{
 impl RocksDbStore {
     pub(crate) async fn write(&self, batch: Batch) -> crate::Result<AssignedIds> {
         let db = self.db.clone();
+        let sync_writes = self.sync_writes;
 
         self.spawn_worker(move || {
             let mut txn = RocksDBTransaction {
                 db: &db,
+                sync_writes,
                 cf_indexes: db.cf_handle(CF_INDEXES).unwrap(),
                 cf_logs: db.cf_handle(CF_LOGS).unwrap(),
                 txn_opts: OptimisticTransactionOptions::default(),
@@ -52,11 +49,9 @@ impl RocksDbStore {
                     Err(CommitError::Internal(err)) => return Err(err),
                     Err(CommitError::RocksDB(err)) => match err.kind() {
                         ErrorKind::Busy | ErrorKind::MergeInProgress | ErrorKind::TryAgain
-                            if retry_count < MAX_COMMIT_ATTEMPTS
-                                && start.elapsed() < MAX_COMMIT_TIME =>
+                            if batch.retry.can_retry(retry_count, start.elapsed()) =>
                         {
-                            let backoff = rand::thread_rng().gen_range(50..=300);
-                            sleep(Duration::from_millis(backoff));
+                            sleep(batch.retry.delay(retry_count));
                             retry_count += 1;
                         }
                         _ => return Err(err.into()),
@@ -98,6 +93,15 @@ impl RocksDbStore {
         .await
     }
 
+    pub(crate) async fn checkpoint(&self) -> crate::Result<()> {
+        let db = self.db.clone();
+        self.spawn_worker(move || {
+            db.flush_wal(true)?;
+            db.flush().map_err(Into::into)
+        })
+        .await
+    }
+
     pub(crate) async fn purge_store(&self) -> crate::Result<()> {
         let db = self.db.clone();
         self.spawn_worker(move || {
@@ -140,6 +144,7 @@ impl RocksDbStore {
 
 struct RocksDBTransaction<'x> {
     db: &'x OptimisticTransactionDB,
+    sync_writes: bool,
     cf_indexes: Arc<BoundColumnFamily<'x>>,
     cf_logs: Arc<BoundColumnFamily<'x>>,
     txn_opts: OptimisticTransactionOptions,
@@ -159,9 +164,9 @@ impl<'x> RocksDBTransaction<'x> {
         let mut change_id = u64::MAX;
         let mut result = AssignedIds::default();
 
-        let txn = self
-            .db
-            .transaction_opt(&WriteOptions::default(), &self.txn_opts);
+        let mut write_opts = WriteOptions::default();
+        write_opts.set_sync(self.sync_writes);
+        let txn = self.db.transaction_opt(&write_opts, &self.txn_opts);
 
         for op in &self.batch.ops {
             match op {
@@ -198,20 +203,17 @@ impl<'x> RocksDBTransaction<'x> {
                             txn.merge_cf(&cf, &key, &by.to_le_bytes()[..])?;
                         }
                         ValueOp::AddAndGet(by) => {
-                            let num = txn
-                                .get_pinned_for_update_cf(&cf, &key, true)
-                                .map_err(CommitError::from)
-                                .and_then(|bytes| {
-                                    if let Some(bytes) = bytes {
-                                        deserialize_i64_le(&bytes)
-                                            .map(|v| v + *by)
-                                            .map_err(CommitError::from)
-                                    } else {
-                                        Ok(*by)
-                                    }
-                                })?;
-                            txn.put_cf(&cf, &key, &num.to_le_bytes()[..])?;
-                            result.push_counter_id(num);
+                            result.push_counter_id(add_and_get(&txn, &cf, &key, *by)?);
+                        }
+                        ValueOp::AddWithLimit { by, limit } => {
+                            let used = add_and_get(&txn, &cf, &key, *by)?;
+                            if *by > 0 && used > *limit {
+                                txn.rollback()?;
+                                return Err(CommitError::Internal(crate::Error::QuotaExceeded {
+                                    used: used as u64,
+                                    limit: *limit as u64,
+                                }));
+                            }
                         }
                         ValueOp::Clear => {
                             txn.delete_cf(&cf, &key)?;
@@ -270,7 +272,36 @@ impl<'x> RocksDBTransaction<'x> {
                             }
                         }
 
-                        document_id = found_ids.random_available_id();
+                        document_id = match self.batch.id_allocator {
+                            IdAllocator::MonotonicNeverReuse => {
+                                let key = ValueClass::<u32>::DocumentIdCounter
+                                    .serialize(account_id, collection, 0, 0, None);
+                                let cf = self.db.subspace_handle(SUBSPACE_QUOTA);
+                                let next_id = found_ids.next_available_id() as i64;
+                                let mut id = add_and_get(&txn, &cf, &key, 1)? - 1;
+                                if id < next_id {
+                                    add_and_get(&txn, &cf, &key, next_id - id)?;
+                                    id = next_id;
+                                }
+                                id as u32
+                            }
+                            IdAllocator::ReuseFreed => {
+                                let id = found_ids.lowest_available_id();
+                                // Conflict with concurrent batches claiming the same id
+                                txn.get_pinned_for_update_cf(
+                                    &cf,
+                                    BitmapKey {
+                                        account_id,
+                                        collection,
+                                        class: BitmapClass::DocumentIds,
+                                        document_id: id,
+                                    }
+                                    .serialize(0),
+                                    true,
+                                )?;
+                                id
+                            }
+                        };
                         result.push_document_id(document_id);
                     }
                     let key =
@@ -317,6 +348,28 @@ impl<'x> RocksDBTransaction<'x> {
     }
 }
 
+fn add_and_get(
+    txn: &Transaction<'_, OptimisticTransactionDB>,
+    cf: &Arc<BoundColumnFamily<'_>>,
+    key: &[u8],
+    by: i64,
+) -> Result<i64, CommitError> {
+    let num = txn
+        .get_pinned_for_update_cf(cf, key, true)
+        .map_err(CommitError::from)
+        .and_then(|bytes| {
+            if let Some(bytes) = bytes {
+                deserialize_i64_le(&bytes)
+                    .map(|v| v + by)
+                    .map_err(CommitError::from)
+            } else {
+                Ok(by)
+            }
+        })?;
+    txn.put_cf(cf, key, &num.to_le_bytes()[..])?;
+    Ok(num)
+}
+
 impl From<rocksdb::Error> for CommitError {
     fn from(err: rocksdb::Error) -> Self {
         CommitError::RocksDB(err)
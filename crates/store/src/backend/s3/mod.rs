@@ -4,21 +4,22 @@
  * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
  */
 
-use std::{io::Write, ops::Range, time::Duration};
+use std::{collections::HashMap, ops::Range, sync::atomic::AtomicBool, time::Duration};
 
 use s3::{
     creds::{error::CredentialsError, Credentials},
     error::S3Error,
     Bucket, Region,
 };
-use utils::{
-    codec::base32_custom::Base32Writer,
-    config::{utils::AsKey, Config},
-};
+use utils::config::{utils::AsKey, Config};
+
+use crate::backend::{BlobKeyEncoding, BlobKeyMapping};
 
 pub struct S3Store {
     bucket: Bucket,
     prefix: Option<String>,
+    key_encoding: BlobKeyEncoding,
+    pub(crate) read_only: AtomicBool,
 }
 
 impl S3Store {
@@ -69,6 +70,14 @@ impl S3Store {
             })
             .ok()?,
             prefix: config.value((&prefix, "key-prefix")).map(|s| s.to_string()),
+            key_encoding: config
+                .property_or_default((&prefix, "key-encoding"), "base32")
+                .unwrap_or_default(),
+            read_only: AtomicBool::new(
+                config
+                    .property_or_default((&prefix, "read-only"), "false")
+                    .unwrap_or(false),
+            ),
         })
     }
 
@@ -77,7 +86,7 @@ impl S3Store {
         key: &[u8],
         range: Range<usize>,
     ) -> crate::Result<Option<Vec<u8>>> {
-        let path = self.build_key(key);
+        let path = self.map_key(key);
         let response = if range.start != 0 || range.end != usize::MAX {
             self.bucket
                 .get_object_range(
@@ -94,6 +103,8 @@ impl S3Store {
                 Ok(Some(response.to_vec()))
             }
             Ok(response) if response.status_code() == 404 => Ok(None),
+            // Range starts past the end of the object
+            Ok(response) if response.status_code() == 416 => Ok(Some(Vec::new())),
             Ok(response) => Err(crate::Error::InternalError(format!(
                 "S3 error code {}: {}",
                 response.status_code(),
@@ -104,7 +115,44 @@ impl S3Store {
     }
 
     pub(crate) async fn put_blob(&self, key: &[u8], data: &[u8]) -> crate::Result<()> {
-        match self.bucket.put_object(self.build_key(key), data).await {
+        match self.bucket.put_object(self.map_key(key), data).await {
+            Ok(response) if (200..300).contains(&response.status_code()) => Ok(()),
+            Ok(response) => Err(crate::Error::InternalError(format!(
+                "S3 error code {}: {}",
+                response.status_code(),
+                String::from_utf8_lossy(response.as_slice())
+            ))),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub(crate) async fn put_blob_if_absent(&self, key: &[u8], data: &[u8]) -> crate::Result<bool> {
+        let mut bucket = self.bucket.clone();
+        bucket.add_header("If-None-Match", "*");
+        match bucket.put_object(self.map_key(key), data).await {
+            Ok(response) if (200..300).contains(&response.status_code()) => Ok(true),
+            Ok(response) if response.status_code() == 412 => Ok(false),
+            Err(S3Error::HttpFailWithBody(412, _)) => Ok(false),
+            Ok(response) => Err(crate::Error::InternalError(format!(
+                "S3 error code {}: {}",
+                response.status_code(),
+                String::from_utf8_lossy(response.as_slice())
+            ))),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub(crate) async fn put_blob_with_meta(
+        &self,
+        key: &[u8],
+        data: &[u8],
+        meta: &HashMap<String, String>,
+    ) -> crate::Result<()> {
+        let mut bucket = self.bucket.clone();
+        for (name, value) in meta {
+            bucket.add_header(&format!("x-amz-meta-{name}"), value);
+        }
+        match bucket.put_object(self.map_key(key), data).await {
             Ok(response) if (200..300).contains(&response.status_code()) => Ok(()),
             Ok(response) => Err(crate::Error::InternalError(format!(
                 "S3 error code {}: {}",
@@ -115,24 +163,32 @@ impl S3Store {
         }
     }
 
+    pub(crate) async fn get_blob_meta(
+        &self,
+        key: &[u8],
+    ) -> crate::Result<Option<HashMap<String, String>>> {
+        match self.bucket.head_object(self.map_key(key)).await {
+            Ok((result, code)) if (200..300).contains(&code) => {
+                Ok(Some(result.metadata.unwrap_or_default()))
+            }
+            Ok((_, 404)) | Err(S3Error::HttpFailWithBody(404, _)) => Ok(None),
+            Ok((_, code)) => Err(crate::Error::InternalError(format!("S3 error code {code}"))),
+            Err(e) => Err(e.into()),
+        }
+    }
+
     pub(crate) async fn delete_blob(&self, key: &[u8]) -> crate::Result<bool> {
         self.bucket
-            .delete_object(self.build_key(key))
+            .delete_object(self.map_key(key))
             .await
             .map(|response| (200..300).contains(&response.status_code()))
             .map_err(|e| e.into())
     }
+}
 
-    fn build_key(&self, key: &[u8]) -> String {
-        if let Some(prefix) = &self.prefix {
-            let mut writer =
-                Base32Writer::with_raw_capacity(prefix.len() + ((key.len() + 3) / 4 * 5));
-            writer.push_string(prefix);
-            writer.write_all(key).unwrap();
-            writer.finalize()
-        } else {
-            Base32Writer::from_bytes(key).finalize()
-        }
+impl BlobKeyMapping for S3Store {
+    fn map_key(&self, key: &[u8]) -> String {
+        self.key_encoding.encode(self.prefix.as_deref(), key)
     }
 }
 
@@ -18,11 +18,24 @@ impl SqliteStore {
     ) -> crate::Result<Option<Vec<u8>>> {
         let conn = self.conn_pool.get()?;
         self.spawn_worker(move || {
+            #[cfg(feature = "sqlite-checksum")]
+            let mut result = conn.prepare_cached("SELECT v, c FROM t WHERE k = ?")?;
+            #[cfg(not(feature = "sqlite-checksum"))]
             let mut result = conn.prepare_cached("SELECT v FROM t WHERE k = ?")?;
             result
                 .query_row([&key], |row| {
                     Ok({
                         let bytes = row.get_ref(0)?.as_bytes()?;
+
+                        #[cfg(feature = "sqlite-checksum")]
+                        super::checksum::verify_checksum(
+                            crate::SUBSPACE_BLOBS,
+                            key,
+                            bytes,
+                            row.get(1)?,
+                        )
+                        .map_err(|err| rusqlite::Error::ToSqlConversionFailure(err.into()))?;
+
                         if range.start == 0 && range.end == usize::MAX {
                             bytes.to_vec()
                         } else {
@@ -42,14 +55,49 @@ impl SqliteStore {
     pub(crate) async fn put_blob(&self, key: &[u8], data: &[u8]) -> crate::Result<()> {
         let conn = self.conn_pool.get()?;
         self.spawn_worker(move || {
-            conn.prepare_cached("INSERT OR REPLACE INTO t (k, v) VALUES (?, ?)")?
-                .execute([key, data])
+            #[cfg(feature = "sqlite-checksum")]
+            let result = conn
+                .prepare_cached("INSERT OR REPLACE INTO t (k, v, c) VALUES (?, ?, ?)")?
+                .execute(rusqlite::params![
+                    key,
+                    data,
+                    super::checksum::checksum(data)
+                ]);
+            #[cfg(not(feature = "sqlite-checksum"))]
+            let result = conn
+                .prepare_cached("INSERT OR REPLACE INTO t (k, v) VALUES (?, ?)")?
+                .execute([key, data]);
+
+            result
                 .map_err(|e| crate::Error::InternalError(format!("Failed to insert blob: {}", e)))
                 .map(|_| ())
         })
         .await
     }
 
+    pub(crate) async fn put_blob_if_absent(&self, key: &[u8], data: &[u8]) -> crate::Result<bool> {
+        let conn = self.conn_pool.get()?;
+        self.spawn_worker(move || {
+            #[cfg(feature = "sqlite-checksum")]
+            let result = conn
+                .prepare_cached("INSERT OR IGNORE INTO t (k, v, c) VALUES (?, ?, ?)")?
+                .execute(rusqlite::params![
+                    key,
+                    data,
+                    super::checksum::checksum(data)
+                ]);
+            #[cfg(not(feature = "sqlite-checksum"))]
+            let result = conn
+                .prepare_cached("INSERT OR IGNORE INTO t (k, v) VALUES (?, ?)")?
+                .execute([key, data]);
+
+            result
+                .map_err(|e| crate::Error::InternalError(format!("Failed to insert blob: {}", e)))
+                .map(|changes| changes > 0)
+        })
+        .await
+    }
+
     pub(crate) async fn delete_blob(&self, key: &[u8]) -> crate::Result<bool> {
         let conn = self.conn_pool.get()?;
         self.spawn_worker(move || {
@@ -0,0 +1,102 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs Ltd <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use rusqlite::Connection;
+
+use crate::{
+    SUBSPACE_BITMAP_ID, SUBSPACE_BITMAP_TAG, SUBSPACE_BITMAP_TEXT, SUBSPACE_COUNTER,
+    SUBSPACE_INDEXES, SUBSPACE_QUOTA,
+};
+
+use super::SqliteStore;
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ScrubReport {
+    pub rows_scanned: u64,
+    pub rows_unchecked: u64,
+    pub corrupted: Vec<(u8, Vec<u8>)>,
+}
+
+pub(super) fn has_checksum(subspace: u8) -> bool {
+    !matches!(
+        subspace,
+        SUBSPACE_COUNTER
+            | SUBSPACE_QUOTA
+            | SUBSPACE_INDEXES
+            | SUBSPACE_BITMAP_ID
+            | SUBSPACE_BITMAP_TAG
+            | SUBSPACE_BITMAP_TEXT
+    )
+}
+
+#[inline(always)]
+pub(super) fn checksum(value: &[u8]) -> i64 {
+    xxhash_rust::xxh3::xxh3_64(value) as i64
+}
+
+pub(super) fn verify_checksum(
+    subspace: u8,
+    key: &[u8],
+    value: &[u8],
+    checksum_: Option<i64>,
+) -> crate::Result<()> {
+    // Rows written before checksums were enabled have no checksum
+    match checksum_ {
+        Some(expected) if expected != checksum(value) => Err(crate::Error::Corrupted(format!(
+            "Checksum mismatch for key {key:?} in table {:?}",
+            char::from(subspace)
+        ))),
+        _ => Ok(()),
+    }
+}
+
+pub(super) fn add_checksum_column(conn: &Connection, subspace: u8) -> crate::Result<()> {
+    let table = char::from(subspace);
+    if conn
+        .prepare(&format!("SELECT c FROM {table} LIMIT 0"))
+        .is_err()
+    {
+        conn.execute(&format!("ALTER TABLE {table} ADD COLUMN c INTEGER"), [])?;
+    }
+
+    Ok(())
+}
+
+impl SqliteStore {
+    pub(crate) async fn scrub(&self, subspaces: &[u8]) -> crate::Result<ScrubReport> {
+        let conn = self.conn_pool.get()?;
+        self.spawn_worker(move || {
+            let mut report = ScrubReport::default();
+
+            for &subspace in subspaces.iter().filter(|subspace| has_checksum(**subspace)) {
+                let mut query =
+                    conn.prepare_cached(&format!("SELECT k, v, c FROM {}", char::from(subspace)))?;
+                let mut rows = query.query([])?;
+
+                while let Some(row) = rows.next()? {
+                    let key = row.get_ref(0)?.as_bytes()?;
+                    let value = row.get_ref(1)?.as_bytes()?;
+                    let checksum_ = row.get::<_, Option<i64>>(2)?;
+
+                    report.rows_scanned += 1;
+                    if checksum_.is_none() {
+                        report.rows_unchecked += 1;
+                    } else if verify_checksum(subspace, key, value, checksum_).is_err() {
+                        tracing::warn!(
+                            "Checksum mismatch for key {:?} in table {:?}",
+                            key,
+                            char::from(subspace)
+                        );
+                        report.corrupted.push((subspace, key.to_vec()));
+                    }
+                }
+            }
+
+            Ok(report)
+        })
+        .await
+    }
+}
@@ -4,18 +4,38 @@
  * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
  */
 
+use std::sync::atomic::AtomicBool;
+
 use r2d2::Pool;
+use rusqlite::{Connection, OptionalExtension};
 use tokio::sync::oneshot;
 use utils::config::{utils::AsKey, Config};
 
-use crate::*;
+use crate::{
+    backend::{DurabilityPolicy, GroupCommit},
+    fts::TokenLimits,
+    write::{
+        cardinality::{CardinalityCache, DEFAULT_CARDINALITY_CACHE_SIZE},
+        queue::{WriteQueue, DEFAULT_WRITE_QUEUE_DEPTH},
+    },
+    *,
+};
 
 use super::{pool::SqliteConnectionManager, SqliteStore};
 
 impl SqliteStore {
     pub fn open(config: &mut Config, prefix: impl AsKey) -> Option<Self> {
         let prefix = prefix.as_key();
-        let db = Self {
+
+        // In WAL mode NORMAL only syncs on checkpoints, FULL syncs every commit
+        let durability = DurabilityPolicy::parse(config, &prefix);
+        let synchronous = if durability == Some(DurabilityPolicy::Synchronous) {
+            "FULL"
+        } else {
+            "NORMAL"
+        };
+
+        let mut db = Self {
             conn_pool: Pool::builder()
                 .max_size(
                     config
@@ -24,12 +44,15 @@ impl SqliteStore {
                 )
                 .build(
                     SqliteConnectionManager::file(config.value_require((&prefix, "path"))?)
-                        .with_init(|c| {
-                            c.execute_batch(concat!(
-                                "PRAGMA journal_mode = WAL; ",
-                                "PRAGMA synchronous = NORMAL; ",
-                                "PRAGMA temp_store = memory;",
-                                "PRAGMA busy_timeout = 30000;"
+                        .with_init(move |c| {
+                            c.execute_batch(&format!(
+                                concat!(
+                                    "PRAGMA journal_mode = WAL; ",
+                                    "PRAGMA synchronous = {}; ",
+                                    "PRAGMA temp_store = memory;",
+                                    "PRAGMA busy_timeout = 30000;"
+                                ),
+                                synchronous
                             ))
                         }),
                 )
@@ -56,8 +79,39 @@ impl SqliteStore {
                     )
                 })
                 .ok()?,
+            max_value_size: config
+                .property_or_default((&prefix, "max-value-size"), "1048576")
+                .unwrap_or(DEFAULT_MAX_VALUE_SIZE),
+            read_only: AtomicBool::new(
+                config
+                    .property_or_default((&prefix, "read-only"), "false")
+                    .unwrap_or(false),
+            ),
+            write_queue: WriteQueue::new(
+                config
+                    .property_or_default((&prefix, "write-queue.depth"), "1024")
+                    .unwrap_or(DEFAULT_WRITE_QUEUE_DEPTH),
+            ),
+            cardinality_cache: CardinalityCache::parse(
+                config,
+                &prefix,
+                DEFAULT_CARDINALITY_CACHE_SIZE,
+            ),
+            token_limits: TokenLimits::parse(config, &prefix),
+            _group_commit: None,
         };
 
+        if let Some(DurabilityPolicy::GroupCommit { interval }) = durability {
+            // Checkpoints sync the WAL to disk
+            let conn_pool = db.conn_pool.clone();
+            db._group_commit = Some(GroupCommit::spawn(interval, move || {
+                conn_pool
+                    .get()?
+                    .query_row("PRAGMA wal_checkpoint(PASSIVE)", [], |_| Ok(()))
+                    .map_err(Into::into)
+            }));
+        }
+
         if let Err(err) = db.create_tables() {
             config.new_build_error(prefix.as_str(), format!("Failed to create tables: {err}"));
         }
@@ -77,6 +131,12 @@ impl SqliteStore {
                 .map_err(|err| {
                     crate::Error::InternalError(format!("Failed to build worker pool: {}", err))
                 })?,
+            max_value_size: DEFAULT_MAX_VALUE_SIZE,
+            read_only: AtomicBool::new(false),
+            write_queue: WriteQueue::default(),
+            cardinality_cache: CardinalityCache::default(),
+            token_limits: TokenLimits::default(),
+            _group_commit: None,
         };
         db.create_tables()?;
         Ok(db)
@@ -101,6 +161,12 @@ impl SqliteStore {
             SUBSPACE_FTS_INDEX,
             SUBSPACE_LOGS,
             SUBSPACE_BLOBS,
+            SUBSPACE_INTENTS,
+            SUBSPACE_QUARANTINE,
+            SUBSPACE_VECTORS,
+            SUBSPACE_COLLECTIONS,
+            SUBSPACE_LABELS,
+            SUBSPACE_CLUSTER,
         ] {
             let table = char::from(table);
             conn.execute(
@@ -112,8 +178,13 @@ impl SqliteStore {
                 ),
                 [],
             )?;
+
+            #[cfg(feature = "sqlite-checksum")]
+            super::checksum::add_checksum_column(&conn, table as u8)?;
         }
 
+        // SQLite has no prefix compression, but key-only tables without a rowid
+        // store each key once rather than in both the table and its primary key index
         for table in [
             SUBSPACE_INDEXES,
             SUBSPACE_BITMAP_ID,
@@ -125,10 +196,15 @@ impl SqliteStore {
                 &format!(
                     "CREATE TABLE IF NOT EXISTS {table} (
                         k BLOB PRIMARY KEY
-                    )"
+                    ) WITHOUT ROWID"
                 ),
                 [],
             )?;
+
+            // Databases created before are converted once
+            if migrate_without_rowid(&conn, table)? {
+                tracing::info!("Converted SQLite table {table:?} to a table without rowid");
+            }
         }
 
         for table in [SUBSPACE_COUNTER, SUBSPACE_QUOTA] {
@@ -169,3 +245,84 @@ impl SqliteStore {
         }
     }
 }
+
+// Copies the keys of a rowid table into a new table without rowid, returning
+// whether the table had to be converted
+fn migrate_without_rowid(conn: &Connection, table: char) -> crate::Result<bool> {
+    let sql = conn
+        .query_row(
+            "SELECT sql FROM sqlite_master WHERE type = 'table' AND name = ?",
+            [table.to_string()],
+            |row| row.get::<_, String>(0),
+        )
+        .optional()?
+        .unwrap_or_default();
+    if sql.is_empty() || sql.to_ascii_uppercase().contains("WITHOUT ROWID") {
+        return Ok(false);
+    }
+
+    conn.execute_batch(&format!(
+        "BEGIN IMMEDIATE;
+        CREATE TABLE {table}_migrate (k BLOB PRIMARY KEY) WITHOUT ROWID;
+        INSERT INTO {table}_migrate SELECT k FROM {table};
+        DROP TABLE {table};
+        ALTER TABLE {table}_migrate RENAME TO {table};
+        COMMIT;"
+    ))
+    .inspect_err(|_| {
+        let _ = conn.execute_batch("ROLLBACK");
+    })?;
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::Connection;
+
+    use super::migrate_without_rowid;
+
+    fn used_pages(conn: &Connection) -> i64 {
+        let page_count: i64 = conn
+            .query_row("PRAGMA page_count", [], |row| row.get(0))
+            .unwrap();
+        let freelist_count: i64 = conn
+            .query_row("PRAGMA freelist_count", [], |row| row.get(0))
+            .unwrap();
+        page_count - freelist_count
+    }
+
+    #[test]
+    fn key_tables_without_rowid() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute("CREATE TABLE i (k BLOB PRIMARY KEY)", [])
+            .unwrap();
+
+        // Index keys sharing an account, collection and field prefix
+        let mut insert = conn.prepare("INSERT INTO i (k) VALUES (?)").unwrap();
+        for document_id in 0u32..20_000 {
+            let mut key = Vec::with_capacity(21);
+            key.extend_from_slice(&1234u32.to_be_bytes());
+            key.push(0);
+            key.push(3);
+            key.extend_from_slice(format!("subject {:07}", document_id % 500).as_bytes());
+            key.extend_from_slice(&document_id.to_be_bytes());
+            insert.execute([key]).unwrap();
+        }
+        drop(insert);
+
+        let before = used_pages(&conn);
+        assert!(migrate_without_rowid(&conn, 'i').unwrap());
+        assert!(!migrate_without_rowid(&conn, 'i').unwrap());
+        let after = used_pages(&conn);
+        assert!(
+            after * 10 < before * 7,
+            "expected at least 30% fewer pages, got {before} before and {after} after"
+        );
+
+        let count: i64 = conn
+            .query_row("SELECT COUNT(*) FROM i", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 20_000);
+    }
+}
@@ -4,11 +4,22 @@
  * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
  */
 
+use std::sync::atomic::AtomicBool;
+
 use r2d2::Pool;
 
+use crate::{
+    fts::TokenLimits,
+    write::{cardinality::CardinalityCache, queue::WriteQueue},
+};
+
 use self::pool::SqliteConnectionManager;
 
+use super::GroupCommit;
+
 pub mod blob;
+#[cfg(feature = "sqlite-checksum")]
+pub mod checksum;
 pub mod lookup;
 pub mod main;
 pub mod pool;
@@ -17,13 +28,22 @@ pub mod write;
 
 impl From<r2d2::Error> for crate::Error {
     fn from(err: r2d2::Error) -> Self {
-        Self::InternalError(format!("Connection pool error: {}", err))
+        Self::Unavailable(format!("Connection pool error: {}", err))
     }
 }
 
 impl From<rusqlite::Error> for crate::Error {
     fn from(err: rusqlite::Error) -> Self {
-        Self::InternalError(format!("SQLite error: {}", err))
+        use rusqlite::ErrorCode;
+
+        let msg = format!("SQLite error: {}", err);
+        match err.sqlite_error_code() {
+            Some(ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked) => Self::Unavailable(msg),
+            Some(ErrorCode::DatabaseCorrupt | ErrorCode::NotADatabase) => Self::Corrupted(msg),
+            // Retrying won't free up space, so a full disk is not transient
+            Some(ErrorCode::DiskFull) => Self::InternalError(msg),
+            _ => Self::InternalError(msg),
+        }
     }
 }
 
@@ -36,4 +56,10 @@ impl From<rusqlite::types::FromSqlError> for crate::Error {
 pub struct SqliteStore {
     pub(crate) conn_pool: Pool<SqliteConnectionManager>,
     pub(crate) worker_pool: rayon::ThreadPool,
+    pub(crate) max_value_size: usize,
+    pub(crate) read_only: AtomicBool,
+    pub(crate) write_queue: WriteQueue,
+    pub(crate) cardinality_cache: CardinalityCache,
+    pub(crate) token_limits: TokenLimits,
+    pub(crate) _group_commit: Option<GroupCommit>,
 }
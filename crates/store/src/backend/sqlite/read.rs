@@ -21,6 +21,25 @@ impl SqliteStore {
     {
         let conn = self.conn_pool.get()?;
         self.spawn_worker(move || {
+            #[cfg(feature = "sqlite-checksum")]
+            if super::checksum::has_checksum(key.subspace()) {
+                let subspace = key.subspace();
+                let mut result = conn.prepare_cached(&format!(
+                    "SELECT v, c FROM {} WHERE k = ?",
+                    char::from(subspace)
+                ))?;
+                let key = key.serialize(0);
+                return result
+                    .query_row([&key], |row| {
+                        let value = row.get_ref(0)?.as_bytes()?;
+                        super::checksum::verify_checksum(subspace, &key, value, row.get(1)?)
+                            .and_then(|_| U::deserialize(value))
+                            .map_err(|err| rusqlite::Error::ToSqlConversionFailure(err.into()))
+                    })
+                    .optional()
+                    .map_err(Into::into);
+            }
+
             let mut result = conn.prepare_cached(&format!(
                 "SELECT v FROM {} WHERE k = ?",
                 char::from(key.subspace())
@@ -73,10 +92,21 @@ impl SqliteStore {
         let conn = self.conn_pool.get()?;
 
         self.spawn_worker(move || {
-            let table = char::from(params.begin.subspace());
+            let subspace = params.begin.subspace();
+            let table = char::from(subspace);
             let begin = params.begin.serialize(0);
             let end = params.end.serialize(0);
-            let keys = if params.values { "k, v" } else { "k" };
+            #[cfg(feature = "sqlite-checksum")]
+            let with_checksum = params.values && super::checksum::has_checksum(subspace);
+            #[cfg(not(feature = "sqlite-checksum"))]
+            let with_checksum = false;
+            let keys = if with_checksum {
+                "k, v, c"
+            } else if params.values {
+                "k, v"
+            } else {
+                "k"
+            };
 
             let mut query = conn.prepare_cached(&match (params.first, params.ascending) {
                 (true, true) => {
@@ -103,6 +133,11 @@ impl SqliteStore {
                     let key = row.get_ref(0)?.as_bytes()?;
                     let value = row.get_ref(1)?.as_bytes()?;
 
+                    #[cfg(feature = "sqlite-checksum")]
+                    if with_checksum {
+                        super::checksum::verify_checksum(subspace, key, value, row.get(2)?)?;
+                    }
+
                     if !cb(key, value)? {
                         break;
                     }
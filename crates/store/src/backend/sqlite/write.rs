@@ -5,12 +5,12 @@
  */
 
 use roaring::RoaringBitmap;
-use rusqlite::{params, OptionalExtension, TransactionBehavior};
+use rusqlite::{params, OptionalExtension, Transaction, TransactionBehavior};
 
 use crate::{
     write::{
-        key::DeserializeBigEndian, AssignedIds, Batch, BitmapClass, Operation, RandomAvailableId,
-        ValueOp,
+        key::DeserializeBigEndian, AssignedIds, AvailableId, Batch, BitmapClass, IdAllocator,
+        Operation, ValueClass, ValueOp,
     },
     BitmapKey, IndexKey, Key, LogKey, SUBSPACE_COUNTER, SUBSPACE_QUOTA, U32_LEN,
 };
@@ -25,6 +25,8 @@ impl SqliteStore {
             let mut collection = u8::MAX;
             let mut document_id = u32::MAX;
             let mut change_id = u64::MAX;
+            // Deferred transactions fail with SQLITE_BUSY when upgrading to a
+            // write lock held by another connection, writes are serialized anyway
             let trx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
             let mut result = AssignedIds::default();
 
@@ -61,6 +63,21 @@ impl SqliteStore {
                         let table = char::from(class.subspace(collection));
 
                         match op {
+                            #[cfg(feature = "sqlite-checksum")]
+                            ValueOp::Set(value)
+                                if super::checksum::has_checksum(class.subspace(collection)) =>
+                            {
+                                let value = value.resolve(&result)?;
+                                trx.prepare_cached(&format!(
+                                    "INSERT OR REPLACE INTO {} (k, v, c) VALUES (?, ?, ?)",
+                                    table
+                                ))?
+                                .execute(params![
+                                    &key,
+                                    value.as_ref(),
+                                    super::checksum::checksum(value.as_ref())
+                                ])?;
+                            }
                             ValueOp::Set(value) => {
                                 trx.prepare_cached(&format!(
                                     "INSERT OR REPLACE INTO {} (k, v) VALUES (?, ?)",
@@ -86,17 +103,17 @@ impl SqliteStore {
                                 }
                             }
                             ValueOp::AddAndGet(by) => {
-                                result.push_counter_id(
-                                    trx.prepare_cached(&format!(
-                                        concat!(
-                                            "INSERT INTO {} (k, v) VALUES (?, ?) ",
-                                            "ON CONFLICT(k) DO UPDATE SET v = v + ",
-                                            "excluded.v RETURNING v"
-                                        ),
-                                        table
-                                    ))?
-                                    .query_row(params![&key, &by], |row| row.get::<_, i64>(0))?,
-                                );
+                                result.push_counter_id(add_and_get(&trx, table, &key, *by)?);
+                            }
+                            ValueOp::AddWithLimit { by, limit } => {
+                                let used = add_and_get(&trx, table, &key, *by)?;
+                                if *by > 0 && used > *limit {
+                                    trx.rollback()?;
+                                    return Err(crate::Error::QuotaExceeded {
+                                        used: used as u64,
+                                        limit: *limit as u64,
+                                    });
+                                }
                             }
                             ValueOp::Clear => {
                                 trx.prepare_cached(&format!("DELETE FROM {} WHERE k = ?", table))?
@@ -153,7 +170,21 @@ impl SqliteStore {
                                 }
                             }
 
-                            document_id = found_ids.random_available_id();
+                            document_id = match batch.id_allocator {
+                                IdAllocator::MonotonicNeverReuse => {
+                                    let key = ValueClass::<u32>::DocumentIdCounter
+                                        .serialize(account_id, collection, 0, 0, None);
+                                    let table = char::from(SUBSPACE_QUOTA);
+                                    let next_id = found_ids.next_available_id() as i64;
+                                    let mut id = add_and_get(&trx, table, &key, 1)? - 1;
+                                    if id < next_id {
+                                        add_and_get(&trx, table, &key, next_id - id)?;
+                                        id = next_id;
+                                    }
+                                    id as u32
+                                }
+                                IdAllocator::ReuseFreed => found_ids.lowest_available_id(),
+                            };
                             result.push_document_id(document_id);
                         }
                         let key = class.serialize(
@@ -189,6 +220,20 @@ impl SqliteStore {
                         }
                         .serialize(0);
 
+                        #[cfg(feature = "sqlite-checksum")]
+                        {
+                            let set = set.resolve(&result)?;
+                            trx.prepare_cached(
+                                "INSERT OR REPLACE INTO l (k, v, c) VALUES (?, ?, ?)",
+                            )?
+                            .execute(params![
+                                &key,
+                                set.as_ref(),
+                                super::checksum::checksum(set.as_ref())
+                            ])?;
+                        }
+
+                        #[cfg(not(feature = "sqlite-checksum"))]
                         trx.prepare_cached("INSERT OR REPLACE INTO l (k, v) VALUES (?, ?)")?
                             .execute([&key, set.resolve(&result)?.as_ref()])?;
                     }
@@ -238,6 +283,24 @@ impl SqliteStore {
         .await
     }
 
+    pub(crate) async fn checkpoint(&self) -> crate::Result<()> {
+        let conn = self.conn_pool.get()?;
+        self.spawn_worker(move || {
+            let busy = conn.query_row("PRAGMA wal_checkpoint(FULL)", [], |row| {
+                row.get::<_, i64>(0)
+            })?;
+
+            if busy == 0 {
+                Ok(())
+            } else {
+                Err(crate::Error::InternalError(
+                    "WAL checkpoint could not complete, database is busy".to_string(),
+                ))
+            }
+        })
+        .await
+    }
+
     pub(crate) async fn delete_range(&self, from: impl Key, to: impl Key) -> crate::Result<()> {
         let conn = self.conn_pool.get()?;
         self.spawn_worker(move || {
@@ -252,3 +315,15 @@ impl SqliteStore {
         .await
     }
 }
+
+fn add_and_get(trx: &Transaction<'_>, table: char, key: &[u8], by: i64) -> rusqlite::Result<i64> {
+    trx.prepare_cached(&format!(
+        concat!(
+            "INSERT INTO {} (k, v) VALUES (?, ?) ",
+            "ON CONFLICT(k) DO UPDATE SET v = v + ",
+            "excluded.v RETURNING v"
+        ),
+        table
+    ))?
+    .query_row(params![key, by], |row| row.get::<_, i64>(0))
+}
@@ -0,0 +1,96 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs Ltd <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::ops::Range;
+
+use utils::config::{utils::AsKey, Config};
+
+use crate::{BlobStore, Stores};
+
+pub struct TieredBlobStore {
+    pub hot: BlobStore,
+    pub cold: BlobStore,
+}
+
+impl TieredBlobStore {
+    pub fn open(config: &mut Config, prefix: impl AsKey, stores: &Stores) -> Option<Self> {
+        let prefix = prefix.as_key();
+        let hot_id = config.value_require((&prefix, "hot"))?.to_string();
+        let cold_id = config.value_require((&prefix, "cold"))?.to_string();
+
+        if hot_id == cold_id {
+            config.new_build_error(
+                prefix.as_str(),
+                "Hot and cold tiers must point to different blob stores",
+            );
+            return None;
+        }
+
+        let mut tiers = Vec::with_capacity(2);
+        for (key, id) in [("hot", hot_id), ("cold", cold_id)] {
+            if let Some(store) = stores.blob_stores.get(&id) {
+                tiers.push(store.clone());
+            } else {
+                config.new_build_error(
+                    (&prefix, key),
+                    format!("Blob store {id:?} not found or not yet configured"),
+                );
+                return None;
+            }
+        }
+        let cold = tiers.pop().unwrap();
+        let hot = tiers.pop().unwrap();
+
+        Some(TieredBlobStore { hot, cold })
+    }
+
+    pub async fn get_blob(
+        &self,
+        key: &[u8],
+        range: Range<usize>,
+    ) -> crate::Result<Option<Vec<u8>>> {
+        match Box::pin(self.hot.get_blob(key, range.clone())).await? {
+            Some(data) => Ok(Some(data)),
+            None => Box::pin(self.cold.get_blob(key, range)).await,
+        }
+    }
+
+    pub async fn put_blob(&self, key: &[u8], data: &[u8]) -> crate::Result<()> {
+        Box::pin(self.hot.put_blob(key, data)).await
+    }
+
+    /// Writes a blob to the hot tier unless it is stored in either tier. The check
+    /// of the cold tier is not atomic with the write, so a blob being migrated at
+    /// the same time may end up in both tiers, which `delete_blob` accounts for.
+    pub async fn put_blob_if_absent(&self, key: &[u8], data: &[u8]) -> crate::Result<bool> {
+        if Box::pin(self.cold.has_blob(key)).await? {
+            Ok(false)
+        } else {
+            Box::pin(self.hot.put_blob_if_absent(key, data)).await
+        }
+    }
+
+    pub async fn delete_blob(&self, key: &[u8]) -> crate::Result<bool> {
+        // Blobs might have been copied to the cold tier without being removed
+        // from the hot tier (i.e. an interrupted migration), so delete from both.
+        let deleted_hot = Box::pin(self.hot.delete_blob(key)).await?;
+        let deleted_cold = Box::pin(self.cold.delete_blob(key)).await?;
+
+        Ok(deleted_hot || deleted_cold)
+    }
+
+    pub async fn migrate_to_cold(&self, key: &[u8]) -> crate::Result<bool> {
+        if let Some(data) = Box::pin(self.hot.get_blob(key, 0..usize::MAX)).await? {
+            // Write to the cold tier first, so the blob is always readable
+            // from at least one tier.
+            Box::pin(self.cold.put_blob(key, &data)).await?;
+            Box::pin(self.hot.delete_blob(key)).await?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+}
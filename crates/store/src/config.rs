@@ -9,14 +9,17 @@ use std::sync::Arc;
 use utils::config::{cron::SimpleCron, utils::ParseValue, Config};
 
 use crate::{
-    backend::fs::FsStore,
+    backend::{fs::FsStore, replicated::ReplicatedStore, tiered::TieredBlobStore},
     write::purge::{PurgeSchedule, PurgeStore},
-    BlobStore, CompressionAlgo, FtsStore, LookupStore, QueryStore, Store, Stores,
+    BlobStore, CompressionAlgo, FtsStore, LookupStore, QueryStore, ReadAhead, Store, Stores,
 };
 
 #[cfg(feature = "s3")]
 use crate::backend::s3::S3Store;
 
+#[cfg(feature = "azure")]
+use crate::backend::azure::AzureStore;
+
 #[cfg(feature = "postgres")]
 use crate::backend::postgres::PostgresStore;
 
@@ -53,6 +56,8 @@ impl Stores {
 
     pub async fn parse_stores(&mut self, config: &mut Config) {
         let is_reload = !self.stores.is_empty();
+        let mut tiered_ids = Vec::new();
+        let mut replicated_ids = Vec::new();
 
         for id in config
             .sub_keys("store", ".type")
@@ -81,6 +86,14 @@ impl Stores {
             let compression_algo = config
                 .property_or_default::<CompressionAlgo>(("store", id, "compression"), "none")
                 .unwrap_or(CompressionAlgo::None);
+            let read_ahead = ReadAhead {
+                chunk_size: config
+                    .property_or_default(("store", id, "read-ahead.chunk-size"), "1048576")
+                    .unwrap_or(1024 * 1024),
+                depth: config
+                    .property_or_default(("store", id, "read-ahead.depth"), "2")
+                    .unwrap_or(2),
+            };
 
             match protocol.as_str() {
                 #[cfg(feature = "rocks")]
@@ -100,7 +113,9 @@ impl Stores {
                         self.fts_stores.insert(store_id.clone(), db.clone().into());
                         self.blob_stores.insert(
                             store_id.clone(),
-                            BlobStore::from(db.clone()).with_compression(compression_algo),
+                            BlobStore::from(db.clone())
+                                .with_compression(compression_algo)
+                                .with_read_ahead(read_ahead),
                         );
                         self.lookup_stores.insert(store_id, db.into());
                     }
@@ -122,7 +137,9 @@ impl Stores {
                         self.fts_stores.insert(store_id.clone(), db.clone().into());
                         self.blob_stores.insert(
                             store_id.clone(),
-                            BlobStore::from(db.clone()).with_compression(compression_algo),
+                            BlobStore::from(db.clone())
+                                .with_compression(compression_algo)
+                                .with_read_ahead(read_ahead),
                         );
                         self.lookup_stores.insert(store_id, db.into());
                     }
@@ -134,7 +151,9 @@ impl Stores {
                         self.fts_stores.insert(store_id.clone(), db.clone().into());
                         self.blob_stores.insert(
                             store_id.clone(),
-                            BlobStore::from(db.clone()).with_compression(compression_algo),
+                            BlobStore::from(db.clone())
+                                .with_compression(compression_algo)
+                                .with_read_ahead(read_ahead),
                         );
                         self.lookup_stores.insert(store_id.clone(), db.into());
                     }
@@ -146,7 +165,9 @@ impl Stores {
                         self.fts_stores.insert(store_id.clone(), db.clone().into());
                         self.blob_stores.insert(
                             store_id.clone(),
-                            BlobStore::from(db.clone()).with_compression(compression_algo),
+                            BlobStore::from(db.clone())
+                                .with_compression(compression_algo)
+                                .with_read_ahead(read_ahead),
                         );
                         self.lookup_stores.insert(store_id.clone(), db.into());
                     }
@@ -168,24 +189,50 @@ impl Stores {
                         self.fts_stores.insert(store_id.clone(), db.clone().into());
                         self.blob_stores.insert(
                             store_id.clone(),
-                            BlobStore::from(db.clone()).with_compression(compression_algo),
+                            BlobStore::from(db.clone())
+                                .with_compression(compression_algo)
+                                .with_read_ahead(read_ahead),
                         );
                         self.lookup_stores.insert(store_id.clone(), db.into());
                     }
                 }
                 "fs" => {
                     if let Some(db) = FsStore::open(config, prefix).await.map(BlobStore::from) {
-                        self.blob_stores
-                            .insert(store_id, db.with_compression(compression_algo));
+                        self.blob_stores.insert(
+                            store_id,
+                            db.with_compression(compression_algo)
+                                .with_read_ahead(read_ahead),
+                        );
                     }
                 }
                 #[cfg(feature = "s3")]
                 "s3" => {
                     if let Some(db) = S3Store::open(config, prefix).await.map(BlobStore::from) {
-                        self.blob_stores
-                            .insert(store_id, db.with_compression(compression_algo));
+                        self.blob_stores.insert(
+                            store_id,
+                            db.with_compression(compression_algo)
+                                .with_read_ahead(read_ahead),
+                        );
                     }
                 }
+                #[cfg(feature = "azure")]
+                "azure" => {
+                    if let Some(db) = AzureStore::open(config, prefix).await.map(BlobStore::from) {
+                        self.blob_stores.insert(
+                            store_id,
+                            db.with_compression(compression_algo)
+                                .with_read_ahead(read_ahead),
+                        );
+                    }
+                }
+                "tiered" => {
+                    // Tiered stores reference other blob stores, parse them last
+                    tiered_ids.push(store_id);
+                }
+                "replicated" => {
+                    // Replicated stores reference other data stores, parse them last
+                    replicated_ids.push(store_id);
+                }
                 #[cfg(feature = "elastic")]
                 "elasticsearch" => {
                     if let Some(db) = ElasticSearchStore::open(config, prefix)
@@ -209,6 +256,25 @@ impl Stores {
                 }
             }
         }
+
+        for store_id in tiered_ids {
+            if let Some(db) = TieredBlobStore::open(config, ("store", store_id.as_str()), self)
+                .map(BlobStore::from)
+            {
+                self.blob_stores.insert(store_id, db);
+            }
+        }
+
+        for store_id in replicated_ids {
+            if let Some(db) =
+                ReplicatedStore::open(config, ("store", store_id.as_str()), self).map(Arc::new)
+            {
+                if !db.replicas.is_empty() {
+                    db.spawn_heartbeat();
+                }
+                self.stores.insert(store_id, Store::Replicated(db));
+            }
+        }
     }
 
     pub async fn parse_lookups(&mut self, config: &mut Config) {
@@ -309,7 +375,16 @@ impl Stores {
 impl From<crate::Error> for String {
     fn from(err: crate::Error) -> Self {
         match err {
-            crate::Error::InternalError(err) => err,
+            crate::Error::InternalError(err)
+            | crate::Error::Unavailable(err)
+            | crate::Error::NotFound(err)
+            | crate::Error::Unsupported(err)
+            | crate::Error::Corrupted(err) => err,
+            crate::Error::ValueTooLarge { .. }
+            | crate::Error::QuotaExceeded { .. }
+            | crate::Error::Timeout(_)
+            | crate::Error::MemoryLimitExceeded { .. }
+            | crate::Error::ReadOnly => err.to_string(),
             crate::Error::AssertValueFailed => unimplemented!(),
         }
     }
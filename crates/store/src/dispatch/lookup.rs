@@ -4,12 +4,15 @@
  * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
  */
 
+use std::sync::atomic::Ordering;
+
 use utils::config::Rate;
 
 use crate::{write::LookupClass, Row};
 #[allow(unused_imports)]
 use crate::{
     write::{
+        assert::{AssertValue, HashedValue},
         key::{DeserializeBigEndian, KeySerializer},
         now, BatchBuilder, Operation, ValueClass, ValueOp,
     },
@@ -47,6 +50,8 @@ impl LookupStore {
         value: Vec<u8>,
         expires: Option<u64>,
     ) -> crate::Result<()> {
+        self.assert_writable()?;
+
         match self {
             LookupStore::Store(store) => {
                 let mut batch = BatchBuilder::new();
@@ -85,10 +90,31 @@ impl LookupStore {
         expires: Option<u64>,
         return_value: bool,
     ) -> crate::Result<i64> {
+        self.assert_writable()?;
+
         match self {
-            LookupStore::Store(store) => {
+            LookupStore::Store(store) => loop {
                 let mut batch = BatchBuilder::new();
 
+                // Expired counters that were not purged yet start over. The expiry is
+                // asserted so that concurrent increments do not reset each other.
+                if let Some(expiry) = store
+                    .get_value::<HashedValue<CounterExpiry>>(ValueKey::from(ValueClass::Lookup(
+                        LookupClass::Key(key.clone()),
+                    )))
+                    .await?
+                    .filter(|expiry| expiry.inner.is_expired(now()))
+                {
+                    batch.assert_value(ValueClass::Lookup(LookupClass::Key(key.clone())), &expiry);
+                    batch.ops.push(Operation::Value {
+                        class: ValueClass::Lookup(LookupClass::Counter(key.clone())),
+                        op: ValueOp::Clear,
+                    });
+                    if expires.is_none() {
+                        batch.clear(ValueClass::Lookup(LookupClass::Key(key.clone())));
+                    }
+                }
+
                 if let Some(expires) = expires {
                     batch.ops.push(Operation::Value {
                         class: ValueClass::Lookup(LookupClass::Key(key.clone())),
@@ -103,7 +129,7 @@ impl LookupStore {
                 }
 
                 batch.ops.push(Operation::Value {
-                    class: ValueClass::Lookup(LookupClass::Counter(key)),
+                    class: ValueClass::Lookup(LookupClass::Counter(key.clone())),
                     op: if return_value {
                         ValueOp::AddAndGet(value)
                     } else {
@@ -111,14 +137,18 @@ impl LookupStore {
                     },
                 });
 
-                store.write(batch.build()).await.and_then(|r| {
-                    if return_value {
-                        r.last_counter_id()
-                    } else {
-                        Ok(0)
+                match store.write(batch.build()).await {
+                    Ok(result) => {
+                        return if return_value {
+                            result.last_counter_id()
+                        } else {
+                            Ok(0)
+                        };
                     }
-                })
-            }
+                    Err(crate::Error::AssertValueFailed) => continue,
+                    Err(err) => return Err(err),
+                }
+            },
             #[cfg(feature = "redis")]
             LookupStore::Redis(store) => store.key_incr(key, value, expires).await,
             LookupStore::Query(_) | LookupStore::Memory(_) => Err(crate::Error::InternalError(
@@ -128,6 +158,8 @@ impl LookupStore {
     }
 
     pub async fn key_delete(&self, key: Vec<u8>) -> crate::Result<()> {
+        self.assert_writable()?;
+
         match self {
             LookupStore::Store(store) => {
                 let mut batch = BatchBuilder::new();
@@ -146,6 +178,8 @@ impl LookupStore {
     }
 
     pub async fn counter_delete(&self, key: Vec<u8>) -> crate::Result<()> {
+        self.assert_writable()?;
+
         match self {
             LookupStore::Store(store) => {
                 let mut batch = BatchBuilder::new();
@@ -196,11 +230,25 @@ impl LookupStore {
     pub async fn counter_get(&self, key: Vec<u8>) -> crate::Result<i64> {
         match self {
             LookupStore::Store(store) => {
-                store
+                let counter = store
                     .get_counter(ValueKey::from(ValueClass::Lookup(LookupClass::Counter(
-                        key,
+                        key.clone(),
                     ))))
-                    .await
+                    .await?;
+
+                // Expired counters read as zero until they are purged
+                if counter != 0
+                    && store
+                        .get_value::<CounterExpiry>(ValueKey::from(ValueClass::Lookup(
+                            LookupClass::Key(key),
+                        )))
+                        .await?
+                        .map_or(false, |expiry| expiry.is_expired(now()))
+                {
+                    Ok(0)
+                } else {
+                    Ok(counter)
+                }
             }
             #[cfg(feature = "redis")]
             LookupStore::Redis(store) => store.counter_get(key).await,
@@ -234,6 +282,37 @@ impl LookupStore {
         }
     }
 
+    pub async fn key_range(&self, from: Vec<u8>, to: Vec<u8>) -> crate::Result<Vec<Vec<u8>>> {
+        match self {
+            LookupStore::Store(store) => {
+                let current_time = now();
+                let mut keys = Vec::new();
+                store
+                    .iterate(
+                        IterateParams::new(
+                            ValueKey::from(ValueClass::Lookup(LookupClass::Key(from))),
+                            ValueKey::from(ValueClass::Lookup(LookupClass::Key(to))),
+                        ),
+                        |key, value| {
+                            // Counters store a zero expiry followed by the actual expiry
+                            let expiry = value.deserialize_be_u64(0)?;
+                            if expiry == 0 || expiry > current_time {
+                                keys.push(key.to_vec());
+                            }
+                            Ok(true)
+                        },
+                    )
+                    .await?;
+                Ok(keys)
+            }
+            #[cfg(feature = "redis")]
+            LookupStore::Redis(store) => store.key_range(from, to).await,
+            LookupStore::Query(_) | LookupStore::Memory(_) => Err(crate::Error::InternalError(
+                "This store does not support key_range".into(),
+            )),
+        }
+    }
+
     pub async fn is_rate_allowed(
         &self,
         key: &[u8],
@@ -266,65 +345,7 @@ impl LookupStore {
     pub async fn purge_lookup_store(&self) -> crate::Result<()> {
         match self {
             LookupStore::Store(store) => {
-                // Delete expired keys and counters
-                let from_key = ValueKey::from(ValueClass::Lookup(LookupClass::Key(vec![0u8])));
-                let to_key =
-                    ValueKey::from(ValueClass::Lookup(LookupClass::Key(vec![u8::MAX; 10])));
-
-                let current_time = now();
-                let mut expired_keys = Vec::new();
-                let mut expired_counters = Vec::new();
-                store
-                    .iterate(IterateParams::new(from_key, to_key), |key, value| {
-                        let expiry = value.deserialize_be_u64(0)?;
-                        if expiry == 0 {
-                            if value.deserialize_be_u64(U64_LEN)? <= current_time {
-                                expired_counters.push(key.to_vec());
-                            }
-                        } else if expiry <= current_time {
-                            expired_keys.push(key.to_vec());
-                        }
-                        Ok(true)
-                    })
-                    .await?;
-
-                if !expired_keys.is_empty() {
-                    let mut batch = BatchBuilder::new();
-                    for key in expired_keys {
-                        batch.ops.push(Operation::Value {
-                            class: ValueClass::Lookup(LookupClass::Key(key)),
-                            op: ValueOp::Clear,
-                        });
-                        if batch.ops.len() >= 1000 {
-                            store.write(batch.build()).await?;
-                            batch = BatchBuilder::new();
-                        }
-                    }
-                    if !batch.ops.is_empty() {
-                        store.write(batch.build()).await?;
-                    }
-                }
-
-                if !expired_counters.is_empty() {
-                    let mut batch = BatchBuilder::new();
-                    for key in expired_counters {
-                        batch.ops.push(Operation::Value {
-                            class: ValueClass::Lookup(LookupClass::Counter(key.clone())),
-                            op: ValueOp::Clear,
-                        });
-                        batch.ops.push(Operation::Value {
-                            class: ValueClass::Lookup(LookupClass::Key(key)),
-                            op: ValueOp::Clear,
-                        });
-                        if batch.ops.len() >= 1000 {
-                            store.write(batch.build()).await?;
-                            batch = BatchBuilder::new();
-                        }
-                    }
-                    if !batch.ops.is_empty() {
-                        store.write(batch.build()).await?;
-                    }
-                }
+                store.purge_expired_lookups(true).await?;
             }
             #[cfg(feature = "redis")]
             LookupStore::Redis(_) => {}
@@ -334,6 +355,36 @@ impl LookupStore {
         Ok(())
     }
 
+    /// Puts the lookup store in read-only mode, where writes fail with
+    /// `Error::ReadOnly`, see `Store::set_read_only`.
+    pub fn set_read_only(&self, read_only: bool) {
+        match self {
+            LookupStore::Store(store) => store.set_read_only(read_only),
+            LookupStore::Query(lookup) => lookup.store.set_read_only(read_only),
+            #[cfg(feature = "redis")]
+            LookupStore::Redis(store) => store.read_only.store(read_only, Ordering::Relaxed),
+            LookupStore::Memory(_) => {}
+        }
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        match self {
+            LookupStore::Store(store) => store.is_read_only(),
+            LookupStore::Query(lookup) => lookup.store.is_read_only(),
+            #[cfg(feature = "redis")]
+            LookupStore::Redis(store) => store.read_only.load(Ordering::Relaxed),
+            LookupStore::Memory(_) => false,
+        }
+    }
+
+    fn assert_writable(&self) -> crate::Result<()> {
+        if !self.is_read_only() {
+            Ok(())
+        } else {
+            Err(crate::Error::ReadOnly)
+        }
+    }
+
     pub fn is_sql(&self) -> bool {
         match self {
             LookupStore::Store(store) => store.is_sql(),
@@ -342,6 +393,96 @@ impl LookupStore {
     }
 }
 
+impl Store {
+    /// Deletes the counters whose expiry has passed. Cleanup is eventual: expired
+    /// counters keep using space until the next sweep, although
+    /// `LookupStore::counter_get` already reads them as zero. Counters incremented
+    /// while the sweep runs are left for the next one. Returns the number of
+    /// counters deleted.
+    pub async fn purge_expired_counters(&self) -> crate::Result<u64> {
+        self.purge_expired_lookups(false).await
+    }
+
+    async fn purge_expired_lookups(&self, purge_keys: bool) -> crate::Result<u64> {
+        // Counter expiries are stored next to the keys, so both expire in a single scan
+        let from_key = ValueKey::from(ValueClass::Lookup(LookupClass::Key(vec![0u8])));
+        let to_key = ValueKey::from(ValueClass::Lookup(LookupClass::Key(vec![u8::MAX; 10])));
+
+        let current_time = now();
+        let mut expired_keys = Vec::new();
+        let mut expired_counters = Vec::new();
+        self.iterate(IterateParams::new(from_key, to_key), |key, value| {
+            let expiry = CounterExpiry::deserialize(value)?;
+            if expiry.is_expired(current_time) {
+                expired_counters.push((key.to_vec(), xxhash_rust::xxh3::xxh3_64(value)));
+            } else if purge_keys
+                && expiry.0.is_none()
+                && value.deserialize_be_u64(0)? <= current_time
+            {
+                expired_keys.push(key.to_vec());
+            }
+            Ok(true)
+        })
+        .await?;
+
+        if !expired_keys.is_empty() {
+            let mut batch = BatchBuilder::new();
+            for key in expired_keys {
+                batch.ops.push(Operation::Value {
+                    class: ValueClass::Lookup(LookupClass::Key(key)),
+                    op: ValueOp::Clear,
+                });
+                if batch.ops.len() >= 1000 {
+                    self.write(batch.build()).await?;
+                    batch = BatchBuilder::new();
+                }
+            }
+            if !batch.ops.is_empty() {
+                self.write(batch.build()).await?;
+            }
+        }
+
+        // Each counter is deleted only if its expiry was not extended in the meantime
+        let mut purged = 0;
+        for (key, hash) in expired_counters {
+            let mut batch = BatchBuilder::new();
+            batch
+                .assert_value(
+                    ValueClass::Lookup(LookupClass::Key(key.clone())),
+                    AssertValue::Hash(hash),
+                )
+                .clear(ValueClass::Lookup(LookupClass::Counter(key.clone())))
+                .clear(ValueClass::Lookup(LookupClass::Key(key)));
+            match self.write(batch.build()).await {
+                Ok(_) => purged += 1,
+                Err(crate::Error::AssertValueFailed) => {}
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(purged)
+    }
+}
+
+// Expiry of a counter, stored as a zero timestamp followed by the expiry time
+struct CounterExpiry(Option<u64>);
+
+impl CounterExpiry {
+    fn is_expired(&self, now: u64) -> bool {
+        self.0.map_or(false, |expires| expires <= now)
+    }
+}
+
+impl Deserialize for CounterExpiry {
+    fn deserialize(bytes: &[u8]) -> crate::Result<Self> {
+        Ok(CounterExpiry(if bytes.deserialize_be_u64(0)? == 0 {
+            Some(bytes.deserialize_be_u64(U64_LEN)?)
+        } else {
+            None
+        }))
+    }
+}
+
 enum LookupValue<T> {
     Value(T),
     None,
@@ -11,6 +11,7 @@ use crate::Store;
 pub mod blob;
 pub mod fts;
 pub mod lookup;
+pub mod stats;
 pub mod store;
 
 impl Store {
@@ -26,6 +27,7 @@ impl Store {
             Self::MySQL(_) => "mysql",
             #[cfg(feature = "rocks")]
             Self::RocksDb(_) => "rocksdb",
+            Self::Replicated(_) => "replicated",
             Self::None => "none",
         }
     }
@@ -0,0 +1,298 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs Ltd <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use ahash::AHashMap;
+
+use crate::{
+    write::AnyKey, IterateParams, Store, SUBSPACE_ACL, SUBSPACE_BITMAP_ID, SUBSPACE_BITMAP_TAG,
+    SUBSPACE_BITMAP_TEXT, SUBSPACE_FTS_INDEX, SUBSPACE_INDEXES, SUBSPACE_LOGS, SUBSPACE_PROPERTY,
+    SUBSPACE_QUARANTINE, SUBSPACE_VECTORS, U32_LEN,
+};
+
+// Keys read per iteration, so that no read holds a snapshot or connection for long
+const SCAN_BATCH_SIZE: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScanMode {
+    Exact,
+    /// Splits the key space into `segments` ranges by their leading 4 bytes and
+    /// reads at most `keys_per_segment` keys from each of them, extrapolating the
+    /// totals of the ranges that were not fully read.
+    Sample {
+        segments: u32,
+        keys_per_segment: usize,
+    },
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct SubspaceStats {
+    pub subspace: u8,
+    pub keys: u64,
+    pub bytes: u64,
+    /// Keys and bytes per account for subspaces keyed by account id. When sampling,
+    /// only the accounts that were read are included.
+    pub accounts: AHashMap<u32, KeyUsage>,
+    pub is_exact: bool,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct KeyUsage {
+    pub keys: u64,
+    pub bytes: u64,
+}
+
+struct RangeScan {
+    keys: u64,
+    bytes: u64,
+    last_prefix: u32,
+    is_complete: bool,
+}
+
+impl Store {
+    /// Reports the number of keys in a subspace, their total size including values
+    /// and how they are distributed across accounts. Keys are read in small batches
+    /// without locking, so it is safe to run on a live system, although concurrent
+    /// writes may not be reflected.
+    pub async fn scan_subspace(
+        &self,
+        subspace: u8,
+        mode: ScanMode,
+    ) -> crate::Result<SubspaceStats> {
+        let mut stats = SubspaceStats {
+            subspace,
+            is_exact: true,
+            ..Default::default()
+        };
+
+        let (segments, keys_per_segment) = match mode {
+            ScanMode::Exact => {
+                self.scan_range(
+                    subspace,
+                    vec![0u8],
+                    vec![u8::MAX; 10],
+                    usize::MAX,
+                    &mut stats,
+                )
+                .await?;
+                return Ok(stats);
+            }
+            ScanMode::Sample {
+                segments,
+                keys_per_segment,
+            } => (
+                std::cmp::max(segments, 1) as u64,
+                std::cmp::max(keys_per_segment, 1),
+            ),
+        };
+
+        // Obtain the bounds of the key space
+        let mut bounds = [None, None];
+        for (bound, ascending) in bounds.iter_mut().zip([true, false]) {
+            self.iterate(
+                IterateParams::new(
+                    AnyKey {
+                        subspace,
+                        key: vec![0u8],
+                    },
+                    AnyKey {
+                        subspace,
+                        key: vec![u8::MAX; 10],
+                    },
+                )
+                .set_ascending(ascending)
+                .only_first()
+                .no_values(),
+                |key, _| {
+                    *bound = Some(key_prefix(key));
+                    Ok(false)
+                },
+            )
+            .await?;
+        }
+        let (first, last) = if let [Some(first), Some(last)] = bounds {
+            (first as u64, last as u64)
+        } else {
+            return Ok(stats);
+        };
+
+        let segment_len = (last - first + 1).div_ceil(segments);
+        let mut from = first;
+        while from <= last {
+            let to = std::cmp::min(from + segment_len, last + 1);
+            let mut end_key = ((to - 1) as u32).to_be_bytes().to_vec();
+            end_key.extend_from_slice(&[u8::MAX; 10]);
+
+            let mut segment = SubspaceStats::default();
+            let scan = self
+                .scan_range(
+                    subspace,
+                    (from as u32).to_be_bytes().to_vec(),
+                    end_key,
+                    keys_per_segment,
+                    &mut segment,
+                )
+                .await?;
+
+            if scan.is_complete {
+                stats.keys += scan.keys;
+                stats.bytes += scan.bytes;
+            } else {
+                // Assume the rest of the segment is as dense as the part that was read
+                let ratio = (to - from) as f64 / (scan.last_prefix as u64 - from + 1) as f64;
+                stats.keys += (scan.keys as f64 * ratio).round() as u64;
+                stats.bytes += (scan.bytes as f64 * ratio).round() as u64;
+                stats.is_exact = false;
+            }
+            for (account_id, usage) in segment.accounts {
+                let total = stats.accounts.entry(account_id).or_default();
+                total.keys += usage.keys;
+                total.bytes += usage.bytes;
+            }
+
+            from = to;
+        }
+
+        Ok(stats)
+    }
+
+    // Total size of the keys and values in a range
+    pub(crate) async fn range_bytes(
+        &self,
+        subspace: u8,
+        begin: Vec<u8>,
+        end: Vec<u8>,
+    ) -> crate::Result<u64> {
+        self.scan_range(
+            subspace,
+            begin,
+            end,
+            usize::MAX,
+            &mut SubspaceStats::default(),
+        )
+        .await
+        .map(|scan| scan.bytes)
+    }
+
+    async fn scan_range(
+        &self,
+        subspace: u8,
+        mut begin: Vec<u8>,
+        end: Vec<u8>,
+        max_keys: usize,
+        stats: &mut SubspaceStats,
+    ) -> crate::Result<RangeScan> {
+        let by_account = is_account_keyed(subspace);
+        let mut scan = RangeScan {
+            keys: 0,
+            bytes: 0,
+            last_prefix: 0,
+            is_complete: false,
+        };
+
+        loop {
+            let batch_size = std::cmp::min(SCAN_BATCH_SIZE, max_keys - scan.keys as usize);
+            let mut batch_keys = 0;
+            let mut last_key = None;
+
+            self.iterate(
+                IterateParams::new(
+                    AnyKey {
+                        subspace,
+                        key: begin.as_slice(),
+                    },
+                    AnyKey {
+                        subspace,
+                        key: end.as_slice(),
+                    },
+                )
+                .set_values(has_values(subspace)),
+                |key, value| {
+                    let size = (key.len() + value.len()) as u64;
+                    let prefix = key_prefix(key);
+                    if by_account && key.len() >= U32_LEN {
+                        let usage = stats.accounts.entry(prefix).or_default();
+                        usage.keys += 1;
+                        usage.bytes += size;
+                    }
+                    stats.keys += 1;
+                    stats.bytes += size;
+                    scan.keys += 1;
+                    scan.bytes += size;
+                    scan.last_prefix = prefix;
+                    batch_keys += 1;
+
+                    if batch_keys < batch_size {
+                        Ok(true)
+                    } else {
+                        last_key = Some(key.to_vec());
+                        Ok(false)
+                    }
+                },
+            )
+            .await?;
+
+            match last_key {
+                Some(key) if scan.keys < max_keys as u64 => {
+                    // Resume right after the last key read
+                    begin = key;
+                    begin.push(0);
+                }
+                Some(_) => return Ok(scan),
+                None => {
+                    scan.is_complete = true;
+                    return Ok(scan);
+                }
+            }
+        }
+    }
+}
+
+impl SubspaceStats {
+    /// Returns the accounts using the most bytes, in descending order.
+    pub fn top_accounts(&self, limit: usize) -> Vec<(u32, KeyUsage)> {
+        let mut accounts = self
+            .accounts
+            .iter()
+            .map(|(account_id, usage)| (*account_id, *usage))
+            .collect::<Vec<_>>();
+        accounts.sort_unstable_by(|a, b| b.1.bytes.cmp(&a.1.bytes).then(a.0.cmp(&b.0)));
+        accounts.truncate(limit);
+        accounts
+    }
+}
+
+fn is_account_keyed(subspace: u8) -> bool {
+    matches!(
+        subspace,
+        SUBSPACE_ACL
+            | SUBSPACE_BITMAP_ID
+            | SUBSPACE_BITMAP_TAG
+            | SUBSPACE_BITMAP_TEXT
+            | SUBSPACE_INDEXES
+            | SUBSPACE_LOGS
+            | SUBSPACE_PROPERTY
+            | SUBSPACE_FTS_INDEX
+            | SUBSPACE_QUARANTINE
+            | SUBSPACE_VECTORS
+    )
+}
+
+// Bitmap and index keys are stored without a value
+fn has_values(subspace: u8) -> bool {
+    !matches!(
+        subspace,
+        SUBSPACE_BITMAP_ID | SUBSPACE_BITMAP_TAG | SUBSPACE_BITMAP_TEXT | SUBSPACE_INDEXES
+    )
+}
+
+// Leading 4 bytes of a key, zero padded
+fn key_prefix(key: &[u8]) -> u32 {
+    let mut prefix = [0u8; U32_LEN];
+    for (byte, key_byte) in prefix.iter_mut().zip(key) {
+        *byte = *key_byte;
+    }
+    u32::from_be_bytes(prefix)
+}
@@ -4,18 +4,25 @@
  * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
  */
 
-use std::ops::{BitAndAssign, Range};
+use std::{
+    ops::{BitAndAssign, Range},
+    sync::atomic::{AtomicBool, Ordering},
+};
 
 use roaring::RoaringBitmap;
 
 use crate::{
+    fts::TokenLimits,
     write::{
+        cardinality::CardinalityCache,
+        delete::account_ranges,
         key::{DeserializeBigEndian, KeySerializer},
-        now, AnyClass, AnyKey, AssignedIds, Batch, BatchBuilder, BitmapClass, BitmapHash,
+        now,
+        queue::WriteQueue,
+        AnyClass, AnyKey, AssignedIds, Batch, BatchBuilder, BitmapClass, MaybeDynamicValue,
         Operation, ReportClass, ValueClass, ValueOp,
     },
-    BitmapKey, Deserialize, IterateParams, Key, Store, ValueKey, SUBSPACE_BITMAP_ID,
-    SUBSPACE_BITMAP_TAG, SUBSPACE_BITMAP_TEXT, SUBSPACE_INDEXES, SUBSPACE_LOGS, U32_LEN,
+    BitmapKey, Deserialize, IterateParams, Key, Store, ValueKey, U32_LEN,
 };
 
 use super::DocumentSet;
@@ -42,6 +49,7 @@ impl Store {
             Self::MySQL(store) => store.get_value(key).await,
             #[cfg(feature = "rocks")]
             Self::RocksDb(store) => store.get_value(key).await,
+            Self::Replicated(store) => Box::pin(store.primary.get_value(key)).await,
             Self::None => Err(crate::Error::InternalError("No store configured".into())),
         }
     }
@@ -61,6 +69,7 @@ impl Store {
             Self::MySQL(store) => store.get_bitmap(key).await,
             #[cfg(feature = "rocks")]
             Self::RocksDb(store) => store.get_bitmap(key).await,
+            Self::Replicated(store) => Box::pin(store.primary.get_bitmap(key)).await,
             Self::None => Err(crate::Error::InternalError("No store configured".into())),
         }
     }
@@ -103,6 +112,7 @@ impl Store {
             Self::MySQL(store) => store.iterate(params, cb).await,
             #[cfg(feature = "rocks")]
             Self::RocksDb(store) => store.iterate(params, cb).await,
+            Self::Replicated(store) => Box::pin(store.primary.iterate(params, cb)).await,
             Self::None => Err(crate::Error::InternalError("No store configured".into())),
         }
     }
@@ -122,11 +132,48 @@ impl Store {
             Self::MySQL(store) => store.get_counter(key).await,
             #[cfg(feature = "rocks")]
             Self::RocksDb(store) => store.get_counter(key).await,
+            Self::Replicated(store) => Box::pin(store.primary.get_counter(key)).await,
             Self::None => Err(crate::Error::InternalError("No store configured".into())),
         }
     }
 
-    pub async fn write(&self, batch: Batch) -> crate::Result<AssignedIds> {
+    pub async fn write(&self, mut batch: Batch) -> crate::Result<AssignedIds> {
+        self.assert_writable()?;
+
+        // Large data belongs in the blob store, reject oversized values
+        // before they reach the backend.
+        let max_size = self.max_value_size();
+        for op in &batch.ops {
+            let size = match op {
+                Operation::Value {
+                    class,
+                    op: ValueOp::Set(MaybeDynamicValue::Static(value)),
+                } if !matches!(class, ValueClass::FtsIndex(_)) => value.len(),
+                Operation::Log {
+                    set: MaybeDynamicValue::Static(value),
+                } => value.len(),
+                _ => continue,
+            };
+            if size > max_size {
+                return Err(crate::Error::ValueTooLarge { size, max_size });
+            }
+        }
+
+        // Wait for a slot rather than piling up transactions on the backend
+        let _slot = if let Some(queue) = self.write_queue() {
+            Some(queue.acquire().await?)
+        } else {
+            None
+        };
+
+        // Usage counters are updated and quotas checked in the same transaction
+        let soft_limits = self.track_usage(&mut batch).await?;
+
+        // Cached bitmap cardinalities are invalidated once the batch is written
+        let changed_bitmaps = self
+            .cardinality_cache()
+            .and_then(|cache| cache.changed_bitmaps(&batch.ops));
+
         #[cfg(feature = "test_mode")]
         if std::env::var("PARANOID_WRITE").map_or(false, |v| v == "1") {
             let mut account_id = u32::MAX;
@@ -173,19 +220,13 @@ impl Store {
                 }
             }
 
-            match self {
-                #[cfg(feature = "sqlite")]
-                Self::SQLite(store) => store.write(batch).await,
-                #[cfg(feature = "foundation")]
-                Self::FoundationDb(store) => store.write(batch).await,
-                #[cfg(feature = "postgres")]
-                Self::PostgreSQL(store) => store.write(batch).await,
-                #[cfg(feature = "mysql")]
-                Self::MySQL(store) => store.write(batch).await,
-                #[cfg(feature = "rocks")]
-                Self::RocksDb(store) => store.write(batch).await,
-                Self::None => Err(crate::Error::InternalError("No store configured".into())),
-            }?;
+            let write_result = self.write_backend(batch).await;
+            if let (Some(cache), Some(changed_bitmaps)) =
+                (self.cardinality_cache(), changed_bitmaps)
+            {
+                cache.invalidate(changed_bitmaps, write_result.as_ref().ok());
+            }
+            write_result?;
 
             for (key, class, document_id, set) in bitmaps {
                 let mut bitmaps = BITMAPS.lock();
@@ -214,6 +255,20 @@ impl Store {
             return Ok(AssignedIds::default());
         }
 
+        let result = self.write_backend(batch).await;
+        if let (Some(cache), Some(changed_bitmaps)) = (self.cardinality_cache(), changed_bitmaps) {
+            cache.invalidate(changed_bitmaps, result.as_ref().ok());
+        }
+        let mut result = result?;
+        if !soft_limits.is_empty() {
+            result.soft_limit_reached = self.soft_limits_reached(soft_limits).await?;
+        }
+
+        Ok(result)
+    }
+
+    // Writes a batch that was already checked and accounted for by `write`
+    async fn write_backend(&self, batch: Batch) -> crate::Result<AssignedIds> {
         match self {
             #[cfg(feature = "sqlite")]
             Self::SQLite(store) => store.write(batch).await,
@@ -225,10 +280,126 @@ impl Store {
             Self::MySQL(store) => store.write(batch).await,
             #[cfg(feature = "rocks")]
             Self::RocksDb(store) => store.write(batch).await,
+            Self::Replicated(store) => Box::pin(store.primary.write_backend(batch)).await,
             Self::None => Err(crate::Error::InternalError("No store configured".into())),
         }
     }
 
+    pub fn max_value_size(&self) -> usize {
+        match self {
+            #[cfg(feature = "sqlite")]
+            Self::SQLite(store) => store.max_value_size,
+            #[cfg(feature = "foundation")]
+            Self::FoundationDb(store) => store.max_value_size,
+            #[cfg(feature = "postgres")]
+            Self::PostgreSQL(store) => store.max_value_size,
+            #[cfg(feature = "mysql")]
+            Self::MySQL(store) => store.max_value_size,
+            #[cfg(feature = "rocks")]
+            Self::RocksDb(store) => store.max_value_size,
+            Self::Replicated(store) => store.primary.max_value_size(),
+            Self::None => usize::MAX,
+        }
+    }
+
+    /// Returns the number of writes in progress or waiting for a slot in the write
+    /// queue, along with the queue capacity set by `write-queue.depth`.
+    pub fn write_queue_depth(&self) -> (usize, usize) {
+        self.write_queue()
+            .map_or((0, 0), |queue| (queue.depth(), queue.capacity()))
+    }
+
+    /// Returns the length bounds of full-text index tokens set by
+    /// `fts.min-token-length` and `fts.max-token-length`.
+    pub fn token_limits(&self) -> TokenLimits {
+        match self {
+            #[cfg(feature = "sqlite")]
+            Self::SQLite(store) => store.token_limits,
+            #[cfg(feature = "foundation")]
+            Self::FoundationDb(store) => store.token_limits,
+            #[cfg(feature = "postgres")]
+            Self::PostgreSQL(store) => store.token_limits,
+            #[cfg(feature = "mysql")]
+            Self::MySQL(store) => store.token_limits,
+            #[cfg(feature = "rocks")]
+            Self::RocksDb(store) => store.token_limits,
+            Self::Replicated(store) => store.primary.token_limits(),
+            Self::None => TokenLimits::default(),
+        }
+    }
+
+    pub(crate) fn cardinality_cache(&self) -> Option<&CardinalityCache> {
+        match self {
+            #[cfg(feature = "sqlite")]
+            Self::SQLite(store) => Some(&store.cardinality_cache),
+            #[cfg(feature = "foundation")]
+            Self::FoundationDb(store) => Some(&store.cardinality_cache),
+            #[cfg(feature = "postgres")]
+            Self::PostgreSQL(store) => Some(&store.cardinality_cache),
+            #[cfg(feature = "mysql")]
+            Self::MySQL(store) => Some(&store.cardinality_cache),
+            #[cfg(feature = "rocks")]
+            Self::RocksDb(store) => Some(&store.cardinality_cache),
+            Self::Replicated(store) => store.primary.cardinality_cache(),
+            Self::None => None,
+        }
+    }
+
+    fn write_queue(&self) -> Option<&WriteQueue> {
+        match self {
+            #[cfg(feature = "sqlite")]
+            Self::SQLite(store) => Some(&store.write_queue),
+            #[cfg(feature = "foundation")]
+            Self::FoundationDb(store) => Some(&store.write_queue),
+            #[cfg(feature = "postgres")]
+            Self::PostgreSQL(store) => Some(&store.write_queue),
+            #[cfg(feature = "mysql")]
+            Self::MySQL(store) => Some(&store.write_queue),
+            #[cfg(feature = "rocks")]
+            Self::RocksDb(store) => Some(&store.write_queue),
+            Self::Replicated(store) => store.primary.write_queue(),
+            Self::None => None,
+        }
+    }
+
+    /// Puts the store in maintenance mode, where writes fail with `Error::ReadOnly`
+    /// while reads proceed. Writes that already passed the check are not aborted.
+    pub fn set_read_only(&self, read_only: bool) {
+        if let Some(flag) = self.read_only_flag() {
+            flag.store(read_only, Ordering::Relaxed);
+        }
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only_flag()
+            .map_or(false, |flag| flag.load(Ordering::Relaxed))
+    }
+
+    fn read_only_flag(&self) -> Option<&AtomicBool> {
+        match self {
+            #[cfg(feature = "sqlite")]
+            Self::SQLite(store) => Some(&store.read_only),
+            #[cfg(feature = "foundation")]
+            Self::FoundationDb(store) => Some(&store.read_only),
+            #[cfg(feature = "postgres")]
+            Self::PostgreSQL(store) => Some(&store.read_only),
+            #[cfg(feature = "mysql")]
+            Self::MySQL(store) => Some(&store.read_only),
+            #[cfg(feature = "rocks")]
+            Self::RocksDb(store) => Some(&store.read_only),
+            Self::Replicated(store) => store.primary.read_only_flag(),
+            Self::None => None,
+        }
+    }
+
+    pub(crate) fn assert_writable(&self) -> crate::Result<()> {
+        if !self.is_read_only() {
+            Ok(())
+        } else {
+            Err(crate::Error::ReadOnly)
+        }
+    }
+
     pub async fn purge_store(&self) -> crate::Result<()> {
         // Delete expired reports
         let now = now();
@@ -257,6 +428,13 @@ impl Store {
         )
         .await?;
 
+        // Usage counters are only kept by the write path for new documents
+        self.recalculate_all_usage().await?;
+
+        self.purge_backend().await
+    }
+
+    async fn purge_backend(&self) -> crate::Result<()> {
         match self {
             #[cfg(feature = "sqlite")]
             Self::SQLite(store) => store.purge_store().await,
@@ -268,11 +446,57 @@ impl Store {
             Self::MySQL(store) => store.purge_store().await,
             #[cfg(feature = "rocks")]
             Self::RocksDb(store) => store.purge_store().await,
+            // Replicas are purged through the replication of the primary
+            Self::Replicated(store) => Box::pin(store.primary.purge_backend()).await,
+            Self::None => Err(crate::Error::InternalError("No store configured".into())),
+        }
+    }
+
+    /// Checks whether the store is reachable. For FoundationDB this probes the
+    /// cluster and closes the circuit breaker once it has recovered, other
+    /// backends report errors on each operation and are always considered healthy.
+    pub async fn health_check(&self) -> crate::Result<()> {
+        match self {
+            #[cfg(feature = "foundation")]
+            Self::FoundationDb(store) => store.health_check().await,
+            Self::Replicated(store) => Box::pin(store.primary.health_check()).await,
+            Self::None => Err(crate::Error::InternalError("No store configured".into())),
+            #[allow(unreachable_patterns)]
+            _ => Ok(()),
+        }
+    }
+
+    /// Makes sure all committed writes are on durable storage before returning.
+    ///
+    /// - SQLite: runs a full WAL checkpoint, as commits are not synced to disk
+    ///   in WAL mode until the log is checkpointed.
+    /// - RocksDB: syncs the WAL and flushes the memtables to disk.
+    /// - FoundationDB, PostgreSQL and MySQL: no-op, a committed transaction is
+    ///   already durable.
+    pub async fn checkpoint(&self) -> crate::Result<()> {
+        match self {
+            #[cfg(feature = "sqlite")]
+            Self::SQLite(store) => store.checkpoint().await,
+            #[cfg(feature = "foundation")]
+            Self::FoundationDb(_) => Ok(()),
+            #[cfg(feature = "postgres")]
+            Self::PostgreSQL(_) => Ok(()),
+            #[cfg(feature = "mysql")]
+            Self::MySQL(_) => Ok(()),
+            #[cfg(feature = "rocks")]
+            Self::RocksDb(store) => store.checkpoint().await,
+            Self::Replicated(store) => Box::pin(store.primary.checkpoint()).await,
             Self::None => Err(crate::Error::InternalError("No store configured".into())),
         }
     }
 
     pub async fn delete_range(&self, from: impl Key, to: impl Key) -> crate::Result<()> {
+        self.assert_writable()?;
+
+        if let Some(cache) = self.cardinality_cache() {
+            cache.invalidate_all();
+        }
+
         match self {
             #[cfg(feature = "sqlite")]
             Self::SQLite(store) => store.delete_range(from, to).await,
@@ -284,10 +508,49 @@ impl Store {
             Self::MySQL(store) => store.delete_range(from, to).await,
             #[cfg(feature = "rocks")]
             Self::RocksDb(store) => store.delete_range(from, to).await,
+            Self::Replicated(store) => Box::pin(store.primary.delete_range(from, to)).await,
             Self::None => Err(crate::Error::InternalError("No store configured".into())),
         }
     }
 
+    #[cfg(feature = "sqlite-checksum")]
+    pub async fn scrub(&self) -> crate::Result<crate::backend::sqlite::checksum::ScrubReport> {
+        use crate::*;
+
+        match self {
+            Self::SQLite(store) => {
+                store
+                    .scrub(&[
+                        SUBSPACE_ACL,
+                        SUBSPACE_DIRECTORY,
+                        SUBSPACE_FTS_QUEUE,
+                        SUBSPACE_BLOB_RESERVE,
+                        SUBSPACE_BLOB_LINK,
+                        SUBSPACE_LOOKUP_VALUE,
+                        SUBSPACE_PROPERTY,
+                        SUBSPACE_SETTINGS,
+                        SUBSPACE_QUEUE_MESSAGE,
+                        SUBSPACE_QUEUE_EVENT,
+                        SUBSPACE_REPORT_OUT,
+                        SUBSPACE_REPORT_IN,
+                        SUBSPACE_FTS_INDEX,
+                        SUBSPACE_LOGS,
+                        SUBSPACE_BLOBS,
+                        SUBSPACE_INTENTS,
+                        SUBSPACE_QUARANTINE,
+                        SUBSPACE_VECTORS,
+                        SUBSPACE_COLLECTIONS,
+                        SUBSPACE_LABELS,
+                        SUBSPACE_CLUSTER,
+                    ])
+                    .await
+            }
+            _ => Err(crate::Error::Unsupported(
+                "Scrubbing is only supported by the SQLite store".into(),
+            )),
+        }
+    }
+
     pub async fn delete_documents(
         &self,
         subspace: u8,
@@ -363,53 +626,13 @@ impl Store {
     }
 
     pub async fn purge_account(&self, account_id: u32) -> crate::Result<()> {
-        for subspace in [
-            SUBSPACE_BITMAP_ID,
-            SUBSPACE_BITMAP_TAG,
-            SUBSPACE_BITMAP_TEXT,
-            SUBSPACE_LOGS,
-            SUBSPACE_INDEXES,
-        ] {
+        for (subspace, from, to) in account_ranges(account_id) {
             self.delete_range(
                 AnyKey {
                     subspace,
-                    key: KeySerializer::new(U32_LEN).write(account_id).finalize(),
-                },
-                AnyKey {
-                    subspace,
-                    key: KeySerializer::new(U32_LEN).write(account_id + 1).finalize(),
-                },
-            )
-            .await?;
-        }
-
-        for (from_class, to_class) in [
-            (ValueClass::Acl(account_id), ValueClass::Acl(account_id + 1)),
-            (ValueClass::Property(0), ValueClass::Property(0)),
-            (
-                ValueClass::FtsIndex(BitmapHash {
-                    hash: [0u8; 8],
-                    len: 0,
-                }),
-                ValueClass::FtsIndex(BitmapHash {
-                    hash: [u8::MAX; 8],
-                    len: u8::MAX,
-                }),
-            ),
-        ] {
-            self.delete_range(
-                ValueKey {
-                    account_id,
-                    collection: 0,
-                    document_id: 0,
-                    class: from_class,
-                },
-                ValueKey {
-                    account_id: account_id + 1,
-                    collection: 0,
-                    document_id: 0,
-                    class: to_class,
+                    key: from,
                 },
+                AnyKey { subspace, key: to },
             )
             .await?;
         }
@@ -433,11 +656,14 @@ impl Store {
             Self::MySQL(store) => store.get_blob(key, range).await,
             #[cfg(feature = "rocks")]
             Self::RocksDb(store) => store.get_blob(key, range).await,
+            Self::Replicated(store) => Box::pin(store.primary.get_blob(key, range)).await,
             Self::None => Err(crate::Error::InternalError("No store configured".into())),
         }
     }
 
     pub async fn put_blob(&self, key: &[u8], data: &[u8]) -> crate::Result<()> {
+        self.assert_writable()?;
+
         match self {
             #[cfg(feature = "sqlite")]
             Self::SQLite(store) => store.put_blob(key, data).await,
@@ -449,11 +675,14 @@ impl Store {
             Self::MySQL(store) => store.put_blob(key, data).await,
             #[cfg(feature = "rocks")]
             Self::RocksDb(store) => store.put_blob(key, data).await,
+            Self::Replicated(store) => Box::pin(store.primary.put_blob(key, data)).await,
             Self::None => Err(crate::Error::InternalError("No store configured".into())),
         }
     }
 
     pub async fn delete_blob(&self, key: &[u8]) -> crate::Result<bool> {
+        self.assert_writable()?;
+
         match self {
             #[cfg(feature = "sqlite")]
             Self::SQLite(store) => store.delete_blob(key).await,
@@ -465,6 +694,7 @@ impl Store {
             Self::MySQL(store) => store.delete_blob(key).await,
             #[cfg(feature = "rocks")]
             Self::RocksDb(store) => store.delete_blob(key).await,
+            Self::Replicated(store) => Box::pin(store.primary.delete_blob(key)).await,
             Self::None => Err(crate::Error::InternalError("No store configured".into())),
         }
     }
@@ -495,6 +725,12 @@ impl Store {
             SUBSPACE_REPORT_OUT,
             SUBSPACE_REPORT_IN,
             SUBSPACE_FTS_INDEX,
+            SUBSPACE_INTENTS,
+            SUBSPACE_QUARANTINE,
+            SUBSPACE_VECTORS,
+            SUBSPACE_COLLECTIONS,
+            SUBSPACE_LABELS,
+            SUBSPACE_CLUSTER,
         ] {
             self.delete_range(
                 AnyKey {
@@ -666,6 +902,12 @@ impl Store {
             (SUBSPACE_REPORT_OUT, true),
             (SUBSPACE_REPORT_IN, true),
             (SUBSPACE_FTS_INDEX, true),
+            (SUBSPACE_INTENTS, true),
+            (SUBSPACE_QUARANTINE, true),
+            (SUBSPACE_VECTORS, true),
+            (SUBSPACE_COLLECTIONS, true),
+            (SUBSPACE_LABELS, true),
+            (SUBSPACE_CLUSTER, true),
             (SUBSPACE_BLOB_RESERVE, true),
             (SUBSPACE_BLOB_LINK, true),
             (SUBSPACE_BLOBS, true),
@@ -745,6 +987,18 @@ impl Store {
                                 value
                             );
                         }
+                        SUBSPACE_COLLECTIONS => {
+                            // Registered collections outlive their documents
+                            return Ok(true);
+                        }
+                        SUBSPACE_QUOTA if key.first() == Some(&7) => {
+                            // Usage counters are not updated by range deletions
+                            return Ok(true);
+                        }
+                        SUBSPACE_QUOTA if key.first() == Some(&8) => {
+                            // Document id counters outlive the documents they assigned
+                            return Ok(true);
+                        }
                         SUBSPACE_INDEXES => {
                             println!(
                                 concat!(
@@ -13,11 +13,13 @@ use nlp::{
         stemmer::Stemmer,
         Language,
     },
-    tokenizers::word::WordTokenizer,
+    tokenizers::{
+        address::{tokenize_address, AddressToken},
+        word::WordTokenizer,
+    },
 };
 
 use crate::{
-    backend::MAX_TOKEN_LENGTH,
     dispatch::DocumentSet,
     write::{
         hash::TokenType, key::DeserializeBigEndian, BatchBuilder, BitmapHash, MaybeDynamicId,
@@ -40,6 +42,7 @@ pub(crate) struct Text<'x, T: Into<u8> + Display + Clone + std::fmt::Debug> {
 pub(crate) enum Type {
     Text(Language),
     Tokenize,
+    Address,
     Keyword,
 }
 
@@ -94,6 +97,16 @@ impl<'x, T: Into<u8> + Display + Clone + std::fmt::Debug> FtsDocument<'x, T> {
         });
     }
 
+    /// Indexes addresses and display names so that full addresses, local parts and
+    /// domains can be searched for as a whole, in addition to the words within them.
+    pub fn index_address(&mut self, field: Field<T>, text: impl Into<Cow<'x, str>>) {
+        self.parts.push(Text {
+            field,
+            text: text.into(),
+            typ: Type::Address,
+        });
+    }
+
     pub fn index_keyword(&mut self, field: Field<T>, text: impl Into<Cow<'x, str>>) {
         self.parts.push(Text {
             field,
@@ -119,6 +132,7 @@ impl Store {
         &self,
         document: FtsDocument<'_, T>,
     ) -> crate::Result<()> {
+        let limits = self.token_limits();
         let mut detect = LanguageDetector::new();
         let mut tokens: AHashMap<BitmapHash, Postings> = AHashMap::new();
         let mut parts = Vec::new();
@@ -136,7 +150,9 @@ impl Store {
                 }
                 Type::Tokenize => {
                     let field = u8::from(text.field);
-                    for token in WordTokenizer::new(text.text.as_ref(), MAX_TOKEN_LENGTH) {
+                    for token in WordTokenizer::new(text.text.as_ref(), limits.max_length)
+                        .filter(|token| limits.contains(&token.word))
+                    {
                         tokens
                             .entry(BitmapHash::new(token.word.as_ref()))
                             .or_default()
@@ -145,6 +161,29 @@ impl Store {
                     }
                     position += 10;
                 }
+                Type::Address => {
+                    let field = u8::from(text.field);
+                    for token in tokenize_address(text.text.as_ref(), limits.max_length) {
+                        match token {
+                            AddressToken::Part(part) | AddressToken::Word(part)
+                                if !limits.contains(&part) => {}
+                            AddressToken::Part(part) => {
+                                tokens
+                                    .entry(BitmapHash::new(&part))
+                                    .or_default()
+                                    .insert_keyword(TokenType::word(field));
+                            }
+                            AddressToken::Word(word) => {
+                                tokens
+                                    .entry(BitmapHash::new(&word))
+                                    .or_default()
+                                    .insert(TokenType::word(field), position);
+                                position += 1;
+                            }
+                        }
+                    }
+                    position += 10;
+                }
                 Type::Keyword => {
                     let field = u8::from(text.field);
                     tokens
@@ -167,7 +206,9 @@ impl Store {
             };
             let field: u8 = field.into();
 
-            for token in Stemmer::new(&text, language, MAX_TOKEN_LENGTH) {
+            for token in Stemmer::new(&text, language, limits.max_length)
+                .filter(|token| limits.contains(&token.word))
+            {
                 tokens
                     .entry(BitmapHash::new(token.word.as_ref()))
                     .or_default()
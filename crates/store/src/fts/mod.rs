@@ -7,10 +7,14 @@
 use std::fmt::Display;
 
 use nlp::language::Language;
+use utils::config::{utils::AsKey, Config};
+
+use crate::backend::MAX_TOKEN_LENGTH;
 
 pub mod index;
 pub mod postings;
 pub mod query;
+pub mod terms;
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Field<T: Into<u8> + Display + Clone + std::fmt::Debug> {
@@ -32,6 +36,10 @@ pub enum FtsFilter<T: Into<u8> + Display + Clone + std::fmt::Debug> {
         text: String,
         language: Language,
     },
+    Address {
+        field: Field<T>,
+        text: String,
+    },
     Keyword {
         field: Field<T>,
         text: String,
@@ -79,6 +87,14 @@ impl<T: Into<u8> + Display + Clone + std::fmt::Debug> FtsFilter<T> {
         }
     }
 
+    /// Matches addresses indexed with `FtsDocument::index_address`.
+    pub fn has_address(field: Field<T>, text: impl Into<String>) -> Self {
+        FtsFilter::Address {
+            field,
+            text: text.into(),
+        }
+    }
+
     pub fn has_keyword(field: Field<T>, text: impl Into<String>) -> Self {
         FtsFilter::Keyword {
             field,
@@ -91,6 +107,51 @@ impl<T: Into<u8> + Display + Clone + std::fmt::Debug> FtsFilter<T> {
     }
 }
 
+/// Length bounds in bytes of the tokens written to and looked up in the full-text
+/// index. Tokens outside the bounds are dropped both when indexing and querying,
+/// so changing them requires reindexing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TokenLimits {
+    pub min_length: usize,
+    pub max_length: usize,
+}
+
+impl TokenLimits {
+    pub fn new(min_length: usize, max_length: usize) -> Self {
+        let max_length = max_length.clamp(1, MAX_TOKEN_LENGTH);
+        TokenLimits {
+            min_length: min_length.clamp(1, max_length),
+            max_length,
+        }
+    }
+
+    pub fn parse(config: &mut Config, prefix: impl AsKey) -> Self {
+        let prefix = prefix.as_key();
+        let default = TokenLimits::default();
+        TokenLimits::new(
+            config
+                .property_or_default((&prefix, "fts.min-token-length"), "2")
+                .unwrap_or(default.min_length),
+            config
+                .property_or_default((&prefix, "fts.max-token-length"), "40")
+                .unwrap_or(default.max_length),
+        )
+    }
+
+    pub fn contains(&self, token: &str) -> bool {
+        (self.min_length..=self.max_length).contains(&token.len())
+    }
+}
+
+impl Default for TokenLimits {
+    fn default() -> Self {
+        TokenLimits {
+            min_length: 2,
+            max_length: 40,
+        }
+    }
+}
+
 #[derive(Clone, Copy)]
 pub enum FilterType {
     And,
@@ -10,11 +10,16 @@ use std::{
 };
 
 use ahash::AHashMap;
-use nlp::language::stemmer::Stemmer;
+use nlp::{
+    language::stemmer::Stemmer,
+    tokenizers::{
+        address::{tokenize_address_query, AddressToken},
+        word::WordTokenizer,
+    },
+};
 use roaring::RoaringBitmap;
 
 use crate::{
-    backend::MAX_TOKEN_LENGTH,
     fts::FtsFilter,
     write::{
         hash::TokenType, key::DeserializeBigEndian, BitmapHash, DynamicDocumentId, ValueClass,
@@ -55,6 +60,7 @@ impl Store {
         filters: Vec<FtsFilter<T>>,
     ) -> crate::Result<RoaringBitmap> {
         let collection = collection.into();
+        let limits = self.token_limits();
 
         // Tokenize text
         let mut tokenized_filters = Vec::with_capacity(filters.len());
@@ -69,7 +75,10 @@ impl Store {
                     let mut tokens = Vec::new();
                     let field = TokenType::word(field.into());
 
-                    for token in language.tokenize_text(text.as_ref(), MAX_TOKEN_LENGTH) {
+                    for token in language
+                        .tokenize_text(text.as_ref(), limits.max_length)
+                        .filter(|token| limits.contains(&token.word))
+                    {
                         let hash = BitmapHash::new(token.word.as_ref());
                         token_count.entry(hash).and_modify(|c| *c += 1).or_insert(1);
                         tokens.push((hash, field));
@@ -82,7 +91,9 @@ impl Store {
                     language,
                 } => {
                     let mut tokens = Vec::new();
-                    for token in Stemmer::new(text.as_ref(), language, MAX_TOKEN_LENGTH) {
+                    for token in Stemmer::new(text.as_ref(), language, limits.max_length)
+                        .filter(|token| limits.contains(&token.word))
+                    {
                         let hash = BitmapHash::new(token.word.as_ref());
                         let stemmed_hash = token.stemmed_word.as_deref().map(BitmapHash::new);
 
@@ -101,6 +112,62 @@ impl Store {
                         tokens,
                     }
                 }
+                FtsFilter::Address { field, text } => {
+                    let field = field.into();
+                    let mut words = Vec::new();
+                    let mut parts = Vec::new();
+                    for token in tokenize_address_query(text.as_ref(), limits.max_length) {
+                        match token {
+                            AddressToken::Part(part) | AddressToken::Word(part)
+                                if !limits.contains(&part) => {}
+                            AddressToken::Part(part) => {
+                                let hash = BitmapHash::new(&part);
+                                token_count.entry(hash).and_modify(|c| *c += 1).or_insert(1);
+
+                                // Addresses indexed before address parts were tokenized
+                                // only have their words, which are matched as a phrase
+                                let mut phrase = Vec::new();
+                                for token in WordTokenizer::new(&part, limits.max_length)
+                                    .filter(|token| limits.contains(&token.word))
+                                {
+                                    let hash = BitmapHash::new(token.word.as_ref());
+                                    token_count.entry(hash).and_modify(|c| *c += 1).or_insert(1);
+                                    phrase.push((hash, TokenType::word(field)));
+                                }
+                                parts.push((hash, phrase));
+                            }
+                            AddressToken::Word(word) => {
+                                let hash = BitmapHash::new(&word);
+                                token_count.entry(hash).and_modify(|c| *c += 1).or_insert(1);
+                                words.push((hash, None));
+                            }
+                        }
+                    }
+
+                    if parts.is_empty() {
+                        FtsTokenized::Contains {
+                            field,
+                            tokens: words,
+                        }
+                    } else {
+                        tokenized_filters.push(FtsTokenized::And);
+                        if !words.is_empty() {
+                            tokenized_filters.push(FtsTokenized::Contains {
+                                field,
+                                tokens: words,
+                            });
+                        }
+                        for (token, phrase) in parts {
+                            tokenized_filters.push(FtsTokenized::Or);
+                            tokenized_filters.push(FtsTokenized::Keyword { field, token });
+                            if !phrase.is_empty() {
+                                tokenized_filters.push(FtsTokenized::Exact { tokens: phrase });
+                            }
+                            tokenized_filters.push(FtsTokenized::End);
+                        }
+                        FtsTokenized::End
+                    }
+                }
                 FtsFilter::Keyword { field, text } => {
                     let hash = BitmapHash::new(text);
                     token_count.entry(hash).and_modify(|c| *c += 1).or_insert(1);
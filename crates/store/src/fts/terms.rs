@@ -0,0 +1,159 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs Ltd <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::fmt::Write as _;
+
+use futures::{stream, Stream, StreamExt};
+
+use crate::{
+    write::{hash::TokenType, key::KeySerializer, AnyKey},
+    IterateParams, Store, SUBSPACE_FTS_INDEX, U32_LEN,
+};
+
+use super::postings::SerializedPostings;
+
+// Terms read per iteration
+const TERMS_PAGE_SIZE: usize = 1000;
+// Index keys end with the collection and document id
+const KEY_SUFFIX_LEN: usize = U32_LEN + 1;
+
+impl Store {
+    /// Streams the terms indexed for `field` in a collection, each one with the
+    /// number of documents containing it, optionally limited to the terms starting
+    /// with `prefix`. Terms are read in pages, in the order they are stored.
+    ///
+    /// Only terms of up to 8 bytes are stored verbatim, longer ones are stored as
+    /// a hash and returned as `#` followed by the hash in hexadecimal. Hashed terms
+    /// never match a prefix. Stemmed forms are not included.
+    pub fn dump_terms(
+        &self,
+        account_id: u32,
+        collection: impl Into<u8>,
+        field: impl Into<u8>,
+        prefix: Option<&str>,
+    ) -> impl Stream<Item = crate::Result<(String, u64)>> + Send + '_ {
+        let collection = collection.into();
+        let field = TokenType::word(field.into());
+        let prefix = prefix.unwrap_or_default().as_bytes().to_vec();
+        let begin = KeySerializer::new(U32_LEN + prefix.len())
+            .write(account_id)
+            .write(prefix.as_slice())
+            .finalize();
+        let end = KeySerializer::new(U32_LEN).write(account_id + 1).finalize();
+
+        stream::unfold(Some(begin), move |begin| {
+            let (end, prefix) = (end.clone(), prefix.clone());
+            async move {
+                match self
+                    .terms_page(collection, field, begin?, &end, &prefix)
+                    .await
+                {
+                    Ok((terms, next)) => Some((Ok(terms), next)),
+                    Err(err) => Some((Err(err), None)),
+                }
+            }
+        })
+        .flat_map(|page| {
+            stream::iter(match page {
+                Ok(terms) => terms.into_iter().map(Ok).collect::<Vec<_>>(),
+                Err(err) => vec![Err(err)],
+            })
+        })
+    }
+
+    // Returns a page of terms and the key the next page starts at, if any
+    async fn terms_page(
+        &self,
+        collection: u8,
+        field: u8,
+        begin: Vec<u8>,
+        end: &[u8],
+        prefix: &[u8],
+    ) -> crate::Result<(Vec<(String, u64)>, Option<Vec<u8>>)> {
+        let mut terms = Vec::new();
+        let mut current: Option<(Vec<u8>, u64)> = None;
+        let mut next = None;
+
+        self.iterate(
+            IterateParams::new(
+                AnyKey {
+                    subspace: SUBSPACE_FTS_INDEX,
+                    key: begin,
+                },
+                AnyKey {
+                    subspace: SUBSPACE_FTS_INDEX,
+                    key: end.to_vec(),
+                },
+            )
+            .ascending(),
+            |key, value| {
+                let term = key
+                    .get(U32_LEN..key.len().saturating_sub(KEY_SUFFIX_LEN))
+                    .unwrap_or_default();
+                if key >= end || !term.starts_with(prefix) {
+                    return Ok(false);
+                }
+
+                if current
+                    .as_ref()
+                    .map_or(true, |(current_term, _)| current_term != term)
+                {
+                    if let Some((current_term, count)) = current.take() {
+                        push_term(&mut terms, &current_term, count, prefix);
+                    }
+                    if terms.len() >= TERMS_PAGE_SIZE {
+                        next = Some(key.to_vec());
+                        return Ok(false);
+                    }
+                    current = Some((term.to_vec(), 0));
+                }
+
+                if key[key.len() - KEY_SUFFIX_LEN] == collection
+                    && SerializedPostings::new(value).has_field(field)
+                {
+                    if let Some((_, count)) = &mut current {
+                        *count += 1;
+                    }
+                }
+
+                Ok(true)
+            },
+        )
+        .await?;
+
+        if let Some((current_term, count)) = current {
+            push_term(&mut terms, &current_term, count, prefix);
+        }
+
+        Ok((terms, next))
+    }
+}
+
+// Terms longer than 8 bytes are stored as an 8 byte hash followed by their length
+fn push_term(terms: &mut Vec<(String, u64)>, term: &[u8], count: u64, prefix: &[u8]) {
+    if count == 0 {
+        return;
+    }
+
+    match term.split_last() {
+        Some((&len, hash)) if term.len() == 9 && len > 8 => {
+            if prefix.is_empty() {
+                let mut name = String::with_capacity(17);
+                name.push('#');
+                for byte in hash {
+                    let _ = write!(name, "{byte:02x}");
+                }
+                terms.push((name, count));
+            }
+        }
+        Some((_, verbatim)) if term.len() == 9 => {
+            terms.push((String::from_utf8_lossy(verbatim).into_owned(), count));
+        }
+        _ => {
+            terms.push((String::from_utf8_lossy(term).into_owned(), count));
+        }
+    }
+}
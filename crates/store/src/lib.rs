@@ -4,7 +4,12 @@
  * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
  */
 
-use std::{borrow::Cow, fmt::Display, sync::Arc};
+use std::{
+    borrow::Cow,
+    fmt::Display,
+    sync::{atomic::AtomicU64, Arc},
+    time::Duration,
+};
 
 pub mod backend;
 pub mod config;
@@ -15,7 +20,9 @@ pub mod write;
 
 pub use ahash;
 use ahash::AHashMap;
-use backend::{fs::FsStore, memory::MemoryStore};
+use backend::{
+    fs::FsStore, memory::MemoryStore, replicated::ReplicatedStore, tiered::TieredBlobStore,
+};
 pub use blake3;
 pub use parking_lot;
 pub use rand;
@@ -25,6 +32,9 @@ use write::{purge::PurgeSchedule, BitmapClass, ValueClass};
 #[cfg(feature = "s3")]
 use backend::s3::S3Store;
 
+#[cfg(feature = "azure")]
+use backend::azure::AzureStore;
+
 #[cfg(feature = "postgres")]
 use backend::postgres::PostgresStore;
 
@@ -132,6 +142,54 @@ impl Default for BlobClass {
 pub enum Error {
     InternalError(String),
     AssertValueFailed,
+    Unavailable(String),
+    ValueTooLarge { size: usize, max_size: usize },
+    QuotaExceeded { used: u64, limit: u64 },
+    Timeout(Duration),
+    MemoryLimitExceeded { size: usize, max_size: usize },
+    NotFound(String),
+    Unsupported(String),
+    Corrupted(String),
+    ReadOnly,
+}
+
+/// Error categories, used to decide whether an operation can be retried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorKind {
+    /// Temporary failure, such as an unreachable or busy backend, safe to retry
+    Transient,
+    NotFound,
+    /// Concurrent modification, safe to retry after reading the current state
+    Conflict,
+    Unsupported,
+    Corruption,
+    QuotaExceeded,
+    Timeout,
+    /// Write rejected by a store in read-only mode, fails until the mode is lifted
+    ReadOnly,
+    Internal,
+}
+
+impl Error {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::InternalError(_) => ErrorKind::Internal,
+            Error::AssertValueFailed => ErrorKind::Conflict,
+            Error::Unavailable(_) => ErrorKind::Transient,
+            Error::ReadOnly => ErrorKind::ReadOnly,
+            Error::ValueTooLarge { .. }
+            | Error::QuotaExceeded { .. }
+            | Error::MemoryLimitExceeded { .. } => ErrorKind::QuotaExceeded,
+            Error::Timeout(_) => ErrorKind::Timeout,
+            Error::NotFound(_) => ErrorKind::NotFound,
+            Error::Unsupported(_) => ErrorKind::Unsupported,
+            Error::Corrupted(_) => ErrorKind::Corruption,
+        }
+    }
+
+    pub fn is_retryable(&self) -> bool {
+        matches!(self.kind(), ErrorKind::Transient | ErrorKind::Conflict)
+    }
 }
 
 impl std::error::Error for Error {}
@@ -141,6 +199,27 @@ impl Display for Error {
         match self {
             Error::InternalError(msg) => write!(f, "Internal Error: {}", msg),
             Error::AssertValueFailed => write!(f, "Transaction failed: Hash mismatch"),
+            Error::Unavailable(msg) => write!(f, "Store unavailable: {}", msg),
+            Error::ValueTooLarge { size, max_size } => write!(
+                f,
+                "Value of {} bytes exceeds the maximum value size of {} bytes",
+                size, max_size
+            ),
+            Error::QuotaExceeded { used, limit } => write!(
+                f,
+                "Write would use {} bytes, exceeding the quota of {} bytes",
+                used, limit
+            ),
+            Error::Timeout(timeout) => write!(f, "Query exceeded its timeout of {:?}", timeout),
+            Error::MemoryLimitExceeded { size, max_size } => write!(
+                f,
+                "Query used {} bytes, exceeding its memory limit of {} bytes",
+                size, max_size
+            ),
+            Error::NotFound(msg) => write!(f, "Not found: {}", msg),
+            Error::Unsupported(msg) => write!(f, "Unsupported operation: {}", msg),
+            Error::Corrupted(msg) => write!(f, "Data corruption: {}", msg),
+            Error::ReadOnly => write!(f, "Store is in read-only mode"),
         }
     }
 }
@@ -151,6 +230,8 @@ impl From<String> for Error {
     }
 }
 
+pub const DEFAULT_MAX_VALUE_SIZE: usize = 1024 * 1024;
+
 pub const SUBSPACE_ACL: u8 = b'a';
 pub const SUBSPACE_BITMAP_ID: u8 = b'b';
 pub const SUBSPACE_BITMAP_TAG: u8 = b'c';
@@ -172,13 +253,17 @@ pub const SUBSPACE_QUOTA: u8 = b'u';
 pub const SUBSPACE_REPORT_OUT: u8 = b'h';
 pub const SUBSPACE_REPORT_IN: u8 = b'r';
 pub const SUBSPACE_FTS_INDEX: u8 = b'g';
+pub const SUBSPACE_INTENTS: u8 = b'o';
+pub const SUBSPACE_QUARANTINE: u8 = b'w';
+pub const SUBSPACE_VECTORS: u8 = b'x';
 
-pub const SUBSPACE_RESERVED_1: u8 = b'o';
-pub const SUBSPACE_RESERVED_2: u8 = b'w';
-pub const SUBSPACE_RESERVED_3: u8 = b'x';
-pub const SUBSPACE_RESERVED_4: u8 = b'y';
-pub const SUBSPACE_RESERVED_5: u8 = b'z';
+pub const SUBSPACE_COLLECTIONS: u8 = b'y';
+pub const SUBSPACE_LABELS: u8 = b'z';
+// Server-wide coordination keys, such as locks and replication heartbeats
+pub const SUBSPACE_CLUSTER: u8 = b'_';
 
+/// Range iteration parameters. All backends iterate over keys in byte-lexicographic
+/// (memcmp) order, with both `begin` and `end` inclusive.
 pub struct IterateParams<T: Key> {
     begin: T,
     end: T,
@@ -208,6 +293,7 @@ pub enum Store {
     MySQL(Arc<MysqlStore>),
     #[cfg(feature = "rocks")]
     RocksDb(Arc<RocksDbStore>),
+    Replicated(Arc<ReplicatedStore>),
     #[default]
     None,
 }
@@ -216,6 +302,14 @@ pub enum Store {
 pub struct BlobStore {
     pub backend: BlobBackend,
     pub compression: CompressionAlgo,
+    pub read_ahead: ReadAhead,
+    pub stats: Arc<CompressionCounters>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReadAhead {
+    pub chunk_size: usize,
+    pub depth: usize,
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -224,12 +318,28 @@ pub enum CompressionAlgo {
     Lz4,
 }
 
+#[derive(Debug, Default)]
+pub struct CompressionCounters {
+    original: AtomicU64,
+    stored: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CompressionStats {
+    pub original: u64,
+    pub stored: u64,
+    pub ratio: f64,
+}
+
 #[derive(Clone)]
 pub enum BlobBackend {
     Store(Store),
     Fs(Arc<FsStore>),
     #[cfg(feature = "s3")]
     S3(Arc<S3Store>),
+    #[cfg(feature = "azure")]
+    Azure(Arc<AzureStore>),
+    Tiered(Arc<TieredBlobStore>),
 }
 
 #[derive(Clone)]
@@ -288,11 +398,33 @@ impl From<RocksDbStore> for Store {
     }
 }
 
+impl Default for ReadAhead {
+    fn default() -> Self {
+        ReadAhead {
+            chunk_size: 1024 * 1024,
+            depth: 2,
+        }
+    }
+}
+
 impl From<FsStore> for BlobStore {
     fn from(store: FsStore) -> Self {
         BlobStore {
             backend: BlobBackend::Fs(Arc::new(store)),
             compression: CompressionAlgo::None,
+            read_ahead: ReadAhead::default(),
+            stats: Default::default(),
+        }
+    }
+}
+
+impl From<TieredBlobStore> for BlobStore {
+    fn from(store: TieredBlobStore) -> Self {
+        BlobStore {
+            backend: BlobBackend::Tiered(Arc::new(store)),
+            compression: CompressionAlgo::None,
+            read_ahead: ReadAhead::default(),
+            stats: Default::default(),
         }
     }
 }
@@ -303,6 +435,20 @@ impl From<S3Store> for BlobStore {
         BlobStore {
             backend: BlobBackend::S3(Arc::new(store)),
             compression: CompressionAlgo::None,
+            read_ahead: ReadAhead::default(),
+            stats: Default::default(),
+        }
+    }
+}
+
+#[cfg(feature = "azure")]
+impl From<AzureStore> for BlobStore {
+    fn from(store: AzureStore) -> Self {
+        BlobStore {
+            backend: BlobBackend::Azure(Arc::new(store)),
+            compression: CompressionAlgo::None,
+            read_ahead: ReadAhead::default(),
+            stats: Default::default(),
         }
     }
 }
@@ -329,9 +475,16 @@ impl From<Store> for FtsStore {
 
 impl From<Store> for BlobStore {
     fn from(store: Store) -> Self {
+        // Blobs are always read from the primary, replicas may not have them yet
+        let store = match store {
+            Store::Replicated(store) => store.primary.clone(),
+            store => store,
+        };
         BlobStore {
             backend: BlobBackend::Store(store),
             compression: CompressionAlgo::None,
+            read_ahead: ReadAhead::default(),
+            stats: Default::default(),
         }
     }
 }
@@ -347,6 +500,8 @@ impl Default for BlobStore {
         Self {
             backend: BlobBackend::Store(Store::None),
             compression: CompressionAlgo::None,
+            read_ahead: ReadAhead::default(),
+            stats: Default::default(),
         }
     }
 }
@@ -707,6 +862,7 @@ impl std::fmt::Debug for Store {
             Self::MySQL(_) => f.debug_tuple("MySQL").finish(),
             #[cfg(feature = "rocks")]
             Self::RocksDb(_) => f.debug_tuple("RocksDb").finish(),
+            Self::Replicated(_) => f.debug_tuple("Replicated").finish(),
             Self::None => f.debug_tuple("None").finish(),
         }
     }
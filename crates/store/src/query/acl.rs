@@ -4,11 +4,16 @@
  * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
  */
 
+use ahash::AHashMap;
+use roaring::RoaringBitmap;
+
 use crate::{
-    write::{key::DeserializeBigEndian, BatchBuilder, Operation, ValueClass, ValueOp},
-    Deserialize, Error, IterateParams, Store, ValueKey, U32_LEN,
+    write::{key::DeserializeBigEndian, BatchBuilder, Operation, TagValue, ValueClass, ValueOp},
+    BitmapKey, Deserialize, Error, IterateParams, Store, ValueKey, U32_LEN,
 };
 
+use super::collections::QueryScope;
+
 pub enum AclQuery {
     SharedWith {
         grant_account_id: u32,
@@ -28,7 +33,193 @@ pub struct AclItem {
     pub permissions: u64,
 }
 
+/// The principal a query is evaluated for: its account, the groups it is a
+/// member of and the permission bits of which any must be granted on a shared
+/// document for it to be visible.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Principal {
+    pub account_ids: Vec<u32>,
+    pub permissions: u64,
+    /// Fields of shared documents that can only be searched, sorted by or read
+    /// when any of the paired permission bits is granted, such as the body of a
+    /// message. Restrictions also apply to the fields of child collections.
+    pub restricted_fields: Vec<(u8, u64)>,
+}
+
+impl Principal {
+    pub fn new(account_id: u32, permissions: u64) -> Self {
+        Principal {
+            account_ids: vec![account_id],
+            permissions,
+            restricted_fields: Vec::new(),
+        }
+    }
+
+    pub fn with_member_of(mut self, account_ids: impl IntoIterator<Item = u32>) -> Self {
+        self.account_ids.extend(account_ids);
+        self
+    }
+
+    pub fn with_restricted_field(mut self, field: impl Into<u8>, permissions: u64) -> Self {
+        self.restricted_fields.push((field.into(), permissions));
+        self
+    }
+
+    pub fn is_owner(&self, account_id: u32) -> bool {
+        self.account_ids.contains(&account_id)
+    }
+
+    /// Returns the permission bits of which any is required to access `field`,
+    /// `None` if the field is not restricted.
+    pub fn field_permissions(&self, field: u8) -> Option<u64> {
+        self.restricted_fields
+            .iter()
+            .filter(|(restricted, _)| *restricted == field)
+            .map(|(_, permissions)| *permissions)
+            .reduce(|a, b| a | b)
+    }
+}
+
 impl Store {
+    /// Returns the documents of `scope` visible to `principal`, or `None` when the
+    /// scope belongs to one of the principal's accounts and every document is.
+    pub async fn visible_documents(
+        &self,
+        principal: &Principal,
+        scope: &QueryScope,
+    ) -> crate::Result<Option<RoaringBitmap>> {
+        if principal.is_owner(scope.account_id) {
+            return Ok(None);
+        }
+
+        let grants = self.acl_grants(principal, scope).await?;
+        self.granted_documents(scope, &grants, principal.permissions)
+            .await
+            .map(Some)
+    }
+
+    /// Returns the documents of `scope` on which `principal` can access `field`, or
+    /// `None` when it can access the field on every visible document.
+    pub async fn field_access(
+        &self,
+        principal: &Principal,
+        scope: &QueryScope,
+        field: u8,
+    ) -> crate::Result<Option<RoaringBitmap>> {
+        match principal.field_permissions(field) {
+            Some(permissions) if !principal.is_owner(scope.account_id) => {
+                let grants = self.acl_grants(principal, scope).await?;
+                self.granted_documents(scope, &grants, permissions)
+                    .await
+                    .map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Same as `get_value` for the `field` property of a document of `scope`, but
+    /// returns `None` when the field is restricted and `principal` was not
+    /// granted access to it on the document.
+    pub async fn get_property_as<U>(
+        &self,
+        principal: &Principal,
+        scope: &QueryScope,
+        document_id: u32,
+        field: impl Into<u8>,
+    ) -> crate::Result<Option<U>>
+    where
+        U: Deserialize + 'static,
+    {
+        let field = field.into();
+        if self
+            .field_access(principal, scope, field)
+            .await?
+            .map_or(true, |readable| readable.contains(document_id))
+        {
+            self.get_value(ValueKey::<ValueClass<u32>>::property(
+                scope.account_id,
+                scope.collection,
+                document_id,
+                field,
+            ))
+            .await
+        } else {
+            Ok(None)
+        }
+    }
+
+    // Returns the permissions granted to the principal on each document, or
+    // container, of the scope
+    pub(crate) async fn acl_grants(
+        &self,
+        principal: &Principal,
+        scope: &QueryScope,
+    ) -> crate::Result<AHashMap<u32, u64>> {
+        let acl_collection = scope.container.map_or(scope.collection, |(c, _)| c);
+        let mut grants: AHashMap<u32, u64> = AHashMap::new();
+        for &grant_account_id in &principal.account_ids {
+            for item in self
+                .acl_query(AclQuery::SharedWith {
+                    grant_account_id,
+                    to_account_id: scope.account_id,
+                    to_collection: acl_collection,
+                })
+                .await?
+            {
+                *grants.entry(item.to_document_id).or_default() |= item.permissions;
+            }
+        }
+
+        Ok(grants)
+    }
+
+    // Returns the documents of the scope granted any of the `permissions` bits
+    pub(crate) async fn granted_documents(
+        &self,
+        scope: &QueryScope,
+        grants: &AHashMap<u32, u64>,
+        permissions: u64,
+    ) -> crate::Result<RoaringBitmap> {
+        let mut shared = grants
+            .iter()
+            .filter(|(_, granted)| *granted & permissions != 0)
+            .map(|(id, _)| *id)
+            .collect::<RoaringBitmap>();
+        if shared.is_empty() {
+            return Ok(shared);
+        }
+
+        if let Some((_, field)) = scope.container {
+            let mut documents = RoaringBitmap::new();
+            for container_id in shared {
+                if let Some(bitmap) = self
+                    .get_bitmap(BitmapKey::tag(
+                        scope.account_id,
+                        scope.collection,
+                        field,
+                        TagValue::Id(container_id),
+                    ))
+                    .await?
+                {
+                    documents |= bitmap;
+                }
+            }
+            shared = documents;
+        }
+
+        // ACLs and tags of deleted documents may linger until they are purged
+        if let Some(document_ids) = self
+            .get_bitmap(BitmapKey::document_ids(scope.account_id, scope.collection))
+            .await?
+        {
+            shared &= document_ids;
+        } else {
+            shared.clear();
+        }
+
+        Ok(shared)
+    }
+
     pub async fn acl_query(&self, query: AclQuery) -> crate::Result<Vec<AclItem>> {
         let mut results = Vec::new();
         let (from_key, to_key) = match query {
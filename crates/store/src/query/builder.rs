@@ -0,0 +1,94 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs Ltd <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use super::Filter;
+
+/// Builds the flat filter list consumed by `Store::filter` out of nested groups, so
+/// that `And`/`Or`/`Not` markers are always closed by a matching `End`. Top level
+/// filters are combined with `And`.
+#[derive(Debug, Default)]
+pub struct FilterBuilder {
+    filters: Vec<Filter>,
+}
+
+impl FilterBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, filter: impl Into<FilterBuilder>) -> Self {
+        self.filters.extend(filter.into().filters);
+        self
+    }
+
+    pub fn and<T: Into<FilterBuilder>>(self, filters: impl IntoIterator<Item = T>) -> Self {
+        self.group(Filter::And, filters)
+    }
+
+    pub fn or<T: Into<FilterBuilder>>(self, filters: impl IntoIterator<Item = T>) -> Self {
+        self.group(Filter::Or, filters)
+    }
+
+    /// Matches documents that match none of `filters`.
+    pub fn not<T: Into<FilterBuilder>>(self, filters: impl IntoIterator<Item = T>) -> Self {
+        self.group(Filter::Not, filters)
+    }
+
+    fn group<T: Into<FilterBuilder>>(
+        mut self,
+        op: Filter,
+        filters: impl IntoIterator<Item = T>,
+    ) -> Self {
+        self.filters.push(op);
+        for filter in filters {
+            self.filters.extend(filter.into().filters);
+        }
+        self.filters.push(Filter::End);
+        self
+    }
+
+    /// Returns the flat filter list, or an error if raw markers added with `with`
+    /// left a group unbalanced.
+    pub fn build(self) -> crate::Result<Vec<Filter>> {
+        validate(&self.filters)?;
+        Ok(self.filters)
+    }
+}
+
+/// Checks that every `And`, `Or` and `Not` marker in a flat filter list is closed
+/// by an `End`, and that no `End` appears outside of a group.
+pub fn validate(filters: &[Filter]) -> crate::Result<()> {
+    let mut depth = 0usize;
+    for (pos, filter) in filters.iter().enumerate() {
+        match filter {
+            Filter::And | Filter::Or | Filter::Not => depth += 1,
+            Filter::End => {
+                depth = depth.checked_sub(1).ok_or_else(|| {
+                    crate::Error::InternalError(format!(
+                        "Unexpected end of filter group at position {pos}"
+                    ))
+                })?;
+            }
+            _ => (),
+        }
+    }
+
+    if depth == 0 {
+        Ok(())
+    } else {
+        Err(crate::Error::InternalError(format!(
+            "{depth} filter group(s) not closed"
+        )))
+    }
+}
+
+impl From<Filter> for FilterBuilder {
+    fn from(filter: Filter) -> Self {
+        FilterBuilder {
+            filters: vec![filter],
+        }
+    }
+}
@@ -0,0 +1,162 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs Ltd <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use roaring::RoaringBitmap;
+
+use crate::{BitmapKey, Store};
+
+use super::{acl::Principal, Filter, ResultSet};
+
+/// A filter evaluated over every collection of an account, see
+/// `Store::query_collections`.
+#[derive(Debug, Clone, Default)]
+pub struct Query {
+    filters: Vec<Filter>,
+    excluded: Vec<u8>,
+    excluded_tags: Vec<(u8, u32)>,
+    candidates: Option<RoaringBitmap>,
+}
+
+impl Query {
+    pub fn new(filters: Vec<Filter>) -> Self {
+        Query {
+            filters,
+            excluded: Vec::new(),
+            excluded_tags: Vec::new(),
+            candidates: None,
+        }
+    }
+
+    /// Limits the results to the given documents, such as a previous result set
+    /// being refined. The candidates are the starting working set of every
+    /// filter rather than being intersected with the results at the end.
+    pub fn within(mut self, candidates: RoaringBitmap) -> Self {
+        self.candidates = Some(candidates);
+        self
+    }
+
+    /// Skips the given collections, which are left out before any filter is
+    /// evaluated.
+    pub fn exclude_collections(mut self, collections: &[u8]) -> Self {
+        self.excluded.extend_from_slice(collections);
+        self
+    }
+
+    /// Skips the documents tagged with any of `ids` in `field`, such as the emails
+    /// in the Trash or Junk mailboxes. Mailboxes are documents rather than
+    /// collections, so they are left out through the tags of their emails.
+    pub fn exclude_tagged(mut self, field: impl Into<u8>, ids: &[u32]) -> Self {
+        let field = field.into();
+        self.excluded_tags.extend(ids.iter().map(|id| (field, *id)));
+        self
+    }
+}
+
+/// A collection of an account to query, see `Store::filter_as`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueryScope {
+    pub account_id: u32,
+    pub collection: u8,
+    /// Collection and tag field of the documents holding the ACLs, such as the
+    /// mailboxes of an email. By default ACLs are read from the documents queried.
+    pub container: Option<(u8, u8)>,
+}
+
+impl QueryScope {
+    pub fn new(account_id: u32, collection: impl Into<u8>) -> Self {
+        QueryScope {
+            account_id,
+            collection: collection.into(),
+            container: None,
+        }
+    }
+
+    pub fn with_container(mut self, collection: impl Into<u8>, field: impl Into<u8>) -> Self {
+        self.container = Some((collection.into(), field.into()));
+        self
+    }
+}
+
+impl Store {
+    /// Evaluates a query on each collection of an account that holds documents,
+    /// as listed by `list_collections`, and returns the non-empty result sets in
+    /// ascending collection order.
+    pub async fn query_collections(
+        &self,
+        account_id: u32,
+        query: &Query,
+    ) -> crate::Result<Vec<ResultSet>> {
+        let mut results = Vec::new();
+        for collection in self.list_collections(account_id).await? {
+            if query.excluded.contains(&collection) {
+                continue;
+            }
+
+            let mut result = match &query.candidates {
+                Some(candidates) if query.filters.is_empty() => {
+                    let mut result = self.filter(account_id, collection, vec![]).await?;
+                    result.results &= candidates;
+                    result
+                }
+                Some(candidates) => {
+                    // The top level And starts from the candidates and
+                    // short-circuits as soon as no candidate is left
+                    let mut filters = Vec::with_capacity(query.filters.len() + 1);
+                    filters.push(Filter::is_in_set(candidates.clone()));
+                    filters.extend(query.filters.iter().cloned());
+                    self.filter(account_id, collection, filters).await?
+                }
+                None => {
+                    self.filter(account_id, collection, query.filters.clone())
+                        .await?
+                }
+            };
+            for (field, id) in &query.excluded_tags {
+                if result.results.is_empty() {
+                    break;
+                }
+                if let Some(tagged) = self
+                    .get_bitmap(BitmapKey::tag(account_id, collection, *field, *id))
+                    .await?
+                {
+                    result.results -= tagged;
+                }
+            }
+            if !result.results.is_empty() {
+                results.push(result);
+            }
+        }
+
+        Ok(results)
+    }
+
+    /// Evaluates `filters` on each scope on behalf of `principal`, see
+    /// `Store::filter_as`. Returns the non-empty result sets, each one carrying its
+    /// owning account, with the results of repeated scopes merged.
+    pub async fn query_shared(
+        &self,
+        principal: &Principal,
+        scopes: &[QueryScope],
+        filters: Vec<Filter>,
+    ) -> crate::Result<Vec<ResultSet>> {
+        let mut results: Vec<ResultSet> = Vec::new();
+        for scope in scopes {
+            let result = self.filter_as(principal, scope, filters.clone()).await?;
+            if result.results.is_empty() {
+                continue;
+            }
+            if let Some(existing) = results.iter_mut().find(|existing| {
+                existing.account_id == result.account_id && existing.collection == result.collection
+            }) {
+                existing.results |= result.results;
+            } else {
+                results.push(result);
+            }
+        }
+
+        Ok(results)
+    }
+}
@@ -0,0 +1,94 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs Ltd <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::time::Instant;
+
+use roaring::RoaringBitmap;
+
+use super::Filter;
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct FilterExplain {
+    pub filter: String,
+    pub matches: u64,
+    pub elapsed_us: u64,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[serde(default)]
+    pub children: Vec<FilterExplain>,
+}
+
+impl FilterExplain {
+    pub(crate) fn new(filter: String, bm: Option<&RoaringBitmap>, started: Instant) -> Self {
+        FilterExplain {
+            filter,
+            matches: bm.map_or(0, |bm| bm.len()),
+            elapsed_us: started.elapsed().as_micros() as u64,
+            children: Vec::new(),
+        }
+    }
+
+    pub(crate) fn finish(&mut self, bm: Option<&RoaringBitmap>, started: Instant) {
+        self.matches = bm.map_or(0, |bm| bm.len());
+        self.elapsed_us = started.elapsed().as_micros() as u64;
+    }
+}
+
+impl Filter {
+    pub fn explain_label(&self) -> String {
+        match self {
+            Filter::MatchValue { field, op, value } => {
+                format!(
+                    "MatchValue(field: {field}, op: {op:?}, value: {:?})",
+                    String::from_utf8_lossy(value)
+                )
+            }
+            Filter::MatchValues { field, values } => {
+                format!("MatchValues(field: {field}, values: {})", values.len())
+            }
+            Filter::MatchRange { field, from, to } => format!(
+                "MatchRange(field: {field}, from: {:?}, to: {:?})",
+                String::from_utf8_lossy(from),
+                String::from_utf8_lossy(to)
+            ),
+            Filter::HasText {
+                field,
+                text,
+                tokenize,
+            } => format!("HasText(field: {field}, text: {text:?}, tokenize: {tokenize})"),
+            Filter::InBitmap(class) => format!("InBitmap({class:?})"),
+            Filter::InThread { field, thread_id } => {
+                format!("InThread(field: {field}, thread_id: {thread_id})")
+            }
+            Filter::HasAttachment {
+                field,
+                content_type,
+            } => format!("HasAttachment(field: {field}, content_type: {content_type:?})"),
+            Filter::DocumentRange { from, to } => format!("DocumentRange(from: {from}, to: {to})"),
+            Filter::VectorSearch {
+                field,
+                query_vector,
+                top_k,
+            } => format!(
+                "VectorSearch(field: {field}, dimension: {}, top_k: {top_k})",
+                query_vector.len()
+            ),
+            Filter::DocumentSet(set) => format!("DocumentSet(len: {})", set.len()),
+            Filter::HasChild {
+                child_collection,
+                filters,
+            } => format!(
+                "HasChild(child_collection: {child_collection}, filters: {})",
+                filters.len()
+            ),
+            Filter::SizeAtLeast(size) => format!("SizeAtLeast({size})"),
+            Filter::SizeAtMost(size) => format!("SizeAtMost({size})"),
+            Filter::And => "And".to_string(),
+            Filter::Or => "Or".to_string(),
+            Filter::Not => "Not".to_string(),
+            Filter::End => "End".to_string(),
+        }
+    }
+}
@@ -0,0 +1,476 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs Ltd <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::fmt::Write as _;
+
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use utils::codec::base32_custom::{Base32Reader, Base32Writer};
+
+use crate::{
+    write::{key::DeserializeBigEndian, AnyKey, ValueClass},
+    Deserialize, IndexKey, IndexKeyPrefix, IterateParams, Key, Serialize, Store, ValueKey,
+    SUBSPACE_INDEXES, U32_LEN,
+};
+
+use super::ResultSet;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    NdJson,
+    Csv,
+}
+
+enum ExportValue<'x> {
+    Text(&'x str),
+    Number(u64),
+    Binary(&'x [u8]),
+    None,
+}
+
+/// Sort field and checkpoint interval of a resumable export, optionally resuming
+/// from a token emitted by a previous run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExportCursor {
+    pub sort_field: u8,
+    pub checkpoint_every: u64,
+    pub resume: Option<ResumeToken>,
+}
+
+/// Sort value and id of the last document written. Documents without a sort value
+/// are exported last and have no sort key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResumeToken {
+    pub sort_key: Option<Vec<u8>>,
+    pub document_id: u32,
+}
+
+struct RawValue(Vec<u8>);
+
+// Index entries read per iteration while exporting by sort value
+const EXPORT_PAGE_SIZE: usize = 1000;
+
+impl Store {
+    /// Writes the values of `fields` for each document in `result_set` as NDJSON or
+    /// CSV, one document per line, and returns the number of documents written.
+    /// Values are read from the stored properties one document at a time as rows
+    /// are written, so fields that are only indexed are exported as null.
+    ///
+    /// Stored values carry no type information, so printable UTF-8 is exported as
+    /// text, 4 and 8 byte values as big-endian integers and anything else as
+    /// hexadecimal.
+    pub async fn export(
+        &self,
+        result_set: &ResultSet,
+        fields: &[u8],
+        format: ExportFormat,
+        writer: &mut (impl AsyncWrite + Unpin),
+    ) -> crate::Result<u64> {
+        let mut line = String::new();
+        if format == ExportFormat::Csv {
+            write_csv_header(&mut line, fields);
+            writer.write_all(line.as_bytes()).await?;
+        }
+
+        let mut count = 0;
+        for document_id in &result_set.results {
+            self.export_row(result_set, fields, format, document_id, &mut line)
+                .await?;
+            writer.write_all(line.as_bytes()).await?;
+            count += 1;
+        }
+        writer.flush().await?;
+
+        Ok(count)
+    }
+
+    /// Exports the documents in `result_set` ordered by the indexed value of a sort
+    /// field and then by id, followed by the documents without a value for it
+    /// ordered by id. Every `checkpoint_every` documents the writer is flushed and
+    /// a resume token is passed to `on_checkpoint`. Passing the last token received
+    /// to a new export continues right after the last document written.
+    ///
+    /// Tokens are anchored to the sort value and id of a document rather than to a
+    /// position, so they remain valid when documents are added or removed between
+    /// runs. Values are read from the stored properties and the sort field is
+    /// expected to have a single value per document.
+    pub async fn export_resumable(
+        &self,
+        result_set: &ResultSet,
+        fields: &[u8],
+        format: ExportFormat,
+        cursor: &ExportCursor,
+        writer: &mut (impl AsyncWrite + Unpin),
+        mut on_checkpoint: impl FnMut(ResumeToken) + Send,
+    ) -> crate::Result<u64> {
+        let mut line = String::new();
+        if format == ExportFormat::Csv && cursor.resume.is_none() {
+            write_csv_header(&mut line, fields);
+            writer.write_all(line.as_bytes()).await?;
+        }
+
+        let prefix = IndexKeyPrefix {
+            account_id: result_set.account_id,
+            collection: result_set.collection,
+            field: cursor.sort_field,
+        };
+        let mut anchor = cursor.resume.clone();
+        let mut count = 0;
+        let mut pending = 0;
+
+        // Export documents by sort value, one page at a time
+        while anchor
+            .as_ref()
+            .map_or(true, |anchor| anchor.sort_key.is_some())
+        {
+            let begin = if let Some(ResumeToken {
+                sort_key: Some(sort_key),
+                document_id,
+            }) = &anchor
+            {
+                let mut begin = IndexKey {
+                    account_id: result_set.account_id,
+                    collection: result_set.collection,
+                    document_id: *document_id,
+                    field: cursor.sort_field,
+                    key: sort_key.as_slice(),
+                }
+                .serialize(0);
+                begin.push(0);
+                begin
+            } else {
+                prefix.serialize(0)
+            };
+            let mut page = Vec::with_capacity(EXPORT_PAGE_SIZE);
+            self.iterate(
+                IterateParams::new(
+                    AnyKey {
+                        subspace: SUBSPACE_INDEXES,
+                        key: begin,
+                    },
+                    AnyKey {
+                        subspace: SUBSPACE_INDEXES,
+                        key: index_end(&prefix),
+                    },
+                )
+                .no_values()
+                .ascending(),
+                |key, _| {
+                    let id_pos = key.len() - U32_LEN;
+                    let document_id = key.deserialize_be_u32(id_pos)?;
+                    if result_set.results.contains(document_id) {
+                        let value = key.get(IndexKeyPrefix::len()..id_pos).ok_or_else(|| {
+                            crate::Error::InternalError("Invalid key found in index".to_string())
+                        })?;
+                        page.push((value.to_vec(), document_id));
+                    }
+                    Ok(page.len() < EXPORT_PAGE_SIZE)
+                },
+            )
+            .await?;
+
+            let is_last_page = page.len() < EXPORT_PAGE_SIZE;
+            for (sort_key, document_id) in page {
+                self.export_row(result_set, fields, format, document_id, &mut line)
+                    .await?;
+                writer.write_all(line.as_bytes()).await?;
+                count += 1;
+                pending += 1;
+                anchor = Some(ResumeToken {
+                    sort_key: Some(sort_key),
+                    document_id,
+                });
+                if pending >= cursor.checkpoint_every {
+                    writer.flush().await?;
+                    on_checkpoint(anchor.clone().unwrap());
+                    pending = 0;
+                }
+            }
+
+            if is_last_page {
+                break;
+            }
+        }
+
+        // Export the documents without a sort value
+        let mut unsorted = result_set.results.clone();
+        self.iterate(
+            IterateParams::new(
+                AnyKey {
+                    subspace: SUBSPACE_INDEXES,
+                    key: prefix.serialize(0),
+                },
+                AnyKey {
+                    subspace: SUBSPACE_INDEXES,
+                    key: index_end(&prefix),
+                },
+            )
+            .no_values()
+            .ascending(),
+            |key, _| {
+                unsorted.remove(key.deserialize_be_u32(key.len() - U32_LEN)?);
+                Ok(!unsorted.is_empty())
+            },
+        )
+        .await?;
+        if let Some(ResumeToken {
+            sort_key: None,
+            document_id,
+        }) = &anchor
+        {
+            unsorted.remove_range(..=*document_id);
+        }
+        for document_id in unsorted {
+            self.export_row(result_set, fields, format, document_id, &mut line)
+                .await?;
+            writer.write_all(line.as_bytes()).await?;
+            count += 1;
+            pending += 1;
+            anchor = Some(ResumeToken {
+                sort_key: None,
+                document_id,
+            });
+            if pending >= cursor.checkpoint_every {
+                writer.flush().await?;
+                on_checkpoint(anchor.clone().unwrap());
+                pending = 0;
+            }
+        }
+
+        writer.flush().await?;
+        if pending > 0 {
+            on_checkpoint(anchor.unwrap());
+        }
+
+        Ok(count)
+    }
+
+    async fn export_row(
+        &self,
+        result_set: &ResultSet,
+        fields: &[u8],
+        format: ExportFormat,
+        document_id: u32,
+        line: &mut String,
+    ) -> crate::Result<()> {
+        let mut values = Vec::with_capacity(fields.len());
+        for &field in fields {
+            values.push(
+                self.get_raw_property(result_set, document_id, field)
+                    .await?,
+            );
+        }
+        write_row(line, format, document_id, fields, &values);
+        Ok(())
+    }
+
+    async fn get_raw_property(
+        &self,
+        result_set: &ResultSet,
+        document_id: u32,
+        field: u8,
+    ) -> crate::Result<Option<Vec<u8>>> {
+        self.get_value::<RawValue>(ValueKey {
+            account_id: result_set.account_id,
+            collection: result_set.collection,
+            document_id,
+            class: ValueClass::Property(field),
+        })
+        .await
+        .map(|value| value.map(|value| value.0))
+    }
+}
+
+impl ExportCursor {
+    pub fn new(sort_field: impl Into<u8>) -> Self {
+        ExportCursor {
+            sort_field: sort_field.into(),
+            checkpoint_every: 1000,
+            resume: None,
+        }
+    }
+
+    pub fn with_checkpoint_every(mut self, documents: u64) -> Self {
+        self.checkpoint_every = std::cmp::max(documents, 1);
+        self
+    }
+
+    pub fn with_resume_token(mut self, token: ResumeToken) -> Self {
+        self.resume = Some(token);
+        self
+    }
+}
+
+impl ResumeToken {
+    /// Encodes the token as a string that clients can store and send back to
+    /// resume an export.
+    pub fn encode(&self) -> String {
+        Base32Writer::from_bytes(self.serialize()).finalize()
+    }
+
+    pub fn decode(token: &str) -> crate::Result<Self> {
+        ResumeToken::deserialize(&Base32Reader::new(token.as_bytes()).collect::<Vec<_>>())
+    }
+}
+
+// Returns the first key past the index of a field, carrying over into the
+// collection and account when the field is the last one
+fn index_end(prefix: &IndexKeyPrefix) -> Vec<u8> {
+    let mut key = prefix.serialize(0);
+    while let Some(byte) = key.pop() {
+        if byte < u8::MAX {
+            key.push(byte + 1);
+            return key;
+        }
+    }
+    vec![u8::MAX; IndexKeyPrefix::len() + 1]
+}
+
+fn write_csv_header(line: &mut String, fields: &[u8]) {
+    line.push_str("id");
+    for field in fields {
+        let _ = write!(line, ",{field}");
+    }
+    line.push('\n');
+}
+
+fn write_row(
+    line: &mut String,
+    format: ExportFormat,
+    document_id: u32,
+    fields: &[u8],
+    values: &[Option<Vec<u8>>],
+) {
+    line.clear();
+    match format {
+        ExportFormat::NdJson => {
+            let _ = write!(line, "{{\"id\":{document_id}");
+        }
+        ExportFormat::Csv => {
+            let _ = write!(line, "{document_id}");
+        }
+    }
+
+    for (field, value) in fields.iter().zip(values) {
+        let value = value
+            .as_deref()
+            .map_or(ExportValue::None, ExportValue::from_bytes);
+
+        match format {
+            ExportFormat::NdJson => {
+                let _ = write!(line, ",\"{field}\":");
+                value.write_json(line);
+            }
+            ExportFormat::Csv => {
+                line.push(',');
+                value.write_csv(line);
+            }
+        }
+    }
+
+    if format == ExportFormat::NdJson {
+        line.push('}');
+    }
+    line.push('\n');
+}
+
+impl<'x> ExportValue<'x> {
+    fn from_bytes(bytes: &'x [u8]) -> Self {
+        match std::str::from_utf8(bytes) {
+            Ok(text) if !text.chars().any(char::is_control) => ExportValue::Text(text),
+            _ => match bytes.len() {
+                4 => ExportValue::Number(u32::from_be_bytes(bytes.try_into().unwrap()) as u64),
+                8 => ExportValue::Number(u64::from_be_bytes(bytes.try_into().unwrap())),
+                _ => ExportValue::Binary(bytes),
+            },
+        }
+    }
+
+    fn write_json(&self, out: &mut String) {
+        match self {
+            ExportValue::Text(text) => {
+                out.push('"');
+                for ch in text.chars() {
+                    match ch {
+                        '"' => out.push_str("\\\""),
+                        '\\' => out.push_str("\\\\"),
+                        _ => out.push(ch),
+                    }
+                }
+                out.push('"');
+            }
+            ExportValue::Number(number) => {
+                let _ = write!(out, "{number}");
+            }
+            ExportValue::Binary(bytes) => {
+                out.push('"');
+                write_hex(out, bytes);
+                out.push('"');
+            }
+            ExportValue::None => out.push_str("null"),
+        }
+    }
+
+    fn write_csv(&self, out: &mut String) {
+        match self {
+            ExportValue::Text(text) if text.contains([',', '"']) => {
+                out.push('"');
+                out.push_str(&text.replace('"', "\"\""));
+                out.push('"');
+            }
+            ExportValue::Text(text) => out.push_str(text),
+            ExportValue::Number(number) => {
+                let _ = write!(out, "{number}");
+            }
+            ExportValue::Binary(bytes) => write_hex(out, bytes),
+            ExportValue::None => (),
+        }
+    }
+}
+
+fn write_hex(out: &mut String, bytes: &[u8]) {
+    for byte in bytes {
+        let _ = write!(out, "{byte:02x}");
+    }
+}
+
+impl Deserialize for RawValue {
+    fn deserialize(bytes: &[u8]) -> crate::Result<Self> {
+        Ok(RawValue(bytes.to_vec()))
+    }
+}
+
+impl Serialize for &ResumeToken {
+    fn serialize(self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(
+            1 + U32_LEN + self.sort_key.as_ref().map_or(0, |sort_key| sort_key.len()),
+        );
+        bytes.push(self.sort_key.is_some() as u8);
+        bytes.extend_from_slice(&self.document_id.to_be_bytes());
+        if let Some(sort_key) = &self.sort_key {
+            bytes.extend_from_slice(sort_key);
+        }
+        bytes
+    }
+}
+
+impl Deserialize for ResumeToken {
+    fn deserialize(bytes: &[u8]) -> crate::Result<Self> {
+        let document_id = bytes.deserialize_be_u32(1)?;
+        match bytes.first() {
+            Some(0) if bytes.len() == 1 + U32_LEN => Ok(ResumeToken {
+                sort_key: None,
+                document_id,
+            }),
+            Some(1) => Ok(ResumeToken {
+                sort_key: Some(bytes[1 + U32_LEN..].to_vec()),
+                document_id,
+            }),
+            _ => Err(crate::Error::InternalError(
+                "Invalid export resume token".to_string(),
+            )),
+        }
+    }
+}
@@ -4,24 +4,39 @@
  * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
  */
 
-use std::ops::{BitAndAssign, BitOrAssign, BitXorAssign};
+use std::{
+    future::Future,
+    ops::{BitAndAssign, BitOrAssign, BitXorAssign},
+    pin::Pin,
+    time::{Duration, Instant},
+};
 
-use ahash::HashSet;
+use ahash::{AHashMap, HashSet};
 use nlp::tokenizers::word::WordTokenizer;
 use roaring::RoaringBitmap;
 
 use crate::{
-    backend::MAX_TOKEN_LENGTH, write::key::DeserializeBigEndian, BitmapKey, IndexKey,
-    IndexKeyPrefix, IterateParams, Key, Store, U32_LEN,
+    backend::MAX_TOKEN_LENGTH,
+    write::{key::DeserializeBigEndian, AnyKey, BitmapClass, TagValue},
+    BitmapKey, IndexKey, IndexKeyPrefix, IterateParams, Key, Store, SUBSPACE_BITMAP_TAG, U32_LEN,
 };
 
-use super::{Filter, Operator, ResultSet};
+use super::{
+    acl::Principal, collections::QueryScope, explain::FilterExplain, partial::op_matches, Filter,
+    FilterOptions, Operator, ResultSet,
+};
 
 struct State {
     pub op: Filter,
     pub bm: Option<RoaringBitmap>,
 }
 
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Deadline {
+    expires: Instant,
+    timeout: Duration,
+}
+
 impl Store {
     pub async fn filter(
         &self,
@@ -29,30 +44,256 @@ impl Store {
         collection: impl Into<u8> + Sync + Send,
         filters: Vec<Filter>,
     ) -> crate::Result<ResultSet> {
-        let collection = collection.into();
+        self.filter_with_options(account_id, collection, filters, &FilterOptions::default())
+            .await
+    }
+
+    /// Same as `filter`, evaluated with `options`. Evaluation is aborted once it
+    /// exceeds any of the `limits`, soft deleted documents are only included
+    /// in the results when `include_tombstones` is set and the filters run on a
+    /// replica when `max_staleness` is set.
+    pub async fn filter_with_options(
+        &self,
+        account_id: u32,
+        collection: impl Into<u8> + Sync + Send,
+        filters: Vec<Filter>,
+        options: &FilterOptions,
+    ) -> crate::Result<ResultSet> {
+        self.read_store(options.max_staleness)
+            .filter_(account_id, collection.into(), filters, options, false)
+            .await
+            .map(|(result, _)| result)
+    }
+
+    /// Same as `filter`, but evaluated on behalf of `principal`. Results are
+    /// limited up front to the documents of `scope` the principal can see, see
+    /// `Store::visible_documents`, and filters on restricted fields, including
+    /// those nested in `Filter::HasChild`, only match the documents the principal
+    /// was granted access to them.
+    pub async fn filter_as(
+        &self,
+        principal: &Principal,
+        scope: &QueryScope,
+        filters: Vec<Filter>,
+    ) -> crate::Result<ResultSet> {
+        if principal.is_owner(scope.account_id) {
+            return self
+                .filter(scope.account_id, scope.collection, filters)
+                .await;
+        }
+
+        let grants = self.acl_grants(principal, scope).await?;
+        let visible = self
+            .granted_documents(scope, &grants, principal.permissions)
+            .await?;
+        if visible.is_empty() {
+            return Ok(ResultSet::new(scope.account_id, scope.collection, visible));
+        }
+
+        let mut searchable = AHashMap::new();
+        for (field, permissions) in &principal.restricted_fields {
+            if filters.iter().any(|filter| filter.uses_field(*field)) {
+                let documents = self.granted_documents(scope, &grants, *permissions).await?;
+                searchable
+                    .entry(*field)
+                    .and_modify(|granted: &mut RoaringBitmap| *granted |= &documents)
+                    .or_insert(documents);
+            }
+        }
+
+        // Seeds the top level And, which short-circuits once it is empty
+        let mut visible_filters = Vec::with_capacity(filters.len() + 1);
+        visible_filters.push(Filter::is_in_set(visible));
+        for filter in filters {
+            let documents = searchable
+                .iter()
+                .filter(|(field, _)| filter.uses_field(**field))
+                .fold(None, |acc: Option<RoaringBitmap>, (_, documents)| {
+                    Some(acc.map_or_else(|| documents.clone(), |acc| acc & documents))
+                });
+            if let Some(documents) = documents {
+                visible_filters.extend([
+                    Filter::And,
+                    filter,
+                    Filter::is_in_set(documents),
+                    Filter::End,
+                ]);
+            } else {
+                visible_filters.push(filter);
+            }
+        }
+
+        self.filter(scope.account_id, scope.collection, visible_filters)
+            .await
+    }
+
+    pub async fn filter_explained(
+        &self,
+        account_id: u32,
+        collection: impl Into<u8> + Sync + Send,
+        filters: Vec<Filter>,
+    ) -> crate::Result<(ResultSet, FilterExplain)> {
+        self.filter_(
+            account_id,
+            collection.into(),
+            filters,
+            &FilterOptions::default(),
+            true,
+        )
+        .await
+        .map(|(result, explain)| (result, explain.unwrap_or_default()))
+    }
+
+    async fn filter_(
+        &self,
+        account_id: u32,
+        collection: u8,
+        filters: Vec<Filter>,
+        options: &FilterOptions,
+        explain: bool,
+    ) -> crate::Result<(ResultSet, Option<FilterExplain>)> {
+        let partial_indexes = options.partial_indexes.as_slice();
+        let started = Instant::now();
+        let deadline = options.limits.timeout.map(|timeout| Deadline {
+            expires: started + timeout,
+            timeout,
+        });
         if filters.is_empty() {
-            return Ok(ResultSet {
-                account_id,
-                collection,
-                results: self
-                    .get_bitmap(BitmapKey::document_ids(account_id, collection))
-                    .await?
-                    .unwrap_or_else(RoaringBitmap::new),
-            });
+            let mut results = self
+                .get_bitmap(BitmapKey::document_ids(account_id, collection))
+                .await?
+                .unwrap_or_else(RoaringBitmap::new);
+            if !options.include_tombstones && !results.is_empty() {
+                results -= self.get_tombstones(account_id, collection).await?;
+            }
+            let explain = explain
+                .then(|| FilterExplain::new("DocumentIds".to_string(), Some(&results), started));
+
+            return Ok((
+                ResultSet {
+                    account_id,
+                    collection,
+                    results,
+                },
+                explain,
+            ));
         }
 
         let mut state: State = Filter::And.into();
         let mut stack = Vec::new();
         let mut filters = filters.into_iter().peekable();
 
+        // Explain nodes follow the same nesting as the filter state stack
+        let mut explain_node = explain.then(|| {
+            (
+                FilterExplain::new("And".to_string(), None, started),
+                started,
+            )
+        });
+        let mut explain_stack = Vec::new();
+
         let mut not_mask = RoaringBitmap::new();
         let mut not_fetch = false;
 
         while let Some(filter) = filters.next() {
+            Deadline::check(deadline)?;
+
+            let explain_leaf = (explain_node.is_some()
+                && !matches!(filter, Filter::And | Filter::Or | Filter::Not | Filter::End))
+            .then(|| (filter.explain_label(), Instant::now()));
+
             let mut result = match filter {
                 Filter::MatchValue { field, op, value } => {
-                    self.range_to_bitmap(account_id, collection, field, &value, op)
-                        .await?
+                    if partial_indexes
+                        .iter()
+                        .any(|index| index.field == field && !index.covers(op, &value))
+                    {
+                        self.scan_to_bitmap(account_id, collection, field, &value, op, deadline)
+                            .await?
+                    } else {
+                        self.range_to_bitmap(account_id, collection, field, &value, op, deadline)
+                            .await?
+                    }
+                }
+                Filter::MatchRange { field, from, to } => {
+                    if from > to {
+                        None
+                    } else if partial_indexes.iter().any(|index| {
+                        index.field == field
+                            && !(index.covers(Operator::GreaterEqualThan, &from)
+                                && index.covers(Operator::LowerEqualThan, &to))
+                    }) {
+                        let from = self
+                            .scan_to_bitmap(
+                                account_id,
+                                collection,
+                                field,
+                                &from,
+                                Operator::GreaterEqualThan,
+                                deadline,
+                            )
+                            .await?;
+                        let to = self
+                            .scan_to_bitmap(
+                                account_id,
+                                collection,
+                                field,
+                                &to,
+                                Operator::LowerEqualThan,
+                                deadline,
+                            )
+                            .await?;
+                        match (from, to) {
+                            (Some(mut from), Some(to)) => {
+                                from.bitand_assign(to);
+                                (!from.is_empty()).then_some(from)
+                            }
+                            _ => None,
+                        }
+                    } else {
+                        self.between_to_bitmap(account_id, collection, field, &from, &to, deadline)
+                            .await?
+                    }
+                }
+                Filter::MatchValues { field, mut values } => {
+                    values.sort_unstable();
+                    values.dedup();
+
+                    let mut bm: Option<RoaringBitmap> = None;
+                    for value in values {
+                        let result = if partial_indexes.iter().any(|index| {
+                            index.field == field && !index.covers(Operator::Equal, &value)
+                        }) {
+                            self.scan_to_bitmap(
+                                account_id,
+                                collection,
+                                field,
+                                &value,
+                                Operator::Equal,
+                                deadline,
+                            )
+                            .await?
+                        } else {
+                            self.range_to_bitmap(
+                                account_id,
+                                collection,
+                                field,
+                                &value,
+                                Operator::Equal,
+                                deadline,
+                            )
+                            .await?
+                        };
+
+                        if let Some(result) = result {
+                            if let Some(bm) = &mut bm {
+                                bm.bitor_assign(result);
+                            } else {
+                                bm = Some(result);
+                            }
+                        }
+                    }
+                    bm
                 }
                 Filter::HasText {
                     field,
@@ -85,8 +326,75 @@ impl Store {
                     })
                     .await?
                 }
+                Filter::InThread { field, thread_id } => {
+                    self.get_bitmap(BitmapKey {
+                        account_id,
+                        collection,
+                        class: BitmapClass::Tag {
+                            field,
+                            value: TagValue::Id(thread_id),
+                        },
+                        document_id: 0,
+                    })
+                    .await?
+                }
+                Filter::HasAttachment {
+                    field,
+                    content_type,
+                } => {
+                    self.attachment_bitmap(account_id, collection, field, content_type)
+                        .await?
+                }
+                Filter::DocumentRange { from, to } => {
+                    if from <= to {
+                        self.get_bitmap(BitmapKey::document_ids(account_id, collection))
+                            .await?
+                            .and_then(|mut document_ids| {
+                                // Ranges are inserted as whole containers, ids are not enumerated
+                                let mut range = RoaringBitmap::new();
+                                range.insert_range(from..=to);
+                                document_ids.bitand_assign(range);
+                                (!document_ids.is_empty()).then_some(document_ids)
+                            })
+                    } else {
+                        None
+                    }
+                }
+                Filter::VectorSearch {
+                    field,
+                    query_vector,
+                    top_k,
+                } => {
+                    self.vector_search_bitmap(account_id, collection, field, &query_vector, top_k)
+                        .await?
+                }
                 Filter::DocumentSet(set) => Some(set),
+                Filter::HasChild {
+                    child_collection,
+                    filters,
+                } => {
+                    let children = self
+                        .child_filter(account_id, child_collection, filters, options)
+                        .await?;
+                    let parents = self
+                        .get_parents(account_id, child_collection, &children)
+                        .await?;
+                    (!parents.is_empty()).then_some(parents)
+                }
+                Filter::SizeAtLeast(size) => {
+                    self.size_bitmap(account_id, collection, size, true).await?
+                }
+                Filter::SizeAtMost(size) => {
+                    self.size_bitmap(account_id, collection, size, false)
+                        .await?
+                }
                 op @ (Filter::And | Filter::Or | Filter::Not) => {
+                    if let Some(node) = explain_node.take() {
+                        let now = Instant::now();
+                        explain_stack.push(node);
+                        explain_node =
+                            Some((FilterExplain::new(op.explain_label(), None, now), now));
+                    }
                     stack.push(state);
                     state = op.into();
                     continue;
@@ -94,6 +402,13 @@ impl Store {
                 Filter::End => {
                     if let Some(prev_state) = stack.pop() {
                         let bm = state.bm;
+                        if let (Some((mut node, started)), Some(mut parent)) =
+                            (explain_node.take(), explain_stack.pop())
+                        {
+                            node.finish(bm.as_ref(), started);
+                            parent.0.children.push(node);
+                            explain_node = Some(parent);
+                        }
                         state = prev_state;
                         bm
                     } else {
@@ -102,6 +417,11 @@ impl Store {
                 }
             };
 
+            if let (Some((label, started)), Some((node, _))) = (explain_leaf, &mut explain_node) {
+                node.children
+                    .push(FilterExplain::new(label, result.as_ref(), started));
+            }
+
             // Only fetch not mask if we need it
             if matches!(state.op, Filter::Not) && !not_fetch {
                 not_mask = self
@@ -111,6 +431,21 @@ impl Store {
                 not_fetch = true;
             }
 
+            // Account for the intermediate bitmaps held at this point
+            if let Some(max_size) = options.limits.max_memory {
+                let size = result.as_ref().map_or(0, |bm| bm.serialized_size())
+                    + state.bm.as_ref().map_or(0, |bm| bm.serialized_size())
+                    + stack
+                        .iter()
+                        .filter_map(|state| state.bm.as_ref())
+                        .map(|bm| bm.serialized_size())
+                        .sum::<usize>()
+                    + not_mask.serialized_size();
+                if size > max_size {
+                    return Err(crate::Error::MemoryLimitExceeded { size, max_size });
+                }
+            }
+
             // Apply logical operation
             if let Some(dest) = &mut state.bm {
                 match state.op {
@@ -157,11 +492,90 @@ impl Store {
             }
         }
 
-        Ok(ResultSet {
+        let explain = explain_node.map(|(mut node, started)| {
+            node.finish(state.bm.as_ref(), started);
+            node
+        });
+
+        let mut results = state.bm.unwrap_or_default();
+        if !options.include_tombstones && !results.is_empty() {
+            results -= self.get_tombstones(account_id, collection).await?;
+        }
+
+        Ok((
+            ResultSet {
+                account_id,
+                collection,
+                results,
+            },
+            explain,
+        ))
+    }
+
+    /// Returns the distinct values of an indexed field in ascending order,
+    /// stopping after `limit` values.
+    pub async fn distinct_values(
+        &self,
+        account_id: u32,
+        collection: impl Into<u8> + Sync + Send,
+        field: impl Into<u8> + Sync + Send,
+        limit: usize,
+    ) -> crate::Result<Vec<Vec<u8>>> {
+        let collection = collection.into();
+        let field = field.into();
+        let mut values: Vec<Vec<u8>> = Vec::new();
+        if limit == 0 {
+            return Ok(values);
+        }
+
+        let prefix = IndexKeyPrefix {
             account_id,
             collection,
-            results: state.bm.unwrap_or_default(),
-        })
+            field,
+        }
+        .serialize(0);
+
+        self.iterate(
+            IterateParams::new(
+                IndexKey {
+                    account_id,
+                    collection,
+                    document_id: 0,
+                    field,
+                    key: &[][..],
+                },
+                IndexKey {
+                    account_id,
+                    collection,
+                    document_id: u32::MAX,
+                    field: field + 1,
+                    key: &[][..],
+                },
+            )
+            .no_values()
+            .ascending(),
+            |key, _| {
+                if !key.starts_with(&prefix) {
+                    return Ok(false);
+                }
+
+                let value = key
+                    .get(IndexKeyPrefix::len()..key.len() - U32_LEN)
+                    .ok_or_else(|| {
+                        crate::Error::InternalError("Invalid key found in index".to_string())
+                    })?;
+
+                // Entries for the same value are contiguous, one per document
+                if values.last().map_or(true, |last| last != value) {
+                    values.push(value.to_vec());
+                }
+
+                Ok(values.len() < limit)
+            },
+        )
+        .await?;
+
+        Ok(values)
     }
 
     async fn range_to_bitmap(
@@ -171,6 +585,7 @@ impl Store {
         field: u8,
         match_value: &[u8],
         op: Operator,
+        deadline: Option<Deadline>,
     ) -> crate::Result<Option<RoaringBitmap>> {
         let (begin, end) = match op {
             Operator::LowerThan => (
@@ -269,21 +684,77 @@ impl Store {
                 if !key.starts_with(&prefix) {
                     return Ok(false);
                 }
+                Deadline::check(deadline)?;
 
                 let id_pos = key.len() - U32_LEN;
                 let value = key.get(IndexKeyPrefix::len()..id_pos).ok_or_else(|| {
                     crate::Error::InternalError("Invalid key found in index".to_string())
                 })?;
 
-                let matches = match op {
-                    Operator::LowerThan => value < match_value,
-                    Operator::LowerEqualThan => value <= match_value,
-                    Operator::GreaterThan => value > match_value,
-                    Operator::GreaterEqualThan => value >= match_value,
-                    Operator::Equal => value == match_value,
-                };
+                if op_matches(op, value, match_value) {
+                    bm.insert(key.deserialize_be_u32(id_pos)?);
+                }
+
+                Ok(true)
+            },
+        )
+        .await?;
+
+        if !bm.is_empty() {
+            Ok(Some(bm))
+        } else {
+            Ok(None)
+        }
+    }
+
+    async fn between_to_bitmap(
+        &self,
+        account_id: u32,
+        collection: u8,
+        field: u8,
+        from: &[u8],
+        to: &[u8],
+        deadline: Option<Deadline>,
+    ) -> crate::Result<Option<RoaringBitmap>> {
+        let mut bm = RoaringBitmap::new();
+        let prefix = IndexKeyPrefix {
+            account_id,
+            collection,
+            field,
+        }
+        .serialize(0);
+
+        self.iterate(
+            IterateParams::new(
+                IndexKey {
+                    account_id,
+                    collection,
+                    document_id: 0,
+                    field,
+                    key: from,
+                },
+                IndexKey {
+                    account_id,
+                    collection,
+                    document_id: u32::MAX,
+                    field,
+                    key: to,
+                },
+            )
+            .no_values()
+            .ascending(),
+            |key, _| {
+                if !key.starts_with(&prefix) {
+                    return Ok(false);
+                }
+                Deadline::check(deadline)?;
+
+                let id_pos = key.len() - U32_LEN;
+                let value = key.get(IndexKeyPrefix::len()..id_pos).ok_or_else(|| {
+                    crate::Error::InternalError("Invalid key found in index".to_string())
+                })?;
 
-                if matches {
+                if value >= from && value <= to {
                     bm.insert(key.deserialize_be_u32(id_pos)?);
                 }
 
@@ -298,6 +769,111 @@ impl Store {
             Ok(None)
         }
     }
+
+    // Boxed as it is called recursively from `filter_`
+    fn child_filter(
+        &self,
+        account_id: u32,
+        child_collection: u8,
+        filters: Vec<Filter>,
+        options: &FilterOptions,
+    ) -> Pin<Box<dyn Future<Output = crate::Result<RoaringBitmap>> + Send + '_>> {
+        // Partial indexes are declared for the fields of the parent collection
+        let options = FilterOptions {
+            limits: options.limits,
+            partial_indexes: Vec::new(),
+            include_tombstones: options.include_tombstones,
+            max_staleness: options.max_staleness,
+        };
+        Box::pin(async move {
+            self.filter_(account_id, child_collection, filters, &options, false)
+                .await
+                .map(|(result, _)| result.results)
+        })
+    }
+
+    async fn attachment_bitmap(
+        &self,
+        account_id: u32,
+        collection: u8,
+        field: u8,
+        content_type: Option<String>,
+    ) -> crate::Result<Option<RoaringBitmap>> {
+        // Messages with attachments are tagged with an empty value and the
+        // lowercase content type of each attachment
+        let content_type = content_type
+            .map(|content_type| content_type.to_lowercase())
+            .unwrap_or_default();
+        let prefix = if let Some(prefix) = content_type.strip_suffix('*') {
+            prefix
+        } else {
+            return self
+                .get_bitmap(BitmapKey {
+                    account_id,
+                    collection,
+                    class: BitmapClass::Tag {
+                        field,
+                        value: TagValue::Text(content_type.into_bytes()),
+                    },
+                    document_id: 0,
+                })
+                .await;
+        };
+
+        let key = BitmapKey {
+            account_id,
+            collection,
+            class: BitmapClass::Tag {
+                field,
+                value: TagValue::Text(prefix.as_bytes().to_vec()),
+            },
+            document_id: 0,
+        }
+        .serialize(0);
+        let prefix = &key[..key.len() - U32_LEN];
+        let mut end = prefix.to_vec();
+        end.extend_from_slice(&[u8::MAX; U32_LEN * 2]);
+
+        let mut bm = RoaringBitmap::new();
+        self.iterate(
+            IterateParams::new(
+                AnyKey {
+                    subspace: SUBSPACE_BITMAP_TAG,
+                    key: prefix.to_vec(),
+                },
+                AnyKey {
+                    subspace: SUBSPACE_BITMAP_TAG,
+                    key: end,
+                },
+            )
+            .no_values()
+            .ascending(),
+            |key, _| {
+                if key.starts_with(prefix) && key.len() >= prefix.len() + U32_LEN {
+                    bm.insert(key.deserialize_be_u32(key.len() - U32_LEN)?);
+                }
+                Ok(true)
+            },
+        )
+        .await?;
+
+        if !bm.is_empty() {
+            Ok(Some(bm))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+impl Deadline {
+    pub(crate) fn check(deadline: Option<Deadline>) -> crate::Result<()> {
+        match deadline {
+            Some(deadline) if Instant::now() >= deadline.expires => {
+                Err(crate::Error::Timeout(deadline.timeout))
+            }
+            _ => Ok(()),
+        }
+    }
 }
 
 impl From<Filter> for State {
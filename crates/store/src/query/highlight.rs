@@ -0,0 +1,159 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs Ltd <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::ops::Range;
+
+use nlp::tokenizers::word::WordTokenizer;
+
+use crate::backend::MAX_TOKEN_LENGTH;
+
+use super::Filter;
+
+// Bytes of context included before and after each match
+const SNIPPET_CONTEXT: usize = 40;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snippet {
+    /// Offset of the snippet in the field text
+    pub offset: usize,
+    pub text: String,
+    /// Byte ranges of the matched terms, relative to `text`
+    pub matches: Vec<Range<usize>>,
+}
+
+/// Returns the passages of `field_text` that matched the `HasText` filters on `field`,
+/// tokenized the same way the index is. Quoted text is highlighted only where the
+/// words appear as a phrase, terms under a `Not` operator are never highlighted.
+pub fn highlight(field_text: &str, filters: &[Filter], field: impl Into<u8>) -> Vec<Snippet> {
+    let field = field.into();
+    let mut phrases: Vec<Vec<String>> = Vec::new();
+    let mut stack = Vec::new();
+
+    for filter in filters {
+        match filter {
+            Filter::HasText {
+                field: text_field,
+                text,
+                tokenize,
+            } if *text_field == field && !stack.contains(&true) => {
+                if !*tokenize {
+                    phrases.push(vec![text.to_string()]);
+                } else if let Some(phrase) = text
+                    .trim()
+                    .strip_prefix('"')
+                    .and_then(|text| text.strip_suffix('"'))
+                {
+                    phrases.push(
+                        WordTokenizer::new(phrase, MAX_TOKEN_LENGTH)
+                            .map(|token| token.word.into_owned())
+                            .collect(),
+                    );
+                } else {
+                    phrases.extend(
+                        WordTokenizer::new(text, MAX_TOKEN_LENGTH)
+                            .map(|token| vec![token.word.into_owned()]),
+                    );
+                }
+            }
+            Filter::And | Filter::Or => stack.push(false),
+            Filter::Not => stack.push(true),
+            Filter::End => {
+                stack.pop();
+            }
+            _ => (),
+        }
+    }
+    phrases.retain(|phrase| !phrase.is_empty());
+    if phrases.is_empty() {
+        return Vec::new();
+    }
+
+    // Find matches
+    let tokens = WordTokenizer::new(field_text, MAX_TOKEN_LENGTH).collect::<Vec<_>>();
+    let mut matches: Vec<Range<usize>> = Vec::new();
+    for (pos, token) in tokens.iter().enumerate() {
+        let mut match_end = None;
+        for phrase in &phrases {
+            if tokens.len() - pos >= phrase.len()
+                && phrase
+                    .iter()
+                    .zip(&tokens[pos..])
+                    .all(|(word, token)| word == token.word.as_ref())
+            {
+                let end = tokens[pos + phrase.len() - 1].to;
+                match_end = Some(match_end.map_or(end, |match_end: usize| match_end.max(end)));
+            }
+        }
+
+        if let Some(end) = match_end {
+            match matches.last_mut() {
+                Some(last) if last.end > token.from => {
+                    last.end = last.end.max(end);
+                }
+                _ => matches.push(token.from..end),
+            }
+        }
+    }
+
+    // Group matches that share context into snippets
+    let mut snippets: Vec<(Range<usize>, Vec<Range<usize>>)> = Vec::new();
+    for range in matches {
+        let from = context_start(field_text, range.start);
+        match snippets.last_mut() {
+            Some((snippet_range, snippet_matches)) if snippet_range.end >= from => {
+                snippet_range.end = context_end(field_text, range.end);
+                snippet_matches.push(range);
+            }
+            _ => {
+                snippets.push((from..context_end(field_text, range.end), vec![range]));
+            }
+        }
+    }
+
+    snippets
+        .into_iter()
+        .map(|(range, matches)| Snippet {
+            offset: range.start,
+            text: field_text[range.clone()].to_string(),
+            matches: matches
+                .into_iter()
+                .map(|m| m.start - range.start..m.end - range.start)
+                .collect(),
+        })
+        .collect()
+}
+
+fn context_start(text: &str, offset: usize) -> usize {
+    let mut start = offset.saturating_sub(SNIPPET_CONTEXT);
+    while !text.is_char_boundary(start) {
+        start += 1;
+    }
+
+    // Avoid cutting words in half
+    if start > 0 {
+        if let Some((pos, ch)) = text[start..offset]
+            .char_indices()
+            .find(|(_, ch)| ch.is_whitespace())
+        {
+            start += pos + ch.len_utf8();
+        }
+    }
+    start
+}
+
+fn context_end(text: &str, offset: usize) -> usize {
+    let mut end = std::cmp::min(offset + SNIPPET_CONTEXT, text.len());
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    if end < text.len() {
+        if let Some(pos) = text[offset..end].rfind(char::is_whitespace) {
+            end = offset + pos;
+        }
+    }
+    end
+}
@@ -0,0 +1,237 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs Ltd <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use roaring::RoaringBitmap;
+
+use crate::{
+    write::{key::DeserializeBigEndian, BatchBuilder, TagValue, ValueClass, SIZE_BUCKET_FIELD},
+    BitmapKey, Deserialize, IndexKey, IndexKeyPrefix, IterateParams, Key, Store, ValueKey, U32_LEN,
+};
+
+/// Returns the log-scale bucket of a size, which is its number of significant
+/// bits. Bucket `n` holds the sizes between `2^(n-1)` and `2^n - 1`.
+pub fn size_bucket(size: u32) -> u8 {
+    (u32::BITS - size.leading_zeros()) as u8
+}
+
+impl Store {
+    pub async fn get_content_length(
+        &self,
+        account_id: u32,
+        collection: impl Into<u8> + Sync + Send,
+        document_id: u32,
+    ) -> crate::Result<Option<u32>> {
+        self.get_value::<u32>(ValueKey {
+            account_id,
+            collection: collection.into(),
+            document_id,
+            class: ValueClass::ContentLength,
+        })
+        .await
+    }
+
+    pub async fn content_length_sum(
+        &self,
+        account_id: u32,
+        collection: impl Into<u8> + Sync + Send,
+    ) -> crate::Result<u64> {
+        let collection = collection.into();
+        let mut total = 0u64;
+
+        self.iterate(
+            IterateParams::new(
+                content_length_key(account_id, collection, 0),
+                content_length_key(account_id, collection, u32::MAX),
+            )
+            .ascending(),
+            |_, value| {
+                total += u32::deserialize(value)? as u64;
+                Ok(true)
+            },
+        )
+        .await?;
+
+        Ok(total)
+    }
+
+    /// Stores the content length and size bucket of documents written before content
+    /// lengths were tracked, using the values of an existing index on `size_field`
+    /// (which must hold big-endian u32 sizes). Returns the number of documents updated.
+    pub async fn backfill_content_length(
+        &self,
+        account_id: u32,
+        collection: impl Into<u8> + Sync + Send,
+        size_field: impl Into<u8> + Sync + Send,
+    ) -> crate::Result<u64> {
+        let collection = collection.into();
+        let size_field = size_field.into();
+
+        // Obtain documents that already have a content length
+        let mut has_length = RoaringBitmap::new();
+        self.iterate(
+            IterateParams::new(
+                content_length_key(account_id, collection, 0),
+                content_length_key(account_id, collection, u32::MAX),
+            )
+            .no_values()
+            .ascending(),
+            |key, _| {
+                has_length.insert(key.deserialize_be_u32(key.len() - U32_LEN)?);
+                Ok(true)
+            },
+        )
+        .await?;
+
+        let mut lengths = Vec::new();
+        let prefix = IndexKeyPrefix {
+            account_id,
+            collection,
+            field: size_field,
+        }
+        .serialize(0);
+        self.iterate(
+            IterateParams::new(
+                IndexKey {
+                    account_id,
+                    collection,
+                    document_id: 0,
+                    field: size_field,
+                    key: &[][..],
+                },
+                IndexKey {
+                    account_id,
+                    collection,
+                    document_id: u32::MAX,
+                    field: size_field + 1,
+                    key: &[][..],
+                },
+            )
+            .no_values()
+            .ascending(),
+            |key, _| {
+                if !key.starts_with(&prefix) {
+                    return Ok(false);
+                }
+
+                let id_pos = key.len() - U32_LEN;
+                let document_id = key.deserialize_be_u32(id_pos)?;
+                if !has_length.contains(document_id) {
+                    let length = key
+                        .get(IndexKeyPrefix::len()..id_pos)
+                        .ok_or_else(|| {
+                            crate::Error::InternalError("Invalid key found in index".to_string())
+                        })
+                        .and_then(u32::deserialize)?;
+                    lengths.push((document_id, length));
+                }
+
+                Ok(true)
+            },
+        )
+        .await?;
+
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(account_id)
+            .with_collection(collection);
+        for (num, (document_id, length)) in lengths.iter().enumerate() {
+            batch
+                .update_document(*document_id)
+                .set_content_length(*length)
+                .set_size_bucket(*length);
+
+            if num % 1000 == 999 {
+                self.write(batch.build_batch()).await?;
+                batch
+                    .with_account_id(account_id)
+                    .with_collection(collection);
+            }
+        }
+        if !batch.is_empty() {
+            self.write(batch.build()).await?;
+        }
+
+        Ok(lengths.len() as u64)
+    }
+
+    // Documents in the buckets above (or below) the one of `size` match as a whole,
+    // those in the same bucket are compared against their content length
+    pub(crate) async fn size_bitmap(
+        &self,
+        account_id: u32,
+        collection: u8,
+        size: u32,
+        at_least: bool,
+    ) -> crate::Result<Option<RoaringBitmap>> {
+        let boundary = size_bucket(size);
+        let buckets = if at_least {
+            (boundary + 1..=u32::BITS as u8).collect::<Vec<_>>()
+        } else {
+            (0..boundary).collect::<Vec<_>>()
+        };
+
+        let mut result = RoaringBitmap::new();
+        for bucket in buckets {
+            if let Some(bitmap) = self
+                .get_bitmap(BitmapKey::tag(
+                    account_id,
+                    collection,
+                    SIZE_BUCKET_FIELD,
+                    TagValue::Text(vec![bucket]),
+                ))
+                .await?
+            {
+                result |= bitmap;
+            }
+        }
+
+        if let Some(candidates) = self
+            .get_bitmap(BitmapKey::tag(
+                account_id,
+                collection,
+                SIZE_BUCKET_FIELD,
+                TagValue::Text(vec![boundary]),
+            ))
+            .await?
+        {
+            if let (Some(min), Some(max)) = (candidates.min(), candidates.max()) {
+                self.iterate(
+                    IterateParams::new(
+                        content_length_key(account_id, collection, min),
+                        content_length_key(account_id, collection, max),
+                    )
+                    .ascending(),
+                    |key, value| {
+                        let document_id = key.deserialize_be_u32(key.len() - U32_LEN)?;
+                        if candidates.contains(document_id) {
+                            let length = u32::deserialize(value)?;
+                            if (at_least && length >= size) || (!at_least && length <= size) {
+                                result.insert(document_id);
+                            }
+                        }
+                        Ok(true)
+                    },
+                )
+                .await?;
+            }
+        }
+
+        Ok((!result.is_empty()).then_some(result))
+    }
+}
+
+fn content_length_key(
+    account_id: u32,
+    collection: u8,
+    document_id: u32,
+) -> ValueKey<ValueClass<u32>> {
+    ValueKey {
+        account_id,
+        collection,
+        document_id,
+        class: ValueClass::ContentLength,
+    }
+}
@@ -5,9 +5,21 @@
  */
 
 pub mod acl;
+pub mod builder;
+pub mod collections;
+pub mod explain;
+pub mod export;
 pub mod filter;
+pub mod highlight;
+pub mod length;
 pub mod log;
+pub mod normalize;
+pub mod partial;
 pub mod sort;
+pub mod unread;
+pub mod vector;
+
+use std::time::Duration;
 
 use roaring::RoaringBitmap;
 
@@ -16,6 +28,8 @@ use crate::{
     BitmapKey, IterateParams, Key, Serialize,
 };
 
+use self::partial::PartialIndex;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Operator {
     LowerThan,
@@ -25,20 +39,52 @@ pub enum Operator {
     Equal,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Filter {
     MatchValue {
         field: u8,
         op: Operator,
         value: Vec<u8>,
     },
+    MatchValues {
+        field: u8,
+        values: Vec<Vec<u8>>,
+    },
+    MatchRange {
+        field: u8,
+        from: Vec<u8>,
+        to: Vec<u8>,
+    },
     HasText {
         field: u8,
         text: String,
         tokenize: bool,
     },
     InBitmap(BitmapClass<u32>),
+    InThread {
+        field: u8,
+        thread_id: u32,
+    },
+    HasAttachment {
+        field: u8,
+        content_type: Option<String>,
+    },
+    DocumentRange {
+        from: u32,
+        to: u32,
+    },
+    VectorSearch {
+        field: u8,
+        query_vector: Vec<f32>,
+        top_k: usize,
+    },
     DocumentSet(RoaringBitmap),
+    HasChild {
+        child_collection: u8,
+        filters: Vec<Filter>,
+    },
+    SizeAtLeast(u32),
+    SizeAtMost(u32),
     And,
     Or,
     Not,
@@ -47,8 +93,52 @@ pub enum Filter {
 
 #[derive(Debug)]
 pub enum Comparator {
-    Field { field: u8, ascending: bool },
-    DocumentSet { set: RoaringBitmap, ascending: bool },
+    Field {
+        field: u8,
+        ascending: bool,
+    },
+    DocumentSet {
+        set: RoaringBitmap,
+        ascending: bool,
+    },
+    Bitmap {
+        class: BitmapClass<u32>,
+        ascending: bool,
+    },
+    /// Orders documents by a relevance score computed by the caller, such as the
+    /// similarities returned by `Store::nearest_neighbors`. Documents without a
+    /// score are sorted last, ties are broken by the comparators that follow and
+    /// finally by document id.
+    Score {
+        scores: Vec<(u32, f32)>,
+        ascending: bool,
+    },
+}
+
+/// Limits applied while evaluating filters, exceeding them aborts the query
+/// with `Error::Timeout` or `Error::MemoryLimitExceeded`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QueryLimits {
+    pub timeout: Option<Duration>,
+    /// Approximate size in bytes of the intermediate bitmaps, as serialized
+    pub max_memory: Option<usize>,
+}
+
+/// Options for evaluating filters with `Store::filter_with_options`.
+#[derive(Debug, Clone, Default)]
+pub struct FilterOptions {
+    pub limits: QueryLimits,
+    /// Index lookups on fields with a partial index are only used when the index
+    /// covers the filter, see `PartialIndex`.
+    pub partial_indexes: Vec<PartialIndex>,
+    /// Includes soft deleted documents in the results. They are excluded by
+    /// default, which requires reading the tombstones of the collection once
+    /// the filters matched any document. It also applies to the child
+    /// collections of `Filter::HasChild`.
+    pub include_tombstones: bool,
+    /// Evaluates the filters on a replica lagging at most this far behind the
+    /// primary, see `Store::read_store`. Filters run on the primary when unset.
+    pub max_staleness: Option<Duration>,
 }
 
 #[derive(Debug)]
@@ -95,6 +185,24 @@ impl Filter {
         }
     }
 
+    pub fn eq_any<T: Serialize>(field: impl Into<u8>, values: impl IntoIterator<Item = T>) -> Self {
+        Filter::MatchValues {
+            field: field.into(),
+            values: values.into_iter().map(|value| value.serialize()).collect(),
+        }
+    }
+
+    /// Matches indexed values between `from` and `to`, both inclusive. Values are
+    /// compared by their serialized bytes, so integers such as epoch timestamps are
+    /// ordered numerically.
+    pub fn between(field: impl Into<u8>, from: impl Serialize, to: impl Serialize) -> Self {
+        Filter::MatchRange {
+            field: field.into(),
+            from: from.serialize(),
+            to: to.serialize(),
+        }
+    }
+
     pub fn lt(field: impl Into<u8>, value: impl Serialize) -> Self {
         Filter::MatchValue {
             field: field.into(),
@@ -150,9 +258,88 @@ impl Filter {
         })
     }
 
+    /// Matches all documents tagged with `thread_id` under the thread field.
+    pub fn in_thread(field: impl Into<u8>, thread_id: u32) -> Self {
+        Filter::InThread {
+            field: field.into(),
+            thread_id,
+        }
+    }
+
+    /// Matches documents with attachments, optionally of a given content type.
+    /// Content types ending in `*` match by prefix, such as `image/*`.
+    pub fn has_attachment(field: impl Into<u8>, content_type: Option<String>) -> Self {
+        Filter::HasAttachment {
+            field: field.into(),
+            content_type,
+        }
+    }
+
+    /// Returns the field a filter matches on, `None` for logical operators and
+    /// filters not bound to a single field of the collection.
+    pub fn field(&self) -> Option<u8> {
+        match self {
+            Filter::MatchValue { field, .. }
+            | Filter::MatchValues { field, .. }
+            | Filter::MatchRange { field, .. }
+            | Filter::HasText { field, .. }
+            | Filter::InThread { field, .. }
+            | Filter::HasAttachment { field, .. }
+            | Filter::VectorSearch { field, .. }
+            | Filter::InBitmap(BitmapClass::Tag { field, .. })
+            | Filter::InBitmap(BitmapClass::Text { field, .. }) => Some(*field),
+            _ => None,
+        }
+    }
+
+    /// Returns whether the filter, or any filter of its child collection, matches
+    /// on `field`.
+    pub fn uses_field(&self, field: u8) -> bool {
+        match self {
+            Filter::HasChild { filters, .. } => {
+                filters.iter().any(|filter| filter.uses_field(field))
+            }
+            filter => filter.field() == Some(field),
+        }
+    }
+
     pub fn is_in_set(set: RoaringBitmap) -> Self {
         Filter::DocumentSet(set)
     }
+
+    /// Matches the documents with at least one child in `child_collection` matching
+    /// `filters`, see `BatchBuilder::set_parent`.
+    pub fn has_child(child_collection: impl Into<u8>, filters: Vec<Filter>) -> Self {
+        Filter::HasChild {
+            child_collection: child_collection.into(),
+            filters,
+        }
+    }
+
+    /// Matches the documents with a content length of at least `size` bytes, see
+    /// `BatchBuilder::set_size_bucket`.
+    pub fn size_at_least(size: u32) -> Self {
+        Filter::SizeAtLeast(size)
+    }
+
+    pub fn size_at_most(size: u32) -> Self {
+        Filter::SizeAtMost(size)
+    }
+
+    /// Matches the existing documents with ids between `from` and `to`, inclusive.
+    pub fn document_range(from: u32, to: u32) -> Self {
+        Filter::DocumentRange { from, to }
+    }
+
+    /// Matches the `top_k` documents with the embeddings nearest to `query_vector`
+    /// by cosine similarity, see `Store::nearest_neighbors`.
+    pub fn vector_search(field: impl Into<u8>, query_vector: Vec<f32>, top_k: usize) -> Self {
+        Filter::VectorSearch {
+            field: field.into(),
+            query_vector,
+            top_k,
+        }
+    }
 }
 
 impl Comparator {
@@ -167,6 +354,38 @@ impl Comparator {
         Self::DocumentSet { set, ascending }
     }
 
+    /// Sorts members of the tag bitmap first when ascending, last otherwise.
+    pub fn tag(field: impl Into<u8>, value: impl Into<TagValue<u32>>, ascending: bool) -> Self {
+        Self::Bitmap {
+            class: BitmapClass::Tag {
+                field: field.into(),
+                value: value.into(),
+            },
+            ascending,
+        }
+    }
+
+    /// Returns the field documents are ordered by, `None` for comparators not
+    /// bound to a field of the collection.
+    pub fn sort_field(&self) -> Option<u8> {
+        match self {
+            Comparator::Field { field, .. }
+            | Comparator::Bitmap {
+                class: BitmapClass::Tag { field, .. } | BitmapClass::Text { field, .. },
+                ..
+            } => Some(*field),
+            _ => None,
+        }
+    }
+
+    /// Most relevant first.
+    pub fn relevance(scores: impl IntoIterator<Item = (u32, f32)>) -> Self {
+        Self::Score {
+            scores: scores.into_iter().collect(),
+            ascending: false,
+        }
+    }
+
     pub fn ascending(field: impl Into<u8>) -> Self {
         Self::Field {
             field: field.into(),
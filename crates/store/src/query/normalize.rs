@@ -0,0 +1,91 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs Ltd <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::{fmt::Debug, sync::Arc};
+
+use ahash::AHashMap;
+
+use super::Filter;
+
+/// Converts the serialized value of a field to a canonical form, so that values
+/// written in different formats (such as phone numbers with or without spaces and
+/// dashes) produce the same index key.
+pub trait FieldNormalizer: Sync + Send {
+    fn normalize(&self, value: &[u8]) -> Vec<u8>;
+}
+
+impl<F> FieldNormalizer for F
+where
+    F: Fn(&[u8]) -> Vec<u8> + Sync + Send,
+{
+    fn normalize(&self, value: &[u8]) -> Vec<u8> {
+        self(value)
+    }
+}
+
+/// Normalizers by field id, applied to index keys by
+/// `BatchBuilder::value_normalized` and to filter values by `normalize_filters`.
+///
+/// Index entries keep the canonical form produced when they were written, so
+/// adding or changing the normalizer of a field requires reindexing the existing
+/// documents, otherwise queries no longer match them.
+#[derive(Clone, Default)]
+pub struct FieldNormalizers {
+    fields: AHashMap<u8, Arc<dyn FieldNormalizer>>,
+}
+
+impl FieldNormalizers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(
+        mut self,
+        field: impl Into<u8>,
+        normalizer: impl FieldNormalizer + 'static,
+    ) -> Self {
+        self.fields.insert(field.into(), Arc::new(normalizer));
+        self
+    }
+
+    pub fn normalize(&self, field: u8, value: Vec<u8>) -> Vec<u8> {
+        if let Some(normalizer) = self.fields.get(&field) {
+            normalizer.normalize(&value)
+        } else {
+            value
+        }
+    }
+
+    /// Normalizes the values compared by `MatchValue`, `MatchValues` and
+    /// `MatchRange` filters on fields with a normalizer.
+    pub fn normalize_filters(&self, filters: &mut [Filter]) {
+        for filter in filters {
+            match filter {
+                Filter::MatchValue { field, value, .. } => {
+                    *value = self.normalize(*field, std::mem::take(value));
+                }
+                Filter::MatchValues { field, values } => {
+                    for value in values {
+                        *value = self.normalize(*field, std::mem::take(value));
+                    }
+                }
+                Filter::MatchRange { field, from, to } => {
+                    *from = self.normalize(*field, std::mem::take(from));
+                    *to = self.normalize(*field, std::mem::take(to));
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+impl Debug for FieldNormalizers {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FieldNormalizers")
+            .field("fields", &self.fields.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
@@ -0,0 +1,109 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs Ltd <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use roaring::RoaringBitmap;
+
+use crate::{
+    write::{key::DeserializeBigEndian, ValueClass},
+    IterateParams, Serialize, Store, ValueKey, U32_LEN,
+};
+
+use super::{filter::Deadline, Operator};
+
+/// A field that is only indexed when its value satisfies `value <op> self.value`
+/// (byte-lexicographic comparison, same as index scans).
+///
+/// A `MatchValue` filter on a partially indexed field can only be answered from
+/// the index when every value it matches also satisfies the predicate (for example,
+/// `flagged = true` against an index on `flagged = true`, or `size > 10MB` against
+/// an index on `size > 1MB`). Any other filter on that field (inequalities or
+/// values outside the predicate) can't use the index and falls back to scanning
+/// the stored property values of every document in the collection, which requires
+/// the field to be written with `F_VALUE`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartialIndex {
+    pub field: u8,
+    pub op: Operator,
+    pub value: Vec<u8>,
+}
+
+impl PartialIndex {
+    pub fn new(field: impl Into<u8>, op: Operator, value: impl Serialize) -> Self {
+        PartialIndex {
+            field: field.into(),
+            op,
+            value: value.serialize(),
+        }
+    }
+
+    pub fn matches(&self, value: &[u8]) -> bool {
+        op_matches(self.op, value, &self.value)
+    }
+
+    /// Returns `true` when all values matching `<op> value` are indexed.
+    pub fn covers(&self, op: Operator, value: &[u8]) -> bool {
+        let pv = self.value.as_slice();
+        match (self.op, op) {
+            (_, Operator::Equal) => self.matches(value),
+            (Operator::GreaterThan, Operator::GreaterThan) => value >= pv,
+            (Operator::GreaterThan, Operator::GreaterEqualThan) => value > pv,
+            (Operator::GreaterEqualThan, Operator::GreaterThan)
+            | (Operator::GreaterEqualThan, Operator::GreaterEqualThan) => value >= pv,
+            (Operator::LowerThan, Operator::LowerThan) => value <= pv,
+            (Operator::LowerThan, Operator::LowerEqualThan) => value < pv,
+            (Operator::LowerEqualThan, Operator::LowerThan)
+            | (Operator::LowerEqualThan, Operator::LowerEqualThan) => value <= pv,
+            _ => false,
+        }
+    }
+}
+
+pub(crate) fn op_matches(op: Operator, value: &[u8], match_value: &[u8]) -> bool {
+    match op {
+        Operator::LowerThan => value < match_value,
+        Operator::LowerEqualThan => value <= match_value,
+        Operator::GreaterThan => value > match_value,
+        Operator::GreaterEqualThan => value >= match_value,
+        Operator::Equal => value == match_value,
+    }
+}
+
+impl Store {
+    pub(crate) async fn scan_to_bitmap(
+        &self,
+        account_id: u32,
+        collection: u8,
+        field: u8,
+        match_value: &[u8],
+        op: Operator,
+        deadline: Option<Deadline>,
+    ) -> crate::Result<Option<RoaringBitmap>> {
+        let mut bm = RoaringBitmap::new();
+
+        self.iterate(
+            IterateParams::new(
+                ValueKey::<ValueClass<u32>>::property(account_id, collection, 0, field),
+                ValueKey::<ValueClass<u32>>::property(account_id, collection, u32::MAX, field),
+            )
+            .ascending(),
+            |key, value| {
+                Deadline::check(deadline)?;
+                if op_matches(op, value, match_value) {
+                    bm.insert(key.deserialize_be_u32(key.len() - U32_LEN)?);
+                }
+
+                Ok(true)
+            },
+        )
+        .await?;
+
+        if !bm.is_empty() {
+            Ok(Some(bm))
+        } else {
+            Ok(None)
+        }
+    }
+}
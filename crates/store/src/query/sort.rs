@@ -10,10 +10,10 @@ use ahash::{AHashMap, AHashSet};
 
 use crate::{
     write::{key::DeserializeBigEndian, ValueClass},
-    IndexKeyPrefix, IterateParams, Store, ValueKey, U32_LEN,
+    BitmapKey, IndexKeyPrefix, IterateParams, Store, ValueKey, U32_LEN,
 };
 
-use super::{Comparator, ResultSet, SortedResultSet};
+use super::{acl::Principal, collections::QueryScope, Comparator, ResultSet, SortedResultSet};
 
 pub struct Pagination {
     requested_position: i32,
@@ -29,6 +29,31 @@ pub struct Pagination {
 }
 
 impl Store {
+    /// Same as `sort`, but on behalf of `principal`. Sorting by a restricted field
+    /// fails with `Error::Unsupported` unless the principal was granted access to
+    /// the field on every document of the result set, as the order would otherwise
+    /// disclose its values.
+    pub async fn sort_as(
+        &self,
+        principal: &Principal,
+        scope: &QueryScope,
+        result_set: ResultSet,
+        comparators: Vec<Comparator>,
+        paginate: Pagination,
+    ) -> crate::Result<SortedResultSet> {
+        for field in comparators.iter().filter_map(|c| c.sort_field()) {
+            if let Some(sortable) = self.field_access(principal, scope, field).await? {
+                if !result_set.results.is_subset(&sortable) {
+                    return Err(crate::Error::Unsupported(format!(
+                        "Sorting by restricted field {field}"
+                    )));
+                }
+            }
+        }
+
+        self.sort(result_set, comparators, paginate).await
+    }
+
     pub async fn sort(
         &self,
         result_set: ResultSet,
@@ -47,7 +72,29 @@ impl Store {
             (a, b) => std::cmp::min(a as usize, b),
         };
 
-        if comparators.len() == 1 && !paginate.prefix_unique {
+        // Bitmap comparators are sorted as document sets
+        for comparator in comparators.iter_mut() {
+            if let Comparator::Bitmap { class, ascending } = comparator {
+                let set = self
+                    .get_bitmap(BitmapKey {
+                        account_id: result_set.account_id,
+                        collection: result_set.collection,
+                        class: class.clone(),
+                        document_id: 0,
+                    })
+                    .await?
+                    .unwrap_or_default();
+                *comparator = Comparator::DocumentSet {
+                    set,
+                    ascending: *ascending,
+                };
+            }
+        }
+
+        // Scores are ranked in memory along with any tie-breaking comparators
+        let is_scored = matches!(comparators.first(), Some(Comparator::Score { .. }));
+
+        if comparators.len() == 1 && !paginate.prefix_unique && !is_scored {
             match comparators.pop().unwrap() {
                 Comparator::Field { field, ascending } => {
                     let mut results = result_set.results;
@@ -100,6 +147,7 @@ impl Store {
                         }
                     }
                 }
+                Comparator::Bitmap { .. } | Comparator::Score { .. } => unreachable!(),
             }
 
             // Obtain prefixes
@@ -117,7 +165,7 @@ impl Store {
             }
 
             Ok(sorted_results)
-        } else if comparators.len() > 1 {
+        } else if comparators.len() > 1 || is_scored {
             //TODO improve this algorithm, avoid re-sorting in memory.
             let mut sorted_ids = AHashMap::with_capacity(paginate.limit);
 
@@ -202,6 +250,46 @@ impl Store {
                             }
                         }
                     }
+                    Comparator::Score {
+                        mut scores,
+                        ascending,
+                    } => {
+                        let mut results = result_set.results.clone();
+                        let mut prev_score = None;
+                        let mut has_grouped_ids = false;
+                        let mut idx = 0;
+
+                        scores.sort_by(|a, b| {
+                            if ascending {
+                                a.1.total_cmp(&b.1)
+                            } else {
+                                b.1.total_cmp(&a.1)
+                            }
+                        });
+                        for (document_id, score) in scores {
+                            if results.remove(document_id) {
+                                if prev_score != Some(score) {
+                                    idx += 1;
+                                    prev_score = Some(score);
+                                } else {
+                                    has_grouped_ids = true;
+                                }
+
+                                sorted_ids.entry(document_id).or_insert([0u32; 4])[pos] = idx;
+                            }
+                        }
+
+                        // Documents without a score go last
+                        if !results.is_empty() {
+                            idx += 1;
+                            for document_id in results {
+                                sorted_ids.entry(document_id).or_insert([0u32; 4])[pos] = idx;
+                            }
+                        } else if !has_grouped_ids {
+                            break;
+                        }
+                    }
+                    Comparator::Bitmap { .. } => unreachable!(),
                 }
             }
 
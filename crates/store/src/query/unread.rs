@@ -0,0 +1,51 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs Ltd <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use crate::{write::TagValue, BitmapKey, Store};
+
+impl Store {
+    /// Counts the documents with an id of at least `since_id` that are not tagged
+    /// with `seen` under `seen_field`, such as the messages without the `$seen`
+    /// keyword, from the document id and tag bitmaps alone. Soft deleted documents
+    /// are not counted.
+    ///
+    /// Document ids are allocated lowest first, so ids freed by deleted documents
+    /// may be reused by documents created after `since_id` was obtained.
+    pub async fn unread_since(
+        &self,
+        account_id: u32,
+        collection: impl Into<u8> + Sync + Send,
+        seen_field: impl Into<u8> + Sync + Send,
+        seen: impl Into<TagValue<u32>> + Sync + Send,
+        since_id: u32,
+    ) -> crate::Result<u64> {
+        let collection = collection.into();
+        let mut unread = if let Some(document_ids) = self
+            .get_bitmap(BitmapKey::document_ids(account_id, collection))
+            .await?
+        {
+            document_ids
+        } else {
+            return Ok(0);
+        };
+        unread.remove_range(..since_id);
+        if unread.is_empty() {
+            return Ok(0);
+        }
+
+        if let Some(seen) = self
+            .get_bitmap(BitmapKey::tag(account_id, collection, seen_field, seen))
+            .await?
+        {
+            unread -= seen;
+        }
+        if !unread.is_empty() {
+            unread -= self.get_tombstones(account_id, collection).await?;
+        }
+
+        Ok(unread.len())
+    }
+}
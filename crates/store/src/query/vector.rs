@@ -0,0 +1,199 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs Ltd <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::{cmp::Ordering, collections::BinaryHeap};
+
+use roaring::RoaringBitmap;
+
+use crate::{
+    write::{key::DeserializeBigEndian, ValueClass},
+    Deserialize, IterateParams, Serialize, Store, ValueKey, U32_LEN,
+};
+
+/// Fixed-dimension float vector, stored as little-endian f32 values.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Embedding(pub Vec<f32>);
+
+struct Neighbor {
+    similarity: f32,
+    document_id: u32,
+}
+
+impl Store {
+    pub async fn get_embedding(
+        &self,
+        account_id: u32,
+        collection: impl Into<u8> + Sync + Send,
+        field: impl Into<u8>,
+        document_id: u32,
+    ) -> crate::Result<Option<Embedding>> {
+        self.get_value::<Embedding>(ValueKey {
+            account_id,
+            collection: collection.into(),
+            document_id,
+            class: ValueClass::Vector(field.into()),
+        })
+        .await
+    }
+
+    /// Returns the `top_k` documents whose embeddings under `field` are nearest to
+    /// `query_vector` by cosine similarity, most similar first. This is a brute-force
+    /// scan over all the embeddings of the collection, an approximate nearest-neighbor
+    /// index could replace it for larger collections. Embeddings with a different
+    /// dimension than the query vector are skipped.
+    pub async fn nearest_neighbors(
+        &self,
+        account_id: u32,
+        collection: impl Into<u8> + Sync + Send,
+        field: impl Into<u8>,
+        query_vector: &[f32],
+        top_k: usize,
+    ) -> crate::Result<Vec<(u32, f32)>> {
+        let collection = collection.into();
+        let field = field.into();
+        let query_norm = norm(query_vector);
+        if top_k == 0 || query_norm == 0.0 {
+            return Ok(Vec::new());
+        }
+
+        // Min-heap holding the best `top_k` matches seen so far
+        let mut heap = BinaryHeap::with_capacity(top_k + 1);
+        self.iterate(
+            IterateParams::new(
+                vector_key(account_id, collection, field, 0),
+                vector_key(account_id, collection, field, u32::MAX),
+            )
+            .ascending(),
+            |key, value| {
+                if value.len() != query_vector.len() * std::mem::size_of::<f32>() {
+                    return Ok(true);
+                }
+
+                let mut dot = 0.0f32;
+                let mut vector_norm = 0.0f32;
+                for (a, b) in query_vector.iter().zip(floats(value)) {
+                    dot += a * b;
+                    vector_norm += b * b;
+                }
+                if vector_norm > 0.0 {
+                    heap.push(Neighbor {
+                        similarity: dot / (query_norm * vector_norm.sqrt()),
+                        document_id: key.deserialize_be_u32(key.len() - U32_LEN)?,
+                    });
+                    if heap.len() > top_k {
+                        heap.pop();
+                    }
+                }
+
+                Ok(true)
+            },
+        )
+        .await?;
+
+        Ok(heap
+            .into_sorted_vec()
+            .into_iter()
+            .map(|neighbor| (neighbor.document_id, neighbor.similarity))
+            .collect())
+    }
+
+    pub(crate) async fn vector_search_bitmap(
+        &self,
+        account_id: u32,
+        collection: u8,
+        field: u8,
+        query_vector: &[f32],
+        top_k: usize,
+    ) -> crate::Result<Option<RoaringBitmap>> {
+        let results = self
+            .nearest_neighbors(account_id, collection, field, query_vector, top_k)
+            .await?;
+
+        if !results.is_empty() {
+            Ok(Some(RoaringBitmap::from_iter(
+                results.into_iter().map(|(document_id, _)| document_id),
+            )))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+fn vector_key(
+    account_id: u32,
+    collection: u8,
+    field: u8,
+    document_id: u32,
+) -> ValueKey<ValueClass<u32>> {
+    ValueKey {
+        account_id,
+        collection,
+        document_id,
+        class: ValueClass::Vector(field),
+    }
+}
+
+fn floats(bytes: &[u8]) -> impl Iterator<Item = f32> + '_ {
+    bytes
+        .chunks_exact(std::mem::size_of::<f32>())
+        .map(|chunk| f32::from_le_bytes(chunk.try_into().unwrap()))
+}
+
+fn norm(vector: &[f32]) -> f32 {
+    vector.iter().map(|v| v * v).sum::<f32>().sqrt()
+}
+
+// Reversed so that the heap pops the least similar neighbor first
+impl Ord for Neighbor {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .similarity
+            .total_cmp(&self.similarity)
+            .then_with(|| self.document_id.cmp(&other.document_id))
+    }
+}
+
+impl PartialOrd for Neighbor {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Neighbor {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Neighbor {}
+
+impl Serialize for Embedding {
+    fn serialize(self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.0.len() * std::mem::size_of::<f32>());
+        for value in self.0 {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes
+    }
+}
+
+impl Deserialize for Embedding {
+    fn deserialize(bytes: &[u8]) -> crate::Result<Self> {
+        if bytes.len() % std::mem::size_of::<f32>() == 0 {
+            Ok(Embedding(floats(bytes).collect()))
+        } else {
+            Err(crate::Error::InternalError(
+                "Invalid embedding length".to_string(),
+            ))
+        }
+    }
+}
+
+impl From<Vec<f32>> for Embedding {
+    fn from(value: Vec<f32>) -> Self {
+        Embedding(value)
+    }
+}
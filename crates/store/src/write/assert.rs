@@ -4,7 +4,9 @@
  * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
  */
 
-use crate::{Deserialize, U32_LEN, U64_LEN};
+use crate::{Deserialize, Store, ValueKey, U32_LEN, U64_LEN};
+
+use super::{BatchBuilder, ValueClass};
 
 #[derive(Debug, Clone)]
 pub struct HashedValue<T: Deserialize> {
@@ -91,3 +93,46 @@ impl<T: Deserialize> Deserialize for HashedValue<T> {
         })
     }
 }
+
+impl Store {
+    /// Applies all `writes` only if every key in `checks` holds the expected value,
+    /// or does not exist when `None` is expected, returning whether the writes were
+    /// applied. Checks and writes run in a single transaction, on FoundationDB the
+    /// checked keys are read with conflict ranges so that concurrent changes to them
+    /// abort the commit.
+    pub async fn conditional_write(
+        &self,
+        checks: Vec<(ValueKey<ValueClass<u32>>, Option<Vec<u8>>)>,
+        writes: Vec<(ValueKey<ValueClass<u32>>, Vec<u8>)>,
+    ) -> crate::Result<bool> {
+        let mut batch = BatchBuilder::new();
+        for (key, expected) in checks {
+            batch
+                .with_account_id(key.account_id)
+                .with_collection(key.collection)
+                .update_document(key.document_id)
+                .assert_value(
+                    key.class.into_dynamic(),
+                    expected.map_or(AssertValue::None, |value| {
+                        AssertValue::Hash(xxhash_rust::xxh3::xxh3_64(&value))
+                    }),
+                );
+        }
+        for (key, value) in writes {
+            batch
+                .with_account_id(key.account_id)
+                .with_collection(key.collection)
+                .update_document(key.document_id)
+                .set(key.class.into_dynamic(), value);
+        }
+        if batch.ops.is_empty() {
+            return Ok(true);
+        }
+
+        match self.write(batch.build()).await {
+            Ok(_) => Ok(true),
+            Err(crate::Error::AssertValueFailed) => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+}
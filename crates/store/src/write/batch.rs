@@ -4,19 +4,58 @@
  * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
  */
 
+use crate::query::{length::size_bucket, normalize::FieldNormalizers, partial::PartialIndex};
+
 use super::{
-    assert::ToAssertValue, Batch, BatchBuilder, BitmapClass, HasFlag, IntoOperations,
-    MaybeDynamicId, MaybeDynamicValue, Operation, Serialize, TagValue, ToBitmaps, ValueClass,
-    ValueOp, F_BITMAP, F_CLEAR, F_INDEX, F_VALUE,
+    assert::{AssertValue, ToAssertValue},
+    usage::Quota,
+    Batch, BatchBuilder, BitmapClass, HasFlag, IdAllocator, IntoOperations, Isolation,
+    MaybeDynamicId, MaybeDynamicValue, Operation, RetryPolicy, Serialize, TagValue, ToBitmaps,
+    ValueClass, ValueOp, F_BITMAP, F_CLEAR, F_INDEX, F_NO_DEDUP, F_VALUE, SIZE_BUCKET_FIELD,
 };
 
 impl BatchBuilder {
     pub fn new() -> Self {
         Self {
             ops: Vec::with_capacity(16),
+            isolation: Isolation::default(),
+            id_allocator: IdAllocator::default(),
+            retry: RetryPolicy::default(),
+            quotas: Vec::new(),
+            soft_delete: false,
         }
     }
 
+    pub fn with_isolation(&mut self, isolation: Isolation) -> &mut Self {
+        self.isolation = isolation;
+        self
+    }
+
+    pub fn with_id_allocator(&mut self, id_allocator: IdAllocator) -> &mut Self {
+        self.id_allocator = id_allocator;
+        self
+    }
+
+    pub fn with_retry_policy(&mut self, retry: RetryPolicy) -> &mut Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Rejects the batch with `Error::QuotaExceeded` if it takes the usage of
+    /// an account over the limit of `quota`, see `Store::account_usage`.
+    pub fn with_quota(&mut self, account_id: u32, quota: Quota) -> &mut Self {
+        self.quotas.retain(|(id, _)| *id != account_id);
+        self.quotas.push((account_id, quota));
+        self
+    }
+
+    /// When enabled, `delete_document` moves documents to the recycle bin rather
+    /// than deleting them, see `soft_delete_document`.
+    pub fn with_soft_delete(&mut self, soft_delete: bool) -> &mut Self {
+        self.soft_delete = soft_delete;
+        self
+    }
+
     pub fn with_change_id(&mut self, change_id: u64) -> &mut Self {
         self.ops.push(Operation::ChangeId { change_id });
         self
@@ -45,7 +84,7 @@ impl BatchBuilder {
             set: true,
         });
 
-        self
+        self.register_collection()
     }
 
     pub fn create_document_with_id(&mut self, document_id: u32) -> &mut Self {
@@ -57,6 +96,15 @@ impl BatchBuilder {
             set: true,
         });
 
+        self.register_collection()
+    }
+
+    // Record the collection in the account's registry, see `Store::list_collections`
+    fn register_collection(&mut self) -> &mut Self {
+        self.ops.push(Operation::Value {
+            class: ValueClass::Collection,
+            op: ValueOp::Set(MaybeDynamicValue::Static(vec![])),
+        });
         self
     }
 
@@ -66,6 +114,9 @@ impl BatchBuilder {
     }
 
     pub fn delete_document(&mut self, document_id: u32) -> &mut Self {
+        if self.soft_delete {
+            return self.soft_delete_document(document_id);
+        }
         self.ops.push(Operation::DocumentId { document_id });
         self.ops.push(Operation::Bitmap {
             class: BitmapClass::DocumentIds,
@@ -92,7 +143,17 @@ impl BatchBuilder {
         value: impl Serialize + ToBitmaps,
         options: u32,
     ) -> &mut Self {
-        let field = field.into();
+        self.value_(field.into(), value, options, None, None)
+    }
+
+    fn value_(
+        &mut self,
+        field: u8,
+        value: impl Serialize + ToBitmaps,
+        options: u32,
+        index: Option<&PartialIndex>,
+        normalizers: Option<&FieldNormalizers>,
+    ) -> &mut Self {
         let is_set = !options.has_flag(F_CLEAR);
 
         if options.has_flag(F_BITMAP) {
@@ -102,11 +163,20 @@ impl BatchBuilder {
         let value = value.serialize();
 
         if options.has_flag(F_INDEX) {
-            self.ops.push(Operation::Index {
-                field,
-                key: value.clone(),
-                set: is_set,
-            });
+            let key = if let Some(normalizers) = normalizers {
+                normalizers.normalize(field, value.clone())
+            } else {
+                value.clone()
+            };
+            if (!is_set || index.map_or(true, |index| index.matches(&key)))
+                && (options.has_flag(F_NO_DEDUP) || !self.has_index_op(field, &key, is_set))
+            {
+                self.ops.push(Operation::Index {
+                    field,
+                    key,
+                    set: is_set,
+                });
+            }
         }
 
         if options.has_flag(F_VALUE) {
@@ -123,6 +193,120 @@ impl BatchBuilder {
         self
     }
 
+    // Whether the last operation on an index key of the current document has the
+    // same effect, so that repeated values of a field are only written once
+    fn has_index_op(&self, field: u8, key: &[u8], set: bool) -> bool {
+        for op in self.ops.iter().rev() {
+            match op {
+                Operation::Index {
+                    field: op_field,
+                    key: op_key,
+                    set: op_set,
+                } if *op_field == field && op_key == key => return *op_set == set,
+                Operation::AccountId { .. }
+                | Operation::Collection { .. }
+                | Operation::DocumentId { .. } => return false,
+                _ => (),
+            }
+        }
+        false
+    }
+
+    /// Writes a value that is only indexed when it satisfies the partial index
+    /// predicate. Clearing a value always removes its index entry.
+    pub fn value_partial(
+        &mut self,
+        field: impl Into<u8>,
+        value: impl Serialize + ToBitmaps,
+        options: u32,
+        index: &PartialIndex,
+    ) -> &mut Self {
+        self.value_(field.into(), value, options, Some(index), None)
+    }
+
+    /// Writes a value whose index key is normalized by the field's normalizer, if
+    /// any. The stored value is left as is. Filters on the field have to be
+    /// normalized with the same normalizers, see `FieldNormalizers::normalize_filters`.
+    pub fn value_normalized(
+        &mut self,
+        field: impl Into<u8>,
+        value: impl Serialize + ToBitmaps,
+        options: u32,
+        normalizers: &FieldNormalizers,
+    ) -> &mut Self {
+        self.value_(field.into(), value, options, None, Some(normalizers))
+    }
+
+    /// Stores the content length of the current document. It has to be set again
+    /// whenever the document content changes and cleared when it is deleted.
+    pub fn set_content_length(&mut self, length: u32) -> &mut Self {
+        self.ops.push(Operation::Value {
+            class: ValueClass::ContentLength,
+            op: ValueOp::Set(length.serialize().into()),
+        });
+        self
+    }
+
+    pub fn clear_content_length(&mut self) -> &mut Self {
+        self.ops.push(Operation::Value {
+            class: ValueClass::ContentLength,
+            op: ValueOp::Clear,
+        });
+        self
+    }
+
+    /// Adds the current document to the bucket of its size, which allows filtering
+    /// with `Filter::SizeAtLeast` and `Filter::SizeAtMost`. The content length has
+    /// to be stored as well, it is used to refine the results of the bucket.
+    pub fn set_size_bucket(&mut self, length: u32) -> &mut Self {
+        self.ops.push(Operation::Bitmap {
+            class: BitmapClass::Tag {
+                field: SIZE_BUCKET_FIELD,
+                value: TagValue::Text(vec![size_bucket(length)]),
+            },
+            set: true,
+        });
+        self
+    }
+
+    pub fn clear_size_bucket(&mut self, length: u32) -> &mut Self {
+        self.ops.push(Operation::Bitmap {
+            class: BitmapClass::Tag {
+                field: SIZE_BUCKET_FIELD,
+                value: TagValue::Text(vec![size_bucket(length)]),
+            },
+            set: false,
+        });
+        self
+    }
+
+    /// Replaces the system flags bitset of the current document.
+    pub fn set_flags(&mut self, flags: u32) -> &mut Self {
+        self.ops.push(Operation::Value {
+            class: ValueClass::Flags,
+            op: ValueOp::Set(flags.serialize().into()),
+        });
+        self
+    }
+
+    pub fn clear_flags(&mut self) -> &mut Self {
+        self.ops.push(Operation::Value {
+            class: ValueClass::Flags,
+            op: ValueOp::Clear,
+        });
+        self
+    }
+
+    /// Sets and clears individual flags, the batch fails with `AssertValueFailed`
+    /// if the stored flags no longer match `current` (`None` when not set).
+    pub fn update_flags(&mut self, current: Option<u32>, set: u32, clear: u32) -> &mut Self {
+        self.assert_value(
+            ValueClass::Flags,
+            current.map_or(AssertValue::None, AssertValue::U32),
+        )
+        .set_flags((current.unwrap_or_default() | set) & !clear)
+    }
+
     pub fn tag(
         &mut self,
         field: impl Into<u8>,
@@ -190,12 +374,22 @@ impl BatchBuilder {
     }
 
     pub fn build(self) -> Batch {
-        Batch { ops: self.ops }
+        Batch {
+            ops: self.ops,
+            isolation: self.isolation,
+            id_allocator: self.id_allocator,
+            retry: self.retry,
+            quotas: self.quotas,
+        }
     }
 
     pub fn build_batch(&mut self) -> Batch {
         Batch {
             ops: std::mem::take(&mut self.ops),
+            isolation: self.isolation,
+            id_allocator: self.id_allocator,
+            retry: self.retry,
+            quotas: self.quotas.clone(),
         }
     }
 
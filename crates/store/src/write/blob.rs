@@ -20,6 +20,15 @@ pub struct BlobQuota {
     pub count: usize,
 }
 
+/// A document linked to a blob that is missing from the blob store.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DanglingBlobLink {
+    pub hash: BlobHash,
+    pub account_id: u32,
+    pub collection: u8,
+    pub document_id: u32,
+}
+
 impl Store {
     pub async fn blob_exists(
         &self,
@@ -48,12 +57,12 @@ impl Store {
             }),
         };
         let to_key = ValueKey {
-            account_id: account_id + 1,
+            account_id,
             collection: 0,
             document_id: 0,
             class: ValueClass::Blob(BlobOp::Reserve {
-                hash: BlobHash::default(),
-                until: 0,
+                hash: BlobHash::new_max(),
+                until: u64::MAX,
             }),
         };
 
@@ -236,6 +245,105 @@ impl Store {
         Ok(())
     }
 
+    /// Checks that every blob linked to a document exists in the blob store and
+    /// returns the links to missing blobs, ordered by hash. With `remove`, the
+    /// dangling links are deleted along with the blob's commit marker, otherwise
+    /// they are only reported. Links created while the check runs may be missed.
+    pub async fn repair_blob_refs(
+        &self,
+        blob_store: &BlobStore,
+        remove: bool,
+    ) -> crate::Result<Vec<DanglingBlobLink>> {
+        let from_key = ValueKey {
+            account_id: 0,
+            collection: 0,
+            document_id: 0,
+            class: ValueClass::Blob(BlobOp::Link {
+                hash: BlobHash::default(),
+            }),
+        };
+        let to_key = ValueKey {
+            account_id: u32::MAX,
+            collection: u8::MAX,
+            document_id: u32::MAX,
+            class: ValueClass::Blob(BlobOp::Link {
+                hash: BlobHash::new_max(),
+            }),
+        };
+        let mut links = Vec::new();
+        self.iterate(
+            IterateParams::new(from_key, to_key).ascending().no_values(),
+            |key, _| {
+                let document_id = key.deserialize_be_u32(key.len() - U32_LEN)?;
+                let collection = key.get(BLOB_HASH_LEN + U32_LEN).copied().ok_or_else(|| {
+                    crate::Error::InternalError(format!("Invalid key {key:?} in blob hash tables"))
+                })?;
+
+                // Skip commit markers and links not owned by a document
+                if document_id != u32::MAX && collection != u8::MAX {
+                    links.push(DanglingBlobLink {
+                        hash: BlobHash::try_from_hash_slice(&key[0..BLOB_HASH_LEN]).unwrap(),
+                        account_id: key.deserialize_be_u32(BLOB_HASH_LEN)?,
+                        collection,
+                        document_id,
+                    });
+                }
+
+                Ok(true)
+            },
+        )
+        .await?;
+
+        // Check each linked blob once
+        let mut dangling = Vec::new();
+        let mut last_hash = None;
+        let mut is_missing = false;
+        for link in links {
+            if last_hash.as_ref() != Some(&link.hash) {
+                is_missing = !blob_store.has_blob(link.hash.as_ref()).await?;
+                last_hash = Some(link.hash.clone());
+            }
+            if is_missing {
+                dangling.push(link);
+            }
+        }
+
+        if remove {
+            let mut batch = BatchBuilder::new();
+            let mut last_hash = None;
+            for link in &dangling {
+                if batch.ops.len() >= 1000 {
+                    self.write(batch.build()).await?;
+                    batch = BatchBuilder::new();
+                }
+                if last_hash != Some(&link.hash) {
+                    last_hash = Some(&link.hash);
+                    batch.ops.push(Operation::Value {
+                        class: ValueClass::Blob(BlobOp::Commit {
+                            hash: link.hash.clone(),
+                        }),
+                        op: ValueOp::Clear,
+                    });
+                }
+                batch
+                    .with_account_id(link.account_id)
+                    .with_collection(link.collection)
+                    .update_document(link.document_id);
+                batch.ops.push(Operation::Value {
+                    class: ValueClass::Blob(BlobOp::Link {
+                        hash: link.hash.clone(),
+                    }),
+                    op: ValueOp::Clear,
+                });
+            }
+            if !batch.is_empty() {
+                self.write(batch.build()).await?;
+            }
+        }
+
+        Ok(dangling)
+    }
+
     pub async fn blob_hash_unlink_account(&self, account_id: u32) -> crate::Result<()> {
         // Validate linked blobs
         let from_key = ValueKey {
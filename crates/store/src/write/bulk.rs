@@ -0,0 +1,228 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs Ltd <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use crate::Store;
+
+use super::{
+    usage::Quota, AssignedIds, Batch, BitmapClass, IdAllocator, Isolation, Operation, RetryPolicy,
+    ValueClass,
+};
+
+const DEFAULT_MAX_OPERATIONS: usize = 5000;
+
+pub struct BulkImporter {
+    store: Store,
+    ops: Vec<Operation>,
+    fts_ops: Vec<Operation>,
+    max_ops: usize,
+    defer_fts: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Context {
+    account_id: u32,
+    collection: u8,
+    document_id: u32,
+    change_id: u64,
+}
+
+impl BulkImporter {
+    pub fn new(store: Store) -> Self {
+        BulkImporter {
+            store,
+            ops: Vec::with_capacity(DEFAULT_MAX_OPERATIONS),
+            fts_ops: Vec::new(),
+            max_ops: DEFAULT_MAX_OPERATIONS,
+            defer_fts: false,
+        }
+    }
+
+    pub fn with_max_operations(mut self, max_ops: usize) -> Self {
+        self.max_ops = max_ops;
+        self
+    }
+
+    pub fn with_deferred_fts(mut self, defer_fts: bool) -> Self {
+        self.defer_fts = defer_fts;
+        self
+    }
+
+    // Batches that need the store to assign ids or check values can't be
+    // merged with others, so they are written right away.
+    pub async fn write(&mut self, batch: Batch) -> crate::Result<Option<AssignedIds>> {
+        let mut ctx = Context::default();
+        let is_mergeable = batch.is_atomic()
+            && batch.quotas.is_empty()
+            && batch.ops.iter().all(|op| {
+                ctx.update(op);
+                !matches!(
+                    op,
+                    Operation::Bitmap {
+                        class: BitmapClass::DocumentIds,
+                        set: true
+                    }
+                ) || ctx.document_id != u32::MAX
+            });
+
+        if is_mergeable {
+            // Reset the context so the batch is written exactly as if it was on its own
+            let ctx = Context::default();
+            self.ops.extend(ctx.operations());
+            self.ops.extend(batch.ops);
+
+            if self.ops.len() >= self.max_ops {
+                self.flush().await?;
+            }
+            Ok(None)
+        } else {
+            self.flush().await?;
+            self.write_batch(batch.ops, batch.quotas).await.map(Some)
+        }
+    }
+
+    pub async fn flush(&mut self) -> crate::Result<()> {
+        if self.ops.is_empty() {
+            return Ok(());
+        }
+
+        let mut ctx = Context::default();
+        let mut ops = Vec::with_capacity(self.ops.len());
+        let mut index_ops = Vec::new();
+
+        for op in std::mem::take(&mut self.ops) {
+            ctx.update(&op);
+            match op {
+                Operation::Index { .. } => {
+                    index_ops.push((ctx, op));
+                }
+                Operation::Value {
+                    class: ValueClass::FtsQueue(_),
+                    ..
+                } if self.defer_fts => {
+                    self.fts_ops.extend(ctx.operations());
+                    self.fts_ops.push(op);
+                }
+                op => {
+                    ops.push(op);
+                }
+            }
+        }
+
+        // Write index entries in key order, the sort is stable so multiple
+        // operations on the same index key keep their relative order.
+        index_ops.sort_by(|(a_ctx, a), (b_ctx, b)| match (a, b) {
+            (
+                Operation::Index {
+                    field: a_field,
+                    key: a_key,
+                    ..
+                },
+                Operation::Index {
+                    field: b_field,
+                    key: b_key,
+                    ..
+                },
+            ) => (
+                a_ctx.account_id,
+                a_ctx.collection,
+                a_field,
+                a_key,
+                a_ctx.document_id,
+            )
+                .cmp(&(
+                    b_ctx.account_id,
+                    b_ctx.collection,
+                    b_field,
+                    b_key,
+                    b_ctx.document_id,
+                )),
+            _ => std::cmp::Ordering::Equal,
+        });
+        let mut last_ctx = None;
+        for (ctx, op) in index_ops {
+            if last_ctx != Some(ctx) {
+                ops.extend(ctx.operations());
+                last_ctx = Some(ctx);
+            }
+            ops.push(op);
+        }
+
+        self.write_batch(ops, Vec::new()).await.map(|_| ())
+    }
+
+    pub async fn finish(mut self) -> crate::Result<()> {
+        self.flush().await?;
+
+        // Queue documents for full-text indexing once everything else is in place,
+        // each queue entry is stored along with its context operations.
+        let mut fts_ops = std::mem::take(&mut self.fts_ops);
+        let chunk_size = std::cmp::max(self.max_ops / 5, 1) * 5;
+        while !fts_ops.is_empty() {
+            let ops = fts_ops
+                .drain(..std::cmp::min(chunk_size, fts_ops.len()))
+                .collect::<Vec<_>>();
+            self.write_batch(ops, Vec::new()).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn write_batch(
+        &self,
+        ops: Vec<Operation>,
+        quotas: Vec<(u32, Quota)>,
+    ) -> crate::Result<AssignedIds> {
+        self.store
+            .write(Batch {
+                ops,
+                isolation: Isolation::default(),
+                id_allocator: IdAllocator::default(),
+                retry: RetryPolicy::default(),
+                quotas,
+            })
+            .await
+    }
+}
+
+impl Context {
+    fn update(&mut self, op: &Operation) {
+        match op {
+            Operation::AccountId { account_id } => self.account_id = *account_id,
+            Operation::Collection { collection } => self.collection = *collection,
+            Operation::DocumentId { document_id } => self.document_id = *document_id,
+            Operation::ChangeId { change_id } => self.change_id = *change_id,
+            _ => (),
+        }
+    }
+
+    fn operations(&self) -> [Operation; 4] {
+        [
+            Operation::AccountId {
+                account_id: self.account_id,
+            },
+            Operation::Collection {
+                collection: self.collection,
+            },
+            Operation::DocumentId {
+                document_id: self.document_id,
+            },
+            Operation::ChangeId {
+                change_id: self.change_id,
+            },
+        ]
+    }
+}
+
+impl Default for Context {
+    fn default() -> Self {
+        Context {
+            account_id: u32::MAX,
+            collection: u8::MAX,
+            document_id: u32::MAX,
+            change_id: u64::MAX,
+        }
+    }
+}
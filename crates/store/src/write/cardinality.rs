@@ -0,0 +1,189 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs Ltd <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use utils::{
+    config::{utils::AsKey, Config},
+    lru_cache::{LruCache, LruCached},
+};
+
+use crate::{BitmapKey, Store, SUBSPACE_BITMAP_ID, SUBSPACE_BITMAP_TAG, SUBSPACE_BITMAP_TEXT};
+
+use super::{
+    AnyClass, AssignedIds, BitmapClass, MaybeDynamicId, Operation, ResolveId, TagValue, ValueClass,
+};
+
+pub(crate) const DEFAULT_CARDINALITY_CACHE_SIZE: usize = 2048;
+
+/// Caches the number of documents in a bitmap. Entries are invalidated by the
+/// writes made through this store, so the cache has to be disabled, by setting
+/// `cache.cardinality.size` to zero, when other servers write to the same
+/// database.
+pub struct CardinalityCache {
+    entries: Option<LruCache<BitmapKey<BitmapClass<u32>>, u64>>,
+    // Incremented on every invalidation, counts read before a write was applied
+    // are only cached if no invalidation happened in the meantime.
+    generation: AtomicU64,
+}
+
+// Bitmaps changed by a batch, dynamic ids are resolved once the batch is written
+pub(crate) struct ChangedBitmaps {
+    keys: Vec<(u32, u8, BitmapClass<MaybeDynamicId>)>,
+    has_raw_keys: bool,
+}
+
+impl CardinalityCache {
+    pub fn new(size: usize) -> Self {
+        CardinalityCache {
+            entries: (size > 0).then(|| LruCache::with_capacity(size)),
+            generation: AtomicU64::new(0),
+        }
+    }
+
+    pub fn parse(config: &mut Config, prefix: impl AsKey, default_size: usize) -> Self {
+        let prefix = prefix.as_key();
+        Self::new(
+            config
+                .property((&prefix, "cache.cardinality.size"))
+                .unwrap_or(default_size),
+        )
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.entries.is_some()
+    }
+
+    pub(crate) fn changed_bitmaps(&self, ops: &[Operation]) -> Option<ChangedBitmaps> {
+        self.entries.as_ref()?;
+
+        let mut changes = ChangedBitmaps {
+            keys: Vec::new(),
+            has_raw_keys: false,
+        };
+        let mut account_id = u32::MAX;
+        let mut collection = u8::MAX;
+        for op in ops {
+            match op {
+                Operation::AccountId {
+                    account_id: account_id_,
+                } => {
+                    account_id = *account_id_;
+                }
+                Operation::Collection {
+                    collection: collection_,
+                } => {
+                    collection = *collection_;
+                }
+                Operation::Bitmap { class, .. } => {
+                    changes.keys.push((account_id, collection, class.clone()));
+                }
+                Operation::Value {
+                    class: ValueClass::Any(AnyClass { subspace, .. }),
+                    ..
+                } if [
+                    SUBSPACE_BITMAP_ID,
+                    SUBSPACE_BITMAP_TAG,
+                    SUBSPACE_BITMAP_TEXT,
+                ]
+                .contains(subspace) =>
+                {
+                    changes.has_raw_keys = true;
+                }
+                _ => {}
+            }
+        }
+
+        Some(changes)
+    }
+
+    pub(crate) fn invalidate(&self, changes: ChangedBitmaps, ids: Option<&AssignedIds>) {
+        if let Some(entries) = &self.entries {
+            let mut entries = entries.lock();
+            self.generation.fetch_add(1, Ordering::Relaxed);
+            if changes.has_raw_keys {
+                entries.clear();
+                return;
+            }
+            for (account_id, collection, class) in changes.keys {
+                let class = match class {
+                    BitmapClass::DocumentIds => BitmapClass::DocumentIds,
+                    BitmapClass::Tag { field, value } => BitmapClass::Tag {
+                        field,
+                        value: match value {
+                            TagValue::Id(id) => TagValue::Id(id.resolve_id(ids)),
+                            TagValue::Text(text) => TagValue::Text(text),
+                        },
+                    },
+                    BitmapClass::Text { field, token } => BitmapClass::Text { field, token },
+                };
+                entries.remove(&BitmapKey {
+                    account_id,
+                    collection,
+                    class,
+                    document_id: 0,
+                });
+            }
+        }
+    }
+
+    pub(crate) fn invalidate_all(&self) {
+        if let Some(entries) = &self.entries {
+            let mut entries = entries.lock();
+            self.generation.fetch_add(1, Ordering::Relaxed);
+            entries.clear();
+        }
+    }
+}
+
+impl Default for CardinalityCache {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl Store {
+    /// Returns the number of documents in a bitmap, served from the cardinality
+    /// cache when the bitmap did not change since it was last counted.
+    pub async fn get_bitmap_cardinality(
+        &self,
+        key: BitmapKey<BitmapClass<u32>>,
+    ) -> crate::Result<u64> {
+        let key = BitmapKey {
+            document_id: 0,
+            ..key
+        };
+        let (entries, generation) = match self.cardinality_cache() {
+            Some(CardinalityCache {
+                entries: Some(entries),
+                generation,
+            }) => (entries, generation),
+            _ => {
+                return self
+                    .get_bitmap(key)
+                    .await
+                    .map(|bitmap| bitmap.map_or(0, |bitmap| bitmap.len()))
+            }
+        };
+
+        // The generation is read before the bitmap, a write applied in between
+        // invalidates the cache and prevents the outdated count from being stored.
+        let last_generation = generation.load(Ordering::Relaxed);
+        if let Some(cardinality) = entries.get(&key) {
+            return Ok(cardinality);
+        }
+        let cardinality = self
+            .get_bitmap(key.clone())
+            .await?
+            .map_or(0, |bitmap| bitmap.len());
+        let mut entries = entries.lock();
+        if generation.load(Ordering::Relaxed) == last_generation {
+            entries.insert(key, cardinality);
+        }
+
+        Ok(cardinality)
+    }
+}
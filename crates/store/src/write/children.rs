@@ -0,0 +1,138 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs Ltd <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use roaring::RoaringBitmap;
+
+use crate::{
+    write::key::DeserializeBigEndian, IndexKey, IterateParams, Serialize, Store, ValueKey, U32_LEN,
+};
+
+use super::{BatchBuilder, Operation, ValueClass, PARENT_FIELD};
+
+// Children unlinked per transaction
+const MAX_BATCH_SIZE: usize = 1_000;
+
+impl BatchBuilder {
+    /// Links the current document to its parent, a document of the collection
+    /// being queried with `Filter::HasChild`. A document has at most one parent,
+    /// stored as a value for looking up the parents of matched children and
+    /// indexed for listing the children of a parent.
+    pub fn set_parent(&mut self, parent_id: u32) -> &mut Self {
+        self.ops.push(Operation::Index {
+            field: PARENT_FIELD,
+            key: parent_id.to_be_bytes().to_vec(),
+            set: true,
+        });
+        self.set(ValueClass::Property(PARENT_FIELD), parent_id.serialize())
+    }
+
+    pub fn clear_parent(&mut self, parent_id: u32) -> &mut Self {
+        self.ops.push(Operation::Index {
+            field: PARENT_FIELD,
+            key: parent_id.to_be_bytes().to_vec(),
+            set: false,
+        });
+        self.clear(ValueClass::Property(PARENT_FIELD))
+    }
+}
+
+impl Store {
+    /// Returns the parents of the given documents of `child_collection`.
+    pub async fn get_parents(
+        &self,
+        account_id: u32,
+        child_collection: impl Into<u8> + Sync + Send,
+        children: &RoaringBitmap,
+    ) -> crate::Result<RoaringBitmap> {
+        let child_collection = child_collection.into();
+        let mut parents = RoaringBitmap::new();
+
+        for child_id in children {
+            if let Some(parent_id) = self
+                .get_value::<u32>(ValueKey::<ValueClass<u32>>::property(
+                    account_id,
+                    child_collection,
+                    child_id,
+                    PARENT_FIELD,
+                ))
+                .await?
+            {
+                parents.insert(parent_id);
+            }
+        }
+
+        Ok(parents)
+    }
+
+    /// Removes the links of the children of `parent_id`, to be called when the
+    /// parent is deleted. The children themselves are kept.
+    pub async fn unlink_children(
+        &self,
+        account_id: u32,
+        child_collection: impl Into<u8> + Sync + Send,
+        parent_id: u32,
+    ) -> crate::Result<()> {
+        let child_collection = child_collection.into();
+        let children = self
+            .get_children(account_id, child_collection, parent_id)
+            .await?
+            .into_iter()
+            .collect::<Vec<_>>();
+
+        for children in children.chunks(MAX_BATCH_SIZE) {
+            let mut batch = BatchBuilder::new();
+            batch
+                .with_account_id(account_id)
+                .with_collection(child_collection);
+            for child_id in children {
+                batch.update_document(*child_id).clear_parent(parent_id);
+            }
+            self.write(batch.build()).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the documents of `child_collection` linked to `parent_id`.
+    pub async fn get_children(
+        &self,
+        account_id: u32,
+        child_collection: impl Into<u8> + Sync + Send,
+        parent_id: u32,
+    ) -> crate::Result<RoaringBitmap> {
+        let child_collection = child_collection.into();
+        let mut children = RoaringBitmap::new();
+
+        self.iterate(
+            IterateParams::new(
+                IndexKey {
+                    account_id,
+                    collection: child_collection,
+                    document_id: 0,
+                    field: PARENT_FIELD,
+                    key: parent_id.to_be_bytes(),
+                },
+                IndexKey {
+                    account_id,
+                    collection: child_collection,
+                    document_id: u32::MAX,
+                    field: PARENT_FIELD,
+                    key: parent_id.to_be_bytes(),
+                },
+            )
+            .ascending()
+            .no_values(),
+            |key, _| {
+                children.insert(key.deserialize_be_u32(key.len() - U32_LEN)?);
+
+                Ok(true)
+            },
+        )
+        .await?;
+
+        Ok(children)
+    }
+}
@@ -0,0 +1,206 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs Ltd <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use crate::{
+    write::key::{DeserializeBigEndian, KeySerializer},
+    BitmapKey, IterateParams, Store, ValueKey, SUBSPACE_BITMAP_ID, SUBSPACE_CLUSTER,
+    SUBSPACE_COLLECTIONS, U32_LEN,
+};
+
+use super::{AnyClass, AnyKey, BatchBuilder, ValueClass};
+
+// Set once the collections created before the registry existed were registered
+pub const COLLECTIONS_BACKFILL_KEY: &[u8] = b"backfill.collections";
+
+// Collections registered per transaction
+const MAX_BATCH_SIZE: usize = 1_000;
+
+impl Store {
+    /// Returns the collections of an account that contain at least one document,
+    /// including soft deleted ones, in ascending order.
+    ///
+    /// Collections are read from a registry updated whenever a document is created,
+    /// which is not pruned when a collection is emptied, so each registered collection
+    /// is checked for remaining documents.
+    pub async fn list_collections(&self, account_id: u32) -> crate::Result<Vec<u8>> {
+        let mut registered = Vec::new();
+        self.iterate(
+            IterateParams::new(
+                AnyKey {
+                    subspace: SUBSPACE_COLLECTIONS,
+                    key: KeySerializer::new(U32_LEN).write(account_id).finalize(),
+                },
+                AnyKey {
+                    subspace: SUBSPACE_COLLECTIONS,
+                    key: KeySerializer::new(U32_LEN + 1)
+                        .write(account_id)
+                        .write(u8::MAX)
+                        .finalize(),
+                },
+            )
+            .no_values(),
+            |key, _| {
+                // Collection names are stored after the registry key of their collection
+                if key.len() == U32_LEN + 1 {
+                    registered.push(key[U32_LEN]);
+                }
+                Ok(true)
+            },
+        )
+        .await?;
+
+        let mut collections = Vec::with_capacity(registered.len());
+        for collection in registered {
+            let mut has_documents = false;
+            let mut to_key = BitmapKey::document_ids(account_id, collection);
+            to_key.document_id = u32::MAX;
+            self.iterate(
+                IterateParams::new(BitmapKey::document_ids(account_id, collection), to_key)
+                    .only_first()
+                    .no_values(),
+                |_, _| {
+                    has_documents = true;
+                    Ok(false)
+                },
+            )
+            .await?;
+
+            if has_documents {
+                collections.push(collection);
+            }
+        }
+
+        Ok(collections)
+    }
+
+    /// Registers the collections holding documents created before the registry
+    /// was introduced, so that `list_collections` includes them. The document ids
+    /// of every account are scanned once per store, later calls return right away.
+    /// Returns the number of collections registered.
+    pub async fn backfill_collections(&self) -> crate::Result<u64> {
+        let marker = ValueKey::from(ValueClass::Any(AnyClass {
+            subspace: SUBSPACE_CLUSTER,
+            key: COLLECTIONS_BACKFILL_KEY.to_vec(),
+        }));
+        if self.get_value::<()>(marker).await?.is_some() {
+            return Ok(0);
+        }
+
+        let mut collections: Vec<(u32, u8)> = Vec::new();
+        self.iterate(
+            IterateParams::new(
+                AnyKey {
+                    subspace: SUBSPACE_BITMAP_ID,
+                    key: vec![0u8],
+                },
+                AnyKey {
+                    subspace: SUBSPACE_BITMAP_ID,
+                    key: vec![u8::MAX; U32_LEN + 2],
+                },
+            )
+            .no_values(),
+            |key, _| {
+                let collection = (
+                    key.deserialize_be_u32(0)?,
+                    *key.get(U32_LEN).ok_or_else(|| {
+                        crate::Error::InternalError("Invalid document ids key".to_string())
+                    })?,
+                );
+                if collections.last() != Some(&collection) {
+                    collections.push(collection);
+                }
+                Ok(true)
+            },
+        )
+        .await?;
+
+        let mut registered = 0;
+        let mut batch = BatchBuilder::new();
+        for (account_id, collection) in collections {
+            if self
+                .get_value::<()>(ValueKey {
+                    account_id,
+                    collection,
+                    document_id: 0,
+                    class: ValueClass::Collection,
+                })
+                .await?
+                .is_none()
+            {
+                batch
+                    .with_account_id(account_id)
+                    .with_collection(collection)
+                    .set(ValueClass::Collection, vec![]);
+                registered += 1;
+
+                if batch.ops.len() >= MAX_BATCH_SIZE {
+                    self.write(batch.build()).await?;
+                    batch = BatchBuilder::new();
+                }
+            }
+        }
+        batch.set(
+            ValueClass::Any(AnyClass {
+                subspace: SUBSPACE_CLUSTER,
+                key: COLLECTIONS_BACKFILL_KEY.to_vec(),
+            }),
+            vec![],
+        );
+        self.write(batch.build()).await?;
+
+        Ok(registered)
+    }
+
+    /// Sets the display name of a collection. Collections are identified by their
+    /// id in every key and their names are only kept in the account's registry, so
+    /// a rename is a single write that leaves documents untouched. Note that IMAP
+    /// mailboxes are documents rather than collections, renaming one only updates
+    /// its name property. Fails with `Error::NotFound` if the collection has no
+    /// documents registered in the account.
+    pub async fn rename_collection(
+        &self,
+        account_id: u32,
+        collection: impl Into<u8>,
+        new_name: &str,
+    ) -> crate::Result<()> {
+        let collection = collection.into();
+        if self
+            .get_value::<()>(ValueKey {
+                account_id,
+                collection,
+                document_id: 0,
+                class: ValueClass::Collection,
+            })
+            .await?
+            .is_none()
+        {
+            return Err(crate::Error::NotFound(format!(
+                "Collection {collection} not found"
+            )));
+        }
+
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(account_id)
+            .with_collection(collection)
+            .set(ValueClass::CollectionName, new_name.as_bytes().to_vec());
+        self.write(batch.build()).await.map(|_| ())
+    }
+
+    pub async fn get_collection_name(
+        &self,
+        account_id: u32,
+        collection: impl Into<u8>,
+    ) -> crate::Result<Option<String>> {
+        self.get_value::<String>(ValueKey {
+            account_id,
+            collection: collection.into(),
+            document_id: 0,
+            class: ValueClass::CollectionName,
+        })
+        .await
+    }
+}
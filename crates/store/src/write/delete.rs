@@ -0,0 +1,343 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs Ltd <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use utils::{BlobHash, BLOB_HASH_LEN};
+
+use crate::{
+    write::key::KeySerializer, IterateParams, Key, Store, SUBSPACE_BITMAP_ID, SUBSPACE_BITMAP_TAG,
+    SUBSPACE_BITMAP_TEXT, SUBSPACE_BLOB_LINK, SUBSPACE_COLLECTIONS, SUBSPACE_COUNTER,
+    SUBSPACE_FTS_INDEX, SUBSPACE_INDEXES, SUBSPACE_LABELS, SUBSPACE_LOGS, SUBSPACE_PROPERTY,
+    SUBSPACE_VECTORS, U32_LEN,
+};
+
+use super::{key::DeserializeBigEndian, AnyClass, AnyKey, BatchBuilder, ValueClass};
+
+// Keys deleted per transaction
+const DEFAULT_CHUNK_SIZE: usize = 1_000;
+
+/// Stops a long running operation from another task. Clones share the same state.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Tracks a bulk deletion, which deletes `chunk_size` keys per transaction and
+/// calls `on_progress` with the number of keys deleted so far after every chunk.
+pub struct DeleteProgress<'x> {
+    chunk_size: usize,
+    cancel: CancellationToken,
+    on_progress: Box<dyn FnMut(u64) + Send + 'x>,
+    keys_deleted: u64,
+}
+
+impl<'x> DeleteProgress<'x> {
+    pub fn new(cancel: CancellationToken, on_progress: impl FnMut(u64) + Send + 'x) -> Self {
+        DeleteProgress {
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            cancel,
+            on_progress: Box::new(on_progress),
+            keys_deleted: 0,
+        }
+    }
+
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = std::cmp::max(chunk_size, 1);
+        self
+    }
+
+    pub fn keys_deleted(&self) -> u64 {
+        self.keys_deleted
+    }
+}
+
+impl Store {
+    /// Deletes the keys between `from` (inclusive) and `to` (exclusive) in chunks,
+    /// each one in its own transaction. Returns `false` if the deletion was
+    /// cancelled, in which case the chunks already deleted are not restored.
+    pub async fn delete_range_chunked(
+        &self,
+        from: impl Key,
+        to: impl Key,
+        progress: &mut DeleteProgress<'_>,
+    ) -> crate::Result<bool> {
+        self.delete_chunked(
+            from.subspace(),
+            from.serialize(0),
+            to.serialize(0),
+            None,
+            progress,
+        )
+        .await
+    }
+
+    /// Same as `purge_account`, deleting keys in chunks so that progress can be
+    /// reported and the purge cancelled.
+    pub async fn purge_account_chunked(
+        &self,
+        account_id: u32,
+        progress: &mut DeleteProgress<'_>,
+    ) -> crate::Result<bool> {
+        for (subspace, from, to) in account_ranges(account_id) {
+            if !self
+                .delete_chunked(subspace, from, to, None, progress)
+                .await?
+            {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Deletes all documents, indexes, change logs and full-text entries of a
+    /// collection in chunks. Returns `false` if the purge was cancelled.
+    pub async fn purge_collection(
+        &self,
+        account_id: u32,
+        collection: impl Into<u8>,
+        progress: &mut DeleteProgress<'_>,
+    ) -> crate::Result<bool> {
+        let collection = collection.into();
+
+        for subspace in [
+            SUBSPACE_BITMAP_ID,
+            SUBSPACE_BITMAP_TAG,
+            SUBSPACE_INDEXES,
+            SUBSPACE_LOGS,
+            SUBSPACE_PROPERTY,
+            SUBSPACE_VECTORS,
+            SUBSPACE_COUNTER,
+            SUBSPACE_COLLECTIONS,
+            SUBSPACE_LABELS,
+        ] {
+            let from = KeySerializer::new(U32_LEN + 1)
+                .write(account_id)
+                .write(collection)
+                .finalize();
+            let to = if collection < u8::MAX {
+                KeySerializer::new(U32_LEN + 1)
+                    .write(account_id)
+                    .write(collection + 1)
+                    .finalize()
+            } else {
+                KeySerializer::new(U32_LEN).write(account_id + 1).finalize()
+            };
+            if !self
+                .delete_chunked(subspace, from, to, None, progress)
+                .await?
+            {
+                return Ok(false);
+            }
+        }
+
+        // Full-text keys are ordered by token, the collection precedes the
+        // field and document id (text bitmaps) or the document id (term index)
+        for (subspace, offset) in [(SUBSPACE_BITMAP_TEXT, 6), (SUBSPACE_FTS_INDEX, 5)] {
+            let is_collection =
+                |key: &[u8]| key.len() >= U32_LEN + offset && key[key.len() - offset] == collection;
+            if !self
+                .delete_chunked(
+                    subspace,
+                    KeySerializer::new(U32_LEN).write(account_id).finalize(),
+                    KeySerializer::new(U32_LEN).write(account_id + 1).finalize(),
+                    Some(&is_collection),
+                    progress,
+                )
+                .await?
+            {
+                return Ok(false);
+            }
+        }
+
+        // Blob links are ordered by hash, followed by the account, collection and
+        // document id
+        let is_collection_link = |key: &[u8]| {
+            key.len() == BLOB_HASH_LEN + U32_LEN + 1 + U32_LEN
+                && key.deserialize_be_u32(BLOB_HASH_LEN).ok() == Some(account_id)
+                && key[BLOB_HASH_LEN + U32_LEN] == collection
+                && key.deserialize_be_u32(BLOB_HASH_LEN + U32_LEN + 1).ok() != Some(u32::MAX)
+        };
+        if !self
+            .delete_chunked(
+                SUBSPACE_BLOB_LINK,
+                BlobHash::default().as_slice().to_vec(),
+                BlobHash::new_max().as_slice().to_vec(),
+                Some(&is_collection_link),
+                progress,
+            )
+            .await?
+        {
+            return Ok(false);
+        }
+
+        // Counters are not updated by range deletions
+        self.recalculate_usage(account_id).await?;
+
+        Ok(true)
+    }
+
+    async fn delete_chunked(
+        &self,
+        subspace: u8,
+        mut begin: Vec<u8>,
+        end: Vec<u8>,
+        filter: Option<&(dyn Fn(&[u8]) -> bool + Sync)>,
+        progress: &mut DeleteProgress<'_>,
+    ) -> crate::Result<bool> {
+        let chunk_size = progress.chunk_size;
+
+        loop {
+            if progress.cancel.is_cancelled() {
+                return Ok(false);
+            }
+
+            let mut keys = Vec::new();
+            let mut scanned = 0;
+            let mut last_key = None;
+            self.iterate(
+                IterateParams::new(
+                    AnyKey {
+                        subspace,
+                        key: begin.as_slice(),
+                    },
+                    AnyKey {
+                        subspace,
+                        key: end.as_slice(),
+                    },
+                )
+                .no_values(),
+                |key, _| {
+                    if key >= end.as_slice() {
+                        return Ok(false);
+                    }
+                    if filter.map_or(false, |filter| filter(key)) {
+                        keys.push(key.to_vec());
+                    }
+                    scanned += 1;
+                    last_key = Some(key.to_vec());
+                    Ok(scanned < chunk_size)
+                },
+            )
+            .await?;
+
+            let last_key = if let Some(last_key) = last_key {
+                last_key
+            } else {
+                return Ok(true);
+            };
+            let mut next_key = last_key;
+            next_key.push(0);
+
+            let deleted = if filter.is_some() {
+                let deleted = keys.len();
+                if !keys.is_empty() {
+                    let mut batch = BatchBuilder::new();
+                    for key in keys {
+                        batch.clear(ValueClass::Any(AnyClass { subspace, key }));
+                    }
+                    self.write(batch.build()).await?;
+                }
+                deleted
+            } else {
+                self.delete_range(
+                    AnyKey {
+                        subspace,
+                        key: begin.as_slice(),
+                    },
+                    AnyKey {
+                        subspace,
+                        key: next_key.as_slice(),
+                    },
+                )
+                .await?;
+                scanned
+            };
+            progress.keys_deleted += deleted as u64;
+            (progress.on_progress)(progress.keys_deleted);
+
+            if scanned < chunk_size {
+                return Ok(true);
+            }
+            begin = next_key;
+        }
+    }
+}
+
+// Key ranges holding the data of an account, as (subspace, from, to)
+pub(crate) fn account_ranges(account_id: u32) -> Vec<(u8, Vec<u8>, Vec<u8>)> {
+    let mut ranges = Vec::new();
+
+    for subspace in [
+        SUBSPACE_BITMAP_ID,
+        SUBSPACE_BITMAP_TAG,
+        SUBSPACE_BITMAP_TEXT,
+        SUBSPACE_LOGS,
+        SUBSPACE_INDEXES,
+    ] {
+        ranges.push((
+            subspace,
+            KeySerializer::new(U32_LEN).write(account_id).finalize(),
+            KeySerializer::new(U32_LEN).write(account_id + 1).finalize(),
+        ));
+    }
+
+    for (from_class, to_class) in [
+        (
+            ValueClass::<u32>::Acl(account_id),
+            ValueClass::<u32>::Acl(account_id + 1),
+        ),
+        (ValueClass::Property(0), ValueClass::Property(0)),
+        (ValueClass::Vector(0), ValueClass::Vector(0)),
+        (ValueClass::DocumentIdCounter, ValueClass::DocumentIdCounter),
+        (ValueClass::Collection, ValueClass::Collection),
+        (ValueClass::Label(vec![]), ValueClass::Label(vec![])),
+        (
+            ValueClass::Directory(super::DirectoryClass::UsedStorage {
+                account_id,
+                subspace: 0,
+            }),
+            ValueClass::Directory(super::DirectoryClass::UsedStorage {
+                account_id: account_id + 1,
+                subspace: 0,
+            }),
+        ),
+        (
+            ValueClass::FtsIndex(super::BitmapHash {
+                hash: [0u8; 8],
+                len: 0,
+            }),
+            ValueClass::FtsIndex(super::BitmapHash {
+                hash: [u8::MAX; 8],
+                len: u8::MAX,
+            }),
+        ),
+    ] {
+        ranges.push((
+            from_class.subspace(0),
+            from_class.serialize(account_id, 0, 0, 0, None),
+            to_class.serialize(account_id + 1, 0, 0, 0, None),
+        ));
+    }
+
+    ranges
+}
@@ -0,0 +1,145 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs Ltd <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use roaring::RoaringBitmap;
+use utils::BlobHash;
+
+use crate::{IndexKeyPrefix, IterateParams, Store, U32_LEN};
+
+use super::{
+    key::DeserializeBigEndian, BatchBuilder, Operation, BLOB_HASH_FIELD, FINGERPRINT_FIELD,
+};
+
+/// Documents that are likely copies of each other.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DuplicateSet {
+    pub documents: RoaringBitmap,
+    /// Whether the documents are linked to the same blob, rather than only sharing
+    /// a content fingerprint.
+    pub is_identical: bool,
+}
+
+impl BatchBuilder {
+    /// Indexes the blob hash and a fingerprint of the current document, used by
+    /// `Store::find_duplicates` to detect byte-identical copies and copies that
+    /// only differ in, for example, headers added in transit. `content` should be
+    /// normalized by the caller and the same arguments have to be passed to
+    /// `clear_fingerprint`.
+    pub fn set_fingerprint(&mut self, blob_hash: &BlobHash, content: &[u8]) -> &mut Self {
+        self.fingerprint(blob_hash, content, true)
+    }
+
+    pub fn clear_fingerprint(&mut self, blob_hash: &BlobHash, content: &[u8]) -> &mut Self {
+        self.fingerprint(blob_hash, content, false)
+    }
+
+    fn fingerprint(&mut self, blob_hash: &BlobHash, content: &[u8], set: bool) -> &mut Self {
+        self.ops.push(Operation::Index {
+            field: BLOB_HASH_FIELD,
+            key: blob_hash.as_slice().to_vec(),
+            set,
+        });
+        self.ops.push(Operation::Index {
+            field: FINGERPRINT_FIELD,
+            key: BlobHash::from(content).as_slice().to_vec(),
+            set,
+        });
+        self
+    }
+}
+
+impl Store {
+    /// Groups the fingerprinted documents of a collection that are linked to the
+    /// same blob, followed by the groups of documents sharing a fingerprint that
+    /// are not already reported as identical, each ordered by their lowest
+    /// document id.
+    pub async fn find_duplicates(
+        &self,
+        account_id: u32,
+        collection: impl Into<u8> + Sync + Send,
+    ) -> crate::Result<Vec<DuplicateSet>> {
+        let collection = collection.into();
+
+        // Byte-identical documents share a blob hash
+        let mut duplicates = self
+            .group_index_values(account_id, collection, BLOB_HASH_FIELD)
+            .await?
+            .into_iter()
+            .map(|documents| DuplicateSet {
+                documents,
+                is_identical: true,
+            })
+            .collect::<Vec<_>>();
+
+        // Near-duplicates share a fingerprint
+        for documents in self
+            .group_index_values(account_id, collection, FINGERPRINT_FIELD)
+            .await?
+        {
+            if !duplicates
+                .iter()
+                .any(|set| set.is_identical && documents.is_subset(&set.documents))
+            {
+                duplicates.push(DuplicateSet {
+                    documents,
+                    is_identical: false,
+                });
+            }
+        }
+
+        duplicates.sort_by_key(|set| (!set.is_identical, set.documents.min()));
+
+        Ok(duplicates)
+    }
+
+    // Returns the sets of documents sharing the same value of an index field,
+    // skipping values held by a single document
+    async fn group_index_values(
+        &self,
+        account_id: u32,
+        collection: u8,
+        field: u8,
+    ) -> crate::Result<Vec<RoaringBitmap>> {
+        let mut groups: Vec<RoaringBitmap> = Vec::new();
+        let mut last_value = Vec::new();
+        self.iterate(
+            IterateParams::new(
+                IndexKeyPrefix {
+                    account_id,
+                    collection,
+                    field,
+                },
+                IndexKeyPrefix {
+                    account_id,
+                    collection,
+                    field: field + 1,
+                },
+            )
+            .ascending()
+            .no_values(),
+            |key, _| {
+                let id_pos = key.len() - U32_LEN;
+                let document_id = key.deserialize_be_u32(id_pos)?;
+                let value = key.get(IndexKeyPrefix::len()..id_pos).ok_or_else(|| {
+                    crate::Error::InternalError("Invalid key found in index".to_string())
+                })?;
+
+                // Entries for the same value are contiguous
+                if value != last_value || groups.is_empty() {
+                    last_value = value.to_vec();
+                    groups.push(RoaringBitmap::new());
+                }
+                groups.last_mut().unwrap().insert(document_id);
+
+                Ok(true)
+            },
+        )
+        .await?;
+        groups.retain(|documents| documents.len() > 1);
+
+        Ok(groups)
+    }
+}
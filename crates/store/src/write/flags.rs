@@ -0,0 +1,64 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs Ltd <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use crate::{Store, ValueKey};
+
+use super::{BatchBuilder, ValueClass};
+
+impl Store {
+    /// Returns the system flags bitset of a document, or zero if none are set.
+    pub async fn get_flags(
+        &self,
+        account_id: u32,
+        collection: impl Into<u8> + Sync + Send,
+        document_id: u32,
+    ) -> crate::Result<u32> {
+        self.get_value::<u32>(ValueKey {
+            account_id,
+            collection: collection.into(),
+            document_id,
+            class: ValueClass::Flags,
+        })
+        .await
+        .map(|flags| flags.unwrap_or_default())
+    }
+
+    /// Atomically sets and clears flags of a document, retrying when another
+    /// writer updates them concurrently. Returns the resulting flags.
+    pub async fn update_flags(
+        &self,
+        account_id: u32,
+        collection: impl Into<u8> + Sync + Send,
+        document_id: u32,
+        set: u32,
+        clear: u32,
+    ) -> crate::Result<u32> {
+        let collection = collection.into();
+
+        loop {
+            let current = self
+                .get_value::<u32>(ValueKey {
+                    account_id,
+                    collection,
+                    document_id,
+                    class: ValueClass::Flags,
+                })
+                .await?;
+            let mut batch = BatchBuilder::new();
+            batch
+                .with_account_id(account_id)
+                .with_collection(collection)
+                .update_document(document_id)
+                .update_flags(current, set, clear);
+
+            match self.write(batch.build()).await {
+                Ok(_) => return Ok((current.unwrap_or_default() | set) & !clear),
+                Err(crate::Error::AssertValueFailed) => continue,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
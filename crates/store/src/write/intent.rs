@@ -0,0 +1,52 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs Ltd <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use crate::{IterateParams, Store, ValueKey, U64_LEN};
+
+use super::{key::DeserializeBigEndian, BatchBuilder, ValueClass};
+
+impl Store {
+    /// Records the plan of a multi-step operation before any of its steps are
+    /// written, so it can be completed or undone if the server crashes halfway.
+    pub async fn intent_begin(&self, intent_id: u64, plan: Vec<u8>) -> crate::Result<()> {
+        let mut batch = BatchBuilder::new();
+        batch.set(ValueClass::Intent(intent_id), plan);
+        self.write(batch.build()).await.map(|_| ())
+    }
+
+    /// Replaces the plan of a pending intent, used to record the progress of its
+    /// steps.
+    pub async fn intent_update(&self, intent_id: u64, plan: Vec<u8>) -> crate::Result<()> {
+        self.intent_begin(intent_id, plan).await
+    }
+
+    /// Removes an intent once all of its steps have been written.
+    pub async fn intent_end(&self, intent_id: u64) -> crate::Result<()> {
+        let mut batch = BatchBuilder::new();
+        batch.clear(ValueClass::Intent(intent_id));
+        self.write(batch.build()).await.map(|_| ())
+    }
+
+    /// Returns the intents that were started but never ended, oldest first.
+    pub async fn pending_intents(&self) -> crate::Result<Vec<(u64, Vec<u8>)>> {
+        let mut intents = Vec::new();
+
+        self.iterate(
+            IterateParams::new(
+                ValueKey::from(ValueClass::Intent(0)),
+                ValueKey::from(ValueClass::Intent(u64::MAX)),
+            )
+            .ascending(),
+            |key, value| {
+                intents.push((key.deserialize_be_u64(key.len() - U64_LEN)?, value.to_vec()));
+                Ok(true)
+            },
+        )
+        .await?;
+
+        Ok(intents)
+    }
+}
@@ -10,15 +10,17 @@ use utils::{codec::leb128::Leb128_, BLOB_HASH_LEN};
 use crate::{
     BitmapKey, Deserialize, IndexKey, IndexKeyPrefix, Key, LogKey, ValueKey, SUBSPACE_ACL,
     SUBSPACE_BITMAP_ID, SUBSPACE_BITMAP_TAG, SUBSPACE_BITMAP_TEXT, SUBSPACE_BLOB_LINK,
-    SUBSPACE_BLOB_RESERVE, SUBSPACE_COUNTER, SUBSPACE_DIRECTORY, SUBSPACE_FTS_INDEX,
-    SUBSPACE_FTS_QUEUE, SUBSPACE_INDEXES, SUBSPACE_LOGS, SUBSPACE_LOOKUP_VALUE, SUBSPACE_PROPERTY,
+    SUBSPACE_BLOB_RESERVE, SUBSPACE_COLLECTIONS, SUBSPACE_COUNTER, SUBSPACE_DIRECTORY,
+    SUBSPACE_FTS_INDEX, SUBSPACE_FTS_QUEUE, SUBSPACE_INDEXES, SUBSPACE_INTENTS, SUBSPACE_LABELS,
+    SUBSPACE_LOGS, SUBSPACE_LOOKUP_VALUE, SUBSPACE_PROPERTY, SUBSPACE_QUARANTINE,
     SUBSPACE_QUEUE_EVENT, SUBSPACE_QUEUE_MESSAGE, SUBSPACE_QUOTA, SUBSPACE_REPORT_IN,
-    SUBSPACE_REPORT_OUT, SUBSPACE_SETTINGS, U32_LEN, U64_LEN, WITH_SUBSPACE,
+    SUBSPACE_REPORT_OUT, SUBSPACE_SETTINGS, SUBSPACE_VECTORS, U32_LEN, U64_LEN, WITH_SUBSPACE,
 };
 
 use super::{
-    AnyKey, AssignedIds, BitmapClass, BlobOp, DirectoryClass, LookupClass, QueueClass, ReportClass,
-    ReportEvent, ResolveId, TagValue, ValueClass,
+    AnyKey, AssignedIds, BitmapClass, BlobOp, DirectoryClass, LookupClass, MaybeDynamicId,
+    QueueClass, ReportClass, ReportEvent, ResolveId, TagValue, ValueClass, CONTENT_LENGTH_FIELD,
+    FLAGS_FIELD, TOMBSTONE_FIELD,
 };
 
 pub struct KeySerializer {
@@ -245,6 +247,21 @@ impl<T: ResolveId> ValueClass<T> {
                 .write(collection)
                 .write(*field)
                 .write(document_id),
+            ValueClass::ContentLength => serializer
+                .write(account_id)
+                .write(collection)
+                .write(CONTENT_LENGTH_FIELD)
+                .write(document_id),
+            ValueClass::Flags => serializer
+                .write(account_id)
+                .write(collection)
+                .write(FLAGS_FIELD)
+                .write(document_id),
+            ValueClass::Tombstone => serializer
+                .write(account_id)
+                .write(collection)
+                .write(TOMBSTONE_FIELD)
+                .write(document_id),
             ValueClass::FtsIndex(hash) => {
                 let serializer = serializer.write(account_id).write(
                     hash.hash
@@ -305,6 +322,10 @@ impl<T: ResolveId> ValueClass<T> {
                     .write_leb128(uid.resolve_id(assigned_ids)),
                 DirectoryClass::Domain(name) => serializer.write(3u8).write(name.as_slice()),
                 DirectoryClass::UsedQuota(uid) => serializer.write(4u8).write_leb128(*uid),
+                DirectoryClass::UsedStorage {
+                    account_id,
+                    subspace,
+                } => serializer.write(7u8).write(*account_id).write(*subspace),
                 DirectoryClass::MemberOf {
                     principal_id,
                     member_of,
@@ -365,6 +386,25 @@ impl<T: ResolveId> ValueClass<T> {
                     serializer.write(2u8).write(*expires).write(*id)
                 }
             },
+            ValueClass::Intent(id) => serializer.write(*id),
+            ValueClass::Quarantine => serializer
+                .write(account_id)
+                .write(collection)
+                .write(document_id),
+            ValueClass::Vector(field) => serializer
+                .write(account_id)
+                .write(collection)
+                .write(*field)
+                .write(document_id),
+            ValueClass::DocumentIdCounter => {
+                serializer.write(8u8).write(account_id).write(collection)
+            }
+            ValueClass::Collection => serializer.write(account_id).write(collection),
+            ValueClass::CollectionName => serializer.write(account_id).write(collection).write(0u8),
+            ValueClass::Label(name) => serializer
+                .write(account_id)
+                .write(collection)
+                .write(name.as_slice()),
             ValueClass::Any(any) => serializer.write(any.key.as_slice()),
         }
         .finalize()
@@ -506,7 +546,10 @@ impl<T: AsRef<[u8]> + Sync + Send> Key for AnyKey<T> {
 impl<T> ValueClass<T> {
     pub fn serialized_size(&self) -> usize {
         match self {
-            ValueClass::Property(_) => U32_LEN * 2 + 3,
+            ValueClass::Property(_)
+            | ValueClass::ContentLength
+            | ValueClass::Flags
+            | ValueClass::Tombstone => U32_LEN * 2 + 3,
             ValueClass::FtsIndex(hash) => {
                 if hash.len >= 8 {
                     U32_LEN * 2 + 10
@@ -523,6 +566,7 @@ impl<T> ValueClass<T> {
                 | DirectoryClass::Domain(v) => v.len(),
                 DirectoryClass::Principal(_) | DirectoryClass::UsedQuota(_) => U32_LEN,
                 DirectoryClass::Members { .. } | DirectoryClass::MemberOf { .. } => U32_LEN * 2,
+                DirectoryClass::UsedStorage { .. } => U32_LEN + 2,
             },
             ValueClass::Blob(op) => match op {
                 BlobOp::Reserve { .. } => BLOB_HASH_LEN + U64_LEN + U32_LEN + 1,
@@ -543,6 +587,13 @@ impl<T> ValueClass<T> {
                 QueueClass::QuotaCount(v) | QueueClass::QuotaSize(v) => v.len(),
             },
             ValueClass::Report(_) => U64_LEN * 2 + 1,
+            ValueClass::Intent(_) => U64_LEN,
+            ValueClass::Quarantine => U32_LEN * 2 + 1,
+            ValueClass::Vector(_) => U32_LEN * 2 + 2,
+            ValueClass::DocumentIdCounter => U32_LEN + 2,
+            ValueClass::Collection => U32_LEN + 1,
+            ValueClass::CollectionName => U32_LEN + 2,
+            ValueClass::Label(name) => U32_LEN + 1 + name.len(),
             ValueClass::Any(v) => v.key.len(),
         }
     }
@@ -556,6 +607,9 @@ impl<T> ValueClass<T> {
                     SUBSPACE_PROPERTY
                 }
             }
+            ValueClass::ContentLength | ValueClass::Flags | ValueClass::Tombstone => {
+                SUBSPACE_PROPERTY
+            }
             ValueClass::Acl(_) => SUBSPACE_ACL,
             ValueClass::FtsIndex(_) => SUBSPACE_FTS_INDEX,
             ValueClass::FtsQueue { .. } => SUBSPACE_FTS_QUEUE,
@@ -571,7 +625,7 @@ impl<T> ValueClass<T> {
                 LookupClass::Counter(_) => SUBSPACE_COUNTER,
             },
             ValueClass::Directory(directory) => match directory {
-                DirectoryClass::UsedQuota(_) => SUBSPACE_QUOTA,
+                DirectoryClass::UsedQuota(_) | DirectoryClass::UsedStorage { .. } => SUBSPACE_QUOTA,
                 _ => SUBSPACE_DIRECTORY,
             },
             ValueClass::Queue(queue) => match queue {
@@ -584,14 +638,23 @@ impl<T> ValueClass<T> {
                 QueueClass::QuotaCount(_) | QueueClass::QuotaSize(_) => SUBSPACE_QUOTA,
             },
             ValueClass::Report(_) => SUBSPACE_REPORT_IN,
+            ValueClass::Intent(_) => SUBSPACE_INTENTS,
+            ValueClass::Quarantine => SUBSPACE_QUARANTINE,
+            ValueClass::Vector(_) => SUBSPACE_VECTORS,
+            ValueClass::DocumentIdCounter => SUBSPACE_QUOTA,
+            ValueClass::Collection | ValueClass::CollectionName => SUBSPACE_COLLECTIONS,
+            ValueClass::Label(_) => SUBSPACE_LABELS,
             ValueClass::Any(any) => any.subspace,
         }
     }
 
     pub fn is_counter(&self, collection: u8) -> bool {
         match self {
-            ValueClass::Directory(DirectoryClass::UsedQuota(_))
+            ValueClass::Directory(
+                DirectoryClass::UsedQuota(_) | DirectoryClass::UsedStorage { .. },
+            )
             | ValueClass::Lookup(LookupClass::Counter(_))
+            | ValueClass::DocumentIdCounter
             | ValueClass::Queue(QueueClass::QuotaCount(_) | QueueClass::QuotaSize(_)) => true,
             ValueClass::Property(84) if collection == 1 => true, // TODO: Find a more elegant way to do this
             _ => false,
@@ -627,6 +690,66 @@ impl<U> From<DirectoryClass<U>> for ValueClass<U> {
     }
 }
 
+impl ValueClass<u32> {
+    /// Converts a class read by key into one that can be written in a batch. This
+    /// is not a `From` impl, which would make class literals passed to
+    /// `BatchBuilder` ambiguous.
+    pub fn into_dynamic(self) -> ValueClass<MaybeDynamicId> {
+        match self {
+            ValueClass::Property(field) => ValueClass::Property(field),
+            ValueClass::ContentLength => ValueClass::ContentLength,
+            ValueClass::Flags => ValueClass::Flags,
+            ValueClass::Tombstone => ValueClass::Tombstone,
+            ValueClass::Acl(grant_account_id) => ValueClass::Acl(grant_account_id),
+            ValueClass::Lookup(lookup) => ValueClass::Lookup(lookup),
+            ValueClass::FtsIndex(hash) => ValueClass::FtsIndex(hash),
+            ValueClass::FtsQueue(queue) => ValueClass::FtsQueue(queue),
+            ValueClass::Directory(directory) => ValueClass::Directory(match directory {
+                DirectoryClass::NameToId(name) => DirectoryClass::NameToId(name),
+                DirectoryClass::EmailToId(email) => DirectoryClass::EmailToId(email),
+                DirectoryClass::MemberOf {
+                    principal_id,
+                    member_of,
+                } => DirectoryClass::MemberOf {
+                    principal_id: MaybeDynamicId::Static(principal_id),
+                    member_of: MaybeDynamicId::Static(member_of),
+                },
+                DirectoryClass::Members {
+                    principal_id,
+                    has_member,
+                } => DirectoryClass::Members {
+                    principal_id: MaybeDynamicId::Static(principal_id),
+                    has_member: MaybeDynamicId::Static(has_member),
+                },
+                DirectoryClass::Domain(domain) => DirectoryClass::Domain(domain),
+                DirectoryClass::Principal(principal_id) => {
+                    DirectoryClass::Principal(MaybeDynamicId::Static(principal_id))
+                }
+                DirectoryClass::UsedQuota(account_id) => DirectoryClass::UsedQuota(account_id),
+                DirectoryClass::UsedStorage {
+                    account_id,
+                    subspace,
+                } => DirectoryClass::UsedStorage {
+                    account_id,
+                    subspace,
+                },
+            }),
+            ValueClass::Blob(op) => ValueClass::Blob(op),
+            ValueClass::Config(key) => ValueClass::Config(key),
+            ValueClass::Queue(queue) => ValueClass::Queue(queue),
+            ValueClass::Report(report) => ValueClass::Report(report),
+            ValueClass::Intent(id) => ValueClass::Intent(id),
+            ValueClass::Quarantine => ValueClass::Quarantine,
+            ValueClass::Vector(field) => ValueClass::Vector(field),
+            ValueClass::DocumentIdCounter => ValueClass::DocumentIdCounter,
+            ValueClass::Collection => ValueClass::Collection,
+            ValueClass::CollectionName => ValueClass::CollectionName,
+            ValueClass::Label(name) => ValueClass::Label(name),
+            ValueClass::Any(any) => ValueClass::Any(any),
+        }
+    }
+}
+
 impl<U> From<BlobOp> for ValueClass<U> {
     fn from(value: BlobOp) -> Self {
         ValueClass::Blob(value)
@@ -0,0 +1,335 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs Ltd <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use roaring::RoaringBitmap;
+use utils::snowflake::SnowflakeIdGenerator;
+
+use crate::{
+    write::key::KeySerializer, BitmapKey, Deserialize, IterateParams, Serialize, Store, ValueKey,
+    SUBSPACE_LABELS, U32_LEN,
+};
+
+use super::{
+    assert::{AssertValue, HashedValue},
+    log::ChangeLogBuilder,
+    relocate::RawValue,
+    AnyKey, BatchBuilder, Bincode, MaybeDynamicId, TagValue, ValueClass, F_CLEAR,
+};
+
+const MAX_BATCH_SIZE: usize = 1_000;
+
+/// Rewrites a serialized list of labels held as a document value, renaming
+/// `name` to the new name or removing it when there is none. Returns `None` when
+/// the value does not contain the label.
+pub type RelabelValue = fn(&[u8], &str, Option<&str>) -> Option<Vec<u8>>;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct LabelMetadata {
+    pub color: Option<String>,
+    pub sort_order: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Label {
+    pub name: String,
+    pub metadata: LabelMetadata,
+}
+
+/// Labels of a collection, such as user defined keywords. The registry holds the
+/// metadata of each label while membership is kept in the tag bitmaps of `field`,
+/// with the label name as tag value.
+#[derive(Debug, Clone, Copy)]
+pub struct LabelRegistry {
+    pub account_id: u32,
+    pub collection: u8,
+    pub field: u8,
+    /// Property holding the thread id of each document. When set, changes are
+    /// logged under the id made of the thread and document ids, as JMAP does.
+    pub thread_field: Option<u8>,
+    /// Updates the labels kept in the `field` value of each document, for
+    /// collections that also store them as a value, such as JMAP keywords.
+    pub relabel_value: Option<RelabelValue>,
+}
+
+impl LabelRegistry {
+    pub fn new(account_id: u32, collection: impl Into<u8>, field: impl Into<u8>) -> Self {
+        LabelRegistry {
+            account_id,
+            collection: collection.into(),
+            field: field.into(),
+            thread_field: None,
+            relabel_value: None,
+        }
+    }
+
+    pub fn with_thread_field(mut self, thread_field: impl Into<u8>) -> Self {
+        self.thread_field = Some(thread_field.into());
+        self
+    }
+
+    pub fn with_relabel_value(mut self, relabel_value: RelabelValue) -> Self {
+        self.relabel_value = Some(relabel_value);
+        self
+    }
+
+    fn batch(&self) -> BatchBuilder {
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(self.account_id)
+            .with_collection(self.collection);
+        batch
+    }
+
+    fn key(&self, name: &str) -> ValueKey<ValueClass<u32>> {
+        ValueKey {
+            account_id: self.account_id,
+            collection: self.collection,
+            document_id: 0,
+            class: ValueClass::Label(name.as_bytes().to_vec()),
+        }
+    }
+}
+
+impl Store {
+    /// Registers a label, failing with `Error::AssertValueFailed` if it exists.
+    pub async fn create_label(
+        &self,
+        registry: &LabelRegistry,
+        name: &str,
+        metadata: LabelMetadata,
+    ) -> crate::Result<()> {
+        let class: ValueClass<MaybeDynamicId> = ValueClass::Label(name.as_bytes().to_vec());
+        let mut batch = registry.batch();
+        batch
+            .assert_value(class.clone(), AssertValue::None)
+            .set(class, Bincode::new(metadata).serialize());
+        self.write(batch.build()).await.map(|_| ())
+    }
+
+    pub async fn update_label(
+        &self,
+        registry: &LabelRegistry,
+        name: &str,
+        metadata: LabelMetadata,
+    ) -> crate::Result<()> {
+        let class: ValueClass<MaybeDynamicId> = ValueClass::Label(name.as_bytes().to_vec());
+        let mut batch = registry.batch();
+        batch
+            .assert_value(class.clone(), AssertValue::Some)
+            .set(class, Bincode::new(metadata).serialize());
+        self.write(batch.build()).await.map(|_| ())
+    }
+
+    pub async fn get_label(
+        &self,
+        registry: &LabelRegistry,
+        name: &str,
+    ) -> crate::Result<Option<LabelMetadata>> {
+        self.get_value::<Bincode<LabelMetadata>>(registry.key(name))
+            .await
+            .map(|metadata| metadata.map(|metadata| metadata.inner))
+    }
+
+    /// Returns the registered labels ordered by sort order and then by name.
+    pub async fn list_labels(&self, registry: &LabelRegistry) -> crate::Result<Vec<Label>> {
+        let prefix = KeySerializer::new(U32_LEN + 1)
+            .write(registry.account_id)
+            .write(registry.collection)
+            .finalize();
+        let mut labels = Vec::new();
+        self.iterate(
+            IterateParams::new(
+                AnyKey {
+                    subspace: SUBSPACE_LABELS,
+                    key: prefix.clone(),
+                },
+                AnyKey {
+                    subspace: SUBSPACE_LABELS,
+                    key: [prefix.as_slice(), &[u8::MAX]].concat(),
+                },
+            ),
+            |key, value| {
+                let name = key
+                    .get(U32_LEN + 1..)
+                    .and_then(|name| std::str::from_utf8(name).ok())
+                    .ok_or_else(|| {
+                        crate::Error::InternalError("Invalid label key found".to_string())
+                    })?;
+                labels.push(Label {
+                    name: name.to_string(),
+                    metadata: Bincode::<LabelMetadata>::deserialize(value)?.inner,
+                });
+                Ok(true)
+            },
+        )
+        .await?;
+
+        labels.sort_by(|a, b| {
+            a.metadata
+                .sort_order
+                .cmp(&b.metadata.sort_order)
+                .then_with(|| a.name.cmp(&b.name))
+        });
+
+        Ok(labels)
+    }
+
+    /// Renames a label and moves its members to the new name. The registry entry is
+    /// renamed first, then members are relabeled in batches until no document is
+    /// left with the old name, which also covers documents tagged concurrently.
+    /// Each batch logs its changes under a new id from `change_ids`. Fails with
+    /// `Error::AssertValueFailed` if the label was modified concurrently or the new
+    /// name is taken. An interrupted rename is completed by calling it again.
+    /// Returns the documents that were relabeled.
+    pub async fn rename_label(
+        &self,
+        registry: &LabelRegistry,
+        name: &str,
+        new_name: &str,
+        change_ids: &SnowflakeIdGenerator,
+    ) -> crate::Result<RoaringBitmap> {
+        let metadata = self
+            .get_value::<HashedValue<Bincode<LabelMetadata>>>(registry.key(name))
+            .await?;
+        if let Some(metadata) = metadata {
+            let (class, new_class): (ValueClass<MaybeDynamicId>, ValueClass<MaybeDynamicId>) = (
+                ValueClass::Label(name.as_bytes().to_vec()),
+                ValueClass::Label(new_name.as_bytes().to_vec()),
+            );
+            let mut batch = registry.batch();
+            batch
+                .assert_value(class.clone(), &metadata)
+                .assert_value(new_class.clone(), AssertValue::None)
+                .clear(class)
+                .set(new_class, metadata.inner.serialize());
+            self.write(batch.build()).await?;
+        } else if self.get_label(registry, new_name).await?.is_none() {
+            return Err(crate::Error::InternalError(format!(
+                "Label {name:?} not found"
+            )));
+        }
+
+        self.relabel_members(registry, name, Some(new_name), change_ids)
+            .await
+    }
+
+    /// Unregisters a label and removes it from all of its members, in batches that
+    /// log their changes under a new id from `change_ids`. Deleting a label that
+    /// is not registered only untags its remaining members. Returns the documents
+    /// that were untagged.
+    pub async fn delete_label(
+        &self,
+        registry: &LabelRegistry,
+        name: &str,
+        change_ids: &SnowflakeIdGenerator,
+    ) -> crate::Result<RoaringBitmap> {
+        let class: ValueClass<MaybeDynamicId> = ValueClass::Label(name.as_bytes().to_vec());
+        let mut batch = registry.batch();
+        batch.clear(class);
+        self.write(batch.build()).await?;
+
+        self.relabel_members(registry, name, None, change_ids).await
+    }
+
+    async fn relabel_members(
+        &self,
+        registry: &LabelRegistry,
+        name: &str,
+        new_name: Option<&str>,
+        change_ids: &SnowflakeIdGenerator,
+    ) -> crate::Result<RoaringBitmap> {
+        let mut relabeled = RoaringBitmap::new();
+
+        loop {
+            let members = self.label_members(registry, name).await?;
+            if members.is_empty() {
+                return Ok(relabeled);
+            }
+
+            let mut changes = ChangeLogBuilder::with_next_change_id(change_ids)?;
+            let mut batch = registry.batch();
+            for document_id in members {
+                batch.update_document(document_id).tag(
+                    registry.field,
+                    TagValue::Text(name.as_bytes().to_vec()),
+                    F_CLEAR,
+                );
+                if let Some(new_name) = new_name {
+                    batch.tag(
+                        registry.field,
+                        TagValue::Text(new_name.as_bytes().to_vec()),
+                        0,
+                    );
+                }
+
+                // Rewrite the stored labels, asserting them so that a concurrent
+                // update of the document is not overwritten
+                if let Some(relabel_value) = registry.relabel_value {
+                    if let Some(value) = self
+                        .get_value::<HashedValue<RawValue>>(ValueKey::<ValueClass<u32>>::property(
+                            registry.account_id,
+                            registry.collection,
+                            document_id,
+                            registry.field,
+                        ))
+                        .await?
+                    {
+                        if let Some(new_value) = relabel_value(&value.inner.0, name, new_name) {
+                            batch
+                                .assert_value(ValueClass::Property(registry.field), &value)
+                                .set(ValueClass::Property(registry.field), new_value);
+                        }
+                    }
+                }
+
+                let thread_id = if let Some(thread_field) = registry.thread_field {
+                    self.get_value::<u32>(ValueKey::<ValueClass<u32>>::property(
+                        registry.account_id,
+                        registry.collection,
+                        document_id,
+                        thread_field,
+                    ))
+                    .await?
+                } else {
+                    None
+                };
+                changes.log_update(
+                    registry.collection,
+                    thread_id.map_or(document_id as u64, |thread_id| {
+                        ((thread_id as u64) << 32) | document_id as u64
+                    }),
+                );
+                relabeled.insert(document_id);
+
+                if batch.ops.len() >= MAX_BATCH_SIZE {
+                    break;
+                }
+            }
+            batch.custom(changes);
+
+            // Members updated concurrently are retried with the next read
+            match self.write(batch.build()).await {
+                Ok(_) | Err(crate::Error::AssertValueFailed) => {}
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    async fn label_members(
+        &self,
+        registry: &LabelRegistry,
+        name: &str,
+    ) -> crate::Result<RoaringBitmap> {
+        self.get_bitmap(BitmapKey::tag(
+            registry.account_id,
+            registry.collection,
+            registry.field,
+            TagValue::Text(name.as_bytes().to_vec()),
+        ))
+        .await
+        .map(|members| members.unwrap_or_default())
+    }
+}
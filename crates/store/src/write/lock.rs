@@ -0,0 +1,175 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs Ltd <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::time::{Duration, SystemTime};
+
+use crate::{write::key::DeserializeBigEndian, Deserialize, Store, SUBSPACE_CLUSTER, U64_LEN};
+
+use super::{
+    assert::{AssertValue, HashedValue},
+    key::KeySerializer,
+    AnyClass, AnyKey, BatchBuilder, ValueClass,
+};
+
+const LOCK_PREFIX: &[u8] = b"lock.";
+
+/// Advisory lock held by a single worker across the cluster until it is
+/// released or its TTL expires. Dropping the guard releases the lock in the
+/// background, use `release` to wait for it.
+pub struct LockGuard {
+    store: Store,
+    key: Vec<u8>,
+    owner: u64,
+    expires: u64,
+    released: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct LockValue {
+    owner: u64,
+    // Milliseconds since the Unix epoch
+    expires: u64,
+}
+
+impl Store {
+    /// Acquires the lock `name` for `ttl`, after which other workers may take it
+    /// over. Fails with `Error::AssertValueFailed` if the lock is held by another
+    /// worker or was taken concurrently.
+    pub async fn acquire_lock(&self, name: &str, ttl: Duration) -> crate::Result<LockGuard> {
+        let key = [LOCK_PREFIX, name.as_bytes()].concat();
+        let current = self
+            .get_value::<HashedValue<LockValue>>(AnyKey {
+                subspace: SUBSPACE_CLUSTER,
+                key: key.as_slice(),
+            })
+            .await?;
+
+        // Expired locks are stolen only if they were not stolen or renewed
+        // since they were read
+        let assert = match &current {
+            Some(current) if current.inner.expires > now_millis() => {
+                return Err(crate::Error::AssertValueFailed);
+            }
+            Some(current) => AssertValue::Hash(current.hash),
+            None => AssertValue::None,
+        };
+
+        let value = LockValue {
+            owner: rand::random(),
+            expires: now_millis() + ttl.as_millis() as u64,
+        };
+        let mut batch = BatchBuilder::new();
+        batch
+            .assert_value(lock_class(&key).into_dynamic(), assert)
+            .set(lock_class(&key).into_dynamic(), value.serialize());
+        self.write(batch.build()).await?;
+
+        Ok(LockGuard {
+            store: self.clone(),
+            key,
+            owner: value.owner,
+            expires: value.expires,
+            released: false,
+        })
+    }
+}
+
+impl LockGuard {
+    pub fn is_expired(&self) -> bool {
+        self.expires <= now_millis()
+    }
+
+    /// Extends the lock for `ttl` from now. Fails with `Error::AssertValueFailed`
+    /// if the lock expired and was taken by another worker.
+    pub async fn extend(&mut self, ttl: Duration) -> crate::Result<()> {
+        let expires = now_millis() + ttl.as_millis() as u64;
+        let mut batch = BatchBuilder::new();
+        batch
+            .assert_value(lock_class(&self.key).into_dynamic(), self.assert())
+            .set(
+                lock_class(&self.key).into_dynamic(),
+                LockValue {
+                    owner: self.owner,
+                    expires,
+                }
+                .serialize(),
+            );
+        self.store.write(batch.build()).await?;
+        self.expires = expires;
+        Ok(())
+    }
+
+    /// Releases the lock. Fails with `Error::AssertValueFailed` if the lock
+    /// expired and was taken by another worker, which is left untouched.
+    pub async fn release(mut self) -> crate::Result<()> {
+        self.released = true;
+        release(&self.store, &self.key, self.assert()).await
+    }
+
+    fn assert(&self) -> AssertValue {
+        AssertValue::Hash(xxhash_rust::xxh3::xxh3_64(
+            &LockValue {
+                owner: self.owner,
+                expires: self.expires,
+            }
+            .serialize(),
+        ))
+    }
+}
+
+impl Drop for LockGuard {
+    fn drop(&mut self) {
+        if !self.released {
+            if let Ok(handle) = tokio::runtime::Handle::try_current() {
+                let (store, key, assert) = (self.store.clone(), self.key.clone(), self.assert());
+                handle.spawn(async move {
+                    if let Err(err) = release(&store, &key, assert).await {
+                        tracing::debug!("Failed to release lock: {err}");
+                    }
+                });
+            }
+        }
+    }
+}
+
+async fn release(store: &Store, key: &[u8], assert: AssertValue) -> crate::Result<()> {
+    let mut batch = BatchBuilder::new();
+    batch
+        .assert_value(lock_class(key).into_dynamic(), assert)
+        .clear(lock_class(key).into_dynamic());
+    store.write(batch.build()).await.map(|_| ())
+}
+
+impl LockValue {
+    fn serialize(&self) -> Vec<u8> {
+        KeySerializer::new(U64_LEN * 2)
+            .write(self.owner)
+            .write(self.expires)
+            .finalize()
+    }
+}
+
+impl Deserialize for LockValue {
+    fn deserialize(bytes: &[u8]) -> crate::Result<Self> {
+        Ok(LockValue {
+            owner: bytes.deserialize_be_u64(0)?,
+            expires: bytes.deserialize_be_u64(U64_LEN)?,
+        })
+    }
+}
+
+fn lock_class(key: &[u8]) -> ValueClass<u32> {
+    ValueClass::Any(AnyClass {
+        subspace: SUBSPACE_CLUSTER,
+        key: key.to_vec(),
+    })
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
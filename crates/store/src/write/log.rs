@@ -5,7 +5,7 @@
  */
 
 use ahash::AHashSet;
-use utils::{codec::leb128::Leb128Vec, map::vec_map::VecMap};
+use utils::{codec::leb128::Leb128Vec, map::vec_map::VecMap, snowflake::SnowflakeIdGenerator};
 
 use crate::Serialize;
 
@@ -40,6 +40,17 @@ impl ChangeLogBuilder {
         }
     }
 
+    /// Starts a change log under a new id from `change_ids`, for operations that
+    /// commit their changes over several transactions.
+    pub fn with_next_change_id(
+        change_ids: &SnowflakeIdGenerator,
+    ) -> crate::Result<ChangeLogBuilder> {
+        change_ids
+            .generate()
+            .map(ChangeLogBuilder::with_change_id)
+            .ok_or_else(|| crate::Error::InternalError("Failed to generate change id".to_string()))
+    }
+
     pub fn log_insert(&mut self, collection: impl Into<u8>, jmap_id: impl Into<u64>) {
         self.changes
             .get_mut_or_insert(collection.into())
@@ -14,7 +14,6 @@ use std::{
 };
 
 use nlp::tokenizers::word::WordTokenizer;
-use rand::Rng;
 use roaring::RoaringBitmap;
 use utils::{
     codec::leb128::{Leb128Iterator, Leb128Vec},
@@ -24,14 +23,33 @@ use utils::{
 use crate::{backend::MAX_TOKEN_LENGTH, BlobClass, Deserialize, Serialize, Value};
 
 use self::assert::AssertValue;
+use self::usage::Quota;
 
 pub mod assert;
 pub mod batch;
 pub mod blob;
+pub mod bulk;
+pub mod cardinality;
+pub mod children;
+pub mod collections;
+pub mod delete;
+pub mod duplicates;
+pub mod flags;
 pub mod hash;
+pub mod intent;
 pub mod key;
+pub mod labels;
+pub mod lock;
 pub mod log;
 pub mod purge;
+pub mod quarantine;
+pub mod queue;
+pub mod relocate;
+pub mod retry;
+pub mod threads;
+pub mod tombstone;
+pub mod usage;
+pub mod versioned;
 
 pub trait SerializeWithId: Send + Sync {
     fn serialize_with_id(&self, ids: &AssignedIds) -> crate::Result<Vec<u8>>;
@@ -59,6 +77,8 @@ pub struct DynamicDocumentId(pub usize);
 pub struct AssignedIds {
     pub document_ids: Vec<u32>,
     pub counter_ids: Vec<i64>,
+    /// Accounts whose usage is at or above their soft limit after the write
+    pub soft_limit_reached: Vec<u32>,
 }
 
 #[cfg(not(feature = "test_mode"))]
@@ -75,15 +95,94 @@ pub const F_VALUE: u32 = 1 << 0;
 pub const F_INDEX: u32 = 1 << 1;
 pub const F_BITMAP: u32 = 1 << 2;
 pub const F_CLEAR: u32 = 1 << 3;
+// Writes an index entry even if the document already has it in this batch
+pub const F_NO_DEDUP: u32 = 1 << 4;
+
+// Reserved property field id holding the content length of a document
+pub const CONTENT_LENGTH_FIELD: u8 = u8::MAX;
+// Reserved property field id holding the system flags bitset of a document
+pub const FLAGS_FIELD: u8 = u8::MAX - 1;
+// Reserved index field id holding content fingerprints, see `find_duplicates`
+pub const FINGERPRINT_FIELD: u8 = u8::MAX - 2;
+// Reserved index field id linking child documents to their parent
+pub const PARENT_FIELD: u8 = u8::MAX - 3;
+// Reserved tag field id holding the log-scale size bucket of a document
+pub const SIZE_BUCKET_FIELD: u8 = u8::MAX - 4;
+// Reserved index field id holding the blob hash of fingerprinted documents
+pub const BLOB_HASH_FIELD: u8 = u8::MAX - 5;
+// Reserved field id marking soft deleted documents, tags with id values are
+// limited to fields below 128
+pub const TOMBSTONE_FIELD: u8 = u8::MAX >> 1;
 
 #[derive(Debug)]
 pub struct Batch {
     pub ops: Vec<Operation>,
+    pub isolation: Isolation,
+    pub id_allocator: IdAllocator,
+    pub retry: RetryPolicy,
+    pub quotas: Vec<(u32, Quota)>,
 }
 
 #[derive(Debug)]
 pub struct BatchBuilder {
     pub ops: Vec<Operation>,
+    pub isolation: Isolation,
+    pub id_allocator: IdAllocator,
+    pub retry: RetryPolicy,
+    pub quotas: Vec<(u32, Quota)>,
+    pub soft_delete: bool,
+}
+
+/// Isolation level of the reads performed while committing a batch, such as
+/// counter increments and value assertions. Batches are serializable unless
+/// requested otherwise, reads outside of a batch (`get_value`, `iterate`, etc.)
+/// always use snapshot reads.
+///
+/// With `Snapshot`, FoundationDB reads made to check assertions do not add read
+/// conflicts. This reduces conflicts under load, but concurrent batches may then both
+/// pass the same value assertion. Counters are always read with conflicts. SQLite
+/// always runs batches as immediate transactions, PostgreSQL and MySQL as
+/// read-committed transactions with locking reads.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Isolation {
+    Snapshot,
+    #[default]
+    Serializable,
+}
+
+/// Policy used to assign ids to the documents created by a batch.
+///
+/// With `MonotonicNeverReuse`, the default, ids are taken from a per-collection
+/// counter stored in the quota subspace, so an id is never handed out twice even
+/// after its document has been deleted or its collection purged, as required by
+/// JMAP. The counter never goes below the highest id in use, which keeps
+/// collections created before the counter existed consistent.
+///
+/// With `ReuseFreed`, the lowest id not present in the collection's document ids
+/// bitmap is assigned, so ids released by `delete_document` are reused first and
+/// the id space stays compact.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum IdAllocator {
+    #[default]
+    MonotonicNeverReuse,
+    ReuseFreed,
+}
+
+/// Retry budget of a batch whose commit fails with a transient error or conflicts
+/// with another transaction. Attempts are spaced by an exponential backoff starting
+/// at `base_delay` and capped at `max_delay`, randomized when `jitter` is set, and
+/// the batch is no longer retried once `max_time` has elapsed since the first attempt.
+///
+/// `INTERACTIVE` gives up quickly so that requests waiting on a client fail fast,
+/// `BACKGROUND` matches the default used by batches that do not set a policy and
+/// `CRITICAL` keeps retrying for up to two minutes, for writes that must not be lost.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    pub max_time: Duration,
+    pub jitter: bool,
 }
 
 #[derive(Debug, PartialEq, Eq, Hash)]
@@ -144,6 +243,9 @@ pub enum TagValue<T> {
 #[derive(Debug, PartialEq, Clone, Eq, Hash)]
 pub enum ValueClass<T> {
     Property(u8),
+    ContentLength,
+    Flags,
+    Tombstone,
     Acl(u32),
     Lookup(LookupClass),
     FtsIndex(BitmapHash),
@@ -153,6 +255,13 @@ pub enum ValueClass<T> {
     Config(Vec<u8>),
     Queue(QueueClass),
     Report(ReportClass),
+    Intent(u64),
+    Quarantine,
+    Vector(u8),
+    DocumentIdCounter,
+    Collection,
+    CollectionName,
+    Label(Vec<u8>),
     Any(AnyClass),
 }
 
@@ -183,6 +292,7 @@ pub enum DirectoryClass<T> {
     Domain(Vec<u8>),
     Principal(T),
     UsedQuota(u32),
+    UsedStorage { account_id: u32, subspace: u8 },
 }
 
 #[derive(Debug, PartialEq, Clone, Eq, Hash)]
@@ -223,6 +333,12 @@ pub enum ValueOp {
     Set(MaybeDynamicValue),
     AtomicAdd(i64),
     AddAndGet(i64),
+    /// Adds to a counter, failing with `Error::QuotaExceeded` if an increase
+    /// takes it over `limit`
+    AddWithLimit {
+        by: i64,
+        limit: i64,
+    },
     #[default]
     Clear,
 }
@@ -847,26 +963,26 @@ impl SerializeWithId for DynamicDocumentId {
     }
 }
 
-pub(crate) trait RandomAvailableId {
-    fn random_available_id(&self) -> u32;
+pub(crate) trait AvailableId {
+    /// Returns the lowest id not in use.
+    fn lowest_available_id(&self) -> u32;
+    /// Returns the id following the highest id in use.
+    fn next_available_id(&self) -> u32;
 }
 
-impl RandomAvailableId for RoaringBitmap {
-    fn random_available_id(&self) -> u32 {
-        let mut last_id = 0;
-        let mut available_ids = Vec::with_capacity(100);
+impl AvailableId for RoaringBitmap {
+    fn lowest_available_id(&self) -> u32 {
+        let mut next_id = 0;
         for id in self.iter() {
-            for i in last_id..id {
-                available_ids.push(i);
+            if id != next_id {
+                break;
             }
-            last_id = id + 1;
-        }
-
-        while available_ids.len() < 100 {
-            available_ids.push(last_id);
-            last_id += 1;
+            next_id = id + 1;
         }
+        next_id
+    }
 
-        available_ids[rand::thread_rng().gen_range(0..available_ids.len())]
+    fn next_available_id(&self) -> u32 {
+        self.max().map_or(0, |id| id + 1)
     }
 }
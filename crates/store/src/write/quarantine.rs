@@ -0,0 +1,154 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs Ltd <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Deserialize as _, IterateParams, Serialize as _, Store, ValueKey, U32_LEN};
+
+use super::{
+    assert::HashedValue, key::DeserializeBigEndian, now, BatchBuilder, Bincode, ValueClass,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuarantineEntry {
+    pub failures: u32,
+    pub last_error: String,
+    pub last_failure: u64,
+    pub is_quarantined: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuarantinedDocument {
+    pub account_id: u32,
+    pub collection: u8,
+    pub document_id: u32,
+    pub entry: QuarantineEntry,
+}
+
+impl Store {
+    /// Records a failure processing a document. Once a document has failed
+    /// `max_failures` times it is quarantined and `true` is returned, callers
+    /// should then stop retrying it until it is released.
+    pub async fn record_failure(
+        &self,
+        account_id: u32,
+        collection: impl Into<u8> + Sync + Send,
+        document_id: u32,
+        error: impl Into<String>,
+        max_failures: u32,
+    ) -> crate::Result<bool> {
+        let collection = collection.into();
+        let error = error.into();
+
+        loop {
+            let current = self
+                .get_value::<HashedValue<Bincode<QuarantineEntry>>>(quarantine_key(
+                    account_id,
+                    collection,
+                    document_id,
+                ))
+                .await?;
+            let failures = current
+                .as_ref()
+                .map_or(0, |current| current.inner.inner.failures)
+                + 1;
+            let entry = QuarantineEntry {
+                failures,
+                last_error: error.clone(),
+                last_failure: now(),
+                is_quarantined: failures >= max_failures,
+            };
+            let is_quarantined = entry.is_quarantined;
+
+            let mut batch = BatchBuilder::new();
+            batch
+                .with_account_id(account_id)
+                .with_collection(collection)
+                .update_document(document_id);
+            if let Some(current) = &current {
+                batch.assert_value(ValueClass::Quarantine, current);
+            } else {
+                batch.assert_value(ValueClass::Quarantine, ());
+            }
+            batch.set(ValueClass::Quarantine, Bincode::new(entry).serialize());
+
+            match self.write(batch.build()).await {
+                Ok(_) => return Ok(is_quarantined),
+                Err(crate::Error::AssertValueFailed) => continue,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    pub async fn get_failures(
+        &self,
+        account_id: u32,
+        collection: impl Into<u8> + Sync + Send,
+        document_id: u32,
+    ) -> crate::Result<Option<QuarantineEntry>> {
+        self.get_value::<Bincode<QuarantineEntry>>(quarantine_key(
+            account_id,
+            collection.into(),
+            document_id,
+        ))
+        .await
+        .map(|entry| entry.map(|entry| entry.inner))
+    }
+
+    /// Forgets the failures of a document, releasing it from quarantine.
+    pub async fn clear_failures(
+        &self,
+        account_id: u32,
+        collection: impl Into<u8> + Sync + Send,
+        document_id: u32,
+    ) -> crate::Result<()> {
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(account_id)
+            .with_collection(collection)
+            .update_document(document_id)
+            .clear(ValueClass::Quarantine);
+        self.write(batch.build()).await.map(|_| ())
+    }
+
+    pub async fn quarantined_documents(&self) -> crate::Result<Vec<QuarantinedDocument>> {
+        let mut documents = Vec::new();
+
+        self.iterate(
+            IterateParams::new(
+                quarantine_key(0, 0, 0),
+                quarantine_key(u32::MAX, u8::MAX, u32::MAX),
+            )
+            .ascending(),
+            |key, value| {
+                let entry = Bincode::<QuarantineEntry>::deserialize(value)?.inner;
+                if entry.is_quarantined {
+                    documents.push(QuarantinedDocument {
+                        account_id: key.deserialize_be_u32(0)?,
+                        collection: *key.get(U32_LEN).ok_or_else(|| {
+                            crate::Error::InternalError("Invalid quarantine key".to_string())
+                        })?,
+                        document_id: key.deserialize_be_u32(U32_LEN + 1)?,
+                        entry,
+                    });
+                }
+                Ok(true)
+            },
+        )
+        .await?;
+
+        Ok(documents)
+    }
+}
+
+fn quarantine_key(account_id: u32, collection: u8, document_id: u32) -> ValueKey<ValueClass<u32>> {
+    ValueKey {
+        account_id,
+        collection,
+        document_id,
+        class: ValueClass::Quarantine,
+    }
+}
@@ -0,0 +1,77 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs Ltd <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+pub(crate) const DEFAULT_WRITE_QUEUE_DEPTH: usize = 1024;
+
+/// Bounds the number of batches written concurrently to a store. Once `capacity`
+/// writes are in progress, further writes wait for one of them to finish instead
+/// of piling up transactions on the backend.
+pub struct WriteQueue {
+    permits: Semaphore,
+    capacity: usize,
+    depth: AtomicUsize,
+}
+
+pub struct WriteQueueGuard<'x> {
+    _permit: SemaphorePermit<'x>,
+    _depth: DepthGuard<'x>,
+}
+
+// Counts a write from the moment it is queued, also when the wait is cancelled
+struct DepthGuard<'x>(&'x AtomicUsize);
+
+impl WriteQueue {
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.clamp(1, Semaphore::MAX_PERMITS);
+        WriteQueue {
+            permits: Semaphore::new(capacity),
+            capacity,
+            depth: AtomicUsize::new(0),
+        }
+    }
+
+    /// Waits until fewer than `capacity` writes are in progress. The slot is held
+    /// until the returned guard is dropped.
+    pub async fn acquire(&self) -> crate::Result<WriteQueueGuard<'_>> {
+        self.depth.fetch_add(1, Ordering::Relaxed);
+        let depth = DepthGuard(&self.depth);
+        let permit = self
+            .permits
+            .acquire()
+            .await
+            .map_err(|_| crate::Error::InternalError("Write queue closed".to_string()))?;
+
+        Ok(WriteQueueGuard {
+            _permit: permit,
+            _depth: depth,
+        })
+    }
+
+    /// Writes in progress or waiting for a slot.
+    pub fn depth(&self) -> usize {
+        self.depth.load(Ordering::Relaxed)
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+impl Default for WriteQueue {
+    fn default() -> Self {
+        WriteQueue::new(DEFAULT_WRITE_QUEUE_DEPTH)
+    }
+}
+
+impl Drop for DepthGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,420 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs Ltd <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use ahash::AHashMap;
+use roaring::RoaringBitmap;
+use utils::{codec::leb128::Leb128Reader, BlobHash, BLOB_HASH_LEN};
+
+use crate::{
+    BitmapKey, Deserialize, IterateParams, Store, ValueKey, SUBSPACE_BITMAP_TAG,
+    SUBSPACE_BITMAP_TEXT, SUBSPACE_BLOB_LINK, SUBSPACE_FTS_INDEX, SUBSPACE_INDEXES,
+    SUBSPACE_VECTORS, U32_LEN,
+};
+
+use super::{
+    assert::AssertValue,
+    key::{DeserializeBigEndian, KeySerializer},
+    log::{Changes, LogInsert},
+    AnyKey, BatchBuilder, BitmapClass, BitmapHash, BlobOp, MaybeDynamicId, Operation, TagValue,
+    ValueClass,
+};
+
+const BM_MARKER: u8 = 1 << 7;
+
+pub(crate) struct RawValue(pub Vec<u8>);
+
+#[derive(Default)]
+pub(super) struct DocumentEntries {
+    values: Vec<(u8, Vec<u8>)>,
+    indexes: Vec<(u8, Vec<u8>)>,
+    vectors: Vec<(u8, Vec<u8>)>,
+    bitmaps: Vec<BitmapClass<MaybeDynamicId>>,
+    pub(super) blob_links: Vec<BlobHash>,
+    fts_index: Vec<(BitmapHash, Vec<u8>)>,
+}
+
+impl Store {
+    /// Moves a document to another collection in a single transaction, re-keying its
+    /// values, indexes, bitmaps, blob links and full-text index entries. The document
+    /// is assigned a free id in the destination collection, which is returned, and the
+    /// move is logged under `change_id` as a deletion and an insertion.
+    ///
+    /// Entries are found by scanning the keys of the account and collection. Blob links
+    /// are keyed by hash, so the links to the document's blobs are only moved for the
+    /// hashes listed in `blob_hashes`. Values and links read are asserted on commit,
+    /// `AssertValueFailed` is returned if they changed in the meantime. Counters, ACLs
+    /// and pending FTS queue entries are not moved.
+    pub async fn move_document(
+        &self,
+        account_id: u32,
+        from_collection: impl Into<u8>,
+        to_collection: impl Into<u8>,
+        document_id: u32,
+        change_id: u64,
+        blob_hashes: &[BlobHash],
+    ) -> crate::Result<u32> {
+        let from_collection = from_collection.into();
+        let to_collection = to_collection.into();
+        if !self
+            .get_bitmap(BitmapKey::document_ids(account_id, from_collection))
+            .await?
+            .map_or(false, |ids| ids.contains(document_id))
+        {
+            return Err(crate::Error::NotFound(format!(
+                "Document {document_id} not found"
+            )));
+        }
+
+        let mut entries = self
+            .document_entries(account_id, from_collection, document_id)
+            .await?;
+        for hash in blob_hashes {
+            if self
+                .get_value::<()>(ValueKey {
+                    account_id,
+                    collection: from_collection,
+                    document_id,
+                    class: ValueClass::Blob(BlobOp::Link { hash: hash.clone() }),
+                })
+                .await?
+                .is_some()
+            {
+                entries.blob_links.push(hash.clone());
+            }
+        }
+
+        // Remove the document from the source collection
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_change_id(change_id)
+            .with_account_id(account_id)
+            .with_collection(from_collection)
+            .delete_document(document_id)
+            .log(Changes::delete([document_id]));
+        entries.assert(&mut batch);
+        entries.clear(&mut batch);
+
+        // Add it to the destination collection, the document id is assigned on commit
+        batch
+            .with_collection(to_collection)
+            .create_document()
+            .log(LogInsert());
+        for (field, value) in entries.values {
+            batch.set(ValueClass::Property(field), value);
+        }
+        for (field, key) in entries.indexes {
+            batch.ops.push(Operation::Index {
+                field,
+                key,
+                set: true,
+            });
+        }
+        for (field, value) in entries.vectors {
+            batch.set(ValueClass::Vector(field), value);
+        }
+        for class in entries.bitmaps {
+            batch.ops.push(Operation::Bitmap { class, set: true });
+        }
+        for hash in entries.blob_links {
+            batch.set(BlobOp::Link { hash }, Vec::new());
+        }
+        for (hash, value) in entries.fts_index {
+            batch.set(ValueClass::FtsIndex(hash), value);
+        }
+
+        self.write(batch.build())
+            .await
+            .and_then(|ids| ids.last_document_id())
+    }
+
+    pub(super) async fn document_entries(
+        &self,
+        account_id: u32,
+        collection: u8,
+        document_id: u32,
+    ) -> crate::Result<DocumentEntries> {
+        let mut entries = DocumentEntries::default();
+        let doc_id = document_id.to_be_bytes();
+        let collection_prefix = KeySerializer::new(U32_LEN + 1)
+            .write(account_id)
+            .write(collection)
+            .finalize();
+        let account_prefix = account_id.to_be_bytes().to_vec();
+
+        // Values
+        let mut fields = Vec::new();
+        self.iterate(
+            IterateParams::new(
+                ValueKey {
+                    account_id,
+                    collection,
+                    document_id: 0,
+                    class: ValueClass::Property(0),
+                },
+                ValueKey {
+                    account_id,
+                    collection,
+                    document_id: u32::MAX,
+                    class: ValueClass::Property(u8::MAX),
+                },
+            )
+            .no_values(),
+            |key, _| {
+                if key.len() == U32_LEN * 2 + 2 && key.ends_with(&doc_id) {
+                    fields.push(key[U32_LEN + 1]);
+                }
+                Ok(true)
+            },
+        )
+        .await?;
+        for field in fields {
+            if let Some(value) = self
+                .get_value::<RawValue>(ValueKey {
+                    account_id,
+                    collection,
+                    document_id,
+                    class: ValueClass::Property(field),
+                })
+                .await?
+            {
+                entries.values.push((field, value.0));
+            }
+        }
+
+        // Indexes
+        self.iterate(
+            prefix_range(SUBSPACE_INDEXES, &collection_prefix).no_values(),
+            |key, _| {
+                if key.len() > U32_LEN * 2 + 1 && key.ends_with(&doc_id) {
+                    entries.indexes.push((
+                        key[U32_LEN + 1],
+                        key[U32_LEN + 2..key.len() - U32_LEN].to_vec(),
+                    ));
+                }
+                Ok(true)
+            },
+        )
+        .await?;
+
+        // Embeddings
+        self.iterate(
+            prefix_range(SUBSPACE_VECTORS, &collection_prefix),
+            |key, value| {
+                if key.len() == U32_LEN * 2 + 2 && key.ends_with(&doc_id) {
+                    entries.vectors.push((key[U32_LEN + 1], value.to_vec()));
+                }
+                Ok(true)
+            },
+        )
+        .await?;
+
+        // Tag bitmaps
+        self.iterate(
+            prefix_range(SUBSPACE_BITMAP_TAG, &collection_prefix).no_values(),
+            |key, _| {
+                if key.len() >= U32_LEN * 2 + 2 && key.ends_with(&doc_id) {
+                    let value = &key[U32_LEN + 2..key.len() - U32_LEN];
+                    let (field, value) = match key[U32_LEN + 1] {
+                        field if field & BM_MARKER == 0 => (
+                            field,
+                            TagValue::Id(MaybeDynamicId::Static(
+                                value
+                                    .read_leb128::<u32>()
+                                    .map(|(id, _)| id)
+                                    .ok_or_else(|| {
+                                        crate::Error::InternalError(
+                                            "Invalid tag bitmap key".to_string(),
+                                        )
+                                    })?,
+                            )),
+                        ),
+                        field => (field & !BM_MARKER, TagValue::Text(value.to_vec())),
+                    };
+                    entries.bitmaps.push(BitmapClass::Tag { field, value });
+                }
+                Ok(true)
+            },
+        )
+        .await?;
+
+        // Text bitmaps and full-text index entries are keyed by hash before the
+        // collection, they are found by scanning the keys of the account
+        for subspace in [SUBSPACE_BITMAP_TEXT, SUBSPACE_FTS_INDEX] {
+            let suffix_len = if subspace == SUBSPACE_BITMAP_TEXT {
+                U32_LEN + 2
+            } else {
+                U32_LEN + 1
+            };
+
+            self.iterate(
+                prefix_range(subspace, &account_prefix).set_values(subspace == SUBSPACE_FTS_INDEX),
+                |key, value| {
+                    if key.len() > U32_LEN + suffix_len
+                        && key.ends_with(&doc_id)
+                        && key[key.len() - suffix_len] == collection
+                    {
+                        let token = parse_hash(&key[U32_LEN..key.len() - suffix_len])?;
+                        if subspace == SUBSPACE_BITMAP_TEXT {
+                            entries.bitmaps.push(BitmapClass::Text {
+                                field: key[key.len() - U32_LEN - 1],
+                                token,
+                            });
+                        } else {
+                            entries.fts_index.push((token, value.to_vec()));
+                        }
+                    }
+                    Ok(true)
+                },
+            )
+            .await?;
+        }
+
+        Ok(entries)
+    }
+
+    /// Returns the blobs linked to the given documents of a collection. Blob links
+    /// are keyed by hash, so this scans the links of all accounts.
+    pub(super) async fn document_blob_links(
+        &self,
+        account_id: u32,
+        collection: u8,
+        document_ids: &RoaringBitmap,
+    ) -> crate::Result<AHashMap<u32, Vec<BlobHash>>> {
+        let mut links: AHashMap<u32, Vec<BlobHash>> = AHashMap::new();
+        self.iterate(
+            IterateParams::new(
+                AnyKey {
+                    subspace: SUBSPACE_BLOB_LINK,
+                    key: vec![0u8],
+                },
+                AnyKey {
+                    subspace: SUBSPACE_BLOB_LINK,
+                    key: vec![u8::MAX; BLOB_HASH_LEN + U32_LEN * 2 + 1],
+                },
+            )
+            .no_values(),
+            |key, _| {
+                if key.len() == BLOB_HASH_LEN + U32_LEN * 2 + 1
+                    && key.deserialize_be_u32(BLOB_HASH_LEN)? == account_id
+                    && key[BLOB_HASH_LEN + U32_LEN] == collection
+                {
+                    let document_id = key.deserialize_be_u32(key.len() - U32_LEN)?;
+                    if document_ids.contains(document_id) {
+                        links.entry(document_id).or_default().push(
+                            BlobHash::try_from_hash_slice(&key[..BLOB_HASH_LEN]).map_err(|_| {
+                                crate::Error::InternalError("Invalid blob hash".to_string())
+                            })?,
+                        );
+                    }
+                }
+                Ok(true)
+            },
+        )
+        .await?;
+
+        Ok(links)
+    }
+}
+
+impl DocumentEntries {
+    /// Adds assertions on the values and blob links read to `batch`, so that it fails
+    /// if any of them changed before it is committed.
+    pub(super) fn assert(&self, batch: &mut BatchBuilder) {
+        for (field, value) in &self.values {
+            batch.assert_value(
+                ValueClass::Property(*field),
+                AssertValue::Hash(xxhash_rust::xxh3::xxh3_64(value)),
+            );
+        }
+        for (field, value) in &self.vectors {
+            batch.assert_value(
+                ValueClass::Vector(*field),
+                AssertValue::Hash(xxhash_rust::xxh3::xxh3_64(value)),
+            );
+        }
+        for hash in &self.blob_links {
+            batch.assert_value(BlobOp::Link { hash: hash.clone() }, AssertValue::Some);
+        }
+    }
+
+    /// Adds the operations removing all entries of the current document to `batch`.
+    pub(super) fn clear(&self, batch: &mut BatchBuilder) {
+        for (field, _) in &self.values {
+            batch.clear(ValueClass::Property(*field));
+        }
+        for (field, key) in &self.indexes {
+            batch.ops.push(Operation::Index {
+                field: *field,
+                key: key.clone(),
+                set: false,
+            });
+        }
+        for (field, _) in &self.vectors {
+            batch.clear(ValueClass::Vector(*field));
+        }
+        for class in &self.bitmaps {
+            batch.ops.push(Operation::Bitmap {
+                class: class.clone(),
+                set: false,
+            });
+        }
+        for hash in &self.blob_links {
+            batch.clear(BlobOp::Link { hash: hash.clone() });
+        }
+        for (hash, _) in &self.fts_index {
+            batch.clear(ValueClass::FtsIndex(*hash));
+        }
+    }
+}
+
+fn prefix_range(subspace: u8, prefix: &[u8]) -> IterateParams<AnyKey<Vec<u8>>> {
+    IterateParams::new(
+        AnyKey {
+            subspace,
+            key: prefix.to_vec(),
+        },
+        AnyKey {
+            subspace,
+            key: prefix_end(prefix),
+        },
+    )
+}
+
+fn prefix_end(prefix: &[u8]) -> Vec<u8> {
+    let mut end = Vec::with_capacity(prefix.len() + U32_LEN * 2);
+    end.extend_from_slice(prefix);
+    end.extend_from_slice(&[u8::MAX; U32_LEN * 2]);
+    end
+}
+
+fn parse_hash(bytes: &[u8]) -> crate::Result<BitmapHash> {
+    let mut hash = [0u8; 8];
+    match bytes.len() {
+        9 => {
+            hash.copy_from_slice(&bytes[..8]);
+            Ok(BitmapHash {
+                hash,
+                len: bytes[8],
+            })
+        }
+        len @ 1..=7 => {
+            hash[..len].copy_from_slice(bytes);
+            Ok(BitmapHash {
+                hash,
+                len: len as u8,
+            })
+        }
+        len => Err(crate::Error::InternalError(format!(
+            "Invalid hash length {len}"
+        ))),
+    }
+}
+
+impl Deserialize for RawValue {
+    fn deserialize(bytes: &[u8]) -> crate::Result<Self> {
+        Ok(RawValue(bytes.to_vec()))
+    }
+}
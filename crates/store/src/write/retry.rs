@@ -0,0 +1,96 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs Ltd <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::{
+    future::Future,
+    time::{Duration, Instant},
+};
+
+use rand::Rng;
+
+use super::{RetryPolicy, MAX_COMMIT_ATTEMPTS, MAX_COMMIT_TIME};
+
+impl RetryPolicy {
+    pub const INTERACTIVE: RetryPolicy = RetryPolicy {
+        max_attempts: 3,
+        base_delay: Duration::from_millis(10),
+        max_delay: Duration::from_millis(100),
+        max_time: Duration::from_secs(1),
+        jitter: true,
+    };
+
+    pub const BACKGROUND: RetryPolicy = RetryPolicy {
+        max_attempts: 10,
+        base_delay: Duration::from_millis(50),
+        max_delay: Duration::from_millis(300),
+        max_time: Duration::from_secs(10),
+        jitter: true,
+    };
+
+    pub const CRITICAL: RetryPolicy = RetryPolicy {
+        max_attempts: 50,
+        base_delay: Duration::from_millis(50),
+        max_delay: Duration::from_secs(2),
+        max_time: Duration::from_secs(120),
+        jitter: true,
+    };
+
+    /// Returns whether another attempt is allowed after `attempt` failed, with
+    /// attempts numbered from zero, `elapsed` after the first attempt started.
+    pub fn can_retry(&self, attempt: u32, elapsed: Duration) -> bool {
+        attempt + 1 < self.max_attempts && elapsed < self.max_time
+    }
+
+    /// Returns the time to wait after `attempt` failed, with attempts numbered from
+    /// zero. Without jitter this is `base_delay * 2^attempt`, with jitter a random
+    /// delay between `base_delay` and twice that value, both capped at `max_delay`.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let backoff = |attempt: u32| {
+            self.base_delay
+                .saturating_mul(1u32.checked_shl(attempt).unwrap_or(u32::MAX))
+                .min(self.max_delay)
+        };
+
+        if self.jitter {
+            let max_delay = backoff(attempt.saturating_add(1));
+            if max_delay > self.base_delay {
+                return rand::thread_rng().gen_range(self.base_delay..=max_delay);
+            }
+        }
+
+        backoff(attempt)
+    }
+
+    /// Runs `f` until it succeeds, fails with an error that is not retryable or the
+    /// policy runs out of attempts, waiting between attempts as defined by the policy.
+    pub async fn run<T, F, Fut>(&self, mut f: F) -> crate::Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = crate::Result<T>>,
+    {
+        let start = Instant::now();
+        let mut attempt = 0;
+        loop {
+            match f().await {
+                Err(err) if err.is_retryable() && self.can_retry(attempt, start.elapsed()) => {
+                    tokio::time::sleep(self.delay(attempt)).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: MAX_COMMIT_ATTEMPTS,
+            max_time: MAX_COMMIT_TIME,
+            ..RetryPolicy::BACKGROUND
+        }
+    }
+}
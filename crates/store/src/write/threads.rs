@@ -0,0 +1,404 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs Ltd <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use ahash::AHashMap;
+use roaring::RoaringBitmap;
+use utils::snowflake::SnowflakeIdGenerator;
+
+use crate::{
+    write::key::DeserializeBigEndian, BitmapKey, Deserialize, IndexKeyPrefix, IterateParams, Store,
+    ValueKey, U32_LEN,
+};
+
+use super::{
+    log::{ChangeLogBuilder, LogInsert},
+    BatchBuilder, ValueClass, F_BITMAP, F_CLEAR, F_VALUE,
+};
+
+// Documents updated per transaction
+const MAX_BATCH_SIZE: usize = 1_000;
+
+/// Describes how messages are threaded. Messages of `collection` store the id of
+/// their thread, a document of `thread_collection`, as the value and tag of
+/// `thread_field`. The `subject_field` index holds the normalized subject of each
+/// message and the `references_field` index its Message-ID, In-Reply-To and
+/// References ids.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ThreadIndex {
+    pub collection: u8,
+    pub thread_collection: u8,
+    pub thread_field: u8,
+    pub subject_field: u8,
+    pub references_field: u8,
+}
+
+impl ThreadIndex {
+    pub fn new(
+        collection: impl Into<u8>,
+        thread_collection: impl Into<u8>,
+        thread_field: impl Into<u8>,
+        subject_field: impl Into<u8>,
+        references_field: impl Into<u8>,
+    ) -> Self {
+        ThreadIndex {
+            collection: collection.into(),
+            thread_collection: thread_collection.into(),
+            thread_field: thread_field.into(),
+            subject_field: subject_field.into(),
+            references_field: references_field.into(),
+        }
+    }
+
+    // Moves messages to another thread, asserting the thread they were read with
+    fn move_batch(
+        &self,
+        account_id: u32,
+        moves: &[(u32, Option<u32>, u32)],
+        change_ids: &SnowflakeIdGenerator,
+    ) -> crate::Result<BatchBuilder> {
+        let mut changes = ChangeLogBuilder::with_next_change_id(change_ids)?;
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(account_id)
+            .with_collection(self.collection);
+        for &(document_id, old_thread_id, thread_id) in moves {
+            batch.update_document(document_id);
+            if let Some(old_thread_id) = old_thread_id {
+                batch
+                    .assert_value(ValueClass::Property(self.thread_field), old_thread_id)
+                    .value(self.thread_field, old_thread_id, F_BITMAP | F_CLEAR);
+                changes.log_move(
+                    self.collection,
+                    ((old_thread_id as u64) << 32) | document_id as u64,
+                    ((thread_id as u64) << 32) | document_id as u64,
+                );
+            } else {
+                batch.assert_value(ValueClass::Property(self.thread_field), ());
+                changes.log_insert(
+                    self.collection,
+                    ((thread_id as u64) << 32) | document_id as u64,
+                );
+            }
+            batch.value(self.thread_field, thread_id, F_VALUE | F_BITMAP);
+        }
+        batch.custom(changes);
+
+        Ok(batch)
+    }
+}
+
+impl Store {
+    /// Rebuilds the threads of an account from scratch. Messages sharing a
+    /// subject and any reference belong to the same thread, while messages
+    /// with no related message are threaded with the messages sharing their
+    /// subject. Existing thread ids are reused where possible so running it
+    /// twice changes nothing. Returns the number of messages that were moved
+    /// to another thread.
+    ///
+    /// Threads are created and messages moved over several transactions, each
+    /// logging its own changes under a new id from `change_ids`, so an
+    /// interrupted run leaves no unlogged change and is completed by running it
+    /// again. Messages ingested meanwhile keep the thread they were assigned:
+    /// moves assert the thread id that was read, skipping messages re-threaded
+    /// concurrently, and threads are only deleted if no message joined them.
+    pub async fn recompute_threads(
+        &self,
+        index: &ThreadIndex,
+        account_id: u32,
+        change_ids: &SnowflakeIdGenerator,
+    ) -> crate::Result<u64> {
+        let document_ids = self
+            .get_bitmap(BitmapKey::document_ids(account_id, index.collection))
+            .await?
+            .unwrap_or_default();
+        if document_ids.is_empty() {
+            return Ok(0);
+        }
+
+        // Obtain subjects
+        let mut subjects: AHashMap<u32, Vec<u8>> = AHashMap::new();
+        self.iterate_index(
+            account_id,
+            index.collection,
+            index.subject_field,
+            |value, id| {
+                subjects.insert(id, value.to_vec());
+            },
+        )
+        .await?;
+
+        // Group messages sharing a subject and a reference
+        let mut threads = Threads::default();
+        let mut references: AHashMap<(Vec<u8>, Vec<u8>), u32> = AHashMap::new();
+        self.iterate_index(
+            account_id,
+            index.collection,
+            index.references_field,
+            |value, id| {
+                if document_ids.contains(id) {
+                    let subject = subjects.get(&id).cloned().unwrap_or_default();
+                    match references.entry((value.to_vec(), subject)) {
+                        std::collections::hash_map::Entry::Occupied(entry) => {
+                            threads.union(*entry.get(), id);
+                        }
+                        std::collections::hash_map::Entry::Vacant(entry) => {
+                            entry.insert(id);
+                        }
+                    }
+                }
+            },
+        )
+        .await?;
+
+        // Messages without related messages, such as replies with broken reference
+        // chains, join the first thread with their subject
+        let mut sizes: AHashMap<u32, u32> = AHashMap::new();
+        for document_id in &document_ids {
+            *sizes.entry(threads.find(document_id)).or_default() += 1;
+        }
+        let mut first_by_subject: AHashMap<&[u8], u32> = AHashMap::new();
+        for document_id in &document_ids {
+            if let Some(subject) = subjects.get(&document_id).filter(|s| !s.is_empty()) {
+                first_by_subject
+                    .entry(subject.as_slice())
+                    .or_insert(document_id);
+            }
+        }
+        for document_id in &document_ids {
+            if sizes[&threads.find(document_id)] == 1 {
+                if let Some(first_id) = subjects
+                    .get(&document_id)
+                    .and_then(|subject| first_by_subject.get(subject.as_slice()))
+                {
+                    threads.union(*first_id, document_id);
+                }
+            }
+        }
+
+        // Obtain current thread ids
+        let mut thread_ids: AHashMap<u32, u32> = AHashMap::new();
+        self.iterate(
+            IterateParams::new(
+                ValueKey {
+                    account_id,
+                    collection: index.collection,
+                    document_id: 0,
+                    class: ValueClass::Property(index.thread_field),
+                },
+                ValueKey {
+                    account_id,
+                    collection: index.collection,
+                    document_id: u32::MAX,
+                    class: ValueClass::Property(index.thread_field),
+                },
+            ),
+            |key, value| {
+                thread_ids.insert(
+                    key.deserialize_be_u32(key.len() - U32_LEN)?,
+                    u32::deserialize(value)?,
+                );
+                Ok(true)
+            },
+        )
+        .await?;
+
+        // Build the new threads, ordered by their first message
+        let mut members: AHashMap<u32, Vec<u32>> = AHashMap::new();
+        for document_id in &document_ids {
+            members
+                .entry(threads.find(document_id))
+                .or_default()
+                .push(document_id);
+        }
+        let mut members = members.into_values().collect::<Vec<_>>();
+        members.sort_unstable_by_key(|ids| ids[0]);
+
+        // Keep the thread id shared by most messages, unless an earlier thread took it
+        let mut used_thread_ids = RoaringBitmap::new();
+        let mut created_thread_ids = Vec::new();
+        let mut moves = Vec::new();
+        for ids in members {
+            let mut counts: AHashMap<u32, u32> = AHashMap::new();
+            for id in &ids {
+                if let Some(thread_id) = thread_ids.get(id) {
+                    *counts.entry(*thread_id).or_default() += 1;
+                }
+            }
+            let mut candidates = counts.into_iter().collect::<Vec<_>>();
+            candidates.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+            let thread_id = if let Some((thread_id, _)) = candidates
+                .into_iter()
+                .find(|(thread_id, _)| !used_thread_ids.contains(*thread_id))
+            {
+                thread_id
+            } else {
+                let change_id = ChangeLogBuilder::with_next_change_id(change_ids)?.change_id;
+                let mut batch = BatchBuilder::new();
+                batch
+                    .with_change_id(change_id)
+                    .with_account_id(account_id)
+                    .with_collection(index.thread_collection)
+                    .create_document()
+                    .log(LogInsert());
+                let thread_id = self.write(batch.build()).await?.first_document_id()?;
+                created_thread_ids.push(thread_id);
+                thread_id
+            };
+            used_thread_ids.insert(thread_id);
+            for document_id in ids {
+                let old_thread_id = thread_ids.get(&document_id).copied();
+                if old_thread_id != Some(thread_id) {
+                    moves.push((document_id, old_thread_id, thread_id));
+                }
+            }
+        }
+
+        // Move messages to their new threads. A batch failing on a message that
+        // was re-threaded concurrently is retried one message at a time.
+        let mut reassigned = 0;
+        for moves in moves.chunks(MAX_BATCH_SIZE) {
+            match self
+                .write(index.move_batch(account_id, moves, change_ids)?.build())
+                .await
+            {
+                Ok(_) => {
+                    reassigned += moves.len() as u64;
+                }
+                Err(crate::Error::AssertValueFailed) => {
+                    for thread_move in moves {
+                        match self
+                            .write(
+                                index
+                                    .move_batch(
+                                        account_id,
+                                        std::slice::from_ref(thread_move),
+                                        change_ids,
+                                    )?
+                                    .build(),
+                            )
+                            .await
+                        {
+                            Ok(_) => {
+                                reassigned += 1;
+                            }
+                            Err(crate::Error::AssertValueFailed) => {}
+                            Err(err) => return Err(err),
+                        }
+                    }
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        // Delete threads left without messages
+        let mut empty_thread_ids = Vec::new();
+        for thread_id in thread_ids
+            .values()
+            .copied()
+            .filter(|thread_id| used_thread_ids.insert(*thread_id))
+            .chain(created_thread_ids)
+        {
+            if self
+                .get_bitmap(BitmapKey::tag(
+                    account_id,
+                    index.collection,
+                    index.thread_field,
+                    thread_id,
+                ))
+                .await?
+                .map_or(true, |members| members.is_empty())
+            {
+                empty_thread_ids.push(thread_id);
+            }
+        }
+        for thread_ids in empty_thread_ids.chunks(MAX_BATCH_SIZE) {
+            let mut changes = ChangeLogBuilder::with_next_change_id(change_ids)?;
+            let mut batch = BatchBuilder::new();
+            batch
+                .with_account_id(account_id)
+                .with_collection(index.thread_collection);
+            for thread_id in thread_ids {
+                batch.delete_document(*thread_id);
+                changes.log_delete(index.thread_collection, *thread_id);
+            }
+            batch.custom(changes);
+            self.write(batch.build()).await?;
+        }
+
+        Ok(reassigned)
+    }
+
+    async fn iterate_index(
+        &self,
+        account_id: u32,
+        collection: u8,
+        field: u8,
+        mut cb: impl FnMut(&[u8], u32) + Sync + Send,
+    ) -> crate::Result<()> {
+        self.iterate(
+            IterateParams::new(
+                IndexKeyPrefix {
+                    account_id,
+                    collection,
+                    field,
+                },
+                IndexKeyPrefix {
+                    account_id,
+                    collection,
+                    field: field + 1,
+                },
+            )
+            .ascending()
+            .no_values(),
+            |key, _| {
+                let id_pos = key.len() - U32_LEN;
+                cb(
+                    key.get(IndexKeyPrefix::len()..id_pos).unwrap_or_default(),
+                    key.deserialize_be_u32(id_pos)?,
+                );
+                Ok(true)
+            },
+        )
+        .await
+    }
+}
+
+// Union-find over document ids
+#[derive(Default)]
+struct Threads {
+    parents: AHashMap<u32, u32>,
+}
+
+impl Threads {
+    fn find(&mut self, document_id: u32) -> u32 {
+        let mut root = document_id;
+        while let Some(&parent) = self.parents.get(&root) {
+            if parent == root {
+                break;
+            }
+            root = parent;
+        }
+
+        // Path compression
+        let mut id = document_id;
+        while id != root {
+            let parent = self.parents.insert(id, root).unwrap_or(root);
+            id = parent;
+        }
+
+        root
+    }
+
+    fn union(&mut self, a: u32, b: u32) {
+        let (a, b) = (self.find(a), self.find(b));
+        if a != b {
+            // The lowest document id is the root
+            self.parents
+                .insert(std::cmp::max(a, b), std::cmp::min(a, b));
+        }
+    }
+}
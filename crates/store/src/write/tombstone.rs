@@ -0,0 +1,101 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs Ltd <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::time::Duration;
+
+use roaring::RoaringBitmap;
+
+use crate::{BitmapKey, Serialize, Store, ValueKey};
+
+use super::{now, BatchBuilder, ValueClass, F_CLEAR, TOMBSTONE_FIELD};
+
+impl BatchBuilder {
+    /// Moves a document to the recycle bin. The document keeps its id and entries,
+    /// but is excluded from the results of filters, unless evaluated with
+    /// `FilterOptions::include_tombstones`, until it is restored with
+    /// `restore_document` or hard deleted by `Store::purge_tombstones`.
+    pub fn soft_delete_document(&mut self, document_id: u32) -> &mut Self {
+        self.update_document(document_id)
+            .tag(TOMBSTONE_FIELD, 0u32, 0)
+            .set(ValueClass::Tombstone, now().serialize())
+    }
+
+    pub fn restore_document(&mut self, document_id: u32) -> &mut Self {
+        self.update_document(document_id)
+            .tag(TOMBSTONE_FIELD, 0u32, F_CLEAR)
+            .clear(ValueClass::Tombstone)
+    }
+}
+
+impl Store {
+    /// Returns the soft deleted documents of a collection.
+    pub async fn get_tombstones(
+        &self,
+        account_id: u32,
+        collection: impl Into<u8> + Sync + Send,
+    ) -> crate::Result<RoaringBitmap> {
+        self.get_bitmap(BitmapKey::tag(
+            account_id,
+            collection,
+            TOMBSTONE_FIELD,
+            0u32,
+        ))
+        .await
+        .map(|tombstones| tombstones.unwrap_or_default())
+    }
+
+    /// Hard deletes the documents of a collection that were soft deleted more than
+    /// `retention` ago, removing all of their entries. Returns the ids of the purged
+    /// documents.
+    pub async fn purge_tombstones(
+        &self,
+        account_id: u32,
+        collection: impl Into<u8> + Sync + Send,
+        retention: Duration,
+    ) -> crate::Result<RoaringBitmap> {
+        let collection = collection.into();
+        let expires = now().saturating_sub(retention.as_secs());
+        let mut purged = RoaringBitmap::new();
+
+        for document_id in self.get_tombstones(account_id, collection).await? {
+            let deleted_at = self
+                .get_value::<u64>(ValueKey {
+                    account_id,
+                    collection,
+                    document_id,
+                    class: ValueClass::Tombstone,
+                })
+                .await?
+                .unwrap_or_default();
+            if deleted_at <= expires {
+                purged.insert(document_id);
+            }
+        }
+        if purged.is_empty() {
+            return Ok(purged);
+        }
+
+        // Values, bitmaps, blob links and the tombstone itself are removed with the entries
+        let mut blob_links = self
+            .document_blob_links(account_id, collection, &purged)
+            .await?;
+        for document_id in &purged {
+            let mut entries = self
+                .document_entries(account_id, collection, document_id)
+                .await?;
+            entries.blob_links = blob_links.remove(&document_id).unwrap_or_default();
+            let mut batch = BatchBuilder::new();
+            batch
+                .with_account_id(account_id)
+                .with_collection(collection)
+                .delete_document(document_id);
+            entries.clear(&mut batch);
+            self.write(batch.build()).await?;
+        }
+
+        Ok(purged)
+    }
+}
@@ -0,0 +1,405 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs Ltd <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use ahash::AHashMap;
+
+use crate::{
+    Deserialize, IterateParams, Store, ValueKey, SUBSPACE_ACL, SUBSPACE_BITMAP_ID,
+    SUBSPACE_BITMAP_TAG, SUBSPACE_BITMAP_TEXT, SUBSPACE_INDEXES, SUBSPACE_PROPERTY,
+    SUBSPACE_QUARANTINE, SUBSPACE_VECTORS, U32_LEN,
+};
+
+use super::{
+    key::{DeserializeBigEndian, KeySerializer},
+    AnyKey, Batch, BatchBuilder, BitmapClass, DirectoryClass, MaybeDynamicValue, Operation,
+    ValueClass, ValueOp,
+};
+
+// Subspaces whose keys are counted towards the usage of an account
+pub const USAGE_SUBSPACES: [u8; 8] = [
+    SUBSPACE_PROPERTY,
+    SUBSPACE_INDEXES,
+    SUBSPACE_BITMAP_ID,
+    SUBSPACE_BITMAP_TAG,
+    SUBSPACE_BITMAP_TEXT,
+    SUBSPACE_ACL,
+    SUBSPACE_VECTORS,
+    SUBSPACE_QUARANTINE,
+];
+
+// Counter holding the sum of all the usage counters and the account quota
+pub const USAGE_TOTAL: u8 = 0;
+
+/// Storage limits of an account in bytes, enforced against the sum of its usage
+/// counters and the blob bytes recorded by the account quota. Blobs reserved for
+/// uploads are not included.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Quota {
+    pub limit: u64,
+    /// Usage at which `AssignedIds::soft_limit_reached` reports the account
+    pub soft_limit: Option<u64>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct UsageBreakdown {
+    /// Bytes of keys and values stored per subspace
+    pub subspaces: AHashMap<u8, u64>,
+    /// Bytes of linked blobs, as recorded by the account quota, and of blobs
+    /// reserved for uploads
+    pub blob_bytes: u64,
+}
+
+impl Store {
+    /// Returns the bytes used by an account, read from the counters kept by
+    /// the write path. Keys removed by raw key, such as with `delete_range`, are
+    /// only accounted for once `recalculate_usage` runs, which `purge_store` does
+    /// periodically.
+    pub async fn account_usage(&self, account_id: u32) -> crate::Result<UsageBreakdown> {
+        let mut usage = UsageBreakdown::default();
+        for subspace in USAGE_SUBSPACES {
+            let bytes = self
+                .get_counter(ValueKey::from(usage_class(account_id, subspace)))
+                .await?;
+            if bytes > 0 {
+                usage.subspaces.insert(subspace, bytes as u64);
+            }
+        }
+
+        let quota = self
+            .get_counter(ValueKey::from(ValueClass::Directory(
+                DirectoryClass::UsedQuota(account_id),
+            )))
+            .await?;
+        usage.blob_bytes =
+            std::cmp::max(quota, 0) as u64 + self.blob_quota(account_id).await?.bytes as u64;
+
+        Ok(usage)
+    }
+
+    /// Rebuilds the usage counters of an account by scanning its keys. Writes
+    /// made while the scan runs may be counted twice or not at all until the
+    /// next recalculation.
+    pub async fn recalculate_usage(&self, account_id: u32) -> crate::Result<UsageBreakdown> {
+        let acl_bytes = self
+            .acl_usage()
+            .await?
+            .get(&account_id)
+            .copied()
+            .unwrap_or(0);
+        self.recalculate_usage_(account_id, acl_bytes).await
+    }
+
+    /// Recalculates the usage of every account with usage counters, see
+    /// `recalculate_usage`.
+    pub async fn recalculate_all_usage(&self) -> crate::Result<()> {
+        let mut account_ids = Vec::new();
+        self.iterate(
+            IterateParams::new(
+                ValueKey::from(usage_class(0, 0)),
+                ValueKey::from(usage_class(u32::MAX, u8::MAX)),
+            )
+            .no_values(),
+            |key, _| {
+                if key.len() == U32_LEN + 2 && key[U32_LEN + 1] == USAGE_TOTAL {
+                    account_ids.push(key.deserialize_be_u32(1)?);
+                }
+                Ok(true)
+            },
+        )
+        .await?;
+
+        if !account_ids.is_empty() {
+            let acl_usage = self.acl_usage().await?;
+            for account_id in account_ids {
+                self.recalculate_usage_(
+                    account_id,
+                    acl_usage.get(&account_id).copied().unwrap_or(0),
+                )
+                .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn recalculate_usage_(
+        &self,
+        account_id: u32,
+        acl_bytes: u64,
+    ) -> crate::Result<UsageBreakdown> {
+        let mut batch = BatchBuilder::new();
+        let mut total = std::cmp::max(
+            self.get_counter(ValueKey::from(ValueClass::Directory(
+                DirectoryClass::UsedQuota(account_id),
+            )))
+            .await?,
+            0,
+        );
+        for subspace in USAGE_SUBSPACES {
+            let bytes = if subspace != SUBSPACE_ACL {
+                let begin = KeySerializer::new(U32_LEN).write(account_id).finalize();
+                let mut end = begin.clone();
+                end.extend_from_slice(&[u8::MAX; 10]);
+                self.range_bytes(subspace, begin, end).await?
+            } else {
+                acl_bytes
+            };
+
+            self.reset_counter(&mut batch, usage_class(account_id, subspace), bytes as i64)
+                .await?;
+            total += bytes as i64;
+        }
+        self.reset_counter(&mut batch, usage_class(account_id, USAGE_TOTAL), total)
+            .await?;
+        if !batch.is_empty() {
+            self.write(batch.build()).await?;
+        }
+
+        self.account_usage(account_id).await
+    }
+
+    // ACLs are keyed by the grantee followed by the owner, they are counted
+    // towards the owner in a single scan of the subspace
+    async fn acl_usage(&self) -> crate::Result<AHashMap<u32, u64>> {
+        let mut usage: AHashMap<u32, u64> = AHashMap::new();
+        self.iterate(
+            IterateParams::new(
+                AnyKey {
+                    subspace: SUBSPACE_ACL,
+                    key: vec![0u8],
+                },
+                AnyKey {
+                    subspace: SUBSPACE_ACL,
+                    key: vec![u8::MAX; 10],
+                },
+            ),
+            |key, value| {
+                if key.len() >= U32_LEN * 2 {
+                    *usage.entry(key.deserialize_be_u32(U32_LEN)?).or_default() +=
+                        (key.len() + value.len()) as u64;
+                }
+                Ok(true)
+            },
+        )
+        .await?;
+
+        Ok(usage)
+    }
+
+    async fn reset_counter(
+        &self,
+        batch: &mut BatchBuilder,
+        class: ValueClass<u32>,
+        value: i64,
+    ) -> crate::Result<()> {
+        let current = self.get_counter(ValueKey::from(class.clone())).await?;
+        if value != current {
+            batch.add(class.into_dynamic(), value - current);
+        }
+        Ok(())
+    }
+
+    // Accounts whose usage reached their soft limit
+    pub(crate) async fn soft_limits_reached(
+        &self,
+        soft_limits: Vec<(u32, u64)>,
+    ) -> crate::Result<Vec<u32>> {
+        let mut reached = Vec::new();
+        for (account_id, soft_limit) in soft_limits {
+            if self
+                .get_counter(ValueKey::from(usage_class(account_id, USAGE_TOTAL)))
+                .await?
+                >= soft_limit as i64
+            {
+                reached.push(account_id);
+            }
+        }
+        Ok(reached)
+    }
+}
+
+impl Store {
+    // Appends the changes in usage of the accounts the batch writes to, checking
+    // their quotas. Index entries and bitmaps are counted by their key size,
+    // values replacing or clearing stored ones are read first so that only the
+    // difference is counted. Returns the soft limits to check once the batch
+    // is written.
+    pub(crate) async fn track_usage(&self, batch: &mut Batch) -> crate::Result<Vec<(u32, u64)>> {
+        let mut account_id = u32::MAX;
+        let mut collection = u8::MAX;
+        let mut document_id = u32::MAX;
+        let mut is_new = false;
+        let mut deltas: AHashMap<(u32, u8), i64> = AHashMap::new();
+        let mut totals: AHashMap<u32, i64> = AHashMap::new();
+        let mut values: Vec<(u32, u8, Vec<u8>, i64, bool)> = Vec::new();
+
+        for op in &batch.ops {
+            match op {
+                Operation::AccountId {
+                    account_id: account_id_,
+                } => {
+                    account_id = *account_id_;
+                    is_new = false;
+                }
+                Operation::Collection {
+                    collection: collection_,
+                } => {
+                    collection = *collection_;
+                    is_new = false;
+                }
+                Operation::DocumentId {
+                    document_id: document_id_,
+                } => {
+                    document_id = *document_id_;
+                    is_new = false;
+                }
+                Operation::Bitmap { class, set } if account_id != u32::MAX => {
+                    if matches!(class, BitmapClass::DocumentIds) {
+                        is_new = *set;
+                    }
+                    let size = class
+                        .serialize(account_id, collection, document_id, 0, None)
+                        .len() as i64;
+                    *deltas.entry((account_id, class.subspace())).or_default() +=
+                        if *set { size } else { -size };
+                }
+                Operation::Index { key, set, .. } if account_id != u32::MAX => {
+                    let size = (U32_LEN * 2 + 2 + key.len()) as i64;
+                    *deltas.entry((account_id, SUBSPACE_INDEXES)).or_default() +=
+                        if *set { size } else { -size };
+                }
+                Operation::Value {
+                    class:
+                        class @ (ValueClass::Property(_)
+                        | ValueClass::ContentLength
+                        | ValueClass::Flags
+                        | ValueClass::Tombstone
+                        | ValueClass::Acl(_)
+                        | ValueClass::Vector(_)
+                        | ValueClass::Quarantine),
+                    op: op @ (ValueOp::Set(_) | ValueOp::Clear),
+                } if account_id != u32::MAX && !class.is_counter(collection) => {
+                    let key = class.serialize(account_id, collection, document_id, 0, None);
+                    // Values serialized at write time are counted by their key only
+                    let new_size = match op {
+                        ValueOp::Set(MaybeDynamicValue::Static(value)) => key.len() + value.len(),
+                        ValueOp::Set(MaybeDynamicValue::Dynamic(_)) => key.len(),
+                        _ => 0,
+                    } as i64;
+                    values.push((
+                        account_id,
+                        class.subspace(collection),
+                        key,
+                        new_size,
+                        is_new,
+                    ));
+                }
+                Operation::Value {
+                    class: ValueClass::Directory(DirectoryClass::UsedQuota(quota_id)),
+                    op: ValueOp::AtomicAdd(by) | ValueOp::AddAndGet(by),
+                } => {
+                    *totals.entry(*quota_id).or_default() += *by;
+                }
+                _ => {}
+            }
+        }
+
+        // Values of documents created by the batch don't replace stored ones
+        let mut sizes: AHashMap<(u8, Vec<u8>), i64> = AHashMap::new();
+        for (account_id, subspace, key, new_size, is_new) in values {
+            let old_size = match sizes.get(&(subspace, key.clone())) {
+                Some(old_size) => *old_size,
+                None if !is_new => self
+                    .get_value::<ValueSize>(AnyKey {
+                        subspace,
+                        key: key.as_slice(),
+                    })
+                    .await?
+                    .map_or(0, |size| (key.len() + size.0) as i64),
+                None => 0,
+            };
+            *deltas.entry((account_id, subspace)).or_default() += new_size - old_size;
+            sizes.insert((subspace, key), new_size);
+        }
+
+        for ((account_id, subspace), delta) in deltas {
+            if delta != 0 {
+                batch.ops.push(Operation::Value {
+                    class: usage_class(account_id, subspace).into_dynamic(),
+                    op: ValueOp::AtomicAdd(delta),
+                });
+                *totals.entry(account_id).or_default() += delta;
+            }
+        }
+
+        // Quotas are checked against the total within the same transaction
+        let mut soft_limits = Vec::new();
+        for (account_id, delta) in totals {
+            if delta == 0 {
+                continue;
+            }
+            let quota = batch
+                .quotas
+                .iter()
+                .find(|(id, _)| *id == account_id)
+                .map(|(_, quota)| *quota);
+            batch.ops.push(Operation::Value {
+                class: usage_class(account_id, USAGE_TOTAL).into_dynamic(),
+                op: if let Some(quota) = quota {
+                    ValueOp::AddWithLimit {
+                        by: delta,
+                        limit: std::cmp::min(quota.limit, i64::MAX as u64) as i64,
+                    }
+                } else {
+                    ValueOp::AtomicAdd(delta)
+                },
+            });
+            if let Some(soft_limit) = quota.and_then(|quota| quota.soft_limit) {
+                if delta > 0 {
+                    soft_limits.push((account_id, soft_limit));
+                }
+            }
+        }
+
+        Ok(soft_limits)
+    }
+}
+
+impl Quota {
+    pub fn new(limit: u64) -> Self {
+        Quota {
+            limit,
+            soft_limit: None,
+        }
+    }
+
+    pub fn with_soft_limit(mut self, soft_limit: u64) -> Self {
+        self.soft_limit = Some(soft_limit);
+        self
+    }
+}
+
+impl UsageBreakdown {
+    pub fn total(&self) -> u64 {
+        self.subspaces.values().sum::<u64>() + self.blob_bytes
+    }
+}
+
+fn usage_class(account_id: u32, subspace: u8) -> ValueClass<u32> {
+    ValueClass::Directory(DirectoryClass::UsedStorage {
+        account_id,
+        subspace,
+    })
+}
+
+// Length of a stored value, without copying it
+struct ValueSize(usize);
+
+impl Deserialize for ValueSize {
+    fn deserialize(bytes: &[u8]) -> crate::Result<Self> {
+        Ok(ValueSize(bytes.len()))
+    }
+}
@@ -0,0 +1,137 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs Ltd <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use crate::{Deserialize, Serialize, Store, ValueKey};
+
+use super::{assert::HashedValue, BatchBuilder, ValueClass};
+
+/// A value format that evolves over time. Values are stored with a leading
+/// version byte so that values written by older releases can still be decoded.
+/// Version 0 identifies legacy values stored without a version byte, so versions
+/// start at 1.
+pub trait Versionable: Sized + Sync + Send {
+    /// Version written along with newly serialized values.
+    const VERSION: u8;
+
+    /// Serializes the value in the current format.
+    fn serialize_current(&self) -> Vec<u8>;
+
+    /// Decodes a value stored with `version`, which is never greater than
+    /// `VERSION`, converting it to the current format if needed.
+    fn deserialize_version(version: u8, bytes: &[u8]) -> crate::Result<Self>;
+
+    /// Returns whether a stored value predates versioning and has no version
+    /// byte, for instance by its length. Formats that were always versioned
+    /// have no legacy values.
+    fn is_legacy(_bytes: &[u8]) -> bool {
+        false
+    }
+
+    /// Decodes a legacy value, see `is_legacy`.
+    fn deserialize_legacy(_bytes: &[u8]) -> crate::Result<Self> {
+        Err(crate::Error::InternalError(
+            "Legacy values are not supported".to_string(),
+        ))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Versioned<T: Versionable> {
+    pub inner: T,
+    /// Version the value was stored with
+    pub version: u8,
+}
+
+impl<T: Versionable> Versioned<T> {
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner,
+            version: T::VERSION,
+        }
+    }
+
+    /// Returns whether the value was stored with an older version of its format.
+    pub fn is_outdated(&self) -> bool {
+        self.version < T::VERSION
+    }
+}
+
+impl<T: Versionable> Serialize for &Versioned<T> {
+    fn serialize(self) -> Vec<u8> {
+        let mut bytes = vec![T::VERSION];
+        bytes.extend_from_slice(&self.inner.serialize_current());
+        bytes
+    }
+}
+
+impl<T: Versionable> Serialize for Versioned<T> {
+    fn serialize(self) -> Vec<u8> {
+        (&self).serialize()
+    }
+}
+
+impl<T: Versionable> Deserialize for Versioned<T> {
+    fn deserialize(bytes: &[u8]) -> crate::Result<Self> {
+        if T::is_legacy(bytes) {
+            return Ok(Versioned {
+                inner: T::deserialize_legacy(bytes)?,
+                version: 0,
+            });
+        }
+
+        match bytes.split_first() {
+            Some((&version, bytes)) if (1..=T::VERSION).contains(&version) => Ok(Versioned {
+                inner: T::deserialize_version(version, bytes)?,
+                version,
+            }),
+            Some((&version, _)) => Err(crate::Error::InternalError(format!(
+                "Unsupported value version {version}, expected 1 to {}",
+                T::VERSION
+            ))),
+            None => Err(crate::Error::InternalError(
+                "Missing value version".to_string(),
+            )),
+        }
+    }
+}
+
+impl Store {
+    /// Reads a versioned value and, if it was stored with an older version, writes
+    /// it back in the current format. The upgrade is skipped if the value changed
+    /// since it was read. The upgraded value is returned even when it can't be
+    /// written, such as in read-only mode, and is upgraded again on the next read.
+    pub async fn get_versioned<T: Versionable + 'static>(
+        &self,
+        key: ValueKey<ValueClass<u32>>,
+    ) -> crate::Result<Option<T>> {
+        let value = if let Some(value) = self
+            .get_value::<HashedValue<Versioned<T>>>(key.clone())
+            .await?
+        {
+            value
+        } else {
+            return Ok(None);
+        };
+
+        if value.inner.is_outdated() {
+            let mut batch = BatchBuilder::new();
+            batch
+                .with_account_id(key.account_id)
+                .with_collection(key.collection)
+                .update_document(key.document_id)
+                .assert_value(key.class.clone().into_dynamic(), &value)
+                .set(key.class.into_dynamic(), (&value.inner).serialize());
+            match self.write(batch.build()).await {
+                Ok(_) | Err(crate::Error::AssertValueFailed | crate::Error::ReadOnly) => (),
+                Err(err) => {
+                    tracing::debug!("Failed to upgrade versioned value: {err}");
+                }
+            }
+        }
+
+        Ok(Some(value.inner.inner))
+    }
+}
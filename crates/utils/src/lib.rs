@@ -46,6 +46,12 @@ impl From<&[u8]> for BlobHash {
     }
 }
 
+impl From<blake3::Hash> for BlobHash {
+    fn from(value: blake3::Hash) -> Self {
+        BlobHash(value.into())
+    }
+}
+
 impl From<Vec<u8>> for BlobHash {
     fn from(value: Vec<u8>) -> Self {
         value.as_slice().into()
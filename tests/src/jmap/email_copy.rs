@@ -4,8 +4,10 @@
  * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
  */
 
+use jmap::email::intent::{MessageMoveIntent, MessageMoveStep};
 use jmap_client::mailbox::Role;
 use jmap_proto::types::id::Id;
+use utils::BlobHash;
 
 use crate::jmap::{assert_is_empty, mailbox::destroy_all_mailboxes};
 
@@ -100,6 +102,92 @@ pub async fn test(params: &mut JMAPTest) {
         .unwrap()
         .is_none());
 
+    // Interrupted moves only remove the messages whose recorded copy exists
+    let raw_message = concat!(
+        "From: bill@example.com\r\n",
+        "To: jdoe@example.com\r\n",
+        "Subject: Intent Report\r\n",
+        "\r\n",
+        "Did you get the memo?"
+    );
+    let mut src_ids = Vec::new();
+    for _ in 0..2 {
+        src_ids.push(
+            params
+                .client
+                .set_default_account_id(Id::new(1).to_string())
+                .email_import(
+                    raw_message.as_bytes().to_vec(),
+                    [&ac1_mailbox_id],
+                    None::<Vec<&str>>,
+                    None,
+                )
+                .await
+                .unwrap()
+                .take_id(),
+        );
+    }
+    let mut request = params.client.build();
+    request
+        .copy_email(Id::new(1).to_string())
+        .create(&src_ids[0])
+        .mailbox_id(&ac2_mailbox_id, true);
+    let dest_id = request
+        .send()
+        .await
+        .unwrap()
+        .method_response_by_pos(0)
+        .unwrap_copy_email()
+        .unwrap()
+        .created(&src_ids[0])
+        .unwrap()
+        .take_id();
+
+    // Both messages share the blob of the copy, only the first one was copied
+    let document_id = |id: &str| Id::from_bytes(id.as_bytes()).unwrap().document_id();
+    let blob_hash = BlobHash::from(raw_message.as_bytes());
+    server
+        .email_move_intent_begin(&MessageMoveIntent {
+            src_account_id: 1,
+            src_mailbox_id: document_id(&ac1_mailbox_id),
+            dest_account_id: 2,
+            messages: vec![
+                MessageMoveStep {
+                    document_id: document_id(&src_ids[0]),
+                    blob_hash: blob_hash.clone(),
+                    dest_document_id: Some(document_id(&dest_id)),
+                },
+                MessageMoveStep {
+                    document_id: document_id(&src_ids[1]),
+                    blob_hash,
+                    dest_document_id: None,
+                },
+            ],
+        })
+        .await
+        .unwrap();
+    server.recover_intents().await;
+    assert!(server
+        .core
+        .storage
+        .data
+        .pending_intents()
+        .await
+        .unwrap()
+        .is_empty());
+    params.client.set_default_account_id(Id::new(1).to_string());
+    for (id, is_moved) in [(&src_ids[0], true), (&src_ids[1], false)] {
+        assert_eq!(
+            params
+                .client
+                .email_get(id, None::<Vec<_>>)
+                .await
+                .unwrap()
+                .is_none(),
+            is_moved
+        );
+    }
+
     // Empty store
     destroy_all_mailboxes(params).await;
     params.client.set_default_account_id(Id::new(2).to_string());
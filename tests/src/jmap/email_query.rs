@@ -77,6 +77,24 @@ pub async fn test(params: &mut JMAPTest, insert: bool) {
 
         // Wait for indexing to complete
         wait_for_index(&server).await;
+
+        // Rebuilding the index should not change the query results
+        assert_eq!(
+            server
+                .reindex_fts(account_id, Collection::Email, 0)
+                .await
+                .unwrap(),
+            server
+                .get_document_ids(account_id, Collection::Email)
+                .await
+                .unwrap()
+                .unwrap()
+                .len()
+        );
+        assert!(server
+            .reindex_fts(account_id, Collection::Mailbox, 0)
+            .await
+            .is_err());
     }
 
     println!("Running JMAP Mail query tests...");
@@ -6,7 +6,8 @@
 
 use crate::jmap::{assert_is_empty, mailbox::destroy_all_mailboxes};
 use jmap_client::mailbox::Role;
-use jmap_proto::types::id::Id;
+use jmap_proto::types::{collection::Collection, id::Id, property::Property};
+use store::query::Filter;
 
 use super::JMAPTest;
 
@@ -51,6 +52,27 @@ pub async fn test(params: &mut JMAPTest) {
         expected_result
     );
 
+    // Thread members can be fetched from the store
+    assert_eq!(
+        server
+            .core
+            .storage
+            .data
+            .filter(
+                1,
+                Collection::Email,
+                vec![Filter::in_thread(
+                    Property::ThreadId,
+                    Id::from_bytes(thread_id.as_bytes()).unwrap().document_id()
+                )],
+            )
+            .await
+            .unwrap()
+            .results
+            .len(),
+        expected_result.len() as u64
+    );
+
     destroy_all_mailboxes(params).await;
     assert_is_empty(server).await;
 }
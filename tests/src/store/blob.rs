@@ -4,14 +4,20 @@
  * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
  */
 
+use std::collections::HashMap;
+
 use ahash::AHashMap;
 use store::{
-    write::{blob::BlobQuota, now, BatchBuilder, BlobOp},
-    BlobClass, BlobStore, Serialize, Stores,
+    backend::BlobKeyEncoding,
+    write::{
+        blob::{BlobQuota, DanglingBlobLink},
+        now, BatchBuilder, BlobOp,
+    },
+    BlobBackend, BlobClass, BlobStore, ReadAhead, Serialize, Stores,
 };
 use utils::{config::Config, BlobHash};
 
-use crate::store::{TempDir, CONFIG};
+use crate::store::{conformance::blob_conformance_tests, TempDir, CONFIG};
 
 #[tokio::test]
 pub async fn blob_tests() {
@@ -20,9 +26,222 @@ pub async fn blob_tests() {
         Config::new(CONFIG.replace("{TMP}", temp_dir.path.as_path().to_str().unwrap())).unwrap();
     let stores = Stores::parse_all(&mut config).await;
 
+    // Blob keys are mapped to object names using the configured encoding
+    println!("Testing blob key mapping...");
+    let key = [0x01u8, 0xab, 0xff];
+    assert_eq!(BlobKeyEncoding::Hex.encode(None, &key), "01abff");
+    assert_eq!(
+        BlobKeyEncoding::Hex.encode(Some("tmp/"), &key),
+        "tmp/01abff"
+    );
+    assert_eq!(
+        BlobKeyEncoding::Base32.encode(Some("tmp/"), &key),
+        format!("tmp/{}", BlobKeyEncoding::Base32.encode(None, &key))
+    );
+    assert_ne!(
+        BlobKeyEncoding::Base32.encode(None, &key),
+        BlobKeyEncoding::Hex.encode(None, &key)
+    );
+    let fs = stores.blob_stores.get("fs").unwrap();
+    let hash = BlobHash::from(b"key mapping".as_slice());
+    fs.put_blob(hash.as_ref(), b"key mapping").await.unwrap();
+    let mut path = temp_dir.path.clone();
+    for byte in hash.as_slice().iter().take(2) {
+        path.push(format!("{:x}", byte));
+    }
+    path.push(BlobKeyEncoding::Hex.encode(None, hash.as_ref()));
+    assert!(path.exists(), "{path:?}");
+    assert!(fs.delete_blob(hash.as_ref()).await.unwrap());
+
     for (store_id, blob_store) in &stores.blob_stores {
         println!("Testing blob store {}...", store_id);
+        blob_conformance_tests(blob_store.clone()).await;
         test_store(blob_store.clone()).await;
+
+        // Both test blobs were written once
+        let stats = blob_store.reset_compression_stats();
+        assert!(stats.original > 50 * 1024 * 1024, "{stats:?}");
+        assert!(stats.stored > 0 && stats.ratio > 0.0, "{stats:?}");
+        assert_eq!(blob_store.compression_stats().original, 0);
+    }
+
+    // Test tiered blob store
+    if let BlobBackend::Tiered(tiered) = &stores.blob_stores.get("tiered").unwrap().backend {
+        println!("Testing tiered blob store migration...");
+        let hash = BlobHash::from(b"tiered".as_slice());
+        tiered.put_blob(hash.as_ref(), b"tiered").await.unwrap();
+        assert!(tiered
+            .hot
+            .get_blob(hash.as_ref(), 0..usize::MAX)
+            .await
+            .unwrap()
+            .is_some());
+
+        // Move blob to the cold tier
+        assert!(tiered.migrate_to_cold(hash.as_ref()).await.unwrap());
+        assert!(!tiered.migrate_to_cold(hash.as_ref()).await.unwrap());
+        assert!(tiered
+            .hot
+            .get_blob(hash.as_ref(), 0..usize::MAX)
+            .await
+            .unwrap()
+            .is_none());
+        assert_eq!(
+            tiered
+                .get_blob(hash.as_ref(), 0..usize::MAX)
+                .await
+                .unwrap()
+                .unwrap(),
+            b"tiered"
+        );
+
+        // Deleting should remove the blob from both tiers
+        assert!(tiered.delete_blob(hash.as_ref()).await.unwrap());
+        assert!(tiered
+            .cold
+            .get_blob(hash.as_ref(), 0..usize::MAX)
+            .await
+            .unwrap()
+            .is_none());
+    } else {
+        panic!("Expected tiered blob store");
+    }
+
+    // Range reads spanning several chunks
+    #[cfg(feature = "foundationdb")]
+    {
+        println!("Testing chunked blob range reads...");
+        let fdb = stores.blob_stores.get("foundationdb").unwrap();
+        let data = (0..35000u32).map(|n| (n % 251) as u8).collect::<Vec<_>>();
+        let hash = BlobHash::from(data.as_slice());
+        fdb.put_blob(hash.as_ref(), &data).await.unwrap();
+        for range in [
+            0..usize::MAX,
+            0..10000,
+            9999..10001,
+            10000..20000,
+            5000..32000,
+            29999..30000,
+            34000..40000,
+        ] {
+            assert_eq!(
+                fdb.get_blob(hash.as_ref(), range.clone())
+                    .await
+                    .unwrap()
+                    .unwrap(),
+                &data[range.start..std::cmp::min(range.end, data.len())],
+                "{range:?}"
+            );
+        }
+
+        // Rewriting a blob with fewer chunks leaves none of the old ones behind
+        fdb.put_blob(hash.as_ref(), &data[..15000]).await.unwrap();
+        assert_eq!(
+            fdb.get_blob(hash.as_ref(), 0..usize::MAX)
+                .await
+                .unwrap()
+                .unwrap(),
+            &data[..15000]
+        );
+        assert!(fdb.delete_blob(hash.as_ref()).await.unwrap());
+    }
+
+    // Test memory-mapped reads
+    #[cfg(feature = "fs-mmap")]
+    {
+        println!("Testing memory-mapped blob reads...");
+        let fs = stores.blob_stores.get("fs").unwrap();
+        assert!(matches!(fs.backend, BlobBackend::Fs(_)));
+        let data = (0..10000u32).map(|n| (n % 251) as u8).collect::<Vec<_>>();
+        let hash = BlobHash::from(data.as_slice());
+        fs.put_blob(hash.as_ref(), &data).await.unwrap();
+        for range in [0..usize::MAX, 0..100, 5000..6000, 4097..10000, 9999..20000] {
+            assert_eq!(
+                fs.get_blob_mapped(hash.as_ref(), range.clone())
+                    .await
+                    .unwrap()
+                    .unwrap()
+                    .as_ref(),
+                &data[range.start..std::cmp::min(range.end, data.len())],
+                "{range:?}"
+            );
+        }
+        assert!(fs
+            .get_blob_mapped(hash.as_ref(), 20000..30000)
+            .await
+            .unwrap()
+            .unwrap()
+            .is_empty());
+
+        // Mappings remain valid after the blob is deleted
+        let mapped = fs
+            .get_blob_mapped(hash.as_ref(), 0..usize::MAX)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(fs.delete_blob(hash.as_ref()).await.unwrap());
+        assert_eq!(mapped.as_ref(), data.as_slice());
+        assert!(fs
+            .get_blob_mapped(hash.as_ref(), 0..usize::MAX)
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    // Test Azure key prefixes
+    #[cfg(feature = "azure")]
+    {
+        println!("Testing Azure key prefixes...");
+        let azure = stores.blob_stores.get("azure").unwrap();
+        assert!(matches!(azure.backend, BlobBackend::Azure(_)));
+        let mut config = Config::new(
+            r#"[store."azure"]
+type = "azure"
+storage-account = "devstoreaccount1"
+access-key = "Eby8vdM02xNOcqFlqUwJPLlmEtlCDXJ1OUzFT50uSRZ6IFsuFq2UVErCz4I6tq/K1SZFPTOtr/KBHBeksoGMGw=="
+endpoint = "http://127.0.0.1:10000/devstoreaccount1"
+container = "tmp"
+"#,
+        )
+        .unwrap();
+        let unprefixed = Stores::parse_all(&mut config)
+            .await
+            .blob_stores
+            .remove("azure")
+            .unwrap();
+
+        // Blobs written under a prefix are not visible without it
+        let hash = BlobHash::from(b"azure".as_slice());
+        azure.put_blob(hash.as_ref(), b"prefixed").await.unwrap();
+        assert!(!unprefixed.has_blob(hash.as_ref()).await.unwrap());
+        unprefixed
+            .put_blob(hash.as_ref(), b"unprefixed")
+            .await
+            .unwrap();
+        for (store, expected) in [
+            (azure, b"prefixed".as_slice()),
+            (&unprefixed, b"unprefixed"),
+        ] {
+            assert_eq!(
+                store
+                    .get_blob(hash.as_ref(), 0..usize::MAX)
+                    .await
+                    .unwrap()
+                    .unwrap(),
+                expected
+            );
+
+            // Reads past the end of the blob return no data
+            assert!(store
+                .get_blob(hash.as_ref(), 100..200)
+                .await
+                .unwrap()
+                .unwrap()
+                .is_empty());
+        }
+        assert!(azure.delete_blob(hash.as_ref()).await.unwrap());
+        assert!(unprefixed.has_blob(hash.as_ref()).await.unwrap());
+        assert!(unprefixed.delete_blob(hash.as_ref()).await.unwrap());
     }
 
     for (store_id, store) in stores.stores {
@@ -399,6 +618,42 @@ pub async fn blob_tests() {
                     ^ ct
             );
         }
+
+        // Detect and remove links to blobs deleted out of band
+        assert_eq!(
+            store.repair_blob_refs(&blob_store, false).await.unwrap(),
+            vec![]
+        );
+        let hash = BlobHash::from(b"456".as_slice());
+        let blob_class = BlobClass::Linked {
+            account_id: 0,
+            collection: 0,
+            document_id: 1,
+        };
+        let dangling = vec![DanglingBlobLink {
+            hash: hash.clone(),
+            account_id: 0,
+            collection: 0,
+            document_id: 1,
+        }];
+        assert!(blob_store.has_blob(hash.as_ref()).await.unwrap());
+        assert!(blob_store.delete_blob(hash.as_ref()).await.unwrap());
+        assert!(!blob_store.has_blob(hash.as_ref()).await.unwrap());
+        assert_eq!(
+            store.repair_blob_refs(&blob_store, false).await.unwrap(),
+            dangling
+        );
+        assert!(store.blob_has_access(&hash, &blob_class).await.unwrap());
+        assert_eq!(
+            store.repair_blob_refs(&blob_store, true).await.unwrap(),
+            dangling
+        );
+        assert!(!store.blob_has_access(&hash, &blob_class).await.unwrap());
+        assert!(!store.blob_exists(&hash).await.unwrap());
+        assert_eq!(
+            store.repair_blob_refs(&blob_store, false).await.unwrap(),
+            vec![]
+        );
     }
     temp_dir.delete();
 }
@@ -438,6 +693,79 @@ async fn test_store(store: BlobStore) {
         .unwrap()
         .is_none());
 
+    // Writes are rejected while the store is read-only, reads are not
+    store.put_blob(hash.as_slice(), DATA).await.unwrap();
+    store.set_read_only(true);
+    assert!(store.is_read_only());
+    assert!(matches!(
+        store.put_blob(b"read-only", DATA).await,
+        Err(store::Error::ReadOnly)
+    ));
+    assert!(matches!(
+        store.delete_blob(hash.as_slice()).await,
+        Err(store::Error::ReadOnly)
+    ));
+    assert_eq!(
+        store
+            .get_blob(hash.as_slice(), 0..usize::MAX)
+            .await
+            .unwrap()
+            .as_deref(),
+        Some(DATA)
+    );
+    store.set_read_only(false);
+    assert!(store.delete_blob(hash.as_slice()).await.unwrap());
+
+    // Conditional writes do not overwrite existing blobs
+    assert!(store
+        .put_blob_if_absent(hash.as_slice(), DATA)
+        .await
+        .unwrap());
+    assert!(!store
+        .put_blob_if_absent(hash.as_slice(), b"overwritten")
+        .await
+        .unwrap());
+    assert_eq!(
+        store
+            .get_blob(hash.as_slice(), 0..usize::MAX)
+            .await
+            .unwrap()
+            .unwrap(),
+        DATA
+    );
+    assert!(store.delete_blob(hash.as_slice()).await.unwrap());
+
+    // Blob metadata
+    let meta = HashMap::from_iter([
+        ("content-type".to_string(), "text/plain".to_string()),
+        ("origin".to_string(), "test".to_string()),
+    ]);
+    assert_eq!(store.get_blob_meta(hash.as_slice()).await.unwrap(), None);
+    store
+        .put_blob_with_meta(hash.as_slice(), DATA, &meta)
+        .await
+        .unwrap();
+    assert_eq!(
+        store.get_blob_meta(hash.as_slice()).await.unwrap(),
+        Some(meta)
+    );
+    assert_eq!(
+        store
+            .get_blob(hash.as_slice(), 0..usize::MAX)
+            .await
+            .unwrap()
+            .unwrap(),
+        DATA
+    );
+    assert!(store.delete_blob(hash.as_slice()).await.unwrap());
+    assert_eq!(store.get_blob_meta(hash.as_slice()).await.unwrap(), None);
+    store.put_blob(hash.as_slice(), DATA).await.unwrap();
+    assert_eq!(
+        store.get_blob_meta(hash.as_slice()).await.unwrap(),
+        Some(HashMap::new())
+    );
+    assert!(store.delete_blob(hash.as_slice()).await.unwrap());
+
     // Test large blob
     let mut data = Vec::with_capacity(50 * 1024 * 1024);
     while data.len() < 50 * 1024 * 1024 {
@@ -470,7 +798,55 @@ async fn test_store(store: BlobStore) {
         .unwrap(),
         std::str::from_utf8(&data[3000111..4000999]).unwrap()
     );
+
+    // Sequential reads with read-ahead
+    let mut reader = store
+        .clone()
+        .with_read_ahead(ReadAhead {
+            chunk_size: 3 * 1024 * 1024 + 7,
+            depth: 3,
+        })
+        .reader(hash.as_slice());
+    let mut read_data = Vec::with_capacity(data.len());
+    while let Some(chunk) = reader.next_chunk().await.unwrap() {
+        read_data.extend_from_slice(&chunk);
+    }
+    assert!(read_data == data, "read-ahead data mismatch");
+    assert!(reader.next_chunk().await.unwrap().is_none());
+
+    // Streamed writes
+    let mut writer = store.create_blob(b"streamed".to_vec()).await.unwrap();
+    for chunk in data.chunks(1024 * 1024 + 3) {
+        writer.append(chunk).await.unwrap();
+    }
+    assert_eq!(writer.size(), data.len());
+    assert_eq!(writer.finish().await.unwrap(), hash);
+    assert!(
+        store
+            .get_blob(b"streamed", 0..usize::MAX)
+            .await
+            .unwrap()
+            .unwrap()
+            == data,
+        "streamed data mismatch"
+    );
+    assert!(store.delete_blob(b"streamed").await.unwrap());
+    let mut writer = store.create_blob(b"discarded".to_vec()).await.unwrap();
+    writer.append(DATA).await.unwrap();
+    drop(writer);
+    assert!(store
+        .get_blob(b"discarded", 0..usize::MAX)
+        .await
+        .unwrap()
+        .is_none());
+
     assert!(store.delete_blob(hash.as_slice()).await.unwrap());
+    assert!(store
+        .reader(hash.as_slice())
+        .next_chunk()
+        .await
+        .unwrap()
+        .is_none());
     assert!(store
         .get_blob(hash.as_slice(), 0..usize::MAX)
         .await
@@ -0,0 +1,315 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs Ltd <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use store::{
+    write::{assert::HashedValue, BatchBuilder, DirectoryClass, ValueClass},
+    BlobStore, Deserialize, IterateParams, Store, ValueKey,
+};
+use utils::BlobHash;
+
+const ACCOUNT_ID: u32 = 8801;
+
+// Values as stored, without any decoding
+#[derive(Debug, PartialEq, Eq)]
+struct RawValue(Vec<u8>);
+
+/// Checks the contract every data store backend must honor. New backends only
+/// need to be added to `CONFIG` to be covered.
+pub async fn store_conformance_tests(store: Store) {
+    println!("Running store conformance tests...");
+
+    // Missing keys
+    assert_eq!(store.get_value::<RawValue>(key(0)).await.unwrap(), None);
+    assert_eq!(
+        store
+            .get_counter(ValueKey::from(ValueClass::Directory(
+                DirectoryClass::UsedQuota(ACCOUNT_ID)
+            )))
+            .await
+            .unwrap(),
+        0
+    );
+    let mut found = false;
+    store
+        .iterate(IterateParams::new(key(0), key(u8::MAX)), |_, _| {
+            found = true;
+            Ok(true)
+        })
+        .await
+        .unwrap();
+    assert!(!found, "iterating an empty range returned keys");
+
+    // Range scans include both ends and honor the direction
+    let mut batch = BatchBuilder::new();
+    batch
+        .with_account_id(ACCOUNT_ID)
+        .with_collection(0u8)
+        .update_document(0);
+    for field in (0..10).rev() {
+        batch.set(ValueClass::Property(field), vec![field]);
+    }
+    store.write(batch.build_batch()).await.unwrap();
+    for (from, to, ascending, first, expected) in [
+        (3, 6, true, false, vec![3, 4, 5, 6]),
+        (3, 6, false, false, vec![6, 5, 4, 3]),
+        (3, 6, true, true, vec![3]),
+        (3, 6, false, true, vec![6]),
+        (4, 4, true, false, vec![4]),
+        (9, 20, true, false, vec![9]),
+        (10, 20, true, false, vec![]),
+        (6, 3, true, false, vec![]),
+        (6, 3, false, false, vec![]),
+    ] {
+        let mut params = IterateParams::new(key(from), key(to)).set_ascending(ascending);
+        if first {
+            params = params.only_first();
+        }
+        let mut results = Vec::new();
+        store
+            .iterate(params, |_, value| {
+                results.push(value[0]);
+                Ok(true)
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            results, expected,
+            "range {from}..={to} ascending: {ascending} first: {first}"
+        );
+    }
+
+    // Callbacks can stop a scan early
+    let mut results = Vec::new();
+    store
+        .iterate(IterateParams::new(key(0), key(9)), |_, value| {
+            results.push(value[0]);
+            Ok(results.len() < 2)
+        })
+        .await
+        .unwrap();
+    assert_eq!(results, vec![0, 1]);
+
+    // Deleting a range excludes its end
+    store.delete_range(key(0), key(5)).await.unwrap();
+    let mut results = Vec::new();
+    store
+        .iterate(IterateParams::new(key(0), key(9)), |_, value| {
+            results.push(value[0]);
+            Ok(true)
+        })
+        .await
+        .unwrap();
+    assert_eq!(results, vec![5, 6, 7, 8, 9]);
+
+    // Conditional writes only succeed while the value is unchanged
+    let current = store
+        .get_value::<HashedValue<RawValue>>(key(5))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(current.inner, RawValue(vec![5]));
+    assert!(matches!(
+        store
+            .write(
+                BatchBuilder::new()
+                    .with_account_id(ACCOUNT_ID)
+                    .with_collection(0u8)
+                    .update_document(0)
+                    .assert_value(ValueClass::Property(5), ())
+                    .set(ValueClass::Property(5), vec![50])
+                    .build_batch(),
+            )
+            .await,
+        Err(store::Error::AssertValueFailed)
+    ));
+    assert!(matches!(
+        store
+            .write(
+                BatchBuilder::new()
+                    .with_account_id(ACCOUNT_ID)
+                    .with_collection(0u8)
+                    .update_document(0)
+                    .assert_value(ValueClass::Property(0), &current)
+                    .set(ValueClass::Property(0), vec![50])
+                    .build_batch(),
+            )
+            .await,
+        Err(store::Error::AssertValueFailed)
+    ));
+    for value in [50, 51] {
+        let result = store
+            .write(
+                BatchBuilder::new()
+                    .with_account_id(ACCOUNT_ID)
+                    .with_collection(0u8)
+                    .update_document(0)
+                    .assert_value(ValueClass::Property(5), &current)
+                    .set(ValueClass::Property(5), vec![value])
+                    .build_batch(),
+            )
+            .await;
+        if value == 50 {
+            result.unwrap();
+        } else {
+            assert!(matches!(result, Err(store::Error::AssertValueFailed)));
+        }
+    }
+    assert_eq!(
+        store.get_value::<RawValue>(key(5)).await.unwrap(),
+        Some(RawValue(vec![50]))
+    );
+
+    // Concurrent counter updates are never lost
+    let counter = ValueClass::<u32>::Directory(DirectoryClass::UsedQuota(ACCOUNT_ID));
+    let mut handles = Vec::new();
+    for delta in (1..=100).map(|n| if n % 4 == 0 { -n } else { n }) {
+        let (store, counter) = (store.clone(), counter.clone());
+        handles.push(tokio::spawn(async move {
+            store
+                .write(
+                    BatchBuilder::new()
+                        .with_account_id(ACCOUNT_ID)
+                        .with_collection(0u8)
+                        .update_document(0)
+                        .add(counter.into_dynamic(), delta)
+                        .build_batch(),
+                )
+                .await
+                .unwrap();
+        }));
+    }
+    for handle in handles {
+        handle.await.unwrap();
+    }
+    let expected = (1..=100i64)
+        .map(|n| if n % 4 == 0 { -n } else { n })
+        .sum::<i64>();
+    assert_eq!(
+        store
+            .get_counter(ValueKey::from(counter.clone()))
+            .await
+            .unwrap(),
+        expected
+    );
+
+    // Values up to the maximum size are stored intact
+    for size in [0, 1, store.max_value_size()] {
+        let value = (0..size).map(|n| n as u8).collect::<Vec<_>>();
+        store
+            .write(
+                BatchBuilder::new()
+                    .with_account_id(ACCOUNT_ID)
+                    .with_collection(0u8)
+                    .update_document(0)
+                    .set(ValueClass::Property(20), value.clone())
+                    .build_batch(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            store.get_value::<RawValue>(key(20)).await.unwrap(),
+            Some(RawValue(value)),
+            "value size: {size}"
+        );
+    }
+    assert!(matches!(
+        store
+            .write(
+                BatchBuilder::new()
+                    .with_account_id(ACCOUNT_ID)
+                    .with_collection(0u8)
+                    .update_document(0)
+                    .set(
+                        ValueClass::Property(20),
+                        vec![0u8; store.max_value_size() + 1]
+                    )
+                    .build_batch(),
+            )
+            .await,
+        Err(store::Error::ValueTooLarge { .. })
+    ));
+
+    // Leave the store as it was found
+    store
+        .write(
+            BatchBuilder::new()
+                .with_account_id(ACCOUNT_ID)
+                .with_collection(0u8)
+                .update_document(0)
+                .add(counter.into_dynamic(), -expected)
+                .build_batch(),
+        )
+        .await
+        .unwrap();
+    store.delete_range(key(0), key(u8::MAX)).await.unwrap();
+}
+
+/// Checks the contract every blob store must honor, which `blob_tests` runs
+/// against each configured blob store.
+pub async fn blob_conformance_tests(blob_store: BlobStore) {
+    println!("Running blob store conformance tests...");
+
+    // Blobs are read back whole or by range
+    let data = (0..300_000).map(|n| (n % 251) as u8).collect::<Vec<_>>();
+    let hash = BlobHash::from(data.as_slice());
+    let missing = BlobHash::from(b"conformance".as_slice());
+    assert_eq!(
+        blob_store
+            .get_blob(missing.as_ref(), 0..usize::MAX)
+            .await
+            .unwrap(),
+        None
+    );
+    assert!(!blob_store.has_blob(missing.as_ref()).await.unwrap());
+    blob_store.put_blob(hash.as_ref(), &data).await.unwrap();
+    assert!(blob_store.has_blob(hash.as_ref()).await.unwrap());
+    for range in [
+        0..usize::MAX,
+        0..1,
+        10..20,
+        100_000..250_000,
+        299_990..400_000,
+        0..data.len(),
+    ] {
+        assert_eq!(
+            blob_store
+                .get_blob(hash.as_ref(), range.clone())
+                .await
+                .unwrap()
+                .unwrap(),
+            data[range.start..std::cmp::min(range.end, data.len())],
+            "blob range {range:?}"
+        );
+    }
+    assert!(blob_store
+        .get_blob(hash.as_ref(), 400_000..500_000)
+        .await
+        .unwrap()
+        .map_or(true, |bytes| bytes.is_empty()));
+    assert!(blob_store.delete_blob(hash.as_ref()).await.unwrap());
+    assert_eq!(
+        blob_store
+            .get_blob(hash.as_ref(), 0..usize::MAX)
+            .await
+            .unwrap(),
+        None
+    );
+}
+
+fn key(field: u8) -> ValueKey<ValueClass<u32>> {
+    ValueKey {
+        account_id: ACCOUNT_ID,
+        collection: 0,
+        document_id: 0,
+        class: ValueClass::Property(field),
+    }
+}
+
+impl Deserialize for RawValue {
+    fn deserialize(bytes: &[u8]) -> store::Result<Self> {
+        Ok(RawValue(bytes.to_vec()))
+    }
+}
@@ -145,6 +145,9 @@ pub async fn test(db: Store) {
         }
 
         db.write(batch.build()).await.unwrap();
+        db.rename_collection(account_id, 2u8, &format!("Collection {account_id}"))
+            .await
+            .unwrap();
     }
 
     // Create queue, config and lookup data
@@ -304,6 +307,7 @@ impl Snapshot {
             (SUBSPACE_REPORT_OUT, true),
             (SUBSPACE_REPORT_IN, true),
             (SUBSPACE_FTS_INDEX, true),
+            (SUBSPACE_COLLECTIONS, true),
         ] {
             let from_key = AnyKey {
                 subspace,
@@ -317,6 +321,11 @@ impl Snapshot {
             db.iterate(
                 IterateParams::new(from_key, to_key).set_values(with_values),
                 |key, value| {
+                    // Usage counters are not backed up, `recalculate_usage` rebuilds them
+                    if subspace == SUBSPACE_QUOTA && key.first() == Some(&7) {
+                        return Ok(true);
+                    }
+
                     keys.insert(KeyValue {
                         subspace,
                         key: key.to_vec(),
@@ -51,6 +51,20 @@ pub async fn lookup_tests() {
             Some("world".to_string())
         );
 
+        // Writes are rejected while the store is read-only, reads are not
+        store.set_read_only(true);
+        assert!(matches!(
+            store
+                .key_set(key.clone(), "read-only".to_string().into_bytes(), None)
+                .await,
+            Err(store::Error::ReadOnly)
+        ));
+        assert_eq!(
+            store.key_get::<String>(key.clone()).await.unwrap(),
+            Some("world".to_string())
+        );
+        store.set_read_only(false);
+
         // Test value expiry
         store
             .key_set(key.clone(), "hello".to_string().into_bytes(), 1.into())
@@ -68,6 +82,29 @@ pub async fn lookup_tests() {
             store.assert_is_empty(store.clone().into()).await;
         }
 
+        // Test key ranges
+        for key in ["range1", "range2", "range3"] {
+            store
+                .key_set(key.as_bytes().to_vec(), key.as_bytes().to_vec(), None)
+                .await
+                .unwrap();
+        }
+        assert_eq!(
+            store
+                .key_range(b"range1".to_vec(), b"range2".to_vec())
+                .await
+                .unwrap(),
+            vec![b"range1".to_vec(), b"range2".to_vec()]
+        );
+        for key in ["range1", "range2", "range3"] {
+            store.key_delete(key.as_bytes().to_vec()).await.unwrap();
+        }
+        assert!(store
+            .key_range(b"range1".to_vec(), b"range3".to_vec())
+            .await
+            .unwrap()
+            .is_empty());
+
         // Test counter
         let key = "abc".as_bytes().to_vec();
         store
@@ -97,6 +134,39 @@ pub async fn lookup_tests() {
         store.purge_lookup_store().await.unwrap();
         assert_eq!(0, store.counter_get(key.clone()).await.unwrap());
 
+        // Expired counters read as zero before they are purged
+        store
+            .counter_incr(key.clone(), 5, 1.into(), false)
+            .await
+            .unwrap();
+        tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+        assert_eq!(0, store.counter_get(key.clone()).await.unwrap());
+        if let LookupStore::Store(store) = &store {
+            assert_eq!(store.purge_expired_counters().await.unwrap(), 1);
+            assert_eq!(store.purge_expired_counters().await.unwrap(), 0);
+        }
+
+        // Increments without an expiry restart expired counters without an expiry
+        store
+            .counter_incr(key.clone(), 5, 1.into(), false)
+            .await
+            .unwrap();
+        tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+        for value in 1..=2 {
+            assert_eq!(
+                value,
+                store
+                    .counter_incr(key.clone(), 1, None, true)
+                    .await
+                    .unwrap()
+            );
+        }
+        if let LookupStore::Store(store) = &store {
+            assert_eq!(store.purge_expired_counters().await.unwrap(), 0);
+        }
+        assert_eq!(2, store.counter_get(key.clone()).await.unwrap());
+        store.counter_delete(key.clone()).await.unwrap();
+
         // Test rate limiter
         assert!(store
             .is_rate_allowed("rate".as_bytes(), &rate, false)
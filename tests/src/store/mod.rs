@@ -6,6 +6,7 @@
 
 pub mod assign_id;
 pub mod blob;
+pub mod conformance;
 pub mod import_export;
 pub mod lookup;
 pub mod ops;
@@ -31,20 +32,34 @@ region = "eu-central-1"
 endpoint = "http://localhost:9000"
 bucket = "tmp"
 
+[store."azure"]
+type = "azure"
+storage-account = "devstoreaccount1"
+access-key = "Eby8vdM02xNOcqFlqUwJPLlmEtlCDXJ1OUzFT50uSRZ6IFsuFq2UVErCz4I6tq/K1SZFPTOtr/KBHBeksoGMGw=="
+endpoint = "http://127.0.0.1:10000/devstoreaccount1"
+container = "tmp"
+key-prefix = "stalwart/"
+timeout = "10s"
+
 [store."fs"]
 type = "fs"
 path = "{TMP}"
+key-encoding = "hex"
 
 [store."rocksdb"]
 type = "rocksdb"
 path = "{TMP}/rocksdb"
+durability = "sync"
 
 [store."foundationdb"]
 type = "foundationdb"
+blob.chunk-size = 10000
 
 [store."sqlite"]
 type = "sqlite"
 path = "{TMP}/sqlite.db"
+durability = "group-commit"
+durability-interval = "50ms"
 
 [store."postgresql"]
 type = "postgresql"
@@ -62,10 +77,16 @@ database = "stalwart"
 user = "root"
 password = "password"
 
+[store."tiered"]
+type = "tiered"
+hot = "fs"
+cold = "sqlite"
+
 [store."redis"]
 type = "redis"
 urls = "redis://127.0.0.1"
 redis-type = "single"
+key-index = "test-keys"
 
 "#;
 
@@ -91,6 +112,8 @@ pub async fn store_tests() {
         store.destroy().await;
     }
 
+    conformance::store_conformance_tests(store.clone()).await;
+    conformance::blob_conformance_tests(store.clone().into()).await;
     import_export::test(store.clone()).await;
     assign_id::test(store.clone()).await;
     ops::test(store.clone()).await;
@@ -4,20 +4,60 @@
  * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
  */
 
-use std::collections::HashSet;
+use std::{collections::HashSet, sync::Arc, time::Duration};
 
-use jmap_proto::types::{collection::Collection, property::Property};
+use futures::StreamExt;
+use jmap_proto::types::{collection::Collection, keyword::Keyword, property::Property};
+use nlp::language::Language;
 use store::{
+    backend::replicated::{ReplicatedStore, HEARTBEAT_KEY},
+    dispatch::stats::{KeyUsage, ScanMode},
+    fts::{index::FtsDocument, Field, FtsFilter},
+    query::{
+        acl::Principal,
+        builder::FilterBuilder,
+        collections::{Query, QueryScope},
+        export::{ExportCursor, ExportFormat, ResumeToken},
+        log::{Change, Query as LogQuery},
+        normalize::FieldNormalizers,
+        partial::PartialIndex,
+        sort::Pagination,
+        vector::Embedding,
+        Comparator, Filter, FilterOptions, Operator, QueryLimits, ResultSet,
+    },
     write::{
-        BatchBuilder, BitmapClass, DirectoryClass, MaybeDynamicId, TagValue, ValueClass, F_CLEAR,
+        collections::COLLECTIONS_BACKFILL_KEY,
+        delete::{CancellationToken, DeleteProgress},
+        duplicates::DuplicateSet,
+        labels::{Label, LabelMetadata, LabelRegistry},
+        queue::WriteQueue,
+        threads::ThreadIndex,
+        usage::Quota,
+        versioned::{Versionable, Versioned},
+        AnyClass, BatchBuilder, BitmapClass, BlobOp, DirectoryClass, IdAllocator, Isolation,
+        MaybeDynamicId, Operation, RetryPolicy, TagValue, ValueClass, F_BITMAP, F_CLEAR, F_INDEX,
+        F_NO_DEDUP, F_VALUE,
     },
-    BitmapKey, Store, ValueKey,
+    BitmapKey, BlobClass, Deserialize, ErrorKind, LogKey, Serialize, Store, ValueKey,
+    SUBSPACE_CLUSTER, SUBSPACE_INDEXES, SUBSPACE_PROPERTY,
 };
+use utils::{snowflake::SnowflakeIdGenerator, BlobHash};
 
 // FDB max value
 const MAX_VALUE_SIZE: usize = 100000;
 
+// The tests are split into several functions, called one after the other, to
+// keep the stack frames of their futures small
 pub async fn test(db: Store) {
+    test_filters(db.clone()).await;
+    test_writes(db.clone()).await;
+    test_documents(db.clone()).await;
+    test_usage(db.clone()).await;
+    test_collections(db.clone()).await;
+    test_maintenance(db).await;
+}
+
+async fn test_filters(db: Store) {
     #[cfg(feature = "foundationdb")]
     if matches!(db, Store::FoundationDb(_)) && std::env::var("SLOW_FDB_TRX").is_ok() {
         println!("Running slow FoundationDB transaction tests...");
@@ -76,160 +116,3312 @@ pub async fn test(db: Store) {
         )
         .await
         .unwrap();
-
-        // Delete 100 keys
-        let mut batch = BatchBuilder::new();
+
+        // Delete 100 keys
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(0)
+            .with_collection(0)
+            .update_document(0);
+        for n in 0..900000 {
+            batch.clear(ValueClass::Config(format!("key{n:10}").into_bytes()));
+
+            if n % 10000 == 0 {
+                db.write(batch.build_batch()).await.unwrap();
+                batch = BatchBuilder::new();
+                batch
+                    .with_account_id(0)
+                    .with_collection(0)
+                    .update_document(0);
+            }
+        }
+        db.write(batch.build_batch()).await.unwrap();
+    }
+
+    #[cfg(feature = "sqlite-checksum")]
+    if matches!(db, Store::SQLite(_)) {
+        println!("Running SQLite checksum tests...");
+        let key = ValueKey {
+            account_id: 0,
+            collection: 0,
+            document_id: 0,
+            class: ValueClass::Property(0),
+        };
+        db.write(
+            BatchBuilder::new()
+                .with_account_id(0)
+                .with_collection(0)
+                .update_document(0)
+                .set(ValueClass::Property(0), "checksum".as_bytes().to_vec())
+                .build_batch(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            db.get_value::<String>(key.clone()).await.unwrap(),
+            Some("checksum".to_string())
+        );
+        assert!(db.scrub().await.unwrap().corrupted.is_empty());
+
+        // Corrupt the value behind the store's back
+        store::LookupStore::from(db.clone())
+            .query::<usize>(
+                "UPDATE p SET v = ?",
+                vec![store::Value::Blob(b"corrupted".as_slice().into())],
+            )
+            .await
+            .unwrap();
+        assert!(db.get_value::<String>(key.clone()).await.is_err());
+        assert_eq!(db.scrub().await.unwrap().corrupted.len(), 1);
+
+        db.write(
+            BatchBuilder::new()
+                .with_account_id(0)
+                .with_collection(0)
+                .update_document(0)
+                .clear(ValueClass::Property(0))
+                .build_batch(),
+        )
+        .await
+        .unwrap();
+    }
+
+    // Make sure keys are iterated in byte-lexicographic order
+    println!("Running iteration ordering tests...");
+    let mut keys = [
+        b"".to_vec(),
+        vec![0],
+        vec![0, 0],
+        vec![0, u8::MAX],
+        vec![1],
+        vec![0x7f],
+        vec![0x80],
+        vec![0x80, 0],
+        vec![u8::MAX],
+        vec![u8::MAX, 0],
+        b"A".to_vec(),
+        b"a".to_vec(),
+        b"a ".to_vec(),
+        b"a\0".to_vec(),
+        b"ab".to_vec(),
+        b"b".to_vec(),
+    ]
+    .into_iter()
+    .map(|key| [b"ordering-".as_slice(), &key].concat())
+    .collect::<Vec<_>>();
+    let mut batch = BatchBuilder::new();
+    batch
+        .with_account_id(0)
+        .with_collection(0)
+        .update_document(0);
+    for key in &keys {
+        batch.set(ValueClass::Config(key.clone()), key.clone());
+    }
+    db.write(batch.build_batch()).await.unwrap();
+    keys.sort_unstable();
+
+    for ascending in [true, false] {
+        let mut results = Vec::new();
+        db.iterate(
+            store::IterateParams::new(
+                ValueKey::from(ValueClass::Config(b"ordering-".to_vec())),
+                ValueKey::from(ValueClass::Config(
+                    [b"ordering-".as_slice(), &[u8::MAX; 4]].concat(),
+                )),
+            )
+            .set_ascending(ascending),
+            |key, value| {
+                assert_eq!(key, value);
+                results.push(key.to_vec());
+                Ok(true)
+            },
+        )
+        .await
+        .unwrap();
+
+        if !ascending {
+            results.reverse();
+        }
+        assert_eq!(results, keys, "ascending: {ascending}");
+    }
+
+    let mut batch = BatchBuilder::new();
+    batch
+        .with_account_id(0)
+        .with_collection(0)
+        .update_document(0);
+    for key in &keys {
+        batch.clear(ValueClass::Config(key.clone()));
+    }
+    db.write(batch.build_batch()).await.unwrap();
+
+    // Test bulk imports
+    println!("Running bulk import tests...");
+    let mut importer = store::write::bulk::BulkImporter::new(db.clone()).with_max_operations(8);
+    for document_id in [3u32, 1, 2] {
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(1000)
+            .with_collection(0)
+            .create_document_with_id(document_id)
+            .value(0u8, format!("value{}", document_id % 2), F_VALUE | F_INDEX);
+        assert!(importer.write(batch.build()).await.unwrap().is_none());
+    }
+    importer.finish().await.unwrap();
+    assert_eq!(
+        db.filter(1000, 0u8, vec![store::query::Filter::eq(0u8, "value1")])
+            .await
+            .unwrap()
+            .results,
+        store::roaring::RoaringBitmap::from_iter([1u32, 3])
+    );
+    for document_id in [1u32, 2, 3] {
+        assert_eq!(
+            db.get_value::<String>(ValueKey {
+                account_id: 1000,
+                collection: 0,
+                document_id,
+                class: ValueClass::Property(0),
+            })
+            .await
+            .unwrap(),
+            Some(format!("value{}", document_id % 2))
+        );
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(1000)
+            .with_collection(0)
+            .delete_document(document_id)
+            .value(
+                0u8,
+                format!("value{}", document_id % 2),
+                F_VALUE | F_INDEX | F_CLEAR,
+            );
+        db.write(batch.build()).await.unwrap();
+    }
+
+    // Test partial indexes
+    println!("Running partial index tests...");
+    let index = PartialIndex::new(0u8, Operator::GreaterThan, 10u32);
+    for (document_id, size) in [(1u32, 5u32), (2, 15), (3, 25)] {
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(1000)
+            .with_collection(0)
+            .create_document_with_id(document_id)
+            .value_partial(0u8, size, F_VALUE | F_INDEX, &index);
+        db.write(batch.build()).await.unwrap();
+    }
+    // Filters not covered by the partial index fall back to a scan
+    for (op, value, expected, expected_indexed) in [
+        (Operator::GreaterThan, 20u32, vec![3u32], vec![3u32]),
+        (Operator::Equal, 15, vec![2], vec![2]),
+        (Operator::Equal, 5, vec![1], vec![]),
+        (Operator::LowerThan, 20, vec![1, 2], vec![2]),
+    ] {
+        assert_eq!(
+            db.filter_with_options(
+                1000,
+                0u8,
+                vec![Filter::cond(0u8, op, value)],
+                &FilterOptions {
+                    partial_indexes: vec![index.clone()],
+                    ..Default::default()
+                }
+            )
+            .await
+            .unwrap()
+            .results,
+            store::roaring::RoaringBitmap::from_iter(expected)
+        );
+        assert_eq!(
+            db.filter(1000, 0u8, vec![Filter::cond(0u8, op, value)])
+                .await
+                .unwrap()
+                .results,
+            store::roaring::RoaringBitmap::from_iter(expected_indexed)
+        );
+    }
+    for (document_id, size) in [(1u32, 5u32), (2, 15), (3, 25)] {
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(1000)
+            .with_collection(0)
+            .delete_document(document_id)
+            .value_partial(0u8, size, F_VALUE | F_INDEX | F_CLEAR, &index);
+        db.write(batch.build()).await.unwrap();
+    }
+
+    // Test field normalizers
+    println!("Running field normalizer tests...");
+    let normalizers = FieldNormalizers::new().with(0u8, |value: &[u8]| {
+        value
+            .iter()
+            .filter(|ch| ch.is_ascii_digit())
+            .copied()
+            .collect::<Vec<_>>()
+    });
+    for (document_id, phone) in [(1u32, "+1 (555) 010-2000"), (2, "1-555-010-3000")] {
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(1000)
+            .with_collection(0)
+            .create_document_with_id(document_id)
+            .value_normalized(0u8, phone, F_VALUE | F_INDEX, &normalizers);
+        db.write(batch.build()).await.unwrap();
+    }
+    for (filter, expected) in [
+        (Filter::eq(0u8, "15550102000"), vec![1u32]),
+        (Filter::eq(0u8, "1.555.010.3000"), vec![2]),
+        (
+            Filter::eq_any(0u8, ["1 555 010 2000", "1 555 010 3000"]),
+            vec![1, 2],
+        ),
+        (Filter::ge(0u8, "1 555 010 2500"), vec![2]),
+    ] {
+        let mut filters = vec![filter];
+        normalizers.normalize_filters(&mut filters);
+        assert_eq!(
+            db.filter(1000, 0u8, filters).await.unwrap().results,
+            store::roaring::RoaringBitmap::from_iter(expected)
+        );
+    }
+    assert_eq!(
+        db.get_value::<String>(ValueKey {
+            account_id: 1000,
+            collection: 0,
+            document_id: 1,
+            class: ValueClass::Property(0),
+        })
+        .await
+        .unwrap()
+        .as_deref(),
+        Some("+1 (555) 010-2000")
+    );
+    for (document_id, phone) in [(1u32, "+1 (555) 010-2000"), (2, "1-555-010-3000")] {
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(1000)
+            .with_collection(0)
+            .delete_document(document_id)
+            .value_normalized(0u8, phone, F_VALUE | F_INDEX | F_CLEAR, &normalizers);
+        db.write(batch.build()).await.unwrap();
+    }
+
+    // Test distinct index values
+    println!("Running distinct values tests...");
+    let values = [(1u32, "b"), (2, "a"), (3, "b"), (4, "c")];
+    let mut batch = BatchBuilder::new();
+    batch.with_account_id(1000).with_collection(0);
+    for (document_id, value) in values {
+        batch
+            .create_document_with_id(document_id)
+            .value(0u8, value, F_INDEX);
+    }
+    db.write(batch.build()).await.unwrap();
+    assert_eq!(
+        db.distinct_values(1000, 0u8, 0u8, 10).await.unwrap(),
+        vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()]
+    );
+    assert_eq!(
+        db.distinct_values(1000, 0u8, 0u8, 2).await.unwrap(),
+        vec![b"a".to_vec(), b"b".to_vec()]
+    );
+    let mut batch = BatchBuilder::new();
+    batch.with_account_id(1000).with_collection(0);
+    for (document_id, value) in values {
+        batch
+            .delete_document(document_id)
+            .value(0u8, value, F_INDEX | F_CLEAR);
+    }
+    db.write(batch.build()).await.unwrap();
+
+    // Repeated values of a field are indexed once per document unless opted out
+    let recipients = [(1u32, ["a", "b", "a"]), (2, ["a", "a", "a"])];
+    for (options, expected_ops) in [(F_INDEX, 3), (F_INDEX | F_NO_DEDUP, 6)] {
+        let mut batch = BatchBuilder::new();
+        batch.with_account_id(1000).with_collection(0);
+        for (document_id, values) in recipients {
+            batch.create_document_with_id(document_id);
+            for value in values {
+                batch.value(0u8, value, options);
+            }
+        }
+        let batch = batch.build();
+        assert_eq!(
+            batch
+                .ops
+                .iter()
+                .filter(|op| matches!(op, Operation::Index { .. }))
+                .count(),
+            expected_ops
+        );
+        db.write(batch).await.unwrap();
+        for (value, expected) in [("a", 2), ("b", 1)] {
+            assert_eq!(
+                db.filter(1000, 0u8, vec![Filter::eq(0u8, value)])
+                    .await
+                    .unwrap()
+                    .results
+                    .len(),
+                expected
+            );
+        }
+
+        let mut batch = BatchBuilder::new();
+        batch.with_account_id(1000).with_collection(0);
+        for (document_id, values) in recipients {
+            batch.delete_document(document_id);
+            for value in values {
+                batch.value(0u8, value, options | F_CLEAR);
+            }
+        }
+        db.write(batch.build()).await.unwrap();
+    }
+
+    // Test attachment filters
+    println!("Running attachment filter tests...");
+    let attachments: [(u32, &[&str]); 4] = [
+        (1, &["image/png"]),
+        (2, &["application/pdf", "image/jpeg"]),
+        (3, &["application/pdf"]),
+        (4, &[]),
+    ];
+    let mut batch = BatchBuilder::new();
+    batch.with_account_id(1000).with_collection(0);
+    for (document_id, content_types) in attachments {
+        batch.create_document_with_id(document_id);
+        if !content_types.is_empty() {
+            batch.tag(0u8, (), 0);
+        }
+        for content_type in content_types {
+            batch.tag(0u8, content_type.to_string(), 0);
+        }
+    }
+    db.write(batch.build()).await.unwrap();
+    for (content_type, expected) in [
+        (None, vec![1u32, 2, 3]),
+        (Some("image/png"), vec![1]),
+        (Some("application/pdf"), vec![2, 3]),
+        (Some("image/*"), vec![1, 2]),
+        (Some("IMAGE/*"), vec![1, 2]),
+        (Some("video/*"), vec![]),
+    ] {
+        assert_eq!(
+            db.filter(
+                1000,
+                0u8,
+                vec![Filter::has_attachment(
+                    0u8,
+                    content_type.map(|ct| ct.to_string())
+                )]
+            )
+            .await
+            .unwrap()
+            .results,
+            store::roaring::RoaringBitmap::from_iter(expected),
+            "{content_type:?}"
+        );
+    }
+    let mut batch = BatchBuilder::new();
+    batch.with_account_id(1000).with_collection(0);
+    for (document_id, content_types) in attachments {
+        batch.delete_document(document_id);
+        if !content_types.is_empty() {
+            batch.tag(0u8, (), F_CLEAR);
+        }
+        for content_type in content_types {
+            batch.tag(0u8, content_type.to_string(), F_CLEAR);
+        }
+    }
+    db.write(batch.build()).await.unwrap();
+
+    // Test child document filters
+    println!("Running child document filter tests...");
+    let children: [(u32, u32, &str, &str); 4] = [
+        (10, 1, "invoice.pdf", "application/pdf"),
+        (11, 1, "photo.png", "image/png"),
+        (12, 2, "invoice.png", "image/png"),
+        (13, 3, "report.pdf", "application/pdf"),
+    ];
+    let mut batch = BatchBuilder::new();
+    batch.with_account_id(1000).with_collection(0);
+    for document_id in [1u32, 2, 3] {
+        batch.create_document_with_id(document_id);
+    }
+    batch.with_collection(1);
+    for (document_id, parent_id, name, content_type) in children {
+        batch
+            .create_document_with_id(document_id)
+            .value(0u8, name.to_string(), F_INDEX)
+            .tag(1u8, content_type.to_string(), 0)
+            .set_parent(parent_id);
+    }
+    db.write(batch.build()).await.unwrap();
+    let content_type = |content_type: &str| {
+        Filter::InBitmap(BitmapClass::Tag {
+            field: 1,
+            value: TagValue::Text(content_type.as_bytes().to_vec()),
+        })
+    };
+    for (filters, expected) in [
+        (
+            vec![Filter::has_child(
+                1u8,
+                vec![Filter::eq(0u8, "invoice.pdf".to_string())],
+            )],
+            vec![1u32],
+        ),
+        (
+            vec![Filter::has_child(
+                1u8,
+                vec![content_type("application/pdf")],
+            )],
+            vec![1, 3],
+        ),
+        (
+            vec![
+                Filter::Not,
+                Filter::has_child(1u8, vec![content_type("image/png")]),
+                Filter::End,
+            ],
+            vec![3],
+        ),
+        (
+            vec![Filter::has_child(
+                1u8,
+                vec![
+                    Filter::Or,
+                    Filter::eq(0u8, "invoice.png".to_string()),
+                    Filter::eq(0u8, "report.pdf".to_string()),
+                    Filter::End,
+                ],
+            )],
+            vec![2, 3],
+        ),
+        (
+            vec![Filter::has_child(1u8, vec![content_type("video/mp4")])],
+            vec![],
+        ),
+    ] {
+        assert_eq!(
+            db.filter(1000, 0u8, filters.clone()).await.unwrap().results,
+            store::roaring::RoaringBitmap::from_iter(expected),
+            "{filters:?}"
+        );
+    }
+    assert_eq!(
+        db.get_children(1000, 1u8, 1).await.unwrap(),
+        store::roaring::RoaringBitmap::from_iter([10u32, 11])
+    );
+    db.unlink_children(1000, 1u8, 1).await.unwrap();
+    assert!(db.get_children(1000, 1u8, 1).await.unwrap().is_empty());
+    assert_eq!(
+        db.get_parents(
+            1000,
+            1u8,
+            &store::roaring::RoaringBitmap::from_iter([10u32, 11, 12])
+        )
+        .await
+        .unwrap(),
+        store::roaring::RoaringBitmap::from_iter([2u32])
+    );
+    assert!(db
+        .filter(
+            1000,
+            0u8,
+            vec![Filter::has_child(
+                1u8,
+                vec![Filter::eq(0u8, "invoice.pdf".to_string())],
+            )]
+        )
+        .await
+        .unwrap()
+        .results
+        .is_empty());
+    let mut batch = BatchBuilder::new();
+    batch.with_account_id(1000).with_collection(0);
+    for document_id in [1u32, 2, 3] {
+        batch.delete_document(document_id);
+    }
+    batch.with_collection(1);
+    for (document_id, parent_id, name, content_type) in children {
+        batch
+            .delete_document(document_id)
+            .value(0u8, name.to_string(), F_INDEX | F_CLEAR)
+            .tag(1u8, content_type.to_string(), F_CLEAR)
+            .clear_parent(parent_id);
+    }
+    db.write(batch.build()).await.unwrap();
+
+    // Test document ranges
+    println!("Running document range tests...");
+    let mut batch = BatchBuilder::new();
+    batch.with_account_id(1000).with_collection(0);
+    for document_id in [1u32, 5, 6, 7, 100] {
+        batch.create_document_with_id(document_id);
+    }
+    db.write(batch.build()).await.unwrap();
+    for (from, to, expected) in [
+        (0u32, 10u32, vec![1u32, 5, 6, 7]),
+        (5, 7, vec![5, 6, 7]),
+        (6, 6, vec![6]),
+        (8, 99, vec![]),
+        (10, 5, vec![]),
+        (0, u32::MAX, vec![1, 5, 6, 7, 100]),
+    ] {
+        assert_eq!(
+            db.filter(1000, 0u8, vec![Filter::document_range(from, to)])
+                .await
+                .unwrap()
+                .results,
+            store::roaring::RoaringBitmap::from_iter(expected),
+            "{from}..={to}"
+        );
+    }
+    assert_eq!(
+        db.filter(
+            1000,
+            0u8,
+            vec![Filter::Not, Filter::document_range(5, 6), Filter::End]
+        )
+        .await
+        .unwrap()
+        .results,
+        store::roaring::RoaringBitmap::from_iter([1u32, 7, 100])
+    );
+    assert_eq!(
+        db.filter_with_options(
+            1000,
+            0u8,
+            vec![Filter::document_range(0, 10)],
+            &FilterOptions {
+                limits: QueryLimits {
+                    timeout: Some(Duration::ZERO),
+                    ..Default::default()
+                },
+                ..Default::default()
+            }
+        )
+        .await
+        .unwrap_err(),
+        store::Error::Timeout(Duration::ZERO)
+    );
+    assert_eq!(
+        db.filter_with_options(
+            1000,
+            0u8,
+            vec![Filter::document_range(0, 10)],
+            &FilterOptions {
+                limits: QueryLimits {
+                    timeout: Some(Duration::from_secs(60)),
+                    ..Default::default()
+                },
+                ..Default::default()
+            }
+        )
+        .await
+        .unwrap()
+        .results,
+        store::roaring::RoaringBitmap::from_iter([1u32, 5, 6, 7])
+    );
+    for (max_memory, is_ok) in [(1, false), (1024 * 1024, true)] {
+        let result = db
+            .filter_with_options(
+                1000,
+                0u8,
+                vec![
+                    Filter::Or,
+                    Filter::document_range(0, 10),
+                    Filter::is_in_set(store::roaring::RoaringBitmap::from_iter(0..10000)),
+                    Filter::End,
+                ],
+                &FilterOptions {
+                    limits: QueryLimits {
+                        max_memory: Some(max_memory),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+            )
+            .await;
+        if is_ok {
+            assert_eq!(result.unwrap().results.len(), 10000);
+        } else {
+            assert!(matches!(
+                result,
+                Err(store::Error::MemoryLimitExceeded { max_size: 1, .. })
+            ));
+        }
+    }
+
+    // Nested filters built with the combinator builder
+    assert_eq!(
+        db.filter(
+            1000,
+            0u8,
+            FilterBuilder::new()
+                .or([
+                    FilterBuilder::new().and([
+                        Filter::document_range(0, 10),
+                        Filter::document_range(5, 100),
+                    ]),
+                    Filter::document_range(1, 1).into(),
+                ])
+                .not([Filter::document_range(6, 6)])
+                .build()
+                .unwrap()
+        )
+        .await
+        .unwrap()
+        .results,
+        store::roaring::RoaringBitmap::from_iter([1u32, 5, 7])
+    );
+    assert!(FilterBuilder::new().with(Filter::End).build().is_err());
+    assert!(FilterBuilder::new()
+        .or([Filter::Not])
+        .with(Filter::document_range(0, 1))
+        .build()
+        .is_err());
+
+    let mut batch = BatchBuilder::new();
+    batch.with_account_id(1000).with_collection(0);
+    for document_id in [1u32, 5, 6, 7, 100] {
+        batch.delete_document(document_id);
+    }
+    db.write(batch.build()).await.unwrap();
+
+    // Test timestamp ranges
+    println!("Running timestamp range tests...");
+    let timestamps = [
+        (1u32, 1_700_000_000u64),
+        (2, 1_700_000_100),
+        (3, 1_700_086_400),
+        (4, 255),
+        (5, 256),
+    ];
+    let mut batch = BatchBuilder::new();
+    batch.with_account_id(1001).with_collection(0);
+    for (document_id, timestamp) in timestamps {
+        batch.create_document_with_id(document_id).value(
+            Property::ReceivedAt,
+            timestamp,
+            F_VALUE | F_INDEX,
+        );
+    }
+    db.write(batch.build()).await.unwrap();
+    for (from, to, expected) in [
+        (1_700_000_000u64, 1_700_000_100u64, vec![2u32, 1]),
+        (0, u64::MAX, vec![3, 2, 1, 5, 4]),
+        (200, 300, vec![5, 4]),
+        (1_700_000_001, 1_700_000_099, vec![]),
+        (1_700_000_100, 1_700_000_000, vec![]),
+    ] {
+        let results = db
+            .filter(
+                1001,
+                0u8,
+                vec![Filter::between(Property::ReceivedAt, from, to)],
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            db.sort(
+                results,
+                vec![Comparator::descending(Property::ReceivedAt)],
+                Pagination::new(0, 0, None, 0),
+            )
+            .await
+            .unwrap()
+            .ids
+            .into_iter()
+            .map(|id| id as u32)
+            .collect::<Vec<_>>(),
+            expected,
+            "{from}..={to}"
+        );
+    }
+
+    // Relevance ties are broken by the following comparators, then by id
+    let scores = [(1u32, 0.9f32), (2, 0.5), (3, 0.9), (4, 0.5)];
+    for (tie_break, expected) in [
+        (None, vec![1u32, 3, 2, 4, 5]),
+        (
+            Some(Comparator::descending(Property::ReceivedAt)),
+            vec![3, 1, 2, 4, 5],
+        ),
+        (
+            Some(Comparator::ascending(Property::ReceivedAt)),
+            vec![1, 3, 4, 2, 5],
+        ),
+    ] {
+        let results = db
+            .filter(1001, 0u8, vec![Filter::document_range(1, 5)])
+            .await
+            .unwrap();
+        let mut comparators = vec![Comparator::relevance(scores)];
+        comparators.extend(tie_break);
+        assert_eq!(
+            db.sort(results, comparators, Pagination::new(0, 0, None, 0))
+                .await
+                .unwrap()
+                .ids
+                .into_iter()
+                .map(|id| id as u32)
+                .collect::<Vec<_>>(),
+            expected
+        );
+    }
+
+    // Export stored values, fields that are only indexed are exported as null
+    let mut batch = BatchBuilder::new();
+    batch
+        .with_account_id(1001)
+        .with_collection(0)
+        .update_document(4)
+        .value(Property::Subject, "Re: a, \"b\"", F_VALUE)
+        .value(Property::Size, 100u32, F_INDEX)
+        .update_document(5)
+        .value(Property::Subject, "plain", F_VALUE);
+    db.write(batch.build()).await.unwrap();
+    let results = db
+        .filter(
+            1001,
+            0u8,
+            vec![Filter::between(Property::ReceivedAt, 200u64, 300u64)],
+        )
+        .await
+        .unwrap();
+    let (received_at, subject, size) = (
+        u8::from(Property::ReceivedAt),
+        u8::from(Property::Subject),
+        u8::from(Property::Size),
+    );
+    for (format, expected) in [
+        (
+            ExportFormat::NdJson,
+            format!(
+                concat!(
+                    "{{\"id\":4,\"{0}\":255,\"{1}\":\"Re: a, \\\"b\\\"\",\"{2}\":null}}\n",
+                    "{{\"id\":5,\"{0}\":256,\"{1}\":\"plain\",\"{2}\":null}}\n"
+                ),
+                received_at, subject, size
+            ),
+        ),
+        (
+            ExportFormat::Csv,
+            format!(
+                "id,{received_at},{subject},{size}\n4,255,\"Re: a, \"\"b\"\"\",\n5,256,plain,\n"
+            ),
+        ),
+    ] {
+        let mut output = Vec::new();
+        assert_eq!(
+            db.export(&results, &[received_at, subject, size], format, &mut output)
+                .await
+                .unwrap(),
+            2
+        );
+        assert_eq!(String::from_utf8(output).unwrap(), expected);
+    }
+
+    let mut batch = BatchBuilder::new();
+    batch.with_account_id(1001).with_collection(0);
+    for (document_id, timestamp) in timestamps {
+        batch.delete_document(document_id).value(
+            Property::ReceivedAt,
+            timestamp,
+            F_VALUE | F_INDEX | F_CLEAR,
+        );
+    }
+    batch
+        .update_document(4)
+        .value(Property::Subject, "Re: a, \"b\"", F_VALUE | F_CLEAR)
+        .value(Property::Size, 100u32, F_INDEX | F_CLEAR)
+        .update_document(5)
+        .value(Property::Subject, "plain", F_VALUE | F_CLEAR);
+    db.write(batch.build()).await.unwrap();
+
+    // Resumable exports continue after the last checkpoint, even after new writes
+    let mut batch = BatchBuilder::new();
+    batch.with_account_id(7001).with_collection(0);
+    for document_id in 0u32..8 {
+        batch
+            .create_document_with_id(document_id)
+            .value(1u8, format!("d{document_id}"), F_VALUE);
+        if document_id < 6 {
+            batch.value(0u8, 10 - document_id, F_INDEX);
+        }
+    }
+    db.write(batch.build()).await.unwrap();
+    let mut results = ResultSet {
+        account_id: 7001,
+        collection: 0,
+        results: store::roaring::RoaringBitmap::from_iter(0u32..8),
+    };
+    let cursor = ExportCursor::new(0u8).with_checkpoint_every(3);
+    let mut tokens = Vec::new();
+    let mut output = Vec::new();
+    assert_eq!(
+        db.export_resumable(
+            &results,
+            &[1],
+            ExportFormat::NdJson,
+            &cursor,
+            &mut output,
+            |token| tokens.push(token),
+        )
+        .await
+        .unwrap(),
+        8
+    );
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        [5, 4, 3, 2, 1, 0, 6, 7]
+            .into_iter()
+            .map(|id| format!("{{\"id\":{id},\"1\":\"d{id}\"}}\n"))
+            .collect::<String>()
+    );
+    assert_eq!(
+        tokens,
+        vec![
+            ResumeToken {
+                sort_key: Some(7u32.serialize()),
+                document_id: 3
+            },
+            ResumeToken {
+                sort_key: Some(10u32.serialize()),
+                document_id: 0
+            },
+            ResumeToken {
+                sort_key: None,
+                document_id: 7
+            },
+        ]
+    );
+    assert_eq!(
+        ResumeToken::deserialize(&tokens[0].serialize()).unwrap(),
+        tokens[0]
+    );
+    for token in &tokens {
+        assert_eq!(&ResumeToken::decode(&token.encode()).unwrap(), token);
+    }
+    assert!(ResumeToken::decode("").is_err());
+
+    let mut batch = BatchBuilder::new();
+    batch
+        .with_account_id(7001)
+        .with_collection(0)
+        .create_document_with_id(8)
+        .value(1u8, "d8", F_VALUE)
+        .value(0u8, 100u32, F_INDEX);
+    db.write(batch.build()).await.unwrap();
+    results.results.insert(8);
+    for (token, expected) in [
+        (tokens[0].clone(), vec![2, 1, 0, 8, 6, 7]),
+        (tokens[1].clone(), vec![8, 6, 7]),
+        (
+            ResumeToken {
+                sort_key: None,
+                document_id: 6,
+            },
+            vec![7],
+        ),
+        (tokens[2].clone(), vec![]),
+    ] {
+        let mut output = Vec::new();
+        assert_eq!(
+            db.export_resumable(
+                &results,
+                &[1],
+                ExportFormat::Csv,
+                &cursor.clone().with_resume_token(token),
+                &mut output,
+                |_| {},
+            )
+            .await
+            .unwrap(),
+            expected.len() as u64
+        );
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            expected
+                .into_iter()
+                .map(|id| format!("{id},d{id}\n"))
+                .collect::<String>()
+        );
+    }
+    let mut output = Vec::new();
+    assert_eq!(
+        db.export_resumable(
+            &results,
+            &[1],
+            ExportFormat::Csv,
+            &ExportCursor::new(u8::MAX),
+            &mut output,
+            |_| {},
+        )
+        .await
+        .unwrap(),
+        9
+    );
+    db.purge_account(7001).await.unwrap();
+
+    // Test vector search
+    println!("Running vector search tests...");
+    let embeddings = [
+        (1u32, vec![1.0f32, 0.0, 0.0]),
+        (2, vec![0.9, 0.1, 0.0]),
+        (3, vec![0.0, 1.0, 0.0]),
+        (4, vec![0.0, 0.0, 1.0]),
+        (5, vec![1.0, 0.0]),
+    ];
+    let mut batch = BatchBuilder::new();
+    batch.with_account_id(1000).with_collection(0);
+    for (document_id, embedding) in &embeddings {
+        batch.create_document_with_id(*document_id).set(
+            ValueClass::Vector(0),
+            Embedding(embedding.clone()).serialize(),
+        );
+    }
+    db.write(batch.build()).await.unwrap();
+    assert_eq!(
+        db.get_embedding(1000, 0u8, 0u8, 2).await.unwrap(),
+        Some(Embedding(vec![0.9, 0.1, 0.0]))
+    );
+    assert_eq!(
+        db.nearest_neighbors(1000, 0u8, 0u8, &[1.0, 0.2, 0.0], 3)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|(document_id, _)| document_id)
+            .collect::<Vec<_>>(),
+        vec![2, 1, 3]
+    );
+    assert_eq!(
+        db.filter(
+            1000,
+            0u8,
+            vec![Filter::vector_search(0u8, vec![0.0, 0.1, 1.0], 2)]
+        )
+        .await
+        .unwrap()
+        .results,
+        store::roaring::RoaringBitmap::from_iter([3u32, 4])
+    );
+    assert_eq!(
+        db.filter(
+            1000,
+            0u8,
+            vec![
+                Filter::vector_search(0u8, vec![1.0, 0.0, 0.0], 2),
+                Filter::document_range(2, 4)
+            ]
+        )
+        .await
+        .unwrap()
+        .results,
+        store::roaring::RoaringBitmap::from_iter([2u32])
+    );
+    let mut batch = BatchBuilder::new();
+    batch.with_account_id(1000).with_collection(0);
+    for (document_id, _) in &embeddings {
+        batch
+            .delete_document(*document_id)
+            .clear(ValueClass::Vector(0));
+    }
+    db.write(batch.build()).await.unwrap();
+
+    // Test content lengths
+    println!("Running content length tests...");
+    let mut batch = BatchBuilder::new();
+    batch
+        .with_account_id(1000)
+        .with_collection(0)
+        .create_document_with_id(1)
+        .value(0u8, 100u32, F_INDEX)
+        .set_content_length(100)
+        .create_document_with_id(2)
+        .value(0u8, 200u32, F_INDEX);
+    db.write(batch.build()).await.unwrap();
+    assert_eq!(db.content_length_sum(1000, 0u8).await.unwrap(), 100);
+    assert_eq!(db.get_content_length(1000, 0u8, 2).await.unwrap(), None);
+    assert_eq!(db.backfill_content_length(1000, 0u8, 0u8).await.unwrap(), 1);
+    assert_eq!(db.backfill_content_length(1000, 0u8, 0u8).await.unwrap(), 0);
+    assert_eq!(
+        db.get_content_length(1000, 0u8, 2).await.unwrap(),
+        Some(200)
+    );
+    assert_eq!(db.content_length_sum(1000, 0u8).await.unwrap(), 300);
+    assert_eq!(
+        db.filter(1000, 0u8, vec![Filter::size_at_least(150)])
+            .await
+            .unwrap()
+            .results,
+        store::roaring::RoaringBitmap::from_iter([2u32])
+    );
+    let mut batch = BatchBuilder::new();
+    batch.with_account_id(1000).with_collection(0);
+    for (document_id, size) in [(1u32, 100u32), (2, 200)] {
+        batch
+            .delete_document(document_id)
+            .value(0u8, size, F_INDEX | F_CLEAR)
+            .clear_content_length()
+            .clear_size_bucket(size);
+    }
+    db.write(batch.build()).await.unwrap();
+
+    // Size filters match whole buckets and refine the boundary one
+    let sizes = [(1u32, 100u32), (2, 120), (3, 5000), (4, 10_000_000)];
+    let mut batch = BatchBuilder::new();
+    batch.with_account_id(1000).with_collection(0);
+    for (document_id, size) in sizes {
+        batch
+            .create_document_with_id(document_id)
+            .set_content_length(size)
+            .set_size_bucket(size);
+    }
+    db.write(batch.build()).await.unwrap();
+    for (filter, expected) in [
+        (Filter::size_at_least(110), vec![2u32, 3, 4]),
+        (Filter::size_at_most(110), vec![1]),
+        (Filter::size_at_least(0), vec![1, 2, 3, 4]),
+        (Filter::size_at_most(5000), vec![1, 2, 3]),
+        (Filter::size_at_least(10_000_000), vec![4]),
+        (Filter::size_at_least(10_000_001), vec![]),
+    ] {
+        assert_eq!(
+            db.filter(1000, 0u8, vec![filter.clone()])
+                .await
+                .unwrap()
+                .results,
+            store::roaring::RoaringBitmap::from_iter(expected),
+            "{filter:?}"
+        );
+    }
+    let mut batch = BatchBuilder::new();
+    batch.with_account_id(1000).with_collection(0);
+    for (document_id, size) in sizes {
+        batch
+            .delete_document(document_id)
+            .clear_content_length()
+            .clear_size_bucket(size);
+    }
+    db.write(batch.build()).await.unwrap();
+
+    // System flags are stored as a single bitset
+    let seen = Keyword::Seen.flag().unwrap();
+    let flagged = Keyword::Flagged.flag().unwrap();
+    assert_eq!(Keyword::Other("custom".to_string()).flag(), None);
+    assert_eq!(db.get_flags(1000, 0u8, 1).await.unwrap(), 0);
+    assert_eq!(
+        db.update_flags(1000, 0u8, 1, seen | flagged, 0)
+            .await
+            .unwrap(),
+        seen | flagged
+    );
+    assert_eq!(
+        db.update_flags(1000, 0u8, 1, 0, seen).await.unwrap(),
+        flagged
+    );
+    assert_eq!(db.get_flags(1000, 0u8, 1).await.unwrap(), flagged);
+    assert!(matches!(
+        db.write(
+            BatchBuilder::new()
+                .with_account_id(1000)
+                .with_collection(0)
+                .update_document(1)
+                .update_flags(Some(seen), 0, seen)
+                .build_batch(),
+        )
+        .await,
+        Err(store::Error::AssertValueFailed)
+    ));
+    db.write(
+        BatchBuilder::new()
+            .with_account_id(1000)
+            .with_collection(0)
+            .update_document(1)
+            .clear_flags()
+            .build_batch(),
+    )
+    .await
+    .unwrap();
+
+    // Force committed writes to durable storage
+    db.checkpoint().await.unwrap();
+}
+
+async fn test_writes(db: Store) {
+    // Testing ID assignment
+    println!("Running dynamic ID assignment tests...");
+    let mut builder = BatchBuilder::new();
+    builder
+        .with_account_id(0)
+        .with_collection(Collection::Thread)
+        .create_document()
+        .with_collection(Collection::Email)
+        .create_document()
+        .tag(
+            Property::ThreadId,
+            TagValue::Id(MaybeDynamicId::Dynamic(0)),
+            0,
+        )
+        .set(Property::ThreadId, MaybeDynamicId::Dynamic(0));
+
+    let assigned_ids = db.write(builder.build_batch()).await.unwrap();
+    assert_eq!(assigned_ids.document_ids.len(), 2);
+    let thread_id = assigned_ids.first_document_id().unwrap();
+    let email_id = assigned_ids.last_document_id().unwrap();
+
+    let email_ids = db
+        .get_bitmap(BitmapKey {
+            account_id: 0,
+            collection: Collection::Email.into(),
+            class: BitmapClass::DocumentIds,
+            document_id: 0,
+        })
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(email_ids.len(), 1);
+    assert!(email_ids.contains(email_id));
+
+    let thread_ids = db
+        .get_bitmap(BitmapKey {
+            account_id: 0,
+            collection: Collection::Thread.into(),
+            class: BitmapClass::DocumentIds,
+            document_id: 0,
+        })
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(thread_ids.len(), 1);
+    assert!(thread_ids.contains(thread_id));
+
+    let tagged_ids = db
+        .get_bitmap(BitmapKey {
+            account_id: 0,
+            collection: Collection::Email.into(),
+            class: BitmapClass::Tag {
+                field: Property::ThreadId.into(),
+                value: TagValue::Id(thread_id),
+            },
+            document_id: 0,
+        })
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(tagged_ids.len(), 1);
+    assert!(tagged_ids.contains(email_id));
+
+    let stored_thread_id = db
+        .get_value::<u32>(ValueKey {
+            account_id: 0,
+            collection: Collection::Email.into(),
+            document_id: email_id,
+            class: ValueClass::Property(Property::ThreadId.into()),
+        })
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(stored_thread_id, thread_id);
+
+    let mut builder = BatchBuilder::new();
+    builder
+        .with_account_id(0)
+        .with_collection(Collection::Thread)
+        .delete_document(thread_id)
+        .with_collection(Collection::Email)
+        .delete_document(email_id)
+        .tag(
+            Property::ThreadId,
+            TagValue::Id(MaybeDynamicId::Static(thread_id)),
+            F_CLEAR,
+        )
+        .clear(Property::ThreadId);
+    db.write(builder.build_batch()).await.unwrap();
+
+    // Monotonic allocation never hands out a deleted id again, unlike ReuseFreed
+    assert_eq!(IdAllocator::default(), IdAllocator::MonotonicNeverReuse);
+    for (collection, id_allocator) in [
+        (10u8, IdAllocator::MonotonicNeverReuse),
+        (11u8, IdAllocator::ReuseFreed),
+    ] {
+        let mut ids = Vec::new();
+        for _ in 0..4 {
+            if ids.len() == 3 {
+                let mut builder = BatchBuilder::new();
+                builder
+                    .with_account_id(0)
+                    .with_collection(collection)
+                    .delete_document(1);
+                db.write(builder.build_batch()).await.unwrap();
+            }
+            let mut builder = BatchBuilder::new();
+            builder
+                .with_account_id(0)
+                .with_collection(collection)
+                .with_id_allocator(id_allocator)
+                .create_document();
+            ids.push(
+                db.write(builder.build_batch())
+                    .await
+                    .unwrap()
+                    .last_document_id()
+                    .unwrap(),
+            );
+        }
+        if id_allocator == IdAllocator::MonotonicNeverReuse {
+            assert_eq!(ids, [0, 1, 2, 3]);
+        } else {
+            assert_eq!(ids, [0, 1, 2, 1]);
+        }
+
+        let mut builder = BatchBuilder::new();
+        builder.with_account_id(0).with_collection(collection);
+        for document_id in [0, 2, ids[3]] {
+            builder.delete_document(document_id);
+        }
+        db.write(builder.build_batch()).await.unwrap();
+    }
+
+    // Bitmaps are stored one key per document, so sparse ids don't allocate
+    // anything for the ranges in between
+    let sparse_ids = [0u32, 1, 65535, 65536, 131072, 1 << 20, 1 << 31];
+    let sparse_key = BitmapKey {
+        account_id: 0,
+        collection: 0,
+        class: BitmapClass::Tag {
+            field: 0,
+            value: TagValue::Id(0),
+        },
+        document_id: 0,
+    };
+    let mut builder = BatchBuilder::new();
+    builder.with_account_id(0).with_collection(0);
+    for document_id in sparse_ids {
+        builder
+            .update_document(document_id)
+            .tag(0u8, TagValue::Id(MaybeDynamicId::Static(0)), 0);
+    }
+    db.write(builder.build_batch()).await.unwrap();
+    assert_eq!(
+        db.get_bitmap(sparse_key.clone())
+            .await
+            .unwrap()
+            .unwrap()
+            .into_iter()
+            .collect::<Vec<_>>(),
+        sparse_ids
+    );
+    let mut builder = BatchBuilder::new();
+    builder.with_account_id(0).with_collection(0);
+    for document_id in sparse_ids {
+        builder.update_document(document_id).tag(
+            0u8,
+            TagValue::Id(MaybeDynamicId::Static(0)),
+            F_CLEAR,
+        );
+    }
+    db.write(builder.build_batch()).await.unwrap();
+    assert_eq!(db.get_bitmap(sparse_key).await.unwrap(), None);
+
+    // Increment a counter 1000 times concurrently
+    let mut handles = Vec::new();
+    let mut assigned_ids = HashSet::new();
+    println!("Incrementing counter 1000 times concurrently...");
+    for _ in 0..1000 {
+        handles.push({
+            let db = db.clone();
+            tokio::spawn(async move {
+                let mut builder = BatchBuilder::new();
+                builder
+                    .with_account_id(0)
+                    .with_collection(0)
+                    .update_document(0)
+                    .add_and_get(ValueClass::Directory(DirectoryClass::UsedQuota(0)), 1);
+                db.write(builder.build_batch())
+                    .await
+                    .unwrap()
+                    .last_counter_id()
+                    .unwrap()
+            })
+        });
+    }
+
+    for handle in handles {
+        let assigned_id = handle.await.unwrap();
+        assert!(
+            assigned_ids.insert(assigned_id),
+            "counter assigned {assigned_id} twice or more times."
+        );
+    }
+    assert_eq!(assigned_ids.len(), 1000);
+    assert_eq!(db.write_queue_depth().0, 0);
+
+    // Writes wait for a slot once the queue is full
+    let queue = WriteQueue::new(2);
+    let slots = [
+        queue.acquire().await.unwrap(),
+        queue.acquire().await.unwrap(),
+    ];
+    assert!(
+        tokio::time::timeout(Duration::from_millis(50), queue.acquire())
+            .await
+            .is_err()
+    );
+    assert_eq!(queue.depth(), 2);
+    let waiting = queue.acquire();
+    drop(slots);
+    let slot = waiting.await.unwrap();
+    assert_eq!(queue.depth(), 1);
+    drop(slot);
+    assert_eq!(queue.depth(), 0);
+    assert_eq!(
+        db.get_counter(ValueKey {
+            account_id: 0,
+            collection: 0,
+            document_id: 0,
+            class: ValueClass::Directory(DirectoryClass::UsedQuota(0)),
+        })
+        .await
+        .unwrap(),
+        1000
+    );
+
+    // Snapshot batches are committed like serializable ones when uncontended
+    assert_eq!(
+        db.write(
+            BatchBuilder::new()
+                .with_isolation(Isolation::Snapshot)
+                .with_account_id(0)
+                .with_collection(0)
+                .update_document(0)
+                .add_and_get(ValueClass::Directory(DirectoryClass::UsedQuota(0)), 1)
+                .build_batch(),
+        )
+        .await
+        .unwrap()
+        .last_counter_id()
+        .unwrap(),
+        1001
+    );
+
+    // Contended snapshot batches don't lose increments
+    let mut handles = Vec::new();
+    for _ in 0..100 {
+        handles.push({
+            let db = db.clone();
+            tokio::spawn(async move {
+                db.write(
+                    BatchBuilder::new()
+                        .with_isolation(Isolation::Snapshot)
+                        .with_account_id(0)
+                        .with_collection(0)
+                        .update_document(0)
+                        .add_and_get(ValueClass::Directory(DirectoryClass::UsedQuota(0)), 1)
+                        .build_batch(),
+                )
+                .await
+                .unwrap()
+                .last_counter_id()
+                .unwrap()
+            })
+        });
+    }
+    let mut assigned_ids = HashSet::new();
+    for handle in handles {
+        assert!(assigned_ids.insert(handle.await.unwrap()));
+    }
+    assert_eq!(assigned_ids.len(), 100);
+    assert_eq!(
+        db.get_counter(ValueKey {
+            account_id: 0,
+            collection: 0,
+            document_id: 0,
+            class: ValueClass::Directory(DirectoryClass::UsedQuota(0)),
+        })
+        .await
+        .unwrap(),
+        1101
+    );
+
+    // Values larger than the configured maximum should be rejected
+    assert!(matches!(
+        db.write(
+            BatchBuilder::new()
+                .with_account_id(0)
+                .with_collection(0)
+                .update_document(0)
+                .set(ValueClass::Property(1), vec![b'A'; db.max_value_size() + 1])
+                .build_batch(),
+        )
+        .await,
+        Err(store::Error::ValueTooLarge { .. })
+    ));
+
+    // Errors are classified for retry decisions
+    for (err, kind, is_retryable) in [
+        (store::Error::AssertValueFailed, ErrorKind::Conflict, true),
+        (
+            store::Error::Unavailable("down".to_string()),
+            ErrorKind::Transient,
+            true,
+        ),
+        (
+            store::Error::ValueTooLarge {
+                size: 2,
+                max_size: 1,
+            },
+            ErrorKind::QuotaExceeded,
+            false,
+        ),
+        (
+            store::Error::QuotaExceeded { used: 2, limit: 1 },
+            ErrorKind::QuotaExceeded,
+            false,
+        ),
+        (
+            store::Error::Timeout(Duration::ZERO),
+            ErrorKind::Timeout,
+            false,
+        ),
+        (
+            store::Error::Corrupted("bad".to_string()),
+            ErrorKind::Corruption,
+            false,
+        ),
+        (store::Error::ReadOnly, ErrorKind::ReadOnly, false),
+        (
+            store::Error::InternalError("oops".to_string()),
+            ErrorKind::Internal,
+            false,
+        ),
+    ] {
+        assert_eq!(err.kind(), kind, "{err:?}");
+        assert_eq!(err.is_retryable(), is_retryable, "{err:?}");
+    }
+    assert_eq!(
+        store::Error::from(std::io::Error::from(std::io::ErrorKind::NotFound)).kind(),
+        ErrorKind::NotFound
+    );
+    assert_eq!(
+        store::Error::from(std::io::Error::from(std::io::ErrorKind::TimedOut)).kind(),
+        ErrorKind::Transient
+    );
+
+    // Intents remain pending until they are ended
+    db.intent_begin(2, b"second".to_vec()).await.unwrap();
+    db.intent_begin(1, b"first".to_vec()).await.unwrap();
+    assert_eq!(
+        db.pending_intents().await.unwrap(),
+        vec![(1, b"first".to_vec()), (2, b"second".to_vec())]
+    );
+    db.intent_end(1).await.unwrap();
+    db.intent_end(2).await.unwrap();
+    assert_eq!(db.pending_intents().await.unwrap(), vec![]);
+
+    // Documents are quarantined after failing too many times
+    for expected in [false, false, true] {
+        assert_eq!(
+            db.record_failure(1000, 0u8, 7, "parse error", 3)
+                .await
+                .unwrap(),
+            expected
+        );
+    }
+    let quarantined = db.quarantined_documents().await.unwrap();
+    assert_eq!(quarantined.len(), 1);
+    assert_eq!(
+        (
+            quarantined[0].account_id,
+            quarantined[0].collection,
+            quarantined[0].document_id,
+            quarantined[0].entry.failures,
+            quarantined[0].entry.last_error.as_str()
+        ),
+        (1000, 0, 7, 3, "parse error")
+    );
+    db.clear_failures(1000, 0u8, 7).await.unwrap();
+    assert_eq!(db.get_failures(1000, 0u8, 7).await.unwrap(), None);
+    assert_eq!(db.quarantined_documents().await.unwrap(), vec![]);
+}
+
+async fn test_documents(db: Store) {
+    // Documents can be moved between collections
+    for (collection, document_id) in [(0u8, 3u32), (1u8, 0u32)] {
+        db.write(
+            BatchBuilder::new()
+                .with_account_id(2000)
+                .with_collection(collection)
+                .create_document_with_id(document_id)
+                .value(0u8, "moved", F_VALUE | F_INDEX)
+                .tag(1u8, 7u32, 0)
+                .set(
+                    BlobOp::Link {
+                        hash: BlobHash::from("moved blob".as_bytes()),
+                    },
+                    Vec::new(),
+                )
+                .build_batch(),
+        )
+        .await
+        .unwrap();
+    }
+    assert_eq!(
+        db.move_document(2000, 0u8, 1u8, 1000, 1, &[])
+            .await
+            .unwrap_err()
+            .kind(),
+        ErrorKind::NotFound
+    );
+    let document_id = db
+        .move_document(
+            2000,
+            0u8,
+            1u8,
+            3,
+            1,
+            &[BlobHash::from("moved blob".as_bytes())],
+        )
+        .await
+        .unwrap();
+    assert_ne!(document_id, 0);
+    for (collection, document_id, has_access) in
+        [(0u8, 3, false), (1u8, 0, true), (1u8, document_id, true)]
+    {
+        assert_eq!(
+            db.blob_has_access(
+                BlobHash::from("moved blob".as_bytes()),
+                BlobClass::Linked {
+                    account_id: 2000,
+                    collection,
+                    document_id,
+                },
+            )
+            .await
+            .unwrap(),
+            has_access
+        );
+    }
+    for (collection, change) in [
+        (0u8, Change::Delete(3)),
+        (1u8, Change::Insert(document_id as u64)),
+    ] {
+        assert_eq!(
+            db.changes(2000, collection, LogQuery::All)
+                .await
+                .unwrap()
+                .changes,
+            vec![change]
+        );
+    }
+    assert_eq!(
+        db.get_value::<String>(ValueKey::<ValueClass<u32>>::property(
+            2000,
+            1u8,
+            document_id,
+            0u8
+        ))
+        .await
+        .unwrap()
+        .as_deref(),
+        Some("moved")
+    );
+    assert_eq!(
+        db.get_value::<String>(ValueKey::<ValueClass<u32>>::property(2000, 0u8, 3, 0u8))
+            .await
+            .unwrap(),
+        None
+    );
+    for (collection, expected) in [(0u8, vec![]), (1u8, vec![0, document_id])] {
+        for filter in [Filter::eq(0u8, "moved"), Filter::is_in_bitmap(1u8, 7u32)] {
+            assert_eq!(
+                db.filter(2000, collection, vec![filter])
+                    .await
+                    .unwrap()
+                    .results
+                    .into_iter()
+                    .collect::<Vec<_>>(),
+                expected
+            );
+        }
+    }
+    for collection in [0u8, 1u8] {
+        let mut batch = BatchBuilder::new();
+        batch.with_account_id(2000).with_collection(collection);
+        for document_id in [0, 3, document_id] {
+            batch
+                .update_document(document_id)
+                .value(0u8, "moved", F_VALUE | F_INDEX | F_CLEAR)
+                .tag(1u8, 7u32, F_CLEAR)
+                .clear(BlobOp::Link {
+                    hash: BlobHash::from("moved blob".as_bytes()),
+                })
+                .delete_document(document_id);
+        }
+        db.write(batch.build_batch()).await.unwrap();
+    }
+    db.delete_range(
+        LogKey {
+            account_id: 2000,
+            collection: 0,
+            change_id: 0,
+        },
+        LogKey {
+            account_id: 2000,
+            collection: u8::MAX,
+            change_id: u64::MAX,
+        },
+    )
+    .await
+    .unwrap();
+
+    // Soft deleted documents are hidden from queries until restored or purged,
+    // unless tombstones are included
+    let include_tombstones = FilterOptions {
+        include_tombstones: true,
+        ..Default::default()
+    };
+    let mut batch = BatchBuilder::new();
+    batch.with_account_id(2001).with_collection(0u8);
+    for document_id in 0..3 {
+        batch
+            .create_document_with_id(document_id)
+            .value(0u8, "trash", F_VALUE | F_INDEX);
+    }
+    db.write(batch.build_batch()).await.unwrap();
+    db.write(
+        BatchBuilder::new()
+            .with_account_id(2001)
+            .with_collection(0u8)
+            .with_soft_delete(true)
+            .delete_document(1)
+            .delete_document(2)
+            .build_batch(),
+    )
+    .await
+    .unwrap();
+    for value in [None, Some("trash")] {
+        let filters = || {
+            value
+                .map(|value| vec![Filter::eq(0u8, value)])
+                .unwrap_or_default()
+        };
+        assert_eq!(
+            db.filter(2001, 0u8, filters())
+                .await
+                .unwrap()
+                .results
+                .into_iter()
+                .collect::<Vec<_>>(),
+            vec![0]
+        );
+        assert_eq!(
+            db.filter_with_options(2001, 0u8, filters(), &include_tombstones)
+                .await
+                .unwrap()
+                .results
+                .len(),
+            3
+        );
+    }
+    db.write(
+        BatchBuilder::new()
+            .with_account_id(2001)
+            .with_collection(0u8)
+            .restore_document(2)
+            .build_batch(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        db.get_tombstones(2001, 0u8)
+            .await
+            .unwrap()
+            .into_iter()
+            .collect::<Vec<_>>(),
+        vec![1]
+    );
+    assert!(db
+        .purge_tombstones(2001, 0u8, Duration::from_secs(3600))
+        .await
+        .unwrap()
+        .is_empty());
+    assert_eq!(
+        db.purge_tombstones(2001, 0u8, Duration::ZERO)
+            .await
+            .unwrap()
+            .into_iter()
+            .collect::<Vec<_>>(),
+        vec![1]
+    );
+    assert_eq!(
+        db.filter(2001, 0u8, vec![Filter::eq(0u8, "trash")])
+            .await
+            .unwrap()
+            .results
+            .into_iter()
+            .collect::<Vec<_>>(),
+        vec![0, 2]
+    );
+    let mut batch = BatchBuilder::new();
+    batch.with_account_id(2001).with_collection(0u8);
+    for document_id in [0, 2] {
+        batch
+            .update_document(document_id)
+            .value(0u8, "trash", F_VALUE | F_INDEX | F_CLEAR)
+            .delete_document(document_id);
+    }
+    db.write(batch.build_batch()).await.unwrap();
+
+    // Unread counts are computed from the document id and seen bitmaps
+    let mut batch = BatchBuilder::new();
+    batch
+        .with_account_id(2002)
+        .with_collection(Collection::Email);
+    for document_id in 0..6 {
+        batch.create_document_with_id(document_id);
+        if [1, 4].contains(&document_id) {
+            batch.tag(Property::Keywords, Keyword::Seen, 0);
+        }
+    }
+    db.write(batch.build_batch()).await.unwrap();
+    db.write(
+        BatchBuilder::new()
+            .with_account_id(2002)
+            .with_collection(Collection::Email)
+            .with_soft_delete(true)
+            .delete_document(5)
+            .build_batch(),
+    )
+    .await
+    .unwrap();
+    for (since_id, expected) in [(0, 3), (3, 1), (5, 0), (100, 0)] {
+        assert_eq!(
+            db.unread_since(
+                2002,
+                Collection::Email,
+                Property::Keywords,
+                Keyword::Seen,
+                since_id
+            )
+            .await
+            .unwrap(),
+            expected
+        );
+    }
+    assert_eq!(
+        db.unread_since(
+            2003,
+            Collection::Email,
+            Property::Keywords,
+            Keyword::Seen,
+            0
+        )
+        .await
+        .unwrap(),
+        0
+    );
+    db.purge_account(2002).await.unwrap();
+
+    // Cached bitmap cardinalities follow sets and clears, including bitmaps
+    // referenced by ids assigned in the same batch
+    let thread_key = || BitmapKey::tag(2004, Collection::Email, Property::ThreadId, 0u32);
+    assert_eq!(db.get_bitmap_cardinality(thread_key()).await.unwrap(), 0);
+    let mut batch = BatchBuilder::new();
+    batch
+        .with_account_id(2004)
+        .with_collection(Collection::Thread)
+        .create_document()
+        .with_collection(Collection::Email);
+    for _ in 0..3 {
+        batch.create_document().tag(
+            Property::ThreadId,
+            TagValue::Id(MaybeDynamicId::Dynamic(0)),
+            0,
+        );
+    }
+    let assigned_ids = db.write(batch.build_batch()).await.unwrap();
+    assert_eq!(assigned_ids.first_document_id().unwrap(), 0);
+    assert_eq!(db.get_bitmap_cardinality(thread_key()).await.unwrap(), 3);
+    assert_eq!(
+        db.get_bitmap_cardinality(BitmapKey::document_ids(2004, Collection::Email))
+            .await
+            .unwrap(),
+        3
+    );
+    db.write(
+        BatchBuilder::new()
+            .with_account_id(2004)
+            .with_collection(Collection::Email)
+            .update_document(1)
+            .tag(
+                Property::ThreadId,
+                TagValue::Id(MaybeDynamicId::Static(0)),
+                F_CLEAR,
+            )
+            .build_batch(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(db.get_bitmap_cardinality(thread_key()).await.unwrap(), 2);
+    db.purge_account(2004).await.unwrap();
+    assert_eq!(db.get_bitmap_cardinality(thread_key()).await.unwrap(), 0);
+    assert_eq!(
+        db.get_bitmap_cardinality(BitmapKey::document_ids(2004, Collection::Email))
+            .await
+            .unwrap(),
+        0
+    );
+
+    // Key distribution by account
+    let mut batch = BatchBuilder::new();
+    for (account_id, num_docs) in [(3001, 5), (3002, 2)] {
+        batch.with_account_id(account_id).with_collection(0u8);
+        for document_id in 0..num_docs {
+            batch
+                .update_document(document_id)
+                .set(ValueClass::Property(0), "abc".as_bytes());
+        }
+    }
+    db.write(batch.build_batch()).await.unwrap();
+    let exact = db
+        .scan_subspace(SUBSPACE_PROPERTY, ScanMode::Exact)
+        .await
+        .unwrap();
+    assert!(exact.is_exact);
+    assert_eq!(exact.accounts[&3001], KeyUsage { keys: 5, bytes: 65 });
+    assert_eq!(exact.accounts[&3002], KeyUsage { keys: 2, bytes: 26 });
+    assert_eq!(exact.top_accounts(1)[0].0, 3001);
+    let sampled = db
+        .scan_subspace(
+            SUBSPACE_PROPERTY,
+            ScanMode::Sample {
+                segments: 4,
+                keys_per_segment: usize::MAX,
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(sampled, exact);
+    assert!(
+        !db.scan_subspace(
+            SUBSPACE_PROPERTY,
+            ScanMode::Sample {
+                segments: 1,
+                keys_per_segment: 1,
+            },
+        )
+        .await
+        .unwrap()
+        .is_exact
+    );
+    let mut batch = BatchBuilder::new();
+    for (account_id, num_docs) in [(3001, 5), (3002, 2)] {
+        batch.with_account_id(account_id).with_collection(0u8);
+        for document_id in 0..num_docs {
+            batch
+                .update_document(document_id)
+                .clear(ValueClass::Property(0));
+        }
+    }
+    db.write(batch.build_batch()).await.unwrap();
+}
+
+async fn test_usage(db: Store) {
+    // Usage counters follow writes and match a full scan
+    db.write(
+        BatchBuilder::new()
+            .with_account_id(3003)
+            .with_collection(0u8)
+            .create_document_with_id(0)
+            .set(ValueClass::Property(0), "abc".as_bytes())
+            .value(1u8, "xyz", F_INDEX)
+            .create_document_with_id(1)
+            .set(ValueClass::Property(0), "abc".as_bytes())
+            .set(ValueClass::Property(0), "abcdef".as_bytes())
+            .build_batch(),
+    )
+    .await
+    .unwrap();
+    let usage = db.account_usage(3003).await.unwrap();
+    assert_eq!(usage.subspaces[&SUBSPACE_PROPERTY], 29);
+    assert_eq!(usage.subspaces[&SUBSPACE_INDEXES], 13);
+    assert_eq!(db.recalculate_usage(3003).await.unwrap(), usage);
+    db.write(
+        BatchBuilder::new()
+            .with_account_id(3003)
+            .with_collection(0u8)
+            .update_document(0)
+            .clear(ValueClass::Property(0))
+            .value(1u8, "xyz", F_INDEX | F_CLEAR)
+            .update_document(1)
+            .set(ValueClass::Property(0), "a".as_bytes())
+            .build_batch(),
+    )
+    .await
+    .unwrap();
+    // Changes to existing documents are reflected as they are written
+    let usage = db.account_usage(3003).await.unwrap();
+    assert_eq!(usage.subspaces[&SUBSPACE_PROPERTY], 11);
+    assert!(!usage.subspaces.contains_key(&SUBSPACE_INDEXES));
+    assert_eq!(db.recalculate_usage(3003).await.unwrap(), usage);
+
+    // Range deletions are only reflected once the counters are recalculated
+    let key = |document_id| ValueKey {
+        account_id: 3003,
+        collection: 0,
+        document_id,
+        class: ValueClass::Property(0),
+    };
+    db.delete_range(key(1), key(2)).await.unwrap();
+    assert_eq!(
+        db.account_usage(3003).await.unwrap().subspaces[&SUBSPACE_PROPERTY],
+        11
+    );
+    assert!(!db
+        .recalculate_usage(3003)
+        .await
+        .unwrap()
+        .subspaces
+        .contains_key(&SUBSPACE_PROPERTY));
+    db.purge_account(3003).await.unwrap();
+    assert_eq!(db.account_usage(3003).await.unwrap().total(), 0);
+    db.recalculate_usage(u32::MAX).await.unwrap();
+
+    // Writes taking an account over its quota are rejected as a whole
+    let quota = Quota::new(100).with_soft_limit(50);
+    let result = db
+        .write(
+            BatchBuilder::new()
+                .with_quota(3004, quota)
+                .with_account_id(3004)
+                .with_collection(0u8)
+                .create_document_with_id(0)
+                .set(ValueClass::Property(0), vec![0u8; 40])
+                .build_batch(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(result.soft_limit_reached, vec![3004]);
+    let used = db.account_usage(3004).await.unwrap().total();
+    assert!(used < 100);
+    assert!(matches!(
+        db.write(
+            BatchBuilder::new()
+                .with_quota(3004, quota)
+                .with_account_id(3004)
+                .with_collection(0u8)
+                .create_document_with_id(1)
+                .set(ValueClass::Property(0), vec![0u8; 40])
+                .build_batch(),
+        )
+        .await,
+        Err(store::Error::QuotaExceeded { limit: 100, .. })
+    ));
+    assert!(matches!(
+        db.write(
+            BatchBuilder::new()
+                .with_quota(3004, quota)
+                .add(DirectoryClass::UsedQuota(3004), 100 - used as i64 + 1)
+                .build_batch(),
+        )
+        .await,
+        Err(store::Error::QuotaExceeded { .. })
+    ));
+    assert_eq!(db.account_usage(3004).await.unwrap().total(), used);
+    assert_eq!(
+        db.get_value::<()>(ValueKey {
+            account_id: 3004,
+            collection: 0,
+            document_id: 1,
+            class: ValueClass::Property(0),
+        })
+        .await
+        .unwrap(),
+        None
+    );
+
+    // Writes freeing space are accepted even over the quota
+    assert!(db
+        .write(
+            BatchBuilder::new()
+                .with_quota(3004, Quota::new(0))
+                .with_account_id(3004)
+                .with_collection(0u8)
+                .update_document(0)
+                .clear(ValueClass::Property(0))
+                .build_batch(),
+        )
+        .await
+        .unwrap()
+        .soft_limit_reached
+        .is_empty());
+    db.purge_account(3004).await.unwrap();
+
+    // Multi-key conditional writes apply all writes or none
+    let key = |document_id| ValueKey {
+        account_id: 4001,
+        collection: 0,
+        document_id,
+        class: ValueClass::Property(0),
+    };
+    assert!(db
+        .conditional_write(
+            vec![(key(0), None)],
+            vec![(key(0), b"a".to_vec()), (key(1), b"b".to_vec())],
+        )
+        .await
+        .unwrap());
+    assert!(!db
+        .conditional_write(vec![(key(0), None)], vec![(key(1), b"x".to_vec())])
+        .await
+        .unwrap());
+    assert!(!db
+        .conditional_write(
+            vec![(key(0), Some(b"a".to_vec())), (key(1), Some(b"x".to_vec()))],
+            vec![(key(0), b"c".to_vec())],
+        )
+        .await
+        .unwrap());
+    for (document_id, value) in [(0, "a"), (1, "b")] {
+        assert_eq!(
+            db.get_value::<String>(key(document_id)).await.unwrap(),
+            Some(value.to_string())
+        );
+    }
+    assert!(db
+        .conditional_write(
+            vec![(key(0), Some(b"a".to_vec())), (key(1), Some(b"b".to_vec()))],
+            vec![(key(0), b"c".to_vec())],
+        )
+        .await
+        .unwrap());
+    assert_eq!(
+        db.get_value::<String>(key(0)).await.unwrap(),
+        Some("c".to_string())
+    );
+    db.write(
+        BatchBuilder::new()
+            .with_account_id(4001)
+            .with_collection(0u8)
+            .update_document(0)
+            .clear(ValueClass::Property(0))
+            .update_document(1)
+            .clear(ValueClass::Property(0))
+            .build_batch(),
+    )
+    .await
+    .unwrap();
+
+    // Writes are rejected while the store is read-only, reads are not
+    db.set_read_only(true);
+    assert!(db.is_read_only());
+    assert!(matches!(
+        db.write(
+            BatchBuilder::new()
+                .with_account_id(4001)
+                .with_collection(0u8)
+                .update_document(0)
+                .set(ValueClass::Property(0), "ro".as_bytes())
+                .build_batch(),
+        )
+        .await,
+        Err(store::Error::ReadOnly)
+    ));
+    assert_eq!(db.get_value::<String>(key(0)).await.unwrap(), None);
+    db.set_read_only(false);
+
+    // Retry policies bound the number of attempts, the delay between them and the
+    // time spent retrying
+    for policy in [
+        RetryPolicy::INTERACTIVE,
+        RetryPolicy::BACKGROUND,
+        RetryPolicy::CRITICAL,
+    ] {
+        for attempt in 0..64 {
+            let delay = policy.delay(attempt);
+            assert!(
+                delay >= policy.base_delay && delay <= policy.max_delay,
+                "{policy:?} {attempt} {delay:?}"
+            );
+        }
+        assert!(policy.can_retry(0, Duration::ZERO));
+        assert!(!policy.can_retry(policy.max_attempts - 1, Duration::ZERO));
+        assert!(!policy.can_retry(0, policy.max_time));
+    }
+    assert!(RetryPolicy::CRITICAL.max_time > RetryPolicy::BACKGROUND.max_time);
+    let policy = RetryPolicy {
+        max_attempts: 4,
+        base_delay: Duration::from_millis(1),
+        max_delay: Duration::from_millis(2),
+        max_time: Duration::from_secs(60),
+        jitter: true,
+    };
+    let mut attempts = 0;
+    assert!(matches!(
+        policy
+            .run(|| {
+                attempts += 1;
+                async { Err::<(), _>(store::Error::Unavailable("down".to_string())) }
+            })
+            .await,
+        Err(store::Error::Unavailable(_))
+    ));
+    assert_eq!(attempts, policy.max_attempts);
+    let mut attempts = 0;
+    assert!(RetryPolicy {
+        max_attempts: 1000,
+        max_time: Duration::from_millis(20),
+        ..policy
+    }
+    .run(|| {
+        attempts += 1;
+        async { Err::<(), _>(store::Error::Unavailable("down".to_string())) }
+    })
+    .await
+    .is_err());
+    assert!(attempts > 1 && attempts < 1000, "{attempts}");
+    let errors: [fn() -> store::Error; 2] = [
+        || store::Error::ReadOnly,
+        || store::Error::InternalError("fatal".to_string()),
+    ];
+    for err in errors {
+        let mut attempts = 0;
+        assert!(policy
+            .run(|| {
+                attempts += 1;
+                async move { Err::<(), _>(err()) }
+            })
+            .await
+            .is_err());
+        assert_eq!(attempts, 1);
+    }
+    db.write(
+        BatchBuilder::new()
+            .with_retry_policy(RetryPolicy::INTERACTIVE)
+            .with_account_id(4001)
+            .with_collection(0u8)
+            .update_document(0)
+            .set(ValueClass::Property(0), "retry".as_bytes())
+            .build_batch(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        db.get_value::<String>(key(0)).await.unwrap(),
+        Some("retry".to_string())
+    );
+    db.write(
+        BatchBuilder::new()
+            .with_account_id(4001)
+            .with_collection(0u8)
+            .update_document(0)
+            .clear(ValueClass::Property(0))
+            .build_batch(),
+    )
+    .await
+    .unwrap();
+}
+
+async fn test_collections(db: Store) {
+    // Collections are registered when documents are created
+    assert_eq!(db.list_collections(5001).await.unwrap(), Vec::<u8>::new());
+    for collection in [5u8, 2, 5] {
+        db.write(
+            BatchBuilder::new()
+                .with_account_id(5001)
+                .with_collection(collection)
+                .create_document()
+                .build_batch(),
+        )
+        .await
+        .unwrap();
+    }
+    assert_eq!(db.list_collections(5001).await.unwrap(), vec![2, 5]);
+    assert_eq!(db.list_collections(5002).await.unwrap(), Vec::<u8>::new());
+
+    // Renaming a collection only updates its registry entry
+    assert!(matches!(
+        db.rename_collection(5001, 3u8, "Archive").await,
+        Err(store::Error::NotFound(_))
+    ));
+    assert_eq!(db.get_collection_name(5001, 5u8).await.unwrap(), None);
+    db.rename_collection(5001, 5u8, "Archive").await.unwrap();
+    db.rename_collection(5001, 5u8, "Old Archive")
+        .await
+        .unwrap();
+    assert_eq!(
+        db.get_collection_name(5001, 5u8).await.unwrap(),
+        Some("Old Archive".to_string())
+    );
+    assert_eq!(db.list_collections(5001).await.unwrap(), vec![2, 5]);
+
+    // Queries over all collections can leave some of them out
+    let query = Query::new(vec![]);
+    assert_eq!(
+        db.query_collections(5001, &query)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|result| (result.collection, result.results.len()))
+            .collect::<Vec<_>>(),
+        vec![(2, 1), (5, 2)]
+    );
+    assert_eq!(
+        db.query_collections(5001, &query.clone().exclude_collections(&[5]))
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|result| (result.collection, result.results.len()))
+            .collect::<Vec<_>>(),
+        vec![(2, 1)]
+    );
+
+    // Documents in excluded mailboxes are left out of every collection
+    let mut batch = BatchBuilder::new();
+    batch
+        .with_account_id(5001)
+        .with_collection(5u8)
+        .update_document(0)
+        .tag(0u8, 7u32, 0)
+        .with_collection(2u8)
+        .update_document(0)
+        .tag(0u8, 8u32, 0);
+    db.write(batch.build_batch()).await.unwrap();
+    for (excluded, expected) in [
+        (vec![7u32], vec![(2, 1), (5, 1)]),
+        (vec![7, 8], vec![(5, 1)]),
+        (vec![9], vec![(2, 1), (5, 2)]),
+    ] {
+        assert_eq!(
+            db.query_collections(5001, &query.clone().exclude_tagged(0u8, &excluded))
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|result| (result.collection, result.results.len()))
+                .collect::<Vec<_>>(),
+            expected
+        );
+    }
+    let mut batch = BatchBuilder::new();
+    batch
+        .with_account_id(5001)
+        .with_collection(5u8)
+        .update_document(0)
+        .tag(0u8, 7u32, F_CLEAR)
+        .with_collection(2u8)
+        .update_document(0)
+        .tag(0u8, 8u32, F_CLEAR);
+    db.write(batch.build_batch()).await.unwrap();
+
+    // Candidate sets limit the results before any filter is evaluated
+    for (query, expected) in [
+        (
+            Query::new(vec![]).within([1, 7].into_iter().collect()),
+            vec![(5, 1)],
+        ),
+        (
+            Query::new(vec![Filter::is_in_set([0, 1].into_iter().collect())])
+                .within([0, 7].into_iter().collect()),
+            vec![(2, 1), (5, 1)],
+        ),
+        (Query::new(vec![]).within(Default::default()), vec![]),
+    ] {
+        assert_eq!(
+            db.query_collections(5001, &query)
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|result| (result.collection, result.results.len()))
+                .collect::<Vec<_>>(),
+            expected
+        );
+    }
+    for document_id in db
+        .get_bitmap(BitmapKey::document_ids(5001, 5u8))
+        .await
+        .unwrap()
+        .unwrap()
+    {
+        db.write(
+            BatchBuilder::new()
+                .with_account_id(5001)
+                .with_collection(5u8)
+                .delete_document(document_id)
+                .build_batch(),
+        )
+        .await
+        .unwrap();
+    }
+    assert_eq!(db.list_collections(5001).await.unwrap(), vec![2]);
+    db.purge_account(5001).await.unwrap();
+    assert_eq!(db.list_collections(5001).await.unwrap(), Vec::<u8>::new());
+
+    // Collections of documents created before the registry existed are backfilled once
+    db.write(
+        BatchBuilder::new()
+            .with_account_id(5004)
+            .with_collection(4u8)
+            .create_document()
+            .clear(ValueClass::Collection)
+            .build_batch(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(db.list_collections(5004).await.unwrap(), Vec::<u8>::new());
+    assert_eq!(db.backfill_collections().await.unwrap(), 1);
+    assert_eq!(db.list_collections(5004).await.unwrap(), vec![4]);
+    assert_eq!(db.backfill_collections().await.unwrap(), 0);
+    db.purge_account(5004).await.unwrap();
+    db.write(
+        BatchBuilder::new()
+            .clear(ValueClass::Any(AnyClass {
+                subspace: SUBSPACE_CLUSTER,
+                key: COLLECTIONS_BACKFILL_KEY.to_vec(),
+            }))
+            .build_batch(),
+    )
+    .await
+    .unwrap();
+
+    // Shared documents are queried through the ACLs of the document or its container
+    for (mailbox_id, grant_account_id, permissions) in [(0u32, 5102u32, 1u64), (1, 5103, 2)] {
+        db.write(
+            BatchBuilder::new()
+                .with_account_id(5101)
+                .with_collection(1u8)
+                .update_document(mailbox_id)
+                .set(ValueClass::Acl(grant_account_id), permissions.serialize())
+                .build_batch(),
+        )
+        .await
+        .unwrap();
+    }
+    for (account_id, mailbox_id) in [(5101, 0u32), (5101, 0), (5101, 1), (5102, 0)] {
+        db.write(
+            BatchBuilder::new()
+                .with_account_id(account_id)
+                .with_collection(0u8)
+                .create_document()
+                .tag(0u8, mailbox_id, 0)
+                .build_batch(),
+        )
+        .await
+        .unwrap();
+    }
+    db.write(
+        BatchBuilder::new()
+            .with_account_id(5101)
+            .with_collection(0u8)
+            .update_document(2)
+            .set(ValueClass::Acl(5102), 1u64.serialize())
+            .build_batch(),
+    )
+    .await
+    .unwrap();
+    let own = QueryScope::new(5102, 0u8);
+    let shared = QueryScope::new(5101, 0u8).with_container(1u8, 0u8);
+    let member = Principal::new(5102, 1).with_member_of([5103]);
+    for (principal, scopes, expected) in [
+        (
+            member.clone(),
+            vec![own, shared],
+            vec![(5102, vec![0]), (5101, vec![0, 1])],
+        ),
+        (
+            Principal {
+                permissions: 1 | 2,
+                ..member.clone()
+            },
+            vec![shared, own, shared],
+            vec![(5101, vec![0, 1, 2]), (5102, vec![0])],
+        ),
+        (Principal::new(5102, 2), vec![shared], vec![]),
+        (
+            Principal::new(5102, 1),
+            vec![QueryScope::new(5101, 0u8)],
+            vec![(5101, vec![2])],
+        ),
+    ] {
+        assert_eq!(
+            db.query_shared(&principal, &scopes, vec![])
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|result| (result.account_id, result.results.into_iter().collect()))
+                .collect::<Vec<(u32, Vec<u32>)>>(),
+            expected
+        );
+    }
+
+    // Documents the principal can't see are never returned by a filter
+    assert_eq!(
+        db.filter_as(&member, &shared, vec![Filter::is_in_set((1..=2).collect())])
+            .await
+            .unwrap()
+            .results
+            .into_iter()
+            .collect::<Vec<_>>(),
+        vec![1]
+    );
+    assert!(db
+        .filter_as(&member, &shared, vec![Filter::is_in_bitmap(0u8, 1u32)])
+        .await
+        .unwrap()
+        .results
+        .is_empty());
+    db.write(
+        BatchBuilder::new()
+            .with_account_id(5101)
+            .with_collection(0u8)
+            .delete_document(2)
+            .build_batch(),
+    )
+    .await
+    .unwrap();
+    assert!(db
+        .filter_as(
+            &Principal::new(5102, 1),
+            &QueryScope::new(5101, 0u8),
+            vec![]
+        )
+        .await
+        .unwrap()
+        .results
+        .is_empty());
+
+    // Restricted fields only match documents granted access to them
+    let mut batch = BatchBuilder::new();
+    batch.with_account_id(5101).with_collection(0u8);
+    for document_id in [0, 1] {
+        batch
+            .update_document(document_id)
+            .value(1u8, "secret", F_VALUE | F_INDEX);
+    }
+    batch.with_collection(2u8);
+    for (document_id, parent_id) in [(20, 0), (21, 1)] {
+        batch
+            .create_document_with_id(document_id)
+            .value(1u8, "secret", F_INDEX)
+            .set_parent(parent_id);
+    }
+    db.write(batch.build_batch()).await.unwrap();
+    let restricted = Principal::new(5102, 1).with_restricted_field(1u8, 4);
+    let secret_child = || vec![Filter::has_child(2u8, vec![Filter::eq(1u8, "secret")])];
+    for (permissions, principal, filters, expected) in [
+        (1u64, &restricted, vec![Filter::eq(1u8, "secret")], vec![]),
+        (
+            1,
+            &restricted,
+            vec![Filter::Not, Filter::eq(1u8, "secret"), Filter::End],
+            vec![0, 1],
+        ),
+        (
+            1,
+            &Principal::new(5102, 1),
+            vec![Filter::eq(1u8, "secret")],
+            vec![0, 1],
+        ),
+        (1, &restricted, secret_child(), vec![]),
+        (1 | 4, &restricted, secret_child(), vec![0, 1]),
+        (
+            1 | 4,
+            &restricted,
+            vec![Filter::eq(1u8, "secret")],
+            vec![0, 1],
+        ),
+    ] {
+        db.write(
+            BatchBuilder::new()
+                .with_account_id(5101)
+                .with_collection(1u8)
+                .update_document(0)
+                .set(ValueClass::Acl(5102), permissions.serialize())
+                .build_batch(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            db.filter_as(principal, &shared, filters)
+                .await
+                .unwrap()
+                .results
+                .into_iter()
+                .collect::<Vec<_>>(),
+            expected
+        );
+    }
+
+    // Restricted fields can only be sorted by or read once access is granted
+    for permissions in [1 | 4, 1u64] {
+        db.write(
+            BatchBuilder::new()
+                .with_account_id(5101)
+                .with_collection(1u8)
+                .update_document(0)
+                .set(ValueClass::Acl(5102), permissions.serialize())
+                .build_batch(),
+        )
+        .await
+        .unwrap();
+        let is_granted = permissions & 4 != 0;
+        let results = db.filter_as(&restricted, &shared, vec![]).await.unwrap();
+        assert_eq!(
+            db.sort_as(
+                &restricted,
+                &shared,
+                results,
+                vec![Comparator::field(1u8, true)],
+                Pagination::new(0, 0, None, 0),
+            )
+            .await
+            .is_ok(),
+            is_granted
+        );
+        assert_eq!(
+            db.get_property_as::<String>(&restricted, &shared, 0, 1u8)
+                .await
+                .unwrap(),
+            is_granted.then(|| "secret".to_string())
+        );
+    }
+    db.purge_account(5101).await.unwrap();
+    db.purge_account(5102).await.unwrap();
+}
+
+async fn test_maintenance(db: Store) {
+    // Versioned values are upgraded to the current format when read
+    let key = ValueKey {
+        account_id: 6001,
+        collection: 0,
+        document_id: 0,
+        class: ValueClass::Property(0),
+    };
+    db.write(
+        BatchBuilder::new()
+            .with_account_id(6001)
+            .with_collection(0u8)
+            .update_document(0)
+            .set(ValueClass::Property(0), vec![1u8, 0, 0, 0, 42])
+            .build_batch(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        db.get_value::<Versioned<StoredQuota>>(key.clone())
+            .await
+            .unwrap()
+            .unwrap(),
+        Versioned {
+            inner: StoredQuota(42),
+            version: 1
+        }
+    );
+    assert_eq!(
+        db.get_versioned::<StoredQuota>(key.clone()).await.unwrap(),
+        Some(StoredQuota(42))
+    );
+    assert_eq!(
+        db.get_value::<Versioned<StoredQuota>>(key.clone())
+            .await
+            .unwrap()
+            .unwrap(),
+        Versioned::new(StoredQuota(42))
+    );
+    db.write(
+        BatchBuilder::new()
+            .with_account_id(6001)
+            .with_collection(0u8)
+            .update_document(0)
+            .set(ValueClass::Property(0), vec![3u8, 0])
+            .build_batch(),
+    )
+    .await
+    .unwrap();
+    assert!(db.get_versioned::<StoredQuota>(key.clone()).await.is_err());
+
+    // Outdated values are still upgraded on read while the store is read-only,
+    // but are only written back once it is writable again
+    for (value, expected) in [(vec![1u8, 0, 0, 0, 42], 42), (vec![0u8, 0, 0, 7], 7)] {
+        db.write(
+            BatchBuilder::new()
+                .with_account_id(6001)
+                .with_collection(0u8)
+                .update_document(0)
+                .set(ValueClass::Property(0), value.clone())
+                .build_batch(),
+        )
+        .await
+        .unwrap();
+        db.set_read_only(true);
+        assert_eq!(
+            db.get_versioned::<StoredQuota>(key.clone()).await.unwrap(),
+            Some(StoredQuota(expected))
+        );
+        db.set_read_only(false);
+        assert!(db
+            .get_value::<Versioned<StoredQuota>>(key.clone())
+            .await
+            .unwrap()
+            .unwrap()
+            .is_outdated());
+        assert_eq!(
+            db.get_versioned::<StoredQuota>(key.clone()).await.unwrap(),
+            Some(StoredQuota(expected))
+        );
+        assert_eq!(
+            db.get_value::<Versioned<StoredQuota>>(key.clone())
+                .await
+                .unwrap()
+                .unwrap(),
+            Versioned::new(StoredQuota(expected))
+        );
+    }
+    db.write(
+        BatchBuilder::new()
+            .with_account_id(6001)
+            .with_collection(0u8)
+            .update_document(0)
+            .clear(ValueClass::Property(0))
+            .build_batch(),
+    )
+    .await
+    .unwrap();
+
+    // Duplicates are grouped by blob first, then by fingerprint
+    let mut batch = BatchBuilder::new();
+    for (account_id, collection, document_id, blob, fingerprint) in [
+        (8001u32, 0u8, 1u32, "msg-a", Some("body-a")),
+        (8001, 0, 2, "msg-a", Some("body-a")),
+        (8001, 0, 3, "msg-a", None),
+        (8001, 0, 4, "msg-b", Some("body-b")),
+        (8001, 0, 5, "msg-b-fwd", Some("body-b")),
+        (8001, 0, 6, "msg-c", Some("body-c")),
+        (8001, 1, 7, "msg-a", Some("body-a")),
+        (8002, 0, 8, "msg-c", Some("body-c")),
+    ] {
+        let hash = BlobHash::from(blob.as_bytes());
+        batch
+            .with_account_id(account_id)
+            .with_collection(collection)
+            .create_document_with_id(document_id)
+            .set(BlobOp::Link { hash: hash.clone() }, vec![]);
+        if let Some(fingerprint) = fingerprint {
+            batch.set_fingerprint(&hash, fingerprint.as_bytes());
+        }
+    }
+    db.write(batch.build_batch()).await.unwrap();
+    assert_eq!(
+        db.find_duplicates(8001, 0u8).await.unwrap(),
+        vec![
+            DuplicateSet {
+                documents: store::roaring::RoaringBitmap::from_iter([1u32, 2]),
+                is_identical: true,
+            },
+            DuplicateSet {
+                documents: store::roaring::RoaringBitmap::from_iter([4u32, 5]),
+                is_identical: false,
+            },
+        ]
+    );
+    assert_eq!(db.find_duplicates(8001, 1u8).await.unwrap(), vec![]);
+    assert_eq!(db.find_duplicates(8002, 0u8).await.unwrap(), vec![]);
+    let hash = BlobHash::from("msg-a".as_bytes());
+    db.write(
+        BatchBuilder::new()
+            .with_account_id(8001)
+            .with_collection(0u8)
+            .delete_document(2)
+            .clear(BlobOp::Link { hash: hash.clone() })
+            .clear_fingerprint(&hash, "body-a".as_bytes())
+            .build_batch(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        db.find_duplicates(8001, 0u8).await.unwrap(),
+        vec![DuplicateSet {
+            documents: store::roaring::RoaringBitmap::from_iter([4u32, 5]),
+            is_identical: false,
+        }]
+    );
+    for account_id in [8001, 8002] {
+        db.blob_hash_unlink_account(account_id).await.unwrap();
+        db.purge_account(account_id).await.unwrap();
+    }
+
+    // Renaming a label moves its members along with its metadata
+    let change_ids = SnowflakeIdGenerator::new();
+    let registry = LabelRegistry::new(9001, 0u8, 1u8);
+    let (work, home) = (
+        LabelMetadata {
+            color: Some("#ff0000".to_string()),
+            sort_order: 2,
+        },
+        LabelMetadata {
+            color: None,
+            sort_order: 1,
+        },
+    );
+    db.create_label(&registry, "work", work.clone())
+        .await
+        .unwrap();
+    db.create_label(&registry, "home", home.clone())
+        .await
+        .unwrap();
+    assert!(matches!(
+        db.create_label(&registry, "work", home.clone()).await,
+        Err(store::Error::AssertValueFailed)
+    ));
+    let mut batch = BatchBuilder::new();
+    batch.with_account_id(9001).with_collection(0);
+    for (document_id, label) in [(1u32, "work"), (2, "work"), (3, "home")] {
+        batch.create_document_with_id(document_id).tag(
+            1u8,
+            TagValue::Text(label.as_bytes().to_vec()),
+            0,
+        );
+    }
+    db.write(batch.build()).await.unwrap();
+    let label_members =
+        |label: &str| BitmapKey::tag(9001, 0u8, 1u8, TagValue::Text(label.as_bytes().to_vec()));
+    assert_eq!(
+        db.rename_label(&registry, "work", "office", &change_ids)
+            .await
+            .unwrap(),
+        store::roaring::RoaringBitmap::from_iter([1u32, 2])
+    );
+    assert_eq!(db.get_bitmap(label_members("work")).await.unwrap(), None);
+    assert_eq!(
+        db.get_bitmap(label_members("office")).await.unwrap(),
+        Some(store::roaring::RoaringBitmap::from_iter([1u32, 2]))
+    );
+    assert_eq!(db.get_label(&registry, "work").await.unwrap(), None);
+    assert_eq!(
+        db.list_labels(&registry).await.unwrap(),
+        vec![
+            Label {
+                name: "home".to_string(),
+                metadata: home.clone(),
+            },
+            Label {
+                name: "office".to_string(),
+                metadata: work.clone(),
+            },
+        ]
+    );
+    assert!(matches!(
+        db.rename_label(&registry, "home", "office", &change_ids)
+            .await,
+        Err(store::Error::AssertValueFailed)
+    ));
+    db.update_label(
+        &registry,
+        "home",
+        LabelMetadata {
+            sort_order: 3,
+            ..home
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        db.list_labels(&registry)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|label| label.name)
+            .collect::<Vec<_>>(),
+        vec!["office".to_string(), "home".to_string()]
+    );
+    for (label, members) in [("office", vec![1u32, 2]), ("home", vec![3])] {
+        assert_eq!(
+            db.delete_label(&registry, label, &change_ids)
+                .await
+                .unwrap(),
+            store::roaring::RoaringBitmap::from_iter(members)
+        );
+        assert_eq!(db.get_bitmap(label_members(label)).await.unwrap(), None);
+    }
+    assert_eq!(db.list_labels(&registry).await.unwrap(), vec![]);
+
+    // Large labels are relabeled in several batches, each logging its changes
+    db.create_label(&registry, "bulk", LabelMetadata::default())
+        .await
+        .unwrap();
+    let mut batch = BatchBuilder::new();
+    batch.with_account_id(9001).with_collection(0);
+    for document_id in 100..1100u32 {
         batch
-            .with_account_id(0)
-            .with_collection(0)
-            .update_document(0);
-        for n in 0..900000 {
-            batch.clear(ValueClass::Config(format!("key{n:10}").into_bytes()));
-
-            if n % 10000 == 0 {
-                db.write(batch.build_batch()).await.unwrap();
-                batch = BatchBuilder::new();
-                batch
-                    .with_account_id(0)
-                    .with_collection(0)
-                    .update_document(0);
-            }
-        }
-        db.write(batch.build_batch()).await.unwrap();
+            .create_document_with_id(document_id)
+            .tag(1u8, TagValue::Text(b"bulk".to_vec()), 0);
     }
+    db.write(batch.build()).await.unwrap();
+    assert_eq!(
+        db.rename_label(&registry, "bulk", "mass", &change_ids)
+            .await
+            .unwrap()
+            .len(),
+        1000
+    );
+    assert_eq!(db.get_bitmap(label_members("bulk")).await.unwrap(), None);
+    assert_eq!(
+        db.get_bitmap(label_members("mass"))
+            .await
+            .unwrap()
+            .unwrap()
+            .len(),
+        1000
+    );
+    let changes = db.changes(9001, 0u8, LogQuery::All).await.unwrap();
+    assert_ne!(changes.from_change_id, changes.to_change_id);
+    assert_eq!(
+        changes
+            .changes
+            .iter()
+            .filter(|change| matches!(change, Change::Update(id) if (100..1100).contains(id)))
+            .count(),
+        1000
+    );
+    db.purge_account(9001).await.unwrap();
 
-    // Testing ID assignment
-    println!("Running dynamic ID assignment tests...");
-    let mut builder = BatchBuilder::new();
-    builder
-        .with_account_id(0)
-        .with_collection(Collection::Thread)
-        .create_document()
+    // Renaming a keyword updates the keywords stored with each email
+    let registry = Keyword::label_registry(9002);
+    db.create_label(&registry, "work", LabelMetadata::default())
+        .await
+        .unwrap();
+    let mut batch = BatchBuilder::new();
+    batch
+        .with_account_id(9002)
         .with_collection(Collection::Email)
-        .create_document()
-        .tag(
-            Property::ThreadId,
-            TagValue::Id(MaybeDynamicId::Dynamic(0)),
-            0,
+        .create_document_with_id(1)
+        .value(
+            Property::Keywords,
+            vec![Keyword::Seen, Keyword::Other("work".to_string())],
+            F_VALUE | F_BITMAP,
         )
-        .set(Property::ThreadId, MaybeDynamicId::Dynamic(0));
-
-    let assigned_ids = db.write(builder.build_batch()).await.unwrap();
-    assert_eq!(assigned_ids.document_ids.len(), 2);
-    let thread_id = assigned_ids.first_document_id().unwrap();
-    let email_id = assigned_ids.last_document_id().unwrap();
-
-    let email_ids = db
-        .get_bitmap(BitmapKey {
-            account_id: 0,
-            collection: Collection::Email.into(),
-            class: BitmapClass::DocumentIds,
-            document_id: 0,
-        })
+        .value(Property::ThreadId, 7u32, F_VALUE);
+    db.write(batch.build()).await.unwrap();
+    db.rename_label(&registry, "work", "office", &change_ids)
         .await
-        .unwrap()
         .unwrap();
-    assert_eq!(email_ids.len(), 1);
-    assert!(email_ids.contains(email_id));
-
-    let thread_ids = db
-        .get_bitmap(BitmapKey {
-            account_id: 0,
-            collection: Collection::Thread.into(),
-            class: BitmapClass::DocumentIds,
-            document_id: 0,
-        })
+    assert_eq!(
+        db.get_value::<Vec<Keyword>>(ValueKey::<ValueClass<u32>>::property(
+            9002,
+            Collection::Email,
+            1,
+            Property::Keywords
+        ))
         .await
-        .unwrap()
-        .unwrap();
-    assert_eq!(thread_ids.len(), 1);
-    assert!(thread_ids.contains(thread_id));
-
-    let tagged_ids = db
-        .get_bitmap(BitmapKey {
-            account_id: 0,
-            collection: Collection::Email.into(),
-            class: BitmapClass::Tag {
-                field: Property::ThreadId.into(),
-                value: TagValue::Id(thread_id),
-            },
-            document_id: 0,
-        })
+        .unwrap(),
+        Some(vec![Keyword::Seen, Keyword::Other("office".to_string())])
+    );
+    assert_eq!(
+        db.changes(9002, Collection::Email, LogQuery::All)
+            .await
+            .unwrap()
+            .changes,
+        vec![Change::Update((7 << 32) | 1)]
+    );
+    db.delete_label(&registry, "office", &change_ids)
         .await
-        .unwrap()
         .unwrap();
-    assert_eq!(tagged_ids.len(), 1);
-    assert!(tagged_ids.contains(email_id));
+    assert_eq!(
+        db.get_value::<Vec<Keyword>>(ValueKey::<ValueClass<u32>>::property(
+            9002,
+            Collection::Email,
+            1,
+            Property::Keywords
+        ))
+        .await
+        .unwrap(),
+        Some(vec![Keyword::Seen])
+    );
+    db.purge_account(9002).await.unwrap();
 
-    let stored_thread_id = db
-        .get_value::<u32>(ValueKey {
-            account_id: 0,
-            collection: Collection::Email.into(),
-            document_id: email_id,
-            class: ValueClass::Property(Property::ThreadId.into()),
-        })
+    // Tokens outside the configured length bounds are neither indexed nor queried
+    let limits = db.token_limits();
+    let long_token = "x".repeat(limits.max_length + 1);
+    let mut document = FtsDocument::with_default_language(Language::English)
+        .with_account_id(9101)
+        .with_collection(0u8)
+        .with_document_id(1);
+    document.index(
+        Field::<u8>::Body,
+        format!("a quick {long_token}"),
+        Language::English,
+    );
+    db.fts_index(document).await.unwrap();
+    for (filter, expected) in [
+        (
+            FtsFilter::has_english_text(Field::<u8>::Body, "a quick"),
+            vec![1u32],
+        ),
+        (FtsFilter::has_keyword(Field::<u8>::Body, "quick"), vec![1]),
+        (FtsFilter::has_keyword(Field::<u8>::Body, "a"), vec![]),
+        (
+            FtsFilter::has_keyword(Field::<u8>::Body, long_token.as_str()),
+            vec![],
+        ),
+    ] {
+        assert_eq!(
+            db.fts_query(9101, 0u8, vec![filter])
+                .await
+                .unwrap()
+                .into_iter()
+                .collect::<Vec<_>>(),
+            expected
+        );
+    }
+
+    // The term dictionary reports document frequencies
+    let mut document = FtsDocument::with_default_language(Language::English)
+        .with_account_id(9101)
+        .with_collection(0u8)
+        .with_document_id(2);
+    document.index(
+        Field::<u8>::Body,
+        "quick brown internationalization",
+        Language::English,
+    );
+    db.fts_index(document).await.unwrap();
+    let terms = db
+        .dump_terms(9101, 0u8, Field::<u8>::Body, None)
+        .map(|term| term.unwrap())
+        .collect::<Vec<_>>()
+        .await;
+    assert!(terms.contains(&("quick".to_string(), 2)), "{terms:?}");
+    assert!(terms.contains(&("brown".to_string(), 1)), "{terms:?}");
+    assert_eq!(
+        terms
+            .iter()
+            .filter(|(term, _)| term.starts_with('#'))
+            .count(),
+        1,
+        "{terms:?}"
+    );
+    assert_eq!(
+        db.dump_terms(9101, 0u8, Field::<u8>::Body, Some("qu"))
+            .map(|term| term.unwrap())
+            .collect::<Vec<_>>()
+            .await,
+        vec![("quick".to_string(), 2)]
+    );
+    assert!(db
+        .dump_terms(9101, 0u8, Field::<u8>::Attachment, None)
+        .collect::<Vec<_>>()
         .await
-        .unwrap()
-        .unwrap();
-    assert_eq!(stored_thread_id, thread_id);
+        .is_empty());
 
-    let mut builder = BatchBuilder::new();
-    builder
-        .with_account_id(0)
-        .with_collection(Collection::Thread)
-        .delete_document(thread_id)
-        .with_collection(Collection::Email)
-        .delete_document(email_id)
-        .tag(
-            Property::ThreadId,
-            TagValue::Id(MaybeDynamicId::Static(thread_id)),
-            F_CLEAR,
-        )
-        .clear(Property::ThreadId);
-    db.write(builder.build_batch()).await.unwrap();
+    // Address parts also match addresses indexed as words before they were tokenized
+    for (document_id, address) in [
+        (3u32, "John Doe <john.doe@example.com>"),
+        (4, "Jane Roe <jane.roe@example.com>"),
+    ] {
+        let mut document = FtsDocument::with_default_language(Language::English)
+            .with_account_id(9101)
+            .with_collection(0u8)
+            .with_document_id(document_id);
+        if document_id == 3 {
+            document.index_address(Field::Header(0u8), address);
+        } else {
+            document.index_tokenized(Field::Header(0u8), address);
+        }
+        db.fts_index(document).await.unwrap();
+    }
+    for (query, expected) in [
+        ("@example.com", vec![3u32, 4]),
+        ("jane.roe@", vec![4]),
+        ("john jane.roe@example.com", vec![]),
+        ("jane jane.roe@example.com", vec![4]),
+        ("jane.roe@example.org", vec![]),
+    ] {
+        assert_eq!(
+            db.fts_query(
+                9101,
+                0u8,
+                vec![FtsFilter::has_address(Field::Header(0u8), query)]
+            )
+            .await
+            .unwrap()
+            .into_iter()
+            .collect::<Vec<_>>(),
+            expected,
+            "{query}"
+        );
+    }
+    db.purge_account(9101).await.unwrap();
 
-    // Increment a counter 1000 times concurrently
-    let mut handles = Vec::new();
-    let mut assigned_ids = HashSet::new();
-    println!("Incrementing counter 1000 times concurrently...");
-    for _ in 0..1000 {
-        handles.push({
-            let db = db.clone();
-            tokio::spawn(async move {
-                let mut builder = BatchBuilder::new();
-                builder
-                    .with_account_id(0)
-                    .with_collection(0)
-                    .update_document(0)
-                    .add_and_get(ValueClass::Directory(DirectoryClass::UsedQuota(0)), 1);
-                db.write(builder.build_batch())
-                    .await
-                    .unwrap()
-                    .last_counter_id()
-                    .unwrap()
-            })
-        });
+    // Bulk deletions report progress and can be cancelled between chunks
+    let mut batch = BatchBuilder::new();
+    batch.with_account_id(9201);
+    for collection in [0u8, 1] {
+        batch.with_collection(collection);
+        for document_id in 0..10 {
+            batch
+                .create_document_with_id(document_id)
+                .value(0u8, format!("value {document_id}"), F_VALUE | F_INDEX)
+                .tag(1u8, document_id % 2, 0)
+                .set(
+                    BlobOp::Link {
+                        hash: BlobHash::from(format!("blob {document_id}").as_bytes()),
+                    },
+                    vec![],
+                );
+        }
+    }
+    db.write(batch.build()).await.unwrap();
+    let cancel = CancellationToken::new();
+    cancel.cancel();
+    let mut progress = DeleteProgress::new(cancel, |_| unreachable!());
+    assert!(!db.purge_collection(9201, 0u8, &mut progress).await.unwrap());
+    assert_eq!(progress.keys_deleted(), 0);
+    let mut reported = Vec::new();
+    let mut progress = DeleteProgress::new(CancellationToken::new(), |keys_deleted| {
+        reported.push(keys_deleted)
+    })
+    .with_chunk_size(5);
+    assert!(db.purge_collection(9201, 0u8, &mut progress).await.unwrap());
+    let keys_deleted = progress.keys_deleted();
+    drop(progress);
+    assert!(keys_deleted >= 30, "{keys_deleted}");
+    assert_eq!(reported.last(), Some(&keys_deleted));
+    assert!(reported.windows(2).all(|w| w[0] <= w[1]));
+    assert!(reported.len() > 1);
+    for (collection, expected) in [(0u8, None), (1, Some(10))] {
+        assert_eq!(
+            db.get_bitmap(BitmapKey::document_ids(9201, collection))
+                .await
+                .unwrap()
+                .map(|bm| bm.len()),
+            expected
+        );
+        assert_eq!(
+            db.blob_has_access(
+                BlobHash::from("blob 3".as_bytes()),
+                BlobClass::Linked {
+                    account_id: 9201,
+                    collection,
+                    document_id: 3,
+                },
+            )
+            .await
+            .unwrap(),
+            expected.is_some()
+        );
     }
+    let mut progress = DeleteProgress::new(CancellationToken::new(), |_| {});
+    assert!(db.purge_account_chunked(9201, &mut progress).await.unwrap());
+    db.blob_hash_unlink_account(9201).await.unwrap();
+    assert!(progress.keys_deleted() >= 30);
+    assert_eq!(
+        db.get_bitmap(BitmapKey::document_ids(9201, 1u8))
+            .await
+            .unwrap(),
+        None
+    );
 
-    for handle in handles {
-        let assigned_id = handle.await.unwrap();
-        assert!(
-            assigned_ids.insert(assigned_id),
-            "counter assigned {assigned_id} twice or more times."
+    // Reads tolerating staleness are routed to replicas that are caught up
+    let replicated = Arc::new(ReplicatedStore::new(db.clone(), vec![db.clone()]));
+    assert!(std::ptr::eq(
+        replicated.read_store(Some(Duration::from_secs(60))),
+        &replicated.primary
+    ));
+    assert_eq!(replicated.replica_lag(), vec![None]);
+    replicated.heartbeat().await.unwrap();
+    replicated.refresh_lag().await;
+    assert!(replicated.replica_lag()[0].unwrap() < Duration::from_secs(60));
+    assert!(std::ptr::eq(
+        replicated.read_store(Some(Duration::from_secs(60))),
+        &replicated.replicas[0].store
+    ));
+    assert!(std::ptr::eq(
+        replicated.read_store(None),
+        &replicated.primary
+    ));
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(std::ptr::eq(
+        replicated.read_store(Some(Duration::from_millis(10))),
+        &replicated.primary
+    ));
+
+    // Replicated stores write to the primary and filter on replicas on request
+    let replicated_db = Store::Replicated(replicated.clone());
+    replicated.heartbeat().await.unwrap();
+    replicated.refresh_lag().await;
+    assert!(std::ptr::eq(
+        replicated_db.read_store(Some(Duration::from_secs(60))),
+        &replicated.replicas[0].store
+    ));
+    assert!(std::ptr::eq(
+        replicated_db.read_store(None),
+        &replicated.primary
+    ));
+    assert!(std::ptr::eq(
+        db.read_store(Some(Duration::from_secs(60))),
+        &db
+    ));
+    replicated_db
+        .write(
+            BatchBuilder::new()
+                .with_account_id(9202)
+                .with_collection(0)
+                .create_document()
+                .tag(0u8, TagValue::Id(MaybeDynamicId::Static(1)), 0)
+                .build_batch(),
+        )
+        .await
+        .unwrap();
+    for max_staleness in [None, Some(Duration::from_secs(60))] {
+        assert_eq!(
+            replicated_db
+                .filter_with_options(
+                    9202,
+                    0u8,
+                    vec![Filter::is_in_bitmap(0u8, 1u32)],
+                    &FilterOptions {
+                        max_staleness,
+                        ..Default::default()
+                    },
+                )
+                .await
+                .unwrap()
+                .results
+                .len(),
+            1
         );
     }
-    assert_eq!(assigned_ids.len(), 1000);
+    db.purge_account(9202).await.unwrap();
+
+    // The heartbeat task measures the lag until the store is dropped
+    let mut replicated = ReplicatedStore::new(db.clone(), vec![db.clone()]);
+    replicated.heartbeat_interval = Duration::from_millis(10);
+    let replicated = Arc::new(replicated);
+    replicated.spawn_heartbeat();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(replicated.replica_lag()[0].is_some());
+    drop(replicated);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let mut batch = BatchBuilder::new();
+    batch.clear(ValueClass::Any(AnyClass {
+        subspace: SUBSPACE_CLUSTER,
+        key: HEARTBEAT_KEY.to_vec(),
+    }));
+    db.write(batch.build()).await.unwrap();
+
+    // Threads are rebuilt from references, falling back to subjects
+    let messages: [(u32, &str, &[&str], Option<u32>); 5] = [
+        (0, "a", &["m0"], Some(5)),
+        (1, "a", &["m1", "m0"], Some(6)),
+        (2, "a", &["m2"], None),
+        (3, "b", &["m3", "m0"], Some(5)),
+        (4, "b", &["m4", "m3"], Some(7)),
+    ];
+    let mut batch = BatchBuilder::new();
+    batch.with_account_id(9301).with_collection(1u8);
+    for thread_id in [5u32, 6, 7] {
+        batch.create_document_with_id(thread_id);
+    }
+    batch.with_collection(0u8);
+    for (document_id, subject, references, thread_id) in messages {
+        batch
+            .create_document_with_id(document_id)
+            .value(1u8, subject.to_string(), F_INDEX);
+        for reference in references {
+            batch.value(2u8, reference.to_string(), F_INDEX);
+        }
+        if let Some(thread_id) = thread_id {
+            batch.value(0u8, thread_id, F_VALUE | F_BITMAP);
+        }
+    }
+    db.write(batch.build()).await.unwrap();
+    let threads = ThreadIndex::new(0u8, 1u8, 0u8, 1u8, 2u8);
     assert_eq!(
-        db.get_counter(ValueKey {
-            account_id: 0,
-            collection: 0,
-            document_id: 0,
-            class: ValueClass::Directory(DirectoryClass::UsedQuota(0)),
-        })
+        db.recompute_threads(&threads, 9301, &change_ids)
+            .await
+            .unwrap(),
+        3
+    );
+    assert_eq!(
+        db.recompute_threads(&threads, 9301, &change_ids)
+            .await
+            .unwrap(),
+        0
+    );
+    let changes = db.changes(9301, 0u8, LogQuery::All).await.unwrap();
+    for (thread_id, document_id) in [(5u64, 1u64), (5, 2), (7, 3)] {
+        assert!(changes
+            .changes
+            .contains(&Change::Insert((thread_id << 32) | document_id)));
+    }
+    for (thread_id, document_id) in [(6u64, 1u64), (5, 3)] {
+        assert!(changes
+            .changes
+            .contains(&Change::Delete((thread_id << 32) | document_id)));
+    }
+    assert!(db
+        .changes(9301, 1u8, LogQuery::All)
         .await
-        .unwrap(),
-        1000
+        .unwrap()
+        .changes
+        .contains(&Change::Delete(6)));
+    for (thread_id, expected) in [(5u32, vec![0u32, 1, 2]), (6, vec![]), (7, vec![3, 4])] {
+        assert_eq!(
+            db.get_bitmap(BitmapKey::tag(9301, 0u8, 0u8, thread_id))
+                .await
+                .unwrap()
+                .unwrap_or_default(),
+            store::roaring::RoaringBitmap::from_iter(expected)
+        );
+    }
+    assert_eq!(
+        db.get_value::<u32>(ValueKey::<ValueClass<u32>>::property(9301, 0u8, 2, 0u8))
+            .await
+            .unwrap(),
+        Some(5)
+    );
+    assert_eq!(
+        db.get_bitmap(BitmapKey::document_ids(9301, 1u8))
+            .await
+            .unwrap()
+            .unwrap_or_default(),
+        store::roaring::RoaringBitmap::from_iter([5u32, 7])
     );
+    db.purge_account(9301).await.unwrap();
+
+    // Advisory locks are exclusive until released or expired
+    let lock = db
+        .acquire_lock("rename", Duration::from_secs(60))
+        .await
+        .unwrap();
+    assert!(matches!(
+        db.acquire_lock("rename", Duration::from_secs(60)).await,
+        Err(store::Error::AssertValueFailed)
+    ));
+    lock.release().await.unwrap();
+    let mut expired = db
+        .acquire_lock("rename", Duration::from_millis(10))
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(expired.is_expired());
+    let lock = db
+        .acquire_lock("rename", Duration::from_secs(60))
+        .await
+        .unwrap();
+    assert!(matches!(
+        expired.extend(Duration::from_secs(60)).await,
+        Err(store::Error::AssertValueFailed)
+    ));
+    assert!(matches!(
+        expired.release().await,
+        Err(store::Error::AssertValueFailed)
+    ));
+    lock.release().await.unwrap();
 
     println!("Running chunking tests...");
     for (test_num, value) in [
@@ -344,3 +3536,30 @@ pub async fn test(db: Store) {
         db.assert_is_empty(db.clone().into()).await;
     }
 }
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct StoredQuota(u64);
+
+impl Versionable for StoredQuota {
+    const VERSION: u8 = 2;
+
+    fn serialize_current(&self) -> Vec<u8> {
+        self.0.to_be_bytes().to_vec()
+    }
+
+    fn deserialize_version(version: u8, bytes: &[u8]) -> store::Result<Self> {
+        match version {
+            1 => u32::deserialize(bytes).map(|quota| StoredQuota(quota as u64)),
+            _ => u64::deserialize(bytes).map(StoredQuota),
+        }
+    }
+
+    // Quotas were stored as plain u32 values before they were versioned
+    fn is_legacy(bytes: &[u8]) -> bool {
+        bytes.len() == std::mem::size_of::<u32>()
+    }
+
+    fn deserialize_legacy(bytes: &[u8]) -> store::Result<Self> {
+        u32::deserialize(bytes).map(|quota| StoredQuota(quota as u64))
+    }
+}
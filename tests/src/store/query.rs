@@ -17,12 +17,12 @@ use store::{
     ahash::AHashMap,
     fts::{index::FtsDocument, Field, FtsFilter},
     query::sort::Pagination,
-    write::ValueClass,
-    FtsStore,
+    write::{BitmapClass, ValueClass},
+    BitmapKey, FtsStore,
 };
 
 use store::{
-    query::{Comparator, Filter},
+    query::{explain::FilterExplain, highlight::highlight, Comparator, Filter},
     write::{BatchBuilder, F_BITMAP, F_INDEX, F_VALUE},
     Store, ValueKey,
 };
@@ -241,10 +241,65 @@ pub async fn test(db: Store, fts_store: FtsStore, do_insert: bool) {
     test_filter(db.clone(), fts_store).await;
     println!("Filtering took {} ms.", now.elapsed().as_millis());
 
+    println!("Running explain tests...");
+    test_explain(db.clone()).await;
+
     println!("Running sort tests...");
     let now = Instant::now();
     test_sort(db).await;
     println!("Sorting took {} ms.", now.elapsed().as_millis());
+
+    println!("Running highlight tests...");
+    test_highlight();
+}
+
+pub fn test_highlight() {
+    let text = concat!(
+        "The quick brown fox jumps over the lazy dog. ",
+        "Meanwhile, far away from the farm, another quick fox was ",
+        "sleeping under a brown tree."
+    );
+
+    for (filters, expected) in [
+        (
+            vec![Filter::has_text(0u8, "fox")],
+            vec![(0, vec![16..19]), (56, vec![38..41])],
+        ),
+        (
+            vec![Filter::has_text(0u8, "\"quick brown\"")],
+            vec![(0, vec![4..15])],
+        ),
+        (
+            vec![
+                Filter::And,
+                Filter::has_text(0u8, "lazy"),
+                Filter::Not,
+                Filter::has_text(0u8, "fox"),
+                Filter::End,
+                Filter::End,
+            ],
+            vec![(0, vec![35..39])],
+        ),
+        (
+            vec![Filter::has_text(0u8, "tree sleeping")],
+            vec![(65, vec![37..45, 60..64])],
+        ),
+        (vec![Filter::has_text(1u8, "fox")], vec![]),
+        (vec![Filter::has_text(0u8, "\"brown fox tree\"")], vec![]),
+    ] {
+        let snippets = highlight(text, &filters, 0u8);
+        assert_eq!(
+            snippets
+                .iter()
+                .map(|s| (s.offset, s.matches.clone()))
+                .collect::<Vec<_>>(),
+            expected,
+            "{filters:?}"
+        );
+        for snippet in snippets {
+            assert_eq!(&text[snippet.offset..][..snippet.text.len()], snippet.text);
+        }
+    }
 }
 
 pub async fn test_filter(db: Store, fts: FtsStore) {
@@ -338,6 +393,24 @@ pub async fn test_filter(db: Store, fts: FtsStore) {
             ],
             vec!["p01764", "t05843"],
         ),
+        (
+            vec![
+                Filter::has_text(fields_u8["artist"], "mauro kunst"),
+                Filter::is_in_bitmap(
+                    fields_u8["artistRole"],
+                    Keyword::Other("artist".to_string()),
+                ),
+                Filter::eq_any(fields_u8["year"], [1971u32, 1969u32, 1971u32]),
+            ],
+            vec!["p01764", "t05843"],
+        ),
+        (
+            vec![
+                Filter::has_text(fields_u8["artist"], "mauro kunst"),
+                Filter::eq_any(fields_u8["year"], Vec::<u32>::new()),
+            ],
+            vec![],
+        ),
         (
             vec![
                 Filter::is_in_set(
@@ -464,6 +537,86 @@ pub async fn test_filter(db: Store, fts: FtsStore) {
     }
 }
 
+pub async fn test_explain(db: Store) {
+    let mut fields = AHashMap::default();
+    for (field_num, field) in FIELDS.iter().enumerate() {
+        fields.insert(field.to_string(), field_num as u8);
+    }
+
+    let has_text = Filter::has_text(fields["artist"], "mauro kunst");
+    let in_bitmap =
+        Filter::is_in_bitmap(fields["artistRole"], Keyword::Other("artist".to_string()));
+    let year_1969 = Filter::eq(fields["year"], 1969u32);
+    let year_1971 = Filter::eq(fields["year"], 1971u32);
+
+    let (result, mut explain) = db
+        .filter_explained(
+            0,
+            COLLECTION_ID,
+            vec![
+                has_text.clone(),
+                in_bitmap.clone(),
+                Filter::Or,
+                year_1969.clone(),
+                year_1971.clone(),
+                Filter::End,
+            ],
+        )
+        .await
+        .unwrap();
+    assert_eq!(result.results.len(), 2);
+
+    // Each step reports the documents it matched on its own
+    let mut expected = FilterExplain {
+        filter: "And".to_string(),
+        matches: 2,
+        ..Default::default()
+    };
+    for filter in [has_text, in_bitmap] {
+        expected
+            .children
+            .push(explain_step(&db, filter.clone(), vec![filter]).await);
+    }
+    let mut or = explain_step(
+        &db,
+        Filter::Or,
+        vec![
+            Filter::Or,
+            year_1969.clone(),
+            year_1971.clone(),
+            Filter::End,
+        ],
+    )
+    .await;
+    for filter in [year_1969, year_1971] {
+        or.children
+            .push(explain_step(&db, filter.clone(), vec![filter]).await);
+    }
+    expected.children.push(or);
+
+    clear_elapsed(&mut explain);
+    assert_eq!(explain, expected);
+    assert!(expected.children.iter().all(|step| step.matches > 0));
+}
+
+async fn explain_step(db: &Store, step: Filter, filters: Vec<Filter>) -> FilterExplain {
+    FilterExplain {
+        filter: step.explain_label(),
+        matches: db
+            .filter(0, COLLECTION_ID, filters)
+            .await
+            .unwrap()
+            .results
+            .len(),
+        ..Default::default()
+    }
+}
+
+fn clear_elapsed(explain: &mut FilterExplain) {
+    explain.elapsed_us = 0;
+    explain.children.iter_mut().for_each(clear_elapsed);
+}
+
 pub async fn test_sort(db: Store) {
     let mut fields = AHashMap::default();
     for (field_num, field) in FIELDS.iter().enumerate() {
@@ -552,4 +705,45 @@ pub async fn test_sort(db: Store) {
         }
         assert_eq!(results, expected_results);
     }
+
+    // Bitmap comparators sort the same way as the equivalent document set
+    let role = Keyword::Other("after".to_string());
+    let role_ids = db
+        .get_bitmap(BitmapKey {
+            account_id: 0,
+            collection: COLLECTION_ID,
+            class: BitmapClass::Tag {
+                field: fields["artistRole"],
+                value: (&role).into(),
+            },
+            document_id: 0,
+        })
+        .await
+        .unwrap()
+        .unwrap();
+    for ascending in [true, false] {
+        let mut results = Vec::new();
+        for comparator in [
+            Comparator::tag(fields["artistRole"], &role, ascending),
+            Comparator::set(role_ids.clone(), ascending),
+        ] {
+            results.push(
+                db.sort(
+                    db.filter(0, COLLECTION_ID, vec![Filter::gt(fields["year"], 1900u32)])
+                        .await
+                        .unwrap(),
+                    vec![
+                        comparator,
+                        Comparator::ascending(fields["accession_number"]),
+                    ],
+                    Pagination::new(50, 0, None, 0),
+                )
+                .await
+                .unwrap()
+                .ids,
+            );
+        }
+        assert_eq!(results[0], results[1]);
+        assert_eq!(role_ids.contains(results[0][0] as u32), ascending);
+    }
 }